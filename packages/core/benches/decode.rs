@@ -0,0 +1,89 @@
+//! Benchmarks for decoding bodies with shallow nesting.
+//!
+//! Besides timing, reports the number of heap allocations per decode, so
+//! the cost of the expansion passes can be compared across changes.
+//!
+//! Run with: cargo bench --bench decode
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use delta_ltsc_core::dictionary::{decompress, parse_dictionary};
+use delta_ltsc_core::types::CompressionConfig;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// System allocator wrapper that counts allocations.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// A stream whose body references two-level entries: each outer entry is
+/// an inner entry followed by a literal.
+fn generate_nested_stream(size: usize, config: &CompressionConfig) -> Vec<u32> {
+    let base = 0xFFFF0000u32;
+    let mut stream = vec![config.dict_start_token];
+    for i in 0..16u32 {
+        stream.extend([base + i, 3, i * 4, i * 4 + 1, i * 4 + 2]);
+    }
+    for i in 0..16u32 {
+        stream.extend([base + 16 + i, 2, base + i, 1000 + i]);
+    }
+    stream.push(config.dict_end_token);
+
+    let mut i = 0u32;
+    while stream.len() < size {
+        stream.extend([base + 16 + i % 16, 5000 + i % 7]);
+        i = i.wrapping_mul(31).wrapping_add(7) % 1009;
+    }
+    stream
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_nested");
+    let config = CompressionConfig::default();
+
+    for size in [1_000, 10_000, 100_000] {
+        let stream = generate_nested_stream(size, &config);
+        let dictionary: HashMap<u32, Vec<u32>> = parse_dictionary(&stream, &config).unwrap();
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        black_box(decompress(&stream, &dictionary, &config));
+        println!(
+            "decode_nested/{}: {} allocations",
+            size,
+            ALLOCATIONS.load(Ordering::Relaxed) - before
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("decompress", size),
+            &stream,
+            |b, stream| {
+                b.iter(|| decompress(black_box(stream), &dictionary, &config));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+//! Discovery on the benchmark corpus under each pattern hasher.
+//!
+//! The hasher is chosen at compile time, so compare the two by saving a
+//! SipHash baseline and measuring the `fast-hash` build against it:
+//!
+//! ```text
+//! cargo bench --bench hashing -- --save-baseline siphash
+//! cargo bench --bench hashing --features fast-hash -- --baseline siphash
+//! ```
+//!
+//! Benchmark ids carry no hasher name so criterion pairs the two runs; the
+//! hasher in use is printed before each group.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use delta_ltsc_core::discovery::{discover_candidates, DiscoveryConfig};
+use delta_ltsc_core::hash::PATTERN_HASHER;
+use std::collections::HashMap;
+
+/// The repository's benchmark corpus, read at run time.
+fn load_corpus() -> String {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../benchmarks/data/structured_corpus.txt");
+    std::fs::read_to_string(path).expect("benchmark corpus")
+}
+
+/// One token per Unicode scalar value, as `text::compress_str` does.
+fn char_tokens(text: &str) -> Vec<u32> {
+    text.chars().map(u32::from).collect()
+}
+
+/// One token per whitespace-separated word, numbered in first-seen order.
+fn word_tokens(text: &str) -> Vec<u32> {
+    let mut vocab: HashMap<&str, u32> = HashMap::new();
+    text.split_whitespace()
+        .map(|word| {
+            let next = vocab.len() as u32;
+            *vocab.entry(word).or_insert(next)
+        })
+        .collect()
+}
+
+/// The corpus repeated until it holds at least `size` tokens.
+fn scaled(tokens: &[u32], size: usize) -> Vec<u32> {
+    tokens.iter().copied().cycle().take(size.max(tokens.len())).collect()
+}
+
+fn bench_discover_corpus(c: &mut Criterion) {
+    let config = DiscoveryConfig::default();
+    let corpus = load_corpus();
+    let inputs = [("chars", char_tokens(&corpus)), ("words", word_tokens(&corpus))];
+
+    for (name, base) in inputs {
+        let mut group = c.benchmark_group(format!("discover_corpus_{}", name));
+        println!("discover_corpus_{}: hasher {}", name, PATTERN_HASHER);
+
+        for size in [base.len(), 10_000, 100_000] {
+            let tokens = scaled(&base, size);
+            group.throughput(Throughput::Elements(tokens.len() as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(tokens.len()), &tokens, |b, tokens| {
+                b.iter(|| discover_candidates(black_box(tokens), &config));
+            });
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_discover_corpus);
+criterion_main!(benches);
@@ -0,0 +1,222 @@
+//! Benchmarks for discovery and selection on pattern-rich input.
+//!
+//! Besides timing, reports the number of heap allocations per run, split
+//! between discovery and selection, so the effect of slicing patterns out
+//! of a shared buffer and slice-keyed pattern bookkeeping can be compared
+//! across changes. Long patterns show it most: each used to be copied for
+//! its candidate and again for every occurrence.
+//!
+//! Run with: cargo bench --bench pipeline
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use delta_ltsc_core::discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
+use delta_ltsc_core::selection::{
+    select_greedy_reranking, select_greedy_with, select_occurrences, DefaultScorer,
+    DEFAULT_MAX_ITERATIONS,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// System allocator wrapper that counts allocations.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Generate tokens built from many short patterns separated by unique filler.
+fn generate_pattern_rich(size: usize) -> Vec<u32> {
+    let patterns: Vec<Vec<u32>> = (0..32u32)
+        .map(|p| (0..2 + p % 7).map(|i| p * 16 + i).collect())
+        .collect();
+
+    let mut tokens = Vec::with_capacity(size);
+    let mut filler = 100_000u32;
+    let mut i = 0usize;
+    while tokens.len() < size {
+        tokens.extend(&patterns[i % patterns.len()]);
+        tokens.push(filler);
+        filler += 1;
+        i = i.wrapping_mul(31).wrapping_add(7) % 1009;
+    }
+    tokens.truncate(size);
+    tokens
+}
+
+/// Like `generate_pattern_rich`, with patterns of 12 to 40 tokens.
+fn generate_long_patterns(size: usize) -> Vec<u32> {
+    let patterns: Vec<Vec<u32>> = (0..32u32)
+        .map(|p| (0..12 + p % 29).map(|i| p * 64 + i).collect())
+        .collect();
+
+    let mut tokens = Vec::with_capacity(size);
+    let mut filler = 100_000u32;
+    let mut i = 0usize;
+    while tokens.len() < size {
+        tokens.extend(&patterns[i % patterns.len()]);
+        tokens.push(filler);
+        filler += 1;
+        i = i.wrapping_mul(31).wrapping_add(7) % 1009;
+    }
+    tokens.truncate(size);
+    tokens
+}
+
+/// Print the allocations one discovery and greedy selection run makes.
+fn report_allocations(name: &str, tokens: &[u32], config: &DiscoveryConfig) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let candidates = deduplicate_candidates(discover_candidates(tokens, config));
+    let discovered = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(select_occurrences(&candidates, "greedy", config.extra_cost));
+    let selected = ALLOCATIONS.load(Ordering::Relaxed);
+    println!(
+        "{}/{}: {} allocations ({} discovery, {} selection)",
+        name,
+        tokens.len(),
+        selected - before,
+        discovered - before,
+        selected - discovered
+    );
+}
+
+fn run_pipeline(tokens: &[u32], config: &DiscoveryConfig) -> usize {
+    let candidates = deduplicate_candidates(discover_candidates(tokens, config));
+    select_occurrences(&candidates, "greedy", config.extra_cost)
+        .selected
+        .len()
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline_pattern_rich");
+    let config = DiscoveryConfig::default();
+
+    for size in [1_000, 10_000, 50_000] {
+        let tokens = generate_pattern_rich(size);
+        report_allocations("pipeline_pattern_rich", &tokens, &config);
+
+        group.bench_with_input(BenchmarkId::new("greedy", size), &tokens, |b, tokens| {
+            b.iter(|| run_pipeline(black_box(tokens), &config));
+        });
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("pipeline_long_patterns");
+    let config = DiscoveryConfig {
+        max_length: 48,
+        ..Default::default()
+    };
+
+    for size in [1_000, 10_000, 50_000] {
+        let tokens = generate_long_patterns(size);
+        report_allocations("pipeline_long_patterns", &tokens, &config);
+
+        group.bench_with_input(BenchmarkId::new("greedy", size), &tokens, |b, tokens| {
+            b.iter(|| run_pipeline(black_box(tokens), &config));
+        });
+    }
+
+    group.finish();
+}
+
+/// Greedy selection alone, over candidates discovered up front, to track
+/// the cost of the refinement rounds.
+fn bench_select_greedy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("select_greedy_pattern_rich");
+    let config = DiscoveryConfig::default();
+
+    for size in [10_000, 50_000, 200_000] {
+        let tokens = generate_pattern_rich(size);
+        let candidates = deduplicate_candidates(discover_candidates(&tokens, &config));
+
+        // Ranked once, then re-ranked before every round as it used to be
+        group.bench_with_input(BenchmarkId::new("greedy", size), &candidates, |b, candidates| {
+            b.iter(|| {
+                let candidates = black_box(candidates);
+                let scorer = &DefaultScorer;
+                select_greedy_with(candidates, config.extra_cost, DEFAULT_MAX_ITERATIONS, scorer)
+            });
+        });
+        group.bench_with_input(
+            BenchmarkId::new("greedy_reranking", size),
+            &candidates,
+            |b, candidates| {
+                b.iter(|| {
+                    let candidates = black_box(candidates);
+                    let scorer = &DefaultScorer;
+                    let max_iterations = DEFAULT_MAX_ITERATIONS;
+                    select_greedy_reranking(candidates, config.extra_cost, max_iterations, scorer)
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Optimal selection over a few patterns with thousands of occurrences
+/// each, where per-pattern weighting dominates the refinement rounds.
+fn bench_select_optimal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("select_optimal_many_occurrences");
+    let config = DiscoveryConfig::default();
+
+    let motif = [1, 2, 3, 1, 2, 4];
+
+    for size in [10_000, 50_000, 200_000] {
+        let tokens: Vec<u32> = (0..size).map(|i| motif[i % motif.len()]).collect();
+        let candidates = deduplicate_candidates(discover_candidates(&tokens, &config));
+
+        group.bench_with_input(BenchmarkId::new("optimal", size), &candidates, |b, candidates| {
+            b.iter(|| select_occurrences(black_box(candidates), "optimal", config.extra_cost));
+        });
+    }
+
+    group.finish();
+}
+
+/// Discovery alone, reporting how many candidates reach selection.
+fn bench_discover(c: &mut Criterion) {
+    let mut group = c.benchmark_group("discover_pattern_rich");
+    let config = DiscoveryConfig::default();
+
+    for size in [10_000, 50_000, 200_000] {
+        let tokens = generate_pattern_rich(size);
+        println!(
+            "discover_pattern_rich/{}: {} candidates",
+            size,
+            discover_candidates(&tokens, &config).len()
+        );
+
+        group.bench_with_input(BenchmarkId::new("suffix_array", size), &tokens, |b, tokens| {
+            b.iter(|| discover_candidates(black_box(tokens), &config));
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_pipeline,
+    bench_select_greedy,
+    bench_select_optimal,
+    bench_discover
+);
+criterion_main!(benches);
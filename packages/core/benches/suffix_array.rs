@@ -96,6 +96,57 @@ fn bench_sequential(c: &mut Criterion) {
     group.finish();
 }
 
+/// Interval extraction with increasing `min_count`; prints how many
+/// intervals each setting hands to discovery.
+fn bench_lcp_intervals(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lcp_intervals");
+
+    for size in [10_000, 50_000].iter() {
+        let tokens = generate_structured_tokens(*size);
+        let sa = SuffixArray::build(&tokens);
+        group.throughput(Throughput::Elements(*size as u64));
+
+        for min_count in [2, 4, 8] {
+            let count = sa.lcp_intervals(2, min_count).len();
+            println!("size {} min_count {}: {} intervals", size, min_count, count);
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("min_count_{}", min_count), size),
+                &sa,
+                |b, sa| {
+                    b.iter(|| sa.lcp_intervals(2, black_box(min_count)));
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+/// Counting-based initial ranking against sort-based ranking on byte input.
+fn bench_build_with_alphabet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("suffix_array_alphabet");
+    group.sample_size(10);
+
+    for size in [100_000, 1_000_000].iter() {
+        let tokens = generate_random_tokens(*size, 256);
+        group.throughput(Throughput::Elements(*size as u64));
+
+        group.bench_with_input(BenchmarkId::new("build", size), &tokens, |b, tokens| {
+            b.iter(|| SuffixArray::build(black_box(tokens)));
+        });
+        group.bench_with_input(
+            BenchmarkId::new("build_with_alphabet", size),
+            &tokens,
+            |b, tokens| {
+                b.iter(|| SuffixArray::build_with_alphabet(black_box(tokens), 256));
+            },
+        );
+    }
+
+    group.finish();
+}
+
 #[cfg(feature = "parallel")]
 fn bench_parallel(c: &mut Criterion) {
     let mut group = c.benchmark_group("suffix_array_parallel");
@@ -171,11 +222,18 @@ fn bench_comparison(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_sequential,
+    bench_lcp_intervals,
+    bench_build_with_alphabet,
     bench_parallel,
     bench_comparison
 );
 
 #[cfg(not(feature = "parallel"))]
-criterion_group!(benches, bench_sequential);
+criterion_group!(
+    benches,
+    bench_sequential,
+    bench_lcp_intervals,
+    bench_build_with_alphabet
+);
 
 criterion_main!(benches);
@@ -0,0 +1,526 @@
+//! Analysis helpers built on top of the compression pipeline.
+//!
+//! These functions run discovery and selection but report on the result
+//! instead of producing a serialized stream.
+
+use crate::dictionary::{
+    build_body, compute_serialized_size, dictionary_overhead, trim_to_dict_fraction,
+};
+use crate::discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
+use crate::error::CompressionError;
+use crate::selection::{select_occurrences_with, DefaultScorer};
+use crate::types::{compute_savings, CompressionConfig, CompressionResult, Occurrence, Token};
+use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Find the `k` contiguous regions contributing the most savings.
+///
+/// Selected occurrences that touch or overlap are merged into a single
+/// region. Each occurrence contributes its share of its pattern's net
+/// savings: `length - 1` body tokens saved, minus the dictionary entry cost
+/// amortized over the pattern's selected occurrences.
+///
+/// Returns `(start, end, savings)` tuples with `end` exclusive, ordered by
+/// savings (highest first).
+pub fn compressible_regions(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    k: usize,
+) -> Vec<(usize, usize, f64)> {
+    if k == 0 || tokens.len() < config.min_subsequence_length * 2 {
+        return Vec::new();
+    }
+
+    let discovery_config = DiscoveryConfig::from_compression_config(config);
+    let candidates = discover_candidates(tokens, &discovery_config);
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+
+    let candidates = deduplicate_candidates(candidates);
+    let selection_result = select_occurrences_with(
+        &candidates,
+        &config.selection_mode,
+        discovery_config.extra_cost,
+        config.selection_max_iterations,
+        &DefaultScorer,
+    );
+
+    let mut regions = group_into_regions(&selection_result.selected, discovery_config.extra_cost);
+
+    regions.sort_by(|a, b| {
+        b.2.partial_cmp(&a.2)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    regions.truncate(k);
+    regions
+}
+
+/// Zero-order empirical entropy of `tokens`, in bits per token.
+///
+/// `-Σ p log2 p` over the frequency `p` of each distinct token.
+pub fn zero_order_entropy(tokens: &[Token]) -> f64 {
+    let mut counts: HashMap<Token, usize> = HashMap::new();
+    for &token in tokens {
+        *counts.entry(token).or_default() += 1;
+    }
+
+    let total = tokens.len() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// How far a result's compressed length is from the zero-order entropy bound.
+///
+/// The bound is the original tokens' entropy in token units: total bits
+/// divided by the bits a token takes over the input's alphabet,
+/// `len * H / log2(distinct)`. Returns the compressed length divided by
+/// the bound, so 1.0 means no better than entropy coding each token on
+/// its own and values below 1.0 mean repeats were exploited beyond it.
+/// Empty inputs give 1.0; inputs of a single distinct token, whose bound
+/// is zero, give infinity.
+pub fn entropy_headroom(result: &CompressionResult) -> f64 {
+    let tokens = &result.original_tokens;
+    if tokens.is_empty() {
+        return 1.0;
+    }
+
+    let distinct = tokens.iter().collect::<HashSet<_>>().len();
+    let bits_per_token = (distinct as f64).log2();
+    if bits_per_token == 0.0 {
+        return f64::INFINITY;
+    }
+
+    let bound = tokens.len() as f64 * zero_order_entropy(tokens) / bits_per_token;
+    result.compressed_length as f64 / bound
+}
+
+/// Estimated compression ratio (compressed/original) of `tokens`.
+///
+/// Runs discovery and selection for the first pass and each hierarchical
+/// pass, stopping as `compress` does once a pass saves under 2%, but sizes
+/// each pass with `compute_serialized_size` instead of serializing it.
+/// Escapes, literal runs, mirrored repeats and skip-grams are not
+/// modelled. Inputs that would not compress give 1.0.
+pub fn estimate_compression(tokens: &[Token], config: &CompressionConfig) -> f64 {
+    if tokens.is_empty() || tokens.len() < config.input_length_threshold() {
+        return 1.0;
+    }
+
+    let discovery_config = DiscoveryConfig::from_compression_config(config);
+    let depth = if config.hierarchical_enabled {
+        config.hierarchical_max_depth.max(1)
+    } else {
+        1
+    };
+
+    let mut sequence = tokens.to_vec();
+    let mut size = tokens.len();
+    let mut dictionary_size = 0;
+    let mut next_meta_token = crate::DEFAULT_NEXT_META_TOKEN;
+    for pass in 0..depth {
+        let candidates = discover_candidates(&sequence, &discovery_config);
+        if candidates.is_empty() {
+            break;
+        }
+        let candidates = deduplicate_candidates(candidates);
+        let selected = select_occurrences_with(
+            &candidates,
+            &config.selection_mode,
+            discovery_config.extra_cost,
+            config.selection_max_iterations,
+            &DefaultScorer,
+        )
+        .selected;
+        let selected = trim_to_dict_fraction(selected, sequence.len(), config);
+        if selected.is_empty() {
+            break;
+        }
+
+        // Later passes share the first pass's delimiters
+        let patterns: Vec<Vec<Token>> =
+            selected.iter().map(|occ| occ.subsequence.to_vec()).collect();
+        let overhead = dictionary_overhead(&patterns, config);
+        let body_size = compute_serialized_size(&selected, sequence.len(), config) - overhead;
+        let pass_dictionary = if pass == 0 { overhead } else { overhead - 2 };
+        let pass_size = dictionary_size + pass_dictionary + body_size;
+        let min_size = if pass == 0 { size } else { size - size / 50 };
+        let direction = config.meta_token_direction;
+        let ids = patterns.iter().collect::<HashSet<_>>().len();
+        if pass_size >= min_size || ids > direction.capacity(next_meta_token) {
+            break;
+        }
+        size = pass_size;
+        dictionary_size += pass_dictionary;
+
+        let mut pattern_to_meta: HashMap<Vec<Token>, Token> = HashMap::new();
+        for pattern in patterns {
+            let meta = direction.step(next_meta_token, pattern_to_meta.len());
+            pattern_to_meta.entry(pattern).or_insert(meta.expect("checked capacity"));
+        }
+        next_meta_token = match direction.step(next_meta_token, ids) {
+            Some(next) => next,
+            None => break,
+        };
+        sequence = build_body(&sequence, &selected, &pattern_to_meta, config.escape_token);
+    }
+
+    size as f64 / tokens.len() as f64
+}
+
+/// Fraction of the savings across `docs` that comes from patterns selected
+/// in more than one document.
+///
+/// Each document goes through a first pass of discovery and selection on
+/// its own, and each pattern it selects saves `compute_savings` for its
+/// occurrences there. Patterns selected in several documents could share
+/// one static dictionary entry, so a high value means a static dictionary
+/// pays off. Gives 0.0 when no document compresses.
+pub fn shared_pattern_coverage(docs: Vec<Vec<Token>>, config: &CompressionConfig) -> f64 {
+    let discovery_config = DiscoveryConfig::from_compression_config(config);
+    let extra_cost = discovery_config.extra_cost;
+
+    // Savings over all documents, and how many documents select the pattern
+    let mut patterns: HashMap<Vec<Token>, (i64, usize)> = HashMap::new();
+    for doc in &docs {
+        if doc.len() < config.input_length_threshold() {
+            continue;
+        }
+        let candidates = discover_candidates(doc, &discovery_config);
+        if candidates.is_empty() {
+            continue;
+        }
+        let selected = select_occurrences_with(
+            &deduplicate_candidates(candidates),
+            &config.selection_mode,
+            extra_cost,
+            config.selection_max_iterations,
+            &DefaultScorer,
+        )
+        .selected;
+
+        let mut counts: HashMap<&[Token], usize> = HashMap::new();
+        for occ in &selected {
+            *counts.entry(occ.subsequence.as_slice()).or_default() += 1;
+        }
+        for (pattern, count) in counts {
+            let (savings, documents) = patterns.entry(pattern.to_vec()).or_default();
+            *savings += compute_savings(pattern.len(), count, extra_cost);
+            *documents += 1;
+        }
+    }
+
+    let total: i64 = patterns.values().map(|&(savings, _)| savings).sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let shared: i64 = patterns
+        .values()
+        .filter(|&&(_, documents)| documents > 1)
+        .map(|&(savings, _)| savings)
+        .sum();
+    shared as f64 / total as f64
+}
+
+/// Cheapest default-based config whose `estimate_compression` for
+/// `tokens` is at most `target_ratio`.
+///
+/// Tries `max_subsequence_length` 8, 16 and 32 with `hierarchical_max_depth`
+/// 1 to 3, cheapest first by the product of the two, under greedy
+/// selection and then under optimal selection, which costs more than any
+/// greedy setting. Returns `None` if none reaches the target.
+pub fn config_for_target_ratio(tokens: &[Token], target_ratio: f64) -> Option<CompressionConfig> {
+    let mut trials: Vec<(usize, usize, usize)> = Vec::new();
+    for max_length in [8, 16, 32] {
+        for depth in 1..=3 {
+            trials.push((max_length * depth, depth, max_length));
+        }
+    }
+    trials.sort_unstable();
+
+    let modes = ["greedy", "optimal"];
+    let mut trials = modes.iter().flat_map(|&mode| trials.iter().map(move |&t| (mode, t)));
+    trials.find_map(|(mode, (_, depth, max_length))| {
+        let config = CompressionConfig {
+            selection_mode: mode.to_string(),
+            max_subsequence_length: max_length,
+            hierarchical_max_depth: depth,
+            ..Default::default()
+        };
+        (estimate_compression(tokens, &config) <= target_ratio).then_some(config)
+    })
+}
+
+/// How one config fared in `compare_configs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigComparison {
+    /// The config compressed with
+    pub config: CompressionConfig,
+    /// Compressed length over original length
+    pub ratio: f64,
+    /// Length of the serialized output
+    pub compressed_length: usize,
+    /// Wall-clock compression time, with the `metrics` feature
+    pub elapsed_ms: Option<f64>,
+}
+
+/// Compress `tokens` under each config, best ratio first.
+///
+/// Configs with equal ratios keep their order. Fails on the first config
+/// that does not validate or compress.
+pub fn compare_configs(
+    tokens: &[Token],
+    configs: Vec<CompressionConfig>,
+) -> Result<Vec<ConfigComparison>, CompressionError> {
+    let mut comparisons = Vec::with_capacity(configs.len());
+    for config in configs {
+        config.validate()?;
+        let started = now_ms();
+        let result = compress_internal(tokens, &config, DEFAULT_NEXT_META_TOKEN)?;
+        let elapsed_ms = started.map(|started| now_ms().unwrap_or(started) - started);
+        comparisons.push(ConfigComparison {
+            config,
+            ratio: result.compression_ratio(),
+            compressed_length: result.compressed_length,
+            elapsed_ms,
+        });
+    }
+    comparisons.sort_by(|a, b| a.ratio.total_cmp(&b.ratio));
+    Ok(comparisons)
+}
+
+/// Milliseconds from an arbitrary origin, when timing is compiled in.
+/// `std::time::Instant` is not available in the browser.
+#[cfg(all(feature = "metrics", target_arch = "wasm32"))]
+fn now_ms() -> Option<f64> {
+    Some(js_sys::Date::now())
+}
+
+#[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+fn now_ms() -> Option<f64> {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    Some(ORIGIN.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0)
+}
+
+#[cfg(not(feature = "metrics"))]
+fn now_ms() -> Option<f64> {
+    None
+}
+
+/// Merge occurrences (sorted by start) into contiguous savings regions.
+fn group_into_regions(selected: &[Occurrence], extra_cost: usize) -> Vec<(usize, usize, f64)> {
+    let mut pattern_counts: HashMap<&[Token], usize> = HashMap::new();
+    for occ in selected {
+        *pattern_counts.entry(occ.subsequence.as_slice()).or_default() += 1;
+    }
+
+    let mut regions: Vec<(usize, usize, f64)> = Vec::new();
+    for occ in selected {
+        let count = pattern_counts[occ.subsequence.as_slice()];
+        let dict_cost = (1 + occ.length + extra_cost) as f64 / count as f64;
+        let share = occ.length as f64 - 1.0 - dict_cost;
+
+        match regions.last_mut() {
+            Some(last) if occ.start <= last.1 => {
+                last.1 = last.1.max(occ.end());
+                last.2 += share;
+            }
+            _ => regions.push((occ.start, occ.end(), share)),
+        }
+    }
+
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressible_regions_two_regions() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for _ in 0..3 {
+            tokens.extend([1, 2, 3, 4]);
+        }
+        tokens.extend(100..110);
+        for _ in 0..3 {
+            tokens.extend([5, 6, 7, 8]);
+        }
+
+        let config = CompressionConfig {
+            max_subsequence_length: 4,
+            ..Default::default()
+        };
+
+        let regions = compressible_regions(&tokens, &config, 5);
+
+        assert_eq!(regions.len(), 2);
+        let mut bounds: Vec<(usize, usize)> = regions.iter().map(|r| (r.0, r.1)).collect();
+        bounds.sort_unstable();
+        assert_eq!(bounds, vec![(0, 12), (22, 34)]);
+        assert!(regions.iter().all(|r| r.2 > 0.0));
+    }
+
+    #[test]
+    fn test_compare_configs_sorts_by_ratio() {
+        let tokens = repeated_records();
+        let shallow = CompressionConfig {
+            hierarchical_max_depth: 1,
+            ..Default::default()
+        };
+        let deep = CompressionConfig {
+            selection_mode: "optimal".to_string(),
+            hierarchical_max_depth: 5,
+            ..Default::default()
+        };
+
+        let comparisons = compare_configs(&tokens, vec![shallow, deep]).unwrap();
+        assert_eq!(comparisons.len(), 2);
+        let (best, worst) = (&comparisons[0], &comparisons[1]);
+        assert!(best.ratio <= worst.ratio);
+        let optimal = comparisons
+            .iter()
+            .find(|c| c.config.selection_mode == "optimal")
+            .unwrap();
+        assert_eq!(optimal.ratio, best.ratio);
+        assert!(optimal.ratio < 1.0);
+        let expected = optimal.compressed_length as f64 / tokens.len() as f64;
+        assert_eq!(optimal.ratio, expected);
+        assert_eq!(optimal.elapsed_ms.is_some(), cfg!(feature = "metrics"));
+
+        let invalid = CompressionConfig {
+            min_subsequence_length: 1,
+            ..Default::default()
+        };
+        assert!(compare_configs(&tokens, vec![invalid]).is_err());
+    }
+
+    #[test]
+    fn test_entropy_headroom_orders_repetitive_below_random() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let config = CompressionConfig::default();
+        let mut state: u32 = 12345;
+        let mut next = |n: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % n
+        };
+
+        // Blocks of eight tokens drawn from four, versus independent tokens
+        let blocks: Vec<Vec<Token>> = (0..4).map(|b| (b * 8..b * 8 + 8).collect()).collect();
+        let repetitive: Vec<Token> = (0..75)
+            .flat_map(|_| blocks[next(4) as usize].clone())
+            .collect();
+        let random: Vec<Token> = (0..600).map(|_| next(64)).collect();
+
+        assert_eq!(zero_order_entropy(&[7, 7, 9, 9]), 1.0);
+
+        let compress = |tokens: &[Token]| {
+            compress_internal(tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap()
+        };
+        let repetitive_headroom = entropy_headroom(&compress(&repetitive));
+        let random_headroom = entropy_headroom(&compress(&random));
+
+        assert!(repetitive_headroom < 0.8);
+        assert!(random_headroom > 0.95);
+        assert!(repetitive_headroom < random_headroom);
+    }
+
+    /// Random 24-token records, each repeated in a shuffled order.
+    fn repeated_records() -> Vec<Token> {
+        let mut state: u64 = 7;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as Token % 5000
+        };
+        let records: Vec<Vec<Token>> = (0..6).map(|_| (0..24).map(|_| next()).collect()).collect();
+        let mut tokens = Vec::new();
+        for _ in 0..20 {
+            tokens.extend(&records[next() as usize % records.len()]);
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_config_for_target_ratio_reaches_target() {
+        let tokens = repeated_records();
+        let cheapest = CompressionConfig {
+            hierarchical_max_depth: 1,
+            ..Default::default()
+        };
+        // The cheapest config estimates about 0.504
+        let target = 0.49;
+        assert!(estimate_compression(&tokens, &cheapest) > target);
+
+        // Reached by escalating greedy settings alone
+        let config = config_for_target_ratio(&tokens, target).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.selection_mode, "greedy");
+        assert!(estimate_compression(&tokens, &config) <= target);
+        assert!(config.max_subsequence_length > 8 || config.hierarchical_max_depth > 1);
+
+        let result = crate::compress_internal(&tokens, &config, crate::DEFAULT_NEXT_META_TOKEN);
+        assert!(result.unwrap().compression_ratio() < 0.7);
+    }
+
+    #[test]
+    fn test_config_for_target_ratio_impossible_target() {
+        let tokens: Vec<Token> = [1, 2, 3, 4, 5].iter().cycle().take(60).copied().collect();
+        assert!(config_for_target_ratio(&tokens, 0.01).is_none());
+
+        let unique: Vec<Token> = (0..200).collect();
+        assert!(config_for_target_ratio(&unique, 0.99).is_none());
+    }
+
+    #[test]
+    fn test_shared_pattern_coverage_reflects_shared_savings() {
+        let config = CompressionConfig::default();
+        let common: Vec<Token> = [1, 2, 3, 4, 5].repeat(4);
+        let doc = |i: Token| {
+            let mut doc = common.clone();
+            doc.extend([100 * i, 100 * i + 1, 100 * i + 2, 100 * i + 3, 100 * i + 4].repeat(4));
+            doc
+        };
+
+        // Half of each document's savings come from the common pattern
+        let mixed = shared_pattern_coverage((1..=3).map(doc).collect(), &config);
+        assert!((mixed - 0.5).abs() < 0.05, "coverage {}", mixed);
+
+        let shared = shared_pattern_coverage(vec![common.clone(); 3], &config);
+        assert_eq!(shared, 1.0);
+
+        // Without the common part, no pattern is in both documents
+        let unique = doc(2)[common.len()..].to_vec();
+        assert_eq!(shared_pattern_coverage(vec![doc(1), unique], &config), 0.0);
+        assert_eq!(shared_pattern_coverage(vec![vec![1, 2, 3]], &config), 0.0);
+    }
+
+    #[test]
+    fn test_compressible_regions_truncates_to_k() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for _ in 0..3 {
+            tokens.extend([1, 2, 3, 4]);
+        }
+        tokens.extend(100..110);
+        for _ in 0..3 {
+            tokens.extend([5, 6, 7, 8]);
+        }
+
+        let config = CompressionConfig {
+            max_subsequence_length: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(compressible_regions(&tokens, &config, 1).len(), 1);
+        assert!(compressible_regions(&tokens, &config, 0).is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! Compression of many small sequences against one shared dictionary.
+//!
+//! Short inputs rarely repeat enough on their own to pay for a dictionary,
+//! but a batch of related inputs often shares patterns. Discovery runs over
+//! the concatenated batch, occurrences crossing a sequence boundary are
+//! discarded, and every sequence's body references the same dictionary.
+
+use crate::dictionary::{
+    build_body, build_dictionary, ensure_ids_fit, expand_body, is_reserved_token, parse_dictionary,
+    trim_to_dict_fraction, trim_to_pool, verify_replacements,
+};
+use crate::discovery::{
+    deduplicate_candidates, exclude_blacklisted, retain_single_type, try_discover_candidates,
+    DiscoveryConfig,
+};
+use crate::error::CompressionError;
+use crate::selection::{select_occurrences_with, DefaultScorer};
+use crate::suffix_array::try_with_capacity;
+use crate::types::{is_compressible, Candidate, CompressionConfig, Occurrence, Token};
+use crate::DEFAULT_NEXT_META_TOKEN;
+use std::collections::HashMap;
+
+/// Result of compressing a batch of sequences.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    /// Serialized shared dictionary (empty if the batch was not compressed)
+    pub dictionary_tokens: Vec<Token>,
+    /// Mapping from meta-token to its expansion
+    pub dictionary_map: HashMap<Token, Vec<Token>>,
+    /// One body per input sequence, in input order
+    pub bodies: Vec<Vec<Token>>,
+    /// Total length of the input sequences
+    pub original_length: usize,
+    /// Dictionary length plus total body length
+    pub compressed_length: usize,
+}
+
+impl BatchResult {
+    fn uncompressed(sequences: Vec<Vec<Token>>) -> Self {
+        let length = sequences.iter().map(Vec::len).sum();
+        Self {
+            dictionary_tokens: Vec::new(),
+            dictionary_map: HashMap::new(),
+            bodies: sequences,
+            original_length: length,
+            compressed_length: length,
+        }
+    }
+}
+
+/// Compress several independent sequences with one shared dictionary.
+///
+/// Patterns never span two sequences. Hierarchical passes and literal runs
+/// are not applied to batch bodies. If sharing a dictionary does not make
+/// the batch smaller, the sequences are returned unchanged with an empty
+/// dictionary.
+///
+/// Returns `OutOfMemory` rather than aborting when the concatenated batch
+/// or its suffix array cannot be allocated.
+pub fn compress_batch(
+    sequences: Vec<Vec<Token>>,
+    config: &CompressionConfig,
+) -> Result<BatchResult, CompressionError> {
+    compress_batch_from(sequences, config, DEFAULT_NEXT_META_TOKEN)
+}
+
+/// `compress_batch` with meta-tokens numbered from `next_meta_token`.
+pub(crate) fn compress_batch_from(
+    sequences: Vec<Vec<Token>>,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<BatchResult, CompressionError> {
+    let mut starts = try_with_capacity(sequences.len())?;
+    let mut concatenated = try_with_capacity(sequences.iter().map(Vec::len).sum())?;
+    for sequence in &sequences {
+        starts.push(concatenated.len());
+        concatenated.extend_from_slice(sequence);
+    }
+
+    if concatenated.len() < config.min_subsequence_length * 2 {
+        return Ok(BatchResult::uncompressed(sequences));
+    }
+
+    let discovery_config = DiscoveryConfig::from_compression_config(config);
+    let candidates = try_discover_candidates(&concatenated, &discovery_config)?;
+    let mut candidates = within_sequences(candidates, &starts, &sequences, &discovery_config);
+    let reserved = |t: &Token| is_reserved_token(*t, config, next_meta_token);
+    candidates.retain(|c| !c.subsequence.iter().any(reserved));
+    if !config.token_types.is_empty() {
+        retain_single_type(&mut candidates, |t| config.token_type(t));
+    }
+    let blacklist = &config.blacklist_patterns;
+    let candidates = exclude_blacklisted(&concatenated, candidates, blacklist, &discovery_config);
+    if candidates.is_empty() {
+        return Ok(BatchResult::uncompressed(sequences));
+    }
+
+    let candidates = deduplicate_candidates(candidates);
+    let selection_result = select_occurrences_with(
+        &candidates,
+        &config.selection_mode,
+        discovery_config.extra_cost,
+        config.selection_max_iterations,
+        &DefaultScorer,
+    );
+    let selected = trim_to_dict_fraction(selection_result.selected, concatenated.len(), config);
+    let selected = trim_to_pool(selected, config.meta_token_pool_size, config);
+    if selected.is_empty() {
+        return Ok(BatchResult::uncompressed(sequences));
+    }
+
+    ensure_ids_fit(&selected, config, next_meta_token)?;
+    let dict = build_dictionary(&selected, config, next_meta_token);
+
+    // Rebase each occurrence onto the sequence it falls in
+    let mut per_sequence: Vec<Vec<Occurrence>> = vec![Vec::new(); sequences.len()];
+    for mut occ in selected {
+        let index = sequence_index(&starts, occ.start);
+        occ.start -= starts[index];
+        per_sequence[index].push(occ);
+    }
+
+    let mut bodies: Vec<Vec<Token>> = Vec::with_capacity(sequences.len());
+    for (sequence, selected) in sequences.iter().zip(&per_sequence) {
+        if config.verify {
+            verify_replacements(sequence, selected, &dict, false)?;
+        }
+        bodies.push(build_body(sequence, selected, &dict.pattern_to_meta, config.escape_token));
+    }
+
+    let compressed_length = dict.tokens.len() + bodies.iter().map(Vec::len).sum::<usize>();
+    if compressed_length >= concatenated.len() {
+        return Ok(BatchResult::uncompressed(sequences));
+    }
+
+    let result = BatchResult {
+        dictionary_tokens: dict.tokens,
+        dictionary_map: dict.entries,
+        bodies,
+        original_length: concatenated.len(),
+        compressed_length,
+    };
+
+    if config.verify_full {
+        let restored = decompress_batch(&result.dictionary_tokens, &result.bodies, config)?;
+        if restored != sequences {
+            return Err(CompressionError::VerificationFailed);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Restore the sequences of a batch from its shared dictionary and bodies.
+pub fn decompress_batch(
+    dictionary: &[Token],
+    bodies: &[Vec<Token>],
+    config: &CompressionConfig,
+) -> Result<Vec<Vec<Token>>, CompressionError> {
+    let dictionary = parse_dictionary(dictionary, config)?;
+    Ok(bodies
+        .iter()
+        .map(|body| expand_body(body.clone(), &dictionary, config.escape_token))
+        .collect())
+}
+
+/// Index of the sequence containing concatenated position `pos`.
+fn sequence_index(starts: &[usize], pos: usize) -> usize {
+    starts.partition_point(|&start| start <= pos) - 1
+}
+
+/// Drop occurrences that cross a sequence boundary, then candidates that
+/// are no longer worth compressing.
+fn within_sequences(
+    candidates: Vec<Candidate>,
+    starts: &[usize],
+    sequences: &[Vec<Token>],
+    config: &DiscoveryConfig,
+) -> Vec<Candidate> {
+    candidates
+        .into_iter()
+        .filter_map(|mut candidate| {
+            let length = candidate.length;
+            let positions: Vec<usize> = candidate
+                .positions()
+                .filter(|&pos| {
+                    let index = sequence_index(starts, pos);
+                    pos + length <= starts[index] + sequences[index].len()
+                })
+                .collect();
+            candidate.set_positions(&positions);
+
+            let min_count = config.min_occurrences.min_count(length, config.extra_cost);
+            let count = positions.len();
+            if count < min_count
+                || !is_compressible(length, count, config.extra_cost)
+            {
+                return None;
+            }
+            Some(candidate)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_internal;
+
+    fn documents() -> Vec<Vec<Token>> {
+        (0..12)
+            .map(|i| vec![100 + i, 1, 2, 3, 4, 200 + i, 5, 6, 7])
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_compresses_where_sequences_do_not() {
+        let config = CompressionConfig::default();
+        let sequences = documents();
+
+        for sequence in &sequences {
+            let single = compress_internal(sequence, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert_eq!(single.compressed_length, sequence.len());
+        }
+
+        let result = compress_batch(sequences.clone(), &config).unwrap();
+
+        assert!(!result.dictionary_map.is_empty());
+        assert_eq!(result.bodies.len(), sequences.len());
+        assert!(result.compressed_length < result.original_length);
+
+        let restored =
+            decompress_batch(&result.dictionary_tokens, &result.bodies, &config).unwrap();
+        assert_eq!(restored, sequences);
+    }
+
+    #[test]
+    fn test_batch_patterns_do_not_span_sequences() {
+        // [1, 2] only repeats across the boundary between sequences
+        let sequences = vec![vec![9, 1], vec![2, 8, 1], vec![2, 7, 1], vec![2], vec![]];
+        let config = CompressionConfig::default();
+
+        let result = compress_batch(sequences.clone(), &config).unwrap();
+
+        assert!(result.dictionary_tokens.is_empty());
+        assert_eq!(result.bodies, sequences);
+    }
+}
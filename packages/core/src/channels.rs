@@ -0,0 +1,262 @@
+//! Compression of interleaved multi-channel token streams.
+//!
+//! A stream of `n` interleaved channels, `[c0, c1, c2, c0, c1, c2, ...]`,
+//! hides each channel's repeats behind the other channels. The stream is
+//! split into one sub-stream per channel and the sub-streams are compressed
+//! as a batch sharing one dictionary (see `batch`). The body holds the
+//! channel bodies back to back, in channel order. Channels differ in length
+//! by at most one token, so a decoder recovers each channel from the total
+//! decoded length alone.
+
+use crate::batch::compress_batch_from;
+use crate::dictionary::encode_body_runs;
+use crate::error::CompressionError;
+use crate::hash::hash_tokens;
+use crate::types::{CompressionConfig, CompressionResult, NoCompressionReason, Token};
+
+/// Split an interleaved stream into `channels` sub-streams.
+///
+/// Token `i` goes to channel `i % channels`.
+pub fn deinterleave(tokens: &[Token], channels: usize) -> Vec<Vec<Token>> {
+    let channels = channels.max(1);
+    let mut streams = vec![Vec::with_capacity(tokens.len().div_ceil(channels)); channels];
+    for (i, &token) in tokens.iter().enumerate() {
+        streams[i % channels].push(token);
+    }
+    streams
+}
+
+/// Interleave channel sub-streams stored back to back in `concatenated`.
+///
+/// Inverse of `deinterleave` followed by concatenation.
+pub fn interleave(concatenated: &[Token], channels: usize) -> Vec<Token> {
+    let channels = channels.max(1);
+    let total = concatenated.len();
+    (0..total)
+        .map(|i| concatenated[source_index(i, total, channels)])
+        .collect()
+}
+
+/// `interleave` for the tokens of `buffer` from `from` on, in place.
+///
+/// The interleaved tokens are written to the spare capacity behind the
+/// channels before replacing them, so a buffer with room for twice the
+/// channels' tokens does not reallocate.
+pub fn interleave_tail(buffer: &mut Vec<Token>, from: usize, channels: usize) {
+    let channels = channels.max(1);
+    let total = buffer.len() - from;
+    for i in 0..total {
+        let token = buffer[from + source_index(i, total, channels)];
+        buffer.push(token);
+    }
+    buffer.drain(from..from + total);
+}
+
+/// Index in the concatenated channels of interleaved token `i`.
+fn source_index(i: usize, total: usize, channels: usize) -> usize {
+    // Earlier channels take the extra token when the total does not divide
+    let channel = i % channels;
+    let start = channel * (total / channels) + channel.min(total % channels);
+    start + i / channels
+}
+
+/// Compress `tokens` as `config.channels` interleaved channels.
+///
+/// Hierarchical passes are not applied to channel bodies. When sharing a
+/// dictionary across the channels does not pay off, the interleaved input
+/// is returned unchanged.
+pub fn compress_channels(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<CompressionResult, CompressionError> {
+    if tokens.len() < config.input_length_threshold() {
+        let reason = NoCompressionReason::TooSmall;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
+
+    let streams = deinterleave(tokens, config.channels);
+    let batch = compress_batch_from(streams, config, next_meta_token)?;
+    if batch.dictionary_tokens.is_empty() {
+        // The batch does not say which step gave up
+        let reason = NoCompressionReason::NotBeneficial;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
+
+    let body = batch.bodies.concat();
+    let mut serialized = batch.dictionary_tokens.clone();
+    serialized.extend(&body);
+
+    let result = CompressionResult {
+        original_tokens: tokens.to_vec(),
+        compressed_length: serialized.len(),
+        serialized_tokens: serialized,
+        dictionary_tokens: batch.dictionary_tokens,
+        body_tokens: body,
+        dictionary_map: batch.dictionary_map,
+        original_length: tokens.len(),
+        static_dictionary_id: None,
+        reason: None,
+        selection_trace: None,
+        original_hash: hash_tokens(tokens),
+    };
+
+    if config.body_runs {
+        return Ok(encode_body_runs(result, config));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::{decode_body, decompress_with_positions};
+    use crate::edit::apply_edit;
+    use crate::spans::remap_spans;
+    use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+
+    fn channel_config() -> CompressionConfig {
+        CompressionConfig {
+            channels: 2,
+            hierarchical_enabled: false,
+            ..Default::default()
+        }
+    }
+
+    /// Two channels, each repeating its own motif between unique tokens.
+    fn channel_result() -> CompressionResult {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..10 {
+            for (a, b) in [(1, 10), (2, 11), (3, 12), (4, 13), (100 + i, 200 + i)] {
+                tokens.extend([a, b]);
+            }
+        }
+        let config = channel_config();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(!result.dictionary_map.is_empty());
+        result
+    }
+
+    #[test]
+    fn test_interleave_inverts_deinterleave() {
+        for len in 0..12 {
+            let tokens: Vec<Token> = (0..len).collect();
+            for channels in 1..5 {
+                let streams = deinterleave(&tokens, channels);
+                assert_eq!(interleave(&streams.concat(), channels), tokens);
+            }
+        }
+    }
+
+    #[test]
+    fn test_channels_expose_per_channel_repeats() {
+        // Each channel repeats its own motif between unique tokens; the
+        // motifs' lengths differ, so they drift apart once interleaved
+        let motifs: [&[Token]; 3] = [
+            &[1, 2, 3, 4],
+            &[10, 11, 12, 13, 14],
+            &[20, 21, 22, 23, 24, 25],
+        ];
+        let rows = 120;
+        let streams: Vec<Vec<Token>> = motifs
+            .iter()
+            .enumerate()
+            .map(|(channel, motif)| {
+                let mut stream = Vec::with_capacity(rows);
+                let mut unique = 1000 * (channel as Token + 1);
+                while stream.len() < rows {
+                    stream.extend_from_slice(motif);
+                    stream.push(unique);
+                    unique += 1;
+                }
+                stream.truncate(rows);
+                stream
+            })
+            .collect();
+        let tokens: Vec<Token> = (0..rows * 3).map(|i| streams[i % 3][i / 3]).collect();
+
+        let plain = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+        let channels = CompressionConfig {
+            channels: 3,
+            verify: true,
+            verify_full: true,
+            ..plain.clone()
+        };
+        let interleaved = compress_internal(&tokens, &plain, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let split = compress_internal(&tokens, &channels, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert_eq!(split.dictionary_map.len(), 3);
+        assert!(split.compressed_length * 2 < interleaved.compressed_length);
+        let restored =
+            decompress_internal(&split.serialized_tokens, &channels, DEFAULT_NEXT_META_TOKEN)
+                .unwrap();
+        assert_eq!(restored, tokens);
+
+        // Uneven channel lengths and literal runs round-trip too
+        let runs = CompressionConfig {
+            body_runs: true,
+            ..channels
+        };
+        let uneven = &tokens[..tokens.len() - 2];
+        let result = compress_internal(uneven, &runs, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(!result.dictionary_map.is_empty());
+        let restored =
+            decompress_internal(&result.serialized_tokens, &runs, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(restored, uneven);
+    }
+
+    #[test]
+    fn test_split_channel_body_decodes_to_input() {
+        let result = channel_result();
+        let config = channel_config();
+        for n in 1..=4 {
+            let segments = result.split_body(n, &config);
+            let decoded: Vec<Token> = segments
+                .iter()
+                .flat_map(|segment| decode_body(segment, &result.dictionary_map, &config))
+                .collect();
+            assert_eq!(interleave(&decoded, config.channels), result.original_tokens);
+
+            #[cfg(feature = "parallel")]
+            {
+                let map = &result.dictionary_map;
+                let parallel =
+                    crate::dictionary::decompress_segments_parallel(&segments, map, &config);
+                assert_eq!(parallel, result.original_tokens);
+            }
+        }
+    }
+
+    #[test]
+    fn test_positions_reject_channel_bodies() {
+        let result = channel_result();
+        let positions = decompress_with_positions(&result.serialized_tokens, &channel_config());
+        assert!(matches!(positions, Err(CompressionError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_uncompressed_spans_reject_channel_bodies() {
+        let result = channel_result();
+        let spans = result.uncompressed_spans(&channel_config());
+        assert!(matches!(spans, Err(CompressionError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_remap_spans_rejects_channel_bodies() {
+        let result = channel_result();
+        let length = result.original_length as u32;
+        let spans: Vec<(u32, u32)> = (0..length).map(|i| (i, i + 1)).collect();
+        let remapped = remap_spans(&result, &spans, &channel_config());
+        assert!(matches!(remapped, Err(CompressionError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_apply_edit_rejects_channel_bodies() {
+        let result = channel_result();
+        let edited = apply_edit(&result, 3, 1, &[7], &channel_config());
+        assert!(matches!(edited, Err(CompressionError::InvalidConfig(_))));
+    }
+}
@@ -42,22 +42,100 @@ pub struct JsCompressionConfig {
     pub min_subsequence_length: Option<usize>,
     /// Maximum pattern length
     pub max_subsequence_length: Option<usize>,
-    /// Selection mode: "greedy", "optimal", "beam"
+    /// Selection mode: "greedy", "optimal", "beam", "exact"
     pub selection_mode: Option<String>,
     /// Beam width for beam search
     pub beam_width: Option<usize>,
+    /// Selection refinement rounds
+    pub selection_max_iterations: Option<usize>,
     /// Enable hierarchical compression
     pub hierarchical_enabled: Option<bool>,
     /// Maximum hierarchical depth
     pub hierarchical_max_depth: Option<usize>,
-    /// Enable verification
+    /// Enable incremental verification
     pub verify: Option<bool>,
+    /// Enable full round-trip verification
+    pub verify_full: Option<bool>,
     /// Dict start token ID
     pub dict_start_token: Option<u32>,
     /// Dict end token ID
     pub dict_end_token: Option<u32>,
     /// Next meta-token ID to use
     pub next_meta_token: Option<u32>,
+    /// Meta-token numbering direction: "ascending" or "descending"
+    pub meta_token_direction: Option<crate::types::MetaTokenDirection>,
+    /// Maximum number of candidates kept during discovery
+    pub max_candidates: Option<usize>,
+    /// Emit literal runs with length prefixes
+    pub body_runs: Option<bool>,
+    /// Literal run marker token ID
+    pub literal_marker_token: Option<u32>,
+    /// Maximum dictionary share of the serialized output
+    pub max_dict_fraction: Option<f64>,
+    /// Longest dictionary entry accepted when decoding
+    pub max_dict_entry_length: Option<usize>,
+    /// Share dictionary entries between patterns and their reverses
+    pub detect_mirrored: Option<bool>,
+    /// Reverse marker token ID
+    pub reverse_marker_token: Option<u32>,
+    /// Escape token ID
+    pub escape_token: Option<u32>,
+    /// Match the Python implementation's output exactly
+    pub strict_python_parity: Option<bool>,
+    /// Seed for the Python meta-token pool shuffle
+    pub python_rng_seed: Option<u64>,
+    /// Sequences kept verbatim in the body
+    pub blacklist_patterns: Option<Vec<Vec<u32>>>,
+    /// Most gaps in a skip-gram pattern
+    pub max_gaps: Option<usize>,
+    /// Tokens per skip-gram gap
+    pub gap_len: Option<usize>,
+    /// Skip-gram wildcard token ID
+    pub gap_token: Option<u32>,
+    /// Frame incompressible input instead of returning it raw
+    pub always_framed: Option<bool>,
+    /// Build suffix arrays in parallel when available
+    pub enable_parallel: Option<bool>,
+    /// Shortest input worth compressing
+    pub min_input_length: Option<usize>,
+    /// Interleaved channels to compress separately
+    pub channels: Option<usize>,
+    /// Check pipeline invariants while compressing
+    pub debug_checks: Option<bool>,
+    /// Dictionary encoding: "flat", "trie" or "nested"
+    pub dict_encoding: Option<String>,
+    /// Order of equal dictionary entries: "lexicographic" or "first_occurrence"
+    pub dict_tie_break: Option<String>,
+    /// Choose delimiters above the largest input token
+    pub auto_delimiters: Option<bool>,
+    /// Add super-patterns for runs of repeated occurrences
+    pub coalesce_runs: Option<bool>,
+    /// Tokens treated as interchangeable during discovery
+    pub equivalence_classes: Option<Vec<Vec<u32>>>,
+    /// Record selection decisions
+    pub selection_trace: Option<bool>,
+    /// Longest input compressed in one pass
+    pub max_single_pass_length: Option<usize>,
+    /// Only discover pattern lengths that are a multiple of this
+    pub length_multiple_of: Option<usize>,
+    /// Occurrence threshold: `{"uniform": n}` or `"compressibility"`
+    pub min_occurrences: Option<crate::discovery::MinOccurrences>,
+    /// Overlap resolution: "earliest_first", "latest_first" or "max_count"
+    pub non_overlap: Option<crate::suffix_array::NonOverlapStrategy>,
+    /// Cap on occurrences offered to selection per pattern (0 = unbounded)
+    pub max_occurrences_per_pattern: Option<usize>,
+    /// Positions of strong token boundaries to prefer patterns aligned to
+    pub merge_boundaries: Option<Vec<usize>>,
+    /// Compress patterns wrapping from the end of the input to its start
+    pub circular: Option<bool>,
+    /// Longest input exact selection is run on
+    pub exact_max_input_length: Option<usize>,
+    /// Keep a compressed form as long as the input
+    pub accept_tie: Option<bool>,
+    /// Emit the lexicographically smallest equally long output
+    pub canonical_output: Option<bool>,
+    /// `[token, type]` pairs; patterns never mix token types
+    pub token_types: Option<Vec<(u32, u8)>>,
 }
 
 impl JsCompressionConfig {
@@ -77,6 +155,9 @@ impl JsCompressionConfig {
         if let Some(v) = self.beam_width {
             config.beam_width = v;
         }
+        if let Some(v) = self.selection_max_iterations {
+            config.selection_max_iterations = v;
+        }
         if let Some(v) = self.hierarchical_enabled {
             config.hierarchical_enabled = v;
         }
@@ -86,12 +167,126 @@ impl JsCompressionConfig {
         if let Some(v) = self.verify {
             config.verify = v;
         }
+        if let Some(v) = self.verify_full {
+            config.verify_full = v;
+        }
         if let Some(v) = self.dict_start_token {
             config.dict_start_token = v;
         }
         if let Some(v) = self.dict_end_token {
             config.dict_end_token = v;
         }
+        if let Some(v) = self.max_candidates {
+            config.max_candidates = v;
+        }
+        if let Some(v) = self.body_runs {
+            config.body_runs = v;
+        }
+        if let Some(v) = self.literal_marker_token {
+            config.literal_marker_token = v;
+        }
+        if let Some(v) = self.max_dict_fraction {
+            config.max_dict_fraction = v;
+        }
+        if let Some(v) = self.max_dict_entry_length {
+            config.max_dict_entry_length = v;
+        }
+        if let Some(v) = self.detect_mirrored {
+            config.detect_mirrored = v;
+        }
+        if let Some(v) = self.reverse_marker_token {
+            config.reverse_marker_token = v;
+        }
+        if let Some(v) = self.escape_token {
+            config.escape_token = v;
+        }
+        if let Some(v) = self.strict_python_parity {
+            config.strict_python_parity = v;
+        }
+        if let Some(v) = self.python_rng_seed {
+            config.python_rng_seed = v;
+        }
+        if let Some(ref v) = self.blacklist_patterns {
+            config.blacklist_patterns = v.clone();
+        }
+        if let Some(v) = self.max_gaps {
+            config.max_gaps = v;
+        }
+        if let Some(v) = self.gap_len {
+            config.gap_len = v;
+        }
+        if let Some(v) = self.gap_token {
+            config.gap_token = v;
+        }
+        if let Some(v) = self.always_framed {
+            config.always_framed = v;
+        }
+        if let Some(v) = self.meta_token_direction {
+            config.meta_token_direction = v;
+        }
+        if let Some(v) = self.enable_parallel {
+            config.enable_parallel = v;
+        }
+        if let Some(v) = self.min_input_length {
+            config.min_input_length = v;
+        }
+        if let Some(v) = self.channels {
+            config.channels = v;
+        }
+        if let Some(v) = self.debug_checks {
+            config.debug_checks = v;
+        }
+        if let Some(ref v) = self.dict_encoding {
+            config.dict_encoding = v.clone();
+        }
+        if let Some(ref v) = self.dict_tie_break {
+            config.dict_tie_break = v.clone();
+        }
+        if let Some(v) = self.auto_delimiters {
+            config.auto_delimiters = v;
+        }
+        if let Some(v) = self.coalesce_runs {
+            config.coalesce_runs = v;
+        }
+        if let Some(ref v) = self.equivalence_classes {
+            config.equivalence_classes = v.clone();
+        }
+        if let Some(v) = self.selection_trace {
+            config.selection_trace = v;
+        }
+        if let Some(v) = self.max_single_pass_length {
+            config.max_single_pass_length = v;
+        }
+        if let Some(v) = self.length_multiple_of {
+            config.length_multiple_of = v;
+        }
+        if let Some(v) = self.min_occurrences {
+            config.min_occurrences = v;
+        }
+        if let Some(v) = self.non_overlap {
+            config.non_overlap = v;
+        }
+        if let Some(v) = self.max_occurrences_per_pattern {
+            config.max_occurrences_per_pattern = v;
+        }
+        if let Some(ref v) = self.merge_boundaries {
+            config.merge_boundaries = v.clone();
+        }
+        if let Some(v) = self.circular {
+            config.circular = v;
+        }
+        if let Some(v) = self.exact_max_input_length {
+            config.exact_max_input_length = v;
+        }
+        if let Some(v) = self.accept_tie {
+            config.accept_tie = v;
+        }
+        if let Some(v) = self.canonical_output {
+            config.canonical_output = v;
+        }
+        if let Some(ref v) = self.token_types {
+            config.token_types = v.iter().copied().collect();
+        }
 
         config
     }
@@ -3,12 +3,64 @@
 //! Implements the LTSC compression format for dictionary and body tokens.
 //! Port of `delta/dictionary.py` and `delta/serialization.py`.
 
-use crate::types::{CompressionConfig, CompressionResult, Occurrence, Token};
-use std::collections::HashMap;
+use crate::error::CompressionError;
+use crate::hash::{hash_tokens, PatternMap, PatternSet};
+use crate::mirror::{expand_with_reversal, expand_with_reversal_into};
+use crate::skipgram::{expand_with_gaps, expand_with_gaps_into, gap_count};
+use crate::sniff::parse_with_lengths;
+use crate::types::{
+    compute_savings, CompressionConfig, CompressionResult, MetaTokenDirection, Occurrence, Token,
+};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Range, RangeInclusive};
 
 /// Default delimiter tokens if not specified in config.
 pub const DEFAULT_DICT_START: Token = 0xFFFFFFF0;
 pub const DEFAULT_DICT_END: Token = 0xFFFFFFF1;
+/// Default marker introducing a length-prefixed literal run.
+pub const DEFAULT_LITERAL_MARKER: Token = 0xFFFFFFF2;
+/// Default marker preceding a reversed meta-token.
+pub const DEFAULT_REVERSE_MARKER: Token = 0xFFFFFFF3;
+/// Default escape token; `[ESCAPE, literal]` emits `literal` unexpanded.
+pub const DEFAULT_ESCAPE_TOKEN: Token = 0xFFFFFFF4;
+/// Default wildcard marking a gap in a skip-gram definition.
+pub const DEFAULT_GAP_TOKEN: Token = 0xFFFFFFF5;
+
+/// Token opening a delimiter header written by `auto_delimiters`.
+///
+/// Reserved at the start of a stream: raw output opening with it is
+/// framed instead.
+pub const DELIMITER_HEADER_TOKEN: Token = 0xFFFFFFF8;
+
+/// Token opening a rotation header written by `circular` compression.
+///
+/// `[ROTATION_HEADER_TOKEN, cut]` means the rest of the stream decodes to
+/// the input rotated left by `cut`. Only read when `circular` is set.
+pub const ROTATION_HEADER_TOKEN: Token = 0xFFFFFFF9;
+
+/// Dictionary encodings `CompressionConfig::validate` accepts.
+///
+/// `flat` writes each definition in full. `trie` writes entries in
+/// depth-first order of the trie of their definitions, each as
+/// `[meta, depth, suffix_len, suffix...]`: the definition is the previous
+/// entry's first `depth` tokens followed by the suffix, so shared prefixes
+/// are stored once. `nested` is laid out as `flat`, but each definition
+/// refers to the shorter patterns it contains by their meta-tokens. Any
+/// other value is treated as `flat`.
+pub const DICT_ENCODINGS: [&str; 3] = ["flat", "trie", "nested"];
+
+/// Orders `CompressionConfig::validate` accepts for dictionary entries of
+/// equal length and count.
+///
+/// `lexicographic` compares their tokens. `first_occurrence` puts the
+/// pattern selected earliest in the input first, so meta-tokens are
+/// numbered in the order the body first uses them; patterns never tie
+/// there, as selected occurrences do not overlap. Any other value is
+/// treated as `lexicographic`.
+pub const DICT_TIE_BREAKS: [&str; 2] = ["lexicographic", "first_occurrence"];
+
+/// `(body_index, output_range)` pairs produced by `decompress_with_positions`.
+pub type BodyPositions = Vec<(usize, Range<usize>)>;
 
 /// Result of dictionary building.
 #[derive(Debug, Clone)]
@@ -25,6 +77,11 @@ pub struct Dictionary {
 ///
 /// Assigns meta-tokens to each unique pattern and produces the serialized
 /// dictionary format: [DICT_START, MT_1, LEN_1, DEF_1..., MT_2, LEN_2, DEF_2..., DICT_END]
+///
+/// # Panics
+///
+/// Panics if the patterns need more ids than remain from `next_meta_token`;
+/// callers check with `ensure_ids_fit` first.
 pub fn build_dictionary(
     selected: &[Occurrence],
     config: &CompressionConfig,
@@ -39,21 +96,27 @@ pub fn build_dictionary(
     }
 
     // Collect unique patterns with their occurrence counts
-    let mut pattern_counts: HashMap<Vec<Token>, usize> = HashMap::new();
+    let mut pattern_counts: PatternMap<Vec<Token>, usize> = PatternMap::default();
     for occ in selected {
-        *pattern_counts.entry(occ.subsequence.clone()).or_default() += 1;
+        *pattern_counts.entry(occ.subsequence.to_vec()).or_default() += 1;
     }
 
     // Order patterns for serialization (topological sort for hierarchical compression)
-    let ordered_patterns = topological_order(&pattern_counts, selected);
+    let mut ordered_patterns =
+        topological_order(&pattern_counts, selected, &config.dict_tie_break);
 
     // Assign meta-tokens
+    let direction = config.meta_token_direction;
+    let mut ids: Vec<Token> = (0..ordered_patterns.len())
+        .map(|i| direction.step(next_meta_token, i).expect("patterns fit the id range"))
+        .collect();
+    if config.canonical_output {
+        ids.sort_unstable();
+        ordered_patterns = canonical_order(ordered_patterns, &ids, config);
+    }
     let mut pattern_to_meta: HashMap<Vec<Token>, Token> = HashMap::new();
-    let mut meta_counter = next_meta_token;
-
-    for pattern in &ordered_patterns {
-        pattern_to_meta.insert(pattern.clone(), meta_counter);
-        meta_counter += 1;
+    for (pattern, &id) in ordered_patterns.iter().zip(&ids) {
+        pattern_to_meta.insert(pattern.clone(), id);
     }
 
     // Build dictionary entries
@@ -69,16 +132,25 @@ pub fn build_dictionary(
     let mut tokens = Vec::new();
     tokens.push(dict_start);
 
+    if config.dict_encoding == "trie" {
+        tokens.extend(serialize_trie(&pattern_to_meta));
+        tokens.push(dict_end);
+        return Dictionary {
+            entries,
+            tokens,
+            pattern_to_meta,
+        };
+    }
+
     for pattern in &ordered_patterns {
         let meta_token = pattern_to_meta[pattern];
         tokens.push(meta_token);
 
+        // Serialize definition (may reference earlier meta-tokens)
+        let definition = serialize_pattern(pattern, &pattern_to_meta, config);
         if config.dict_length_enabled {
-            tokens.push(pattern.len() as Token);
+            tokens.push(definition.len() as Token);
         }
-
-        // Serialize definition (may reference earlier meta-tokens)
-        let definition = serialize_pattern(pattern, &pattern_to_meta);
         tokens.extend(definition);
     }
 
@@ -91,14 +163,244 @@ pub fn build_dictionary(
     }
 }
 
-/// Serialize a pattern, potentially replacing sub-patterns with meta-tokens.
+/// Fail if the patterns of `selected` need more ids than remain from
+/// `next_meta_token` in the config's numbering direction.
+pub fn ensure_ids_fit(
+    selected: &[Occurrence],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<(), CompressionError> {
+    let direction = config.meta_token_direction;
+    let patterns: HashSet<&[Token]> =
+        selected.iter().map(|occ| occ.subsequence.as_slice()).collect();
+    if patterns.len() > direction.capacity(next_meta_token) {
+        return Err(CompressionError::InvalidConfig(format!(
+            "{} meta-tokens do not fit {:?} from {}",
+            patterns.len(),
+            direction,
+            next_meta_token
+        )));
+    }
+    Ok(())
+}
+
+/// Number of tokens `build_dictionary` emits for `patterns`.
+///
+/// Two delimiters plus, per distinct pattern, its meta-token, a length
+/// token when `dict_length_enabled`, and its serialized definition; trie
+/// entries instead hold a depth, a suffix length and the suffix. An
+/// empty set costs nothing, since no dictionary section is written.
+/// Definitions are measured through the same serializer `build_dictionary`
+/// uses, so a `nested` definition counts one token per pattern it refers
+/// to rather than that pattern's length.
+pub fn dictionary_overhead(patterns: &[Vec<Token>], config: &CompressionConfig) -> usize {
+    let mut pattern_to_meta: HashMap<Vec<Token>, Token> = HashMap::new();
+    for pattern in patterns {
+        let next = pattern_to_meta.len() as Token;
+        pattern_to_meta.entry(pattern.clone()).or_insert(next);
+    }
+    if pattern_to_meta.is_empty() {
+        return 0;
+    }
+    if config.dict_encoding == "trie" {
+        return 2 + serialize_trie(&pattern_to_meta).len();
+    }
+
+    let entries: usize = pattern_to_meta
+        .keys()
+        .map(|pattern| {
+            1 + config.extra_cost() + serialize_pattern(pattern, &pattern_to_meta, config).len()
+        })
+        .sum();
+    2 + entries
+}
+
+/// Serialized length of compressing `original_len` tokens with `selected`.
+///
+/// The dictionary is sized as in `dictionary_overhead`; the body keeps
+/// every uncovered token, one meta-token per occurrence and its skip-gram
+/// fillers. This equals `dict.tokens.len() + body.len()` from
+/// `build_dictionary` and `build_body` whenever no literal needs escaping.
+/// Escapes and literal run prefixes only lengthen the body, so it is
+/// otherwise a lower bound. Mirrored bodies are not covered.
+pub fn compute_serialized_size(
+    selected: &[Occurrence],
+    original_len: usize,
+    config: &CompressionConfig,
+) -> usize {
+    let patterns: Vec<Vec<Token>> = selected
+        .iter()
+        .map(|occ| occ.subsequence.to_vec())
+        .collect();
+    let covered: usize = selected.iter().map(|occ| occ.length - 1).sum();
+    let fillers: usize = selected.iter().map(|occ| occ.patches.len()).sum();
+
+    dictionary_overhead(&patterns, config) + original_len - covered + fillers
+}
+
+/// Drop the least valuable patterns until the dictionary fits `max_dict_fraction`.
+///
+/// Sizes are computed from the selection without building the dictionary:
+/// each pattern costs one meta-token, an optional length token and its
+/// definition, and saves `length - 1` body tokens per occurrence. Dropped
+/// patterns' occurrences stay literal in the body.
+pub fn trim_to_dict_fraction(
+    selected: Vec<Occurrence>,
+    original_len: usize,
+    config: &CompressionConfig,
+) -> Vec<Occurrence> {
+    trim_onto_dictionary(selected, original_len, 2, config)
+}
+
+/// `trim_to_dict_fraction` for a pass over a body that already comes with
+/// `dict_len` dictionary tokens, delimiters included, such as a
+/// hierarchical pass.
+pub(crate) fn trim_onto_dictionary(
+    selected: Vec<Occurrence>,
+    original_len: usize,
+    dict_len: usize,
+    config: &CompressionConfig,
+) -> Vec<Occurrence> {
+    if config.max_dict_fraction >= 1.0 || selected.is_empty() {
+        return selected;
+    }
+
+    let extra_cost = config.extra_cost();
+    let patterns = patterns_by_value(&selected, extra_cost);
+
+    let mut dict_len = dict_len;
+    let mut body_len = original_len;
+    for (pattern, count) in &patterns {
+        dict_len += 1 + extra_cost + pattern.len();
+        body_len -= count * (pattern.len() - 1);
+    }
+
+    let mut dropped: PatternSet<Vec<Token>> = PatternSet::default();
+    for (pattern, count) in patterns {
+        if dict_len as f64 <= config.max_dict_fraction * (dict_len + body_len) as f64 {
+            break;
+        }
+        dict_len -= 1 + extra_cost + pattern.len();
+        body_len += count * (pattern.len() - 1);
+        dropped.insert(pattern);
+    }
+
+    if dropped.is_empty() {
+        return selected;
+    }
+    without_patterns(selected, &dropped)
+}
+
+/// Drop the least valuable patterns until no more than `pool_size`
+/// remain, one for each id of the meta-token pool. Patterns are ranked as
+/// in `trim_to_dict_fraction`, and dropped patterns' occurrences stay
+/// literal in the body.
+pub fn trim_to_pool(
+    selected: Vec<Occurrence>,
+    pool_size: usize,
+    config: &CompressionConfig,
+) -> Vec<Occurrence> {
+    let patterns = patterns_by_value(&selected, config.extra_cost());
+    if patterns.len() <= pool_size {
+        return selected;
+    }
+    let excess = patterns.len() - pool_size;
+    let dropped = patterns.into_iter().take(excess).map(|(pattern, _)| pattern).collect();
+    without_patterns(selected, &dropped)
+}
+
+/// The distinct patterns of `selected` with their occurrence counts, least
+/// valuable first, with a token-order tie-break for determinism.
+fn patterns_by_value(selected: &[Occurrence], extra_cost: usize) -> Vec<(Vec<Token>, usize)> {
+    let mut pattern_counts: PatternMap<Vec<Token>, usize> = PatternMap::default();
+    for occ in selected {
+        *pattern_counts.entry(occ.subsequence.to_vec()).or_default() += 1;
+    }
+
+    let mut patterns: Vec<(Vec<Token>, usize)> = pattern_counts.into_iter().collect();
+    patterns.sort_by(|(a, count_a), (b, count_b)| {
+        compute_savings(a.len(), *count_a, extra_cost)
+            .cmp(&compute_savings(b.len(), *count_b, extra_cost))
+            .then_with(|| a.cmp(b))
+    });
+    patterns
+}
+
+/// `selected` without the occurrences of `dropped` patterns.
+fn without_patterns(
+    selected: Vec<Occurrence>,
+    dropped: &PatternSet<Vec<Token>>,
+) -> Vec<Occurrence> {
+    selected
+        .into_iter()
+        .filter(|occ| !dropped.contains(occ.subsequence.as_slice()))
+        .collect()
+}
+
+/// Serialize a pattern, replacing sub-patterns with meta-tokens when nested.
+///
+/// A `nested` definition is written left to right, taking at each position
+/// the longest shorter pattern that starts there as its meta-token. Only
+/// shorter patterns are referenced, so expansion always terminates.
+/// Skip-gram definitions are expanded without nesting, so patterns holding
+/// the gap token are written in full and never referenced.
 fn serialize_pattern(
     pattern: &[Token],
-    _pattern_to_meta: &HashMap<Vec<Token>, Token>,
+    pattern_to_meta: &HashMap<Vec<Token>, Token>,
+    config: &CompressionConfig,
 ) -> Vec<Token> {
-    // For now, don't replace sub-patterns in the dictionary
-    // (hierarchical compression handles this at a higher level)
-    pattern.to_vec()
+    let holds_gap = |p: &[Token]| config.gap_fillers_enabled() && p.contains(&config.gap_token);
+    if config.dict_encoding != "nested" || holds_gap(pattern) {
+        return pattern.to_vec();
+    }
+
+    let mut definition = Vec::with_capacity(pattern.len());
+    let mut pos = 0;
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        let longest = pattern_to_meta
+            .iter()
+            .filter(|(sub, _)| sub.len() < pattern.len() && rest.starts_with(sub))
+            .filter(|(sub, _)| !holds_gap(sub))
+            .max_by_key(|(sub, _)| sub.len());
+        match longest {
+            Some((sub, &meta)) => {
+                definition.push(meta);
+                pos += sub.len();
+            }
+            None => {
+                definition.push(pattern[pos]);
+                pos += 1;
+            }
+        }
+    }
+    definition
+}
+
+/// Serialize definitions as a trie walked depth first (see `DICT_ENCODINGS`).
+///
+/// Sorting the definitions visits the trie's nodes depth first, and each
+/// entry branches off the previous one at their common prefix.
+fn serialize_trie(pattern_to_meta: &HashMap<Vec<Token>, Token>) -> Vec<Token> {
+    let mut definitions: Vec<(Vec<Token>, Token)> = pattern_to_meta
+        .iter()
+        .map(|(pattern, &meta)| (pattern.clone(), meta))
+        .collect();
+    definitions.sort_unstable();
+
+    let mut tokens = Vec::new();
+    let mut previous: &[Token] = &[];
+    for (definition, meta) in &definitions {
+        let depth = previous
+            .iter()
+            .zip(definition)
+            .take_while(|(a, b)| a == b)
+            .count();
+        tokens.extend([*meta, depth as Token, (definition.len() - depth) as Token]);
+        tokens.extend_from_slice(&definition[depth..]);
+        previous = definition;
+    }
+    tokens
 }
 
 /// Topologically order patterns so dependencies come before dependents.
@@ -106,14 +408,24 @@ fn serialize_pattern(
 /// This ensures that if pattern A's definition references pattern B's meta-token,
 /// pattern B appears first in the dictionary.
 fn topological_order(
-    pattern_counts: &HashMap<Vec<Token>, usize>,
-    _selected: &[Occurrence],
+    pattern_counts: &PatternMap<Vec<Token>, usize>,
+    selected: &[Occurrence],
+    tie_break: &str,
 ) -> Vec<Vec<Token>> {
-    // For now, order by length (shorter first) then by count (higher first)
+    // For now, order by length (shorter first) then by count (higher first),
+    // falling back to `tie_break` so the result never depends on hashing
     // This is a simplified approach; full hierarchical support would need
     // to detect when one pattern's definition references another's meta-token
     let mut patterns: Vec<Vec<Token>> = pattern_counts.keys().cloned().collect();
 
+    let mut first_start: PatternMap<&[Token], usize> = PatternMap::default();
+    if tie_break == "first_occurrence" {
+        for occ in selected {
+            let start = first_start.entry(&occ.subsequence[..]).or_insert(occ.start);
+            *start = (*start).min(occ.start);
+        }
+    }
+
     patterns.sort_by(|a, b| {
         let len_cmp = a.len().cmp(&b.len());
         if len_cmp != std::cmp::Ordering::Equal {
@@ -122,24 +434,103 @@ fn topological_order(
         // Higher count first
         let count_a = pattern_counts.get(a).copied().unwrap_or(0);
         let count_b = pattern_counts.get(b).copied().unwrap_or(0);
-        count_b.cmp(&count_a)
+        count_b
+            .cmp(&count_a)
+            .then_with(|| first_start.get(&a[..]).cmp(&first_start.get(&b[..])))
+            .then_with(|| a.cmp(b))
     });
 
     patterns
 }
 
+/// Order `patterns` so the serialized dictionary is lexicographically
+/// smallest, for `CompressionConfig::canonical_output`.
+///
+/// The `i`-th entry gets `ids[i]`, sorted ascending, so the first token of
+/// every entry is already as small as it can be. Entries are then picked
+/// greedily: at each position, the pattern whose length header and
+/// definition, followed by the token written after them, compare
+/// smallest. The dictionary precedes the body and fixes it, so this also
+/// minimizes the whole output. Trie entries are written in definition
+/// order regardless, so they are just sorted. Nested definitions depend on
+/// the ids of the entries they refer to, so those are sorted shortest
+/// first, keeping every referenced entry ahead of its references.
+fn canonical_order(
+    mut patterns: Vec<Vec<Token>>,
+    ids: &[Token],
+    config: &CompressionConfig,
+) -> Vec<Vec<Token>> {
+    if config.dict_encoding == "trie" {
+        patterns.sort_unstable();
+        return patterns;
+    }
+    if config.dict_encoding == "nested" {
+        patterns.sort_unstable_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+        return patterns;
+    }
+
+    fn entry(pattern: &[Token], lengths: bool, next: Token) -> impl Iterator<Item = Token> + '_ {
+        let header = lengths.then_some(pattern.len() as Token);
+        header.into_iter().chain(pattern.iter().copied()).chain(std::iter::once(next))
+    }
+    let lengths = config.dict_length_enabled;
+    let mut ordered = Vec::with_capacity(patterns.len());
+    for position in 0..patterns.len() {
+        let next = ids.get(position + 1).copied().unwrap_or(config.dict_end_token);
+        let best = (0..patterns.len())
+            .min_by(|&a, &b| {
+                entry(&patterns[a], lengths, next).cmp(entry(&patterns[b], lengths, next))
+            })
+            .expect("a pattern remains for every position");
+        ordered.push(patterns.swap_remove(best));
+    }
+    ordered
+}
+
 /// Build body tokens with pattern replacements.
 ///
-/// Replaces selected pattern occurrences with their assigned meta-tokens.
+/// Replaces selected pattern occurrences with their assigned meta-tokens,
+/// each followed by the tokens of its patches (the gap fillers of a
+/// skip-gram occurrence). Literals at or above the first assigned
+/// meta-token, and `escape_token` itself, are written as
+/// `[escape_token, literal]`. Decoding then neither expands them nor
+/// mistakes them for meta-tokens it has no definition for.
 pub fn build_body(
     tokens: &[Token],
     selected: &[Occurrence],
     pattern_to_meta: &HashMap<Vec<Token>, Token>,
+    escape_token: Token,
+) -> Vec<Token> {
+    let first_meta = pattern_to_meta.values().copied().min().unwrap_or(Token::MAX);
+    build_body_with(tokens, selected, pattern_to_meta, escape_token, |literal| {
+        literal >= first_meta
+    })
+}
+
+/// `build_body` with the caller choosing which literals are escaped.
+///
+/// `escape_token` itself is always escaped. Used where meta-tokens are not
+/// numbered upward from the first one assigned (see `MetaTokenDirection`).
+pub fn build_body_with(
+    tokens: &[Token],
+    selected: &[Occurrence],
+    pattern_to_meta: &HashMap<Vec<Token>, Token>,
+    escape_token: Token,
+    needs_escape: impl Fn(Token) -> bool,
 ) -> Vec<Token> {
     if selected.is_empty() || pattern_to_meta.is_empty() {
         return tokens.to_vec();
     }
 
+    let copy_literals = |body: &mut Vec<Token>, literals: &[Token]| {
+        for &literal in literals {
+            if literal == escape_token || needs_escape(literal) {
+                body.push(escape_token);
+            }
+            body.push(literal);
+        }
+    };
+
     // Sort occurrences by start position
     let mut sorted_selected: Vec<&Occurrence> = selected.iter().collect();
     sorted_selected.sort_by_key(|occ| occ.start);
@@ -150,15 +541,16 @@ pub fn build_body(
     for occ in sorted_selected {
         // Copy tokens before this occurrence
         if pos < occ.start {
-            body.extend_from_slice(&tokens[pos..occ.start]);
+            copy_literals(&mut body, &tokens[pos..occ.start]);
         }
 
         // Replace occurrence with meta-token
-        if let Some(&meta_token) = pattern_to_meta.get(&occ.subsequence) {
+        if let Some(&meta_token) = pattern_to_meta.get(occ.subsequence.as_slice()) {
             body.push(meta_token);
+            body.extend(occ.patches.iter().map(|patch| patch.token));
         } else {
             // Pattern not found - keep original tokens (shouldn't happen)
-            body.extend_from_slice(&tokens[occ.start..occ.start + occ.length]);
+            copy_literals(&mut body, &tokens[occ.start..occ.start + occ.length]);
         }
 
         pos = occ.start + occ.length;
@@ -166,58 +558,1101 @@ pub fn build_body(
 
     // Copy remaining tokens
     if pos < tokens.len() {
-        body.extend_from_slice(&tokens[pos..]);
+        copy_literals(&mut body, &tokens[pos..]);
+    }
+
+    body
+}
+
+/// One step of a body described as edits to the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyOp {
+    /// Input tokens `from..from + len`, escaped as literals
+    Copy { from: usize, len: usize },
+    /// A meta-token replacing an occurrence
+    Emit { meta: Token },
+    /// A skip-gram gap filler, written without escaping
+    Fill { token: Token },
+}
+
+/// Describe the body `build_body_with` would build as a list of edits.
+///
+/// Poorly compressing inputs yield bodies that are mostly copies of the
+/// input; the edit list costs one op per occurrence and literal run
+/// instead, and is materialized or streamed with `BodyOpsIter`. Returns
+/// `None` once `selected` covers `max_coverage` or more of the input, when
+/// building the body eagerly is no more expensive.
+pub fn build_body_ops(
+    original_len: usize,
+    selected: &[Occurrence],
+    pattern_to_meta: &HashMap<Vec<Token>, Token>,
+    max_coverage: f64,
+) -> Option<Vec<BodyOp>> {
+    let covered: usize = selected.iter().map(|occ| occ.length).sum();
+    if original_len == 0 || covered as f64 >= max_coverage * original_len as f64 {
+        return None;
+    }
+
+    let mut sorted_selected: Vec<&Occurrence> = selected.iter().collect();
+    sorted_selected.sort_by_key(|occ| occ.start);
+
+    let mut ops = Vec::with_capacity(2 * selected.len() + 1);
+    let mut pos = 0;
+    for occ in sorted_selected {
+        if pos < occ.start {
+            ops.push(BodyOp::Copy {
+                from: pos,
+                len: occ.start - pos,
+            });
+        }
+        match pattern_to_meta.get(occ.subsequence.as_slice()) {
+            Some(&meta) => {
+                ops.push(BodyOp::Emit { meta });
+                let fillers = occ.patches.iter().map(|patch| BodyOp::Fill { token: patch.token });
+                ops.extend(fillers);
+            }
+            None => ops.push(BodyOp::Copy {
+                from: occ.start,
+                len: occ.length,
+            }),
+        }
+        pos = occ.end();
+    }
+    if pos < original_len {
+        ops.push(BodyOp::Copy {
+            from: pos,
+            len: original_len - pos,
+        });
+    }
+
+    Some(ops)
+}
+
+/// Body tokens produced from `BodyOp`s one at a time.
+///
+/// Literals are escaped as in `build_body_with` with the same
+/// `needs_escape`, so collecting yields the eagerly built body.
+pub struct BodyOpsIter<'a, F: Fn(Token) -> bool> {
+    tokens: &'a [Token],
+    ops: std::slice::Iter<'a, BodyOp>,
+    copy: std::slice::Iter<'a, Token>,
+    pending: Option<Token>,
+    escape_token: Token,
+    needs_escape: F,
+}
+
+impl<'a, F: Fn(Token) -> bool> BodyOpsIter<'a, F> {
+    pub fn new(
+        tokens: &'a [Token],
+        ops: &'a [BodyOp],
+        escape_token: Token,
+        needs_escape: F,
+    ) -> Self {
+        Self {
+            tokens,
+            ops: ops.iter(),
+            copy: [].iter(),
+            pending: None,
+            escape_token,
+            needs_escape,
+        }
+    }
+}
+
+impl<F: Fn(Token) -> bool> Iterator for BodyOpsIter<'_, F> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if let Some(literal) = self.pending.take() {
+            return Some(literal);
+        }
+        loop {
+            if let Some(&literal) = self.copy.next() {
+                if literal == self.escape_token || (self.needs_escape)(literal) {
+                    self.pending = Some(literal);
+                    return Some(self.escape_token);
+                }
+                return Some(literal);
+            }
+            match *self.ops.next()? {
+                BodyOp::Copy { from, len } => self.copy = self.tokens[from..from + len].iter(),
+                BodyOp::Emit { meta } => return Some(meta),
+                BodyOp::Fill { token } => return Some(token),
+            }
+        }
+    }
+}
+
+/// Whether a literal could be mistaken for a meta-token or an escape.
+///
+/// Covers the escape token and the whole meta-token pool starting at
+/// `next_meta_token`, including ids later hierarchical passes may assign.
+pub fn is_reserved_token(token: Token, config: &CompressionConfig, next_meta_token: Token) -> bool {
+    token == config.escape_token || meta_token_pool(config, next_meta_token).contains(&token)
+}
+
+/// The `meta_token_pool_size` ids assignable from `next_meta_token`, in
+/// the config's numbering direction, clipped to the token range.
+pub fn meta_token_pool(
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> RangeInclusive<Token> {
+    let size = config.meta_token_pool_size.min(Token::MAX as usize) as Token;
+    if size == 0 {
+        return RangeInclusive::new(1, 0);
+    }
+    match config.meta_token_direction {
+        MetaTokenDirection::Ascending => {
+            next_meta_token..=next_meta_token.saturating_add(size - 1)
+        }
+        MetaTokenDirection::Descending => {
+            next_meta_token.saturating_sub(size - 1)..=next_meta_token
+        }
+    }
+}
+
+/// Rewrite a result's body so each literal run is length-prefixed.
+///
+/// The body becomes `[LITERAL_MARKER, run_len, tokens..., meta_token, ...]`,
+/// letting a decoder skip literal runs without checking every token against
+/// the dictionary. Any token that is not a meta-token counts as a literal.
+pub fn encode_body_runs(
+    mut result: CompressionResult,
+    config: &CompressionConfig,
+) -> CompressionResult {
+    if result.dictionary_tokens.is_empty() {
+        return result;
+    }
+
+    let marker = config.literal_marker_token;
+    let mut body = Vec::with_capacity(result.body_tokens.len() + 8);
+    let mut run: Vec<Token> = Vec::new();
+    let flush_run = |body: &mut Vec<Token>, run: &mut Vec<Token>| {
+        if !run.is_empty() {
+            body.push(marker);
+            body.push(run.len() as Token);
+            body.append(run);
+        }
+    };
+
+    // Runs are copied verbatim on decode, so escaped literals join them
+    // without their escape token
+    let tokens = &result.body_tokens;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        if token == config.escape_token && i + 1 < tokens.len() {
+            run.push(tokens[i + 1]);
+            i += 2;
+            continue;
+        }
+        if config.detect_mirrored && token == config.reverse_marker_token && i + 1 < tokens.len() {
+            flush_run(&mut body, &mut run);
+            body.extend(&tokens[i..i + 2]);
+            i += 2;
+            continue;
+        }
+        if result.dictionary_map.contains_key(&token) {
+            flush_run(&mut body, &mut run);
+            body.push(token);
+        } else {
+            run.push(token);
+        }
+        i += 1;
+    }
+    flush_run(&mut body, &mut run);
+
+    let mut serialized = result.dictionary_tokens.clone();
+    serialized.extend(&body);
+
+    result.compressed_length = serialized.len();
+    result.serialized_tokens = serialized;
+    result.body_tokens = body;
+    result
+}
+
+/// Decode a run-prefixed body.
+///
+/// Literal runs are copied verbatim; a reverse marker and its meta-token
+/// expand reversed when `detect_mirrored` is set; meta-tokens are copied
+/// from an `ExpansionTable`. Appends to `output`.
+fn decode_body_runs(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+    output: &mut Vec<Token>,
+) {
+    let marker = config.literal_marker_token;
+    let reverse_marker = config.reverse_marker_token;
+    let escape = config.escape_token;
+    let mut table = ExpansionTable::new(dictionary, escape);
+    let mut pos = 0;
+
+    while pos < body.len() {
+        let token = body[pos];
+        if token == marker && pos + 1 < body.len() {
+            let run_start = pos + 2;
+            let run_end = (run_start + body[pos + 1] as usize).min(body.len());
+            output.extend_from_slice(&body[run_start..run_end]);
+            pos = run_end;
+        } else if config.detect_mirrored && token == reverse_marker && pos + 1 < body.len() {
+            let unit = &body[pos..pos + 2];
+            expand_with_reversal_into(unit, dictionary, reverse_marker, escape, output);
+            pos += 2;
+        } else if let Some(expansion) = table.expansion(token) {
+            output.extend_from_slice(expansion);
+            pos += 1;
+        } else {
+            expand_into(&body[pos..pos + 1], dictionary, escape, output);
+            pos += 1;
+        }
+    }
+}
+
+/// Full expansions of the meta-tokens a body uses, each expanded once on
+/// first use and copied from then on.
+struct ExpansionTable<'a> {
+    dictionary: &'a HashMap<Token, Vec<Token>>,
+    escape_token: Token,
+    /// Where each expanded meta-token's tokens sit in `tokens`
+    spans: HashMap<Token, Range<usize>>,
+    tokens: Vec<Token>,
+}
+
+impl<'a> ExpansionTable<'a> {
+    fn new(dictionary: &'a HashMap<Token, Vec<Token>>, escape_token: Token) -> Self {
+        Self {
+            dictionary,
+            escape_token,
+            spans: HashMap::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// The full expansion of `meta_token`, or `None` if it has no entry.
+    fn expansion(&mut self, meta_token: Token) -> Option<&[Token]> {
+        if !self.dictionary.contains_key(&meta_token) {
+            return None;
+        }
+        let span = match self.spans.get(&meta_token) {
+            Some(span) => span.clone(),
+            None => {
+                let start = self.tokens.len();
+                let unit = [meta_token];
+                expand_into(&unit, self.dictionary, self.escape_token, &mut self.tokens);
+                self.spans.insert(meta_token, start..self.tokens.len());
+                start..self.tokens.len()
+            }
+        };
+        Some(&self.tokens[span])
+    }
+}
+
+/// Decompress tokens by expanding meta-tokens.
+///
+/// Iteratively expands all meta-tokens until no more remain.
+pub fn decompress(
+    tokens: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+) -> Vec<Token> {
+    let mut output = Vec::with_capacity(tokens.len() * 2);
+    decompress_into(tokens, dictionary, config, &mut output);
+    output
+}
+
+/// `decompress`, appending to `output` without intermediate buffers.
+pub fn decompress_into(
+    tokens: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+    output: &mut Vec<Token>,
+) {
+    match tokens.iter().position(|&t| t == config.dict_end_token) {
+        Some(end_pos) => decode_body_into(&tokens[end_pos + 1..], dictionary, config, output),
+        // Literal runs are only written after a dictionary section
+        None if config.body_runs => expand_into(tokens, dictionary, config.escape_token, output),
+        None => decode_body_into(tokens, dictionary, config, output),
+    }
+}
+
+/// Decode body tokens, those following the dictionary section.
+///
+/// Handles literal runs, reverse markers and skip-gram gap fillers as
+/// enabled in `config`.
+pub fn decode_body(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+) -> Vec<Token> {
+    let mut output = Vec::with_capacity(body.len() * 2);
+    decode_body_into(body, dictionary, config, &mut output);
+    output
+}
+
+/// `decode_body`, appending to `output`.
+pub fn decode_body_into(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+    output: &mut Vec<Token>,
+) {
+    let escape = config.escape_token;
+    if config.body_runs {
+        decode_body_runs(body, dictionary, config, output);
+    } else if config.detect_mirrored {
+        let marker = config.reverse_marker_token;
+        expand_with_reversal_into(body, dictionary, marker, escape, output);
+    } else if config.gap_fillers_enabled() {
+        expand_with_gaps_into(body, dictionary, config.gap_token, escape, output);
+    } else {
+        expand_into(body, dictionary, escape, output);
+    }
+}
+
+/// Length of the body unit starting at `pos`.
+///
+/// A unit is the smallest run of body tokens that decodes on its own: an
+/// escape and its literal, a literal run with its marker and length, a
+/// reverse marker and its meta-token, a skip-gram meta-token and its gap
+/// fillers, or any other single token. Never extends past the body.
+pub fn body_unit_len(
+    body: &[Token],
+    pos: usize,
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+) -> usize {
+    let token = body[pos];
+    let len = if config.body_runs && token == config.literal_marker_token && pos + 1 < body.len() {
+        2 + body[pos + 1] as usize
+    } else if token == config.escape_token
+        || (config.detect_mirrored && token == config.reverse_marker_token)
+    {
+        2
+    } else if config.gap_fillers_enabled() {
+        1 + gap_count(token, dictionary, config.gap_token)
+    } else {
+        1
+    };
+    len.min(body.len() - pos)
+}
+
+/// Decode body segments from `CompressionResult::split_body` concurrently.
+///
+/// Returns the same tokens as decoding the unsplit body, with channel
+/// bodies interleaved back into one stream when `channels > 1`.
+#[cfg(feature = "parallel")]
+pub fn decompress_segments_parallel(
+    segments: &[Vec<Token>],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+) -> Vec<Token> {
+    use rayon::prelude::*;
+
+    let decoded: Vec<Vec<Token>> = segments
+        .par_iter()
+        .map(|segment| decode_body(segment, dictionary, config))
+        .collect();
+    if config.channels > 1 && !dictionary.is_empty() {
+        return crate::channels::interleave(&decoded.concat(), config.channels);
+    }
+    decoded.concat()
+}
+
+/// Meta-tokens a frame references without a dictionary definition.
+///
+/// Any unescaped token outside a literal run that falls in the meta-token
+/// pool starting at `next_meta_token` must be a meta-token, so one the
+/// dictionary cannot expand is reported, in order of first reference.
+/// Definitions are checked too. Frames without a dictionary section
+/// carry no meta-tokens.
+pub fn unresolved_meta_tokens(
+    tokens: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Vec<Token> {
+    let body = match tokens.iter().position(|&t| t == config.dict_end_token) {
+        Some(end_pos) => &tokens[end_pos + 1..],
+        None => return Vec::new(),
+    };
+    let is_meta =
+        |t: Token| t != config.escape_token && is_reserved_token(t, config, next_meta_token);
+
+    let mut unresolved = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = Vec::new();
+    let mut pos = 0;
+
+    while pos < body.len() {
+        let token = body[pos];
+        if config.body_runs && token == config.literal_marker_token && pos + 1 < body.len() {
+            pos += 2 + body[pos + 1] as usize;
+            continue;
+        }
+        if token == config.escape_token {
+            pos += 2;
+            continue;
+        }
+        pos += 1;
+        if config.gap_fillers_enabled() {
+            // Gap fillers are literals whatever their value
+            pos += gap_count(token, dictionary, config.gap_token);
+        }
+
+        stack.push(token);
+        while let Some(token) = stack.pop() {
+            if !is_meta(token) || !seen.insert(token) {
+                continue;
+            }
+            match dictionary.get(&token) {
+                Some(definition) => stack.extend(definition.iter().rev()),
+                None => unresolved.push(token),
+            }
+        }
+    }
+
+    unresolved
+}
+
+/// Delimiters chosen above the input's largest token by `auto_delimiters`.
+///
+/// Written at the start of the stream as
+/// `[DELIMITER_HEADER_TOKEN, dict_start, dict_end, next_meta_token]`, so a
+/// decoder picks them up without being configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DelimiterHeader {
+    pub dict_start_token: Token,
+    pub dict_end_token: Token,
+    pub next_meta_token: Token,
+}
+
+impl DelimiterHeader {
+    /// Tokens the header adds to the stream.
+    pub const LEN: usize = 4;
+
+    /// Delimiters at `max + 1` and `max + 2` and meta-tokens ascending from
+    /// `max + 3`, where `max` is the largest input token or the input length
+    /// if greater. Entry lengths and literal run counts never exceed the
+    /// input length, so none of them reads as a delimiter.
+    ///
+    /// Returns `None` when the meta-token pool would reach the default
+    /// reserved tokens, so the configured delimiters must be used instead.
+    pub fn above(tokens: &[Token], config: &CompressionConfig) -> Option<Self> {
+        let largest = tokens.iter().copied().max().unwrap_or(0) as u64;
+        let max = largest.max(tokens.len() as u64);
+        let pool_end = max + 3 + config.meta_token_pool_size as u64;
+        if pool_end > DEFAULT_DICT_START as u64 {
+            return None;
+        }
+        Some(Self {
+            dict_start_token: (max + 1) as Token,
+            dict_end_token: (max + 2) as Token,
+            next_meta_token: (max + 3) as Token,
+        })
+    }
+
+    /// Read a header at the start of `tokens`, returning it and the rest.
+    pub fn read(tokens: &[Token]) -> Option<(Self, &[Token])> {
+        match tokens {
+            [DELIMITER_HEADER_TOKEN, start, end, next, rest @ ..] => {
+                let header = Self {
+                    dict_start_token: *start,
+                    dict_end_token: *end,
+                    next_meta_token: *next,
+                };
+                Some((header, rest))
+            }
+            _ => None,
+        }
+    }
+
+    /// Split a stream into the stretches decoded with one set of
+    /// delimiters, as `decompress` reads them.
+    ///
+    /// A stream opening with a header is read with the header's delimiters
+    /// up to the next `DELIMITER_HEADER_TOKEN`; any other stream is read
+    /// with `config` to its end. Each stretch comes with the index of its
+    /// first token in `tokens` and the config to read it with.
+    pub fn sections<'a>(
+        tokens: &'a [Token],
+        config: &CompressionConfig,
+    ) -> Vec<(usize, &'a [Token], CompressionConfig)> {
+        let mut sections = Vec::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            let Some((header, rest)) = Self::read(&tokens[start..]) else {
+                sections.push((start, &tokens[start..], config.clone()));
+                break;
+            };
+            let end = rest
+                .iter()
+                .position(|&t| t == DELIMITER_HEADER_TOKEN)
+                .unwrap_or(rest.len());
+            start += Self::LEN;
+            sections.push((start, &rest[..end], header.apply(config)));
+            start += end;
+        }
+        sections
+    }
+
+    /// `config` with this header's delimiters and ascending meta-tokens.
+    pub fn apply(&self, config: &CompressionConfig) -> CompressionConfig {
+        CompressionConfig {
+            dict_start_token: self.dict_start_token,
+            dict_end_token: self.dict_end_token,
+            meta_token_direction: MetaTokenDirection::Ascending,
+            auto_delimiters: false,
+            ..config.clone()
+        }
+    }
+
+    /// Put the header in front of a result's dictionary section.
+    pub fn prepend(&self, result: CompressionResult) -> CompressionResult {
+        let header = [
+            DELIMITER_HEADER_TOKEN,
+            self.dict_start_token,
+            self.dict_end_token,
+            self.next_meta_token,
+        ];
+        let mut dictionary_tokens = header.to_vec();
+        dictionary_tokens.extend(&result.dictionary_tokens);
+        let mut serialized_tokens = header.to_vec();
+        serialized_tokens.extend(&result.serialized_tokens);
+
+        CompressionResult {
+            compressed_length: serialized_tokens.len(),
+            serialized_tokens,
+            dictionary_tokens,
+            ..result
+        }
+    }
+}
+
+/// Split concatenated compressed frames at each dictionary start token.
+///
+/// Every compressed frame opens with `dict_start_token`; tokens before the
+/// first one form a frame stored without compression. Raw tokens after a
+/// frame read as part of its body, so only the first piece of a stream may
+/// be left unframed (see `frame_uncompressed`). Bodies are walked unit by
+/// unit (see `body_unit_len`), so a `dict_start_token` that is an escaped
+/// literal, a run literal or a gap filler does not open a frame.
+pub fn split_frames<'a>(tokens: &'a [Token], config: &CompressionConfig) -> Vec<&'a [Token]> {
+    let mut frames = Vec::new();
+    let mut start = match tokens.iter().position(|&t| t == config.dict_start_token) {
+        Some(0) => 0,
+        Some(first) => {
+            frames.push(&tokens[..first]);
+            first
+        }
+        None => return if tokens.is_empty() { frames } else { vec![tokens] },
+    };
+
+    while start < tokens.len() {
+        let frame = &tokens[start..];
+        let end = match frame.iter().position(|&t| t == config.dict_end_token) {
+            Some(dict_end) => frame_end(frame, dict_end + 1, config),
+            None => frame.len(),
+        };
+        frames.push(&frame[..end]);
+        start += end;
+    }
+
+    frames
+}
+
+/// Length of the frame opening `frame`, whose body starts at `body_start`:
+/// up to the next `dict_start_token` found at a body unit boundary.
+fn frame_end(frame: &[Token], body_start: usize, config: &CompressionConfig) -> usize {
+    // Only gap counts depend on the dictionary; a malformed one is reported
+    // when the frame is decoded
+    let dictionary = if config.gap_fillers_enabled() {
+        parse_dictionary(frame, config).unwrap_or_default()
+    } else {
+        HashMap::new()
+    };
+    let body = &frame[body_start..];
+    let mut pos = 0;
+    while pos < body.len() {
+        if body[pos] == config.dict_start_token {
+            return body_start + pos;
+        }
+        pos += body_unit_len(body, pos, &dictionary, config);
+    }
+    frame.len()
+}
+
+/// Renumber a stream's meta-tokens from `new_base`.
+///
+/// Each frame's meta-tokens keep their order in `old_config`'s numbering
+/// direction, the `index`-th becoming `direction.step(new_base, index)`,
+/// both in the dictionary (ids and nested references) and in the body.
+/// Body literals the new pool would claim are escaped, so the stream
+/// decodes with `new_base` as its `next_meta_token` to the same tokens as
+/// before. Frames without a dictionary section are copied unchanged.
+/// Fails if a frame's dictionary is malformed, has more entries than ids
+/// remain from `new_base`, or has a definition holding a literal in the
+/// new pool, since definitions cannot carry escapes.
+pub fn rekey_meta_tokens(
+    serialized: &[Token],
+    old_config: &CompressionConfig,
+    new_base: Token,
+) -> Result<Vec<Token>, CompressionError> {
+    let mut output = Vec::with_capacity(serialized.len());
+    for frame in split_frames(serialized, old_config) {
+        output.extend(rekey_frame(frame, old_config, new_base)?);
+    }
+    Ok(output)
+}
+
+/// `rekey_meta_tokens` for a single frame.
+fn rekey_frame(
+    frame: &[Token],
+    config: &CompressionConfig,
+    new_base: Token,
+) -> Result<Vec<Token>, CompressionError> {
+    let (start, end) = match (
+        frame.iter().position(|&t| t == config.dict_start_token),
+        frame.iter().position(|&t| t == config.dict_end_token),
+    ) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Ok(frame.to_vec()),
+    };
+    let dictionary = parse_dictionary(frame, config)?;
+
+    let direction = config.meta_token_direction;
+    if dictionary.len() > direction.capacity(new_base) {
+        return Err(CompressionError::InvalidConfig(format!(
+            "{} meta-tokens do not fit {:?} from {}",
+            dictionary.len(),
+            direction,
+            new_base
+        )));
+    }
+    let pool = meta_token_pool(config, new_base);
+    let mut literals = dictionary.values().flatten().filter(|t| !dictionary.contains_key(t));
+    if let Some(literal) = literals.find(|t| pool.contains(t)) {
+        return Err(CompressionError::InvalidConfig(format!(
+            "definition literal {} falls in the meta-token pool from {}",
+            literal, new_base
+        )));
+    }
+    let mut ids: Vec<Token> = dictionary.keys().copied().collect();
+    ids.sort_unstable();
+    if direction == MetaTokenDirection::Descending {
+        ids.reverse();
+    }
+    let rekeyed: HashMap<Token, Token> = ids
+        .iter()
+        .enumerate()
+        .map(|(index, &id)| (id, direction.step(new_base, index).expect("checked capacity")))
+        .collect();
+    let rekey = |t: Token| rekeyed.get(&t).copied().unwrap_or(t);
+
+    let mut output = Vec::with_capacity(frame.len());
+    output.extend_from_slice(&frame[..=start]);
+
+    // Entry headers hold lengths, which must not be renumbered
+    let section = &frame[start + 1..end];
+    let mut pos = 0;
+    while pos < section.len() {
+        let header = match config.dict_encoding.as_str() {
+            "trie" if pos + 3 <= section.len() => 3,
+            "trie" => section.len() - pos,
+            _ if config.dict_length_enabled && pos + 2 <= section.len() => 2,
+            _ => section.len() - pos,
+        };
+        let length = match header {
+            3 => section[pos + 2] as usize,
+            2 => section[pos + 1] as usize,
+            _ => 0,
+        };
+        output.push(rekey(section[pos]));
+        output.extend_from_slice(&section[pos + 1..pos + header]);
+        pos += header;
+        let definition_end = (pos + length).min(section.len());
+        output.extend(section[pos..definition_end].iter().map(|&t| rekey(t)));
+        pos = definition_end;
+    }
+    output.push(config.dict_end_token);
+
+    let body = &frame[end + 1..];
+    let mut pos = 0;
+    while pos < body.len() {
+        let unit = &body[pos..pos + body_unit_len(body, pos, &dictionary, config)];
+        let token = unit[0];
+        let is_run = config.body_runs && token == config.literal_marker_token && unit.len() > 1;
+        let is_reverse = config.detect_mirrored && token == config.reverse_marker_token;
+        if is_run || token == config.escape_token {
+            output.extend_from_slice(unit);
+        } else if is_reverse {
+            output.push(token);
+            output.extend(unit[1..].iter().map(|&t| rekey(t)));
+        } else if rekeyed.contains_key(&token) {
+            // Gap fillers are literals placed by position
+            output.push(rekey(token));
+            output.extend_from_slice(&unit[1..]);
+        } else {
+            if is_reserved_token(token, config, new_base) {
+                output.push(config.escape_token);
+            }
+            output.extend_from_slice(unit);
+        }
+        pos += unit.len();
+    }
+    Ok(output)
+}
+
+/// Each dictionary pattern in `serialized` with the number of times it
+/// is expanded when decoding.
+///
+/// Counts body references and, for entries defined through other entries,
+/// references from those definitions, so an entry nothing expands to has
+/// a count of 0. Frames behind a delimiter header are read with its
+/// delimiters. Patterns are fully expanded; a pattern defined in several
+/// frames gets the sum of its counts. Sorted by count (highest first),
+/// then by pattern.
+pub fn pattern_usage(
+    serialized: &[Token],
+    config: &CompressionConfig,
+) -> Result<Vec<(Vec<Token>, usize)>, CompressionError> {
+    // Same nesting limit as `expand_body`
+    const MAX_DEPTH: usize = 100;
+
+    let mut usage: HashMap<Vec<Token>, usize> = HashMap::new();
+    let sections = DelimiterHeader::sections(serialized, config);
+    let frames = sections.iter().flat_map(|(_, section, config)| {
+        split_frames(section, config).into_iter().map(move |frame| (frame, config))
+    });
+    for (frame, config) in frames {
+        let dictionary = parse_dictionary(frame, config)?;
+        let body = match frame.iter().position(|&t| t == config.dict_end_token) {
+            Some(end_pos) => &frame[end_pos + 1..],
+            None => continue,
+        };
+
+        let mut counts: HashMap<Token, usize> = dictionary.keys().map(|&id| (id, 0)).collect();
+        let mut stack = Vec::new();
+        let mut pos = 0;
+        while pos < body.len() {
+            let len = body_unit_len(body, pos, &dictionary, config);
+            let token = body[pos];
+            if config.detect_mirrored && token == config.reverse_marker_token && len > 1 {
+                stack.push((body[pos + 1], 0));
+            } else if token != config.escape_token {
+                stack.push((token, 0));
+            }
+            pos += len;
+
+            while let Some((token, depth)) = stack.pop() {
+                let Some(count) = counts.get_mut(&token) else {
+                    continue;
+                };
+                *count += 1;
+                if depth < MAX_DEPTH {
+                    stack.extend(dictionary[&token].iter().map(|&t| (t, depth + 1)));
+                }
+            }
+        }
+
+        for (id, count) in counts {
+            let pattern = expand_body(dictionary[&id].clone(), &dictionary, config.escape_token);
+            *usage.entry(pattern).or_default() += count;
+        }
+    }
+
+    let mut usage: Vec<(Vec<Token>, usize)> = usage.into_iter().collect();
+    usage.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(usage)
+}
+
+/// Decompress tokens and map each body token to the output it produced.
+///
+/// Returns the decoded tokens along with `(body_index, start..end)` entries,
+/// where `body_index` counts from the first token after the dictionary and
+/// the range is the span of the decoded output that token expanded to.
+/// Literals map to a single position; meta-tokens map to their full
+/// (possibly nested) expansion; skip-gram gap fillers map to the position
+/// they fill; a reversed meta-token maps to its reversed expansion. Run
+/// markers, run lengths, reverse markers and escape tokens produce no
+/// output and have no entry. Each frame (see
+/// `split_frames`) decodes against its own dictionary, with the delimiters
+/// of any header before it; the headers and sections of frames after the
+/// first count as body tokens without an entry.
+///
+/// Fails with `InvalidConfig` on frames compressed as several channels
+/// (see `channels`), whose output interleaves the channel bodies, and on
+/// streams opening with a rotation header, whose ranges may wrap (see
+/// `decompress_ring_positions`).
+pub fn decompress_with_positions(
+    tokens: &[Token],
+    config: &CompressionConfig,
+) -> Result<(Vec<Token>, BodyPositions), CompressionError> {
+    if config.circular && tokens.first() == Some(&ROTATION_HEADER_TOKEN) {
+        return Err(CompressionError::InvalidConfig(
+            "a rotated ring's output ranges wrap around its end".to_string(),
+        ));
+    }
+    let mut output = Vec::with_capacity(tokens.len() * 2);
+    let mut positions = Vec::with_capacity(tokens.len());
+    let mut body_origin = None;
+
+    for (section_start, section, config) in DelimiterHeader::sections(tokens, config) {
+        let mut frame_start = section_start;
+        for frame in split_frames(section, &config) {
+            let dictionary = parse_dictionary(frame, &config)?;
+            if config.channels > 1 && !dictionary.is_empty() {
+                return Err(CompressionError::InvalidConfig(
+                    "channel bodies interleave on decode, so body tokens have no output range"
+                        .to_string(),
+                ));
+            }
+            let dict_end = frame.iter().position(|&t| t == config.dict_end_token);
+            let body_start = dict_end.map_or(0, |end_pos| end_pos + 1);
+            let origin = *body_origin.get_or_insert(frame_start + body_start);
+            let offset = frame_start + body_start - origin;
+            let body = &frame[body_start..];
+            let frame_positions = FramePositions {
+                offset,
+                is_framed: dict_end.is_some(),
+            };
+            frame_positions.decode(body, &dictionary, &config, &mut output, &mut positions);
+            frame_start += frame.len();
+        }
+    }
+
+    Ok((output, positions))
+}
+
+/// `decompress_with_positions` for a stream that may open with a rotation
+/// header (see `ROTATION_HEADER_TOKEN`).
+///
+/// The output is rotated back into input order, while the ranges index
+/// the output of the rotated stream: position `i` of a range lands at
+/// `(i + cut) % len` of the output, for the returned `cut`.
+pub(crate) fn decompress_ring_positions(
+    tokens: &[Token],
+    config: &CompressionConfig,
+) -> Result<(Vec<Token>, BodyPositions, usize), CompressionError> {
+    let (cut, rest, config) = match (config.circular, tokens) {
+        (true, [ROTATION_HEADER_TOKEN, cut, rest @ ..]) => {
+            let linear_config = CompressionConfig {
+                circular: false,
+                ..config.clone()
+            };
+            (*cut as usize, rest, linear_config)
+        }
+        _ => (0, tokens, config.clone()),
+    };
+    let (mut output, positions) = decompress_with_positions(rest, &config)?;
+    if cut > output.len() {
+        return Err(CompressionError::MalformedDictionary(format!(
+            "rotation header cuts the ring at {} but it decodes to {} tokens",
+            cut,
+            output.len()
+        )));
     }
+    output.rotate_right(cut);
+    Ok((output, positions, cut))
+}
 
-    body
+/// Where one frame's body sits for `decompress_with_positions`.
+struct FramePositions {
+    /// Body index of the frame's first body token
+    offset: usize,
+    /// Whether the frame has a dictionary section; raw frames hold no runs
+    is_framed: bool,
 }
 
-/// Decompress tokens by expanding meta-tokens.
+impl FramePositions {
+    /// Append the decoded `body` to `output` and its entries to `positions`.
+    fn decode(
+        &self,
+        body: &[Token],
+        dictionary: &HashMap<Token, Vec<Token>>,
+        config: &CompressionConfig,
+        output: &mut Vec<Token>,
+        positions: &mut BodyPositions,
+    ) {
+        let offset = self.offset;
+        let mut pos = 0;
+
+        while pos < body.len() {
+            let token = body[pos];
+            let is_run = config.body_runs && self.is_framed && token == config.literal_marker_token;
+            if is_run && pos + 1 < body.len() {
+                let run_start = pos + 2;
+                let run_end = (run_start + body[pos + 1] as usize).min(body.len());
+                for (index, &literal) in body.iter().enumerate().take(run_end).skip(run_start) {
+                    positions.push((offset + index, output.len()..output.len() + 1));
+                    output.push(literal);
+                }
+                pos = run_end;
+                continue;
+            }
+
+            if token == config.escape_token && pos + 1 < body.len() {
+                positions.push((offset + pos + 1, output.len()..output.len() + 1));
+                output.push(body[pos + 1]);
+                pos += 2;
+                continue;
+            }
+
+            let is_reversed = config.detect_mirrored && token == config.reverse_marker_token;
+            if is_reversed && pos + 1 < body.len() {
+                let start = output.len();
+                let unit = &body[pos..pos + 2];
+                let marker = config.reverse_marker_token;
+                output.extend(expand_with_reversal(unit, dictionary, marker, config.escape_token));
+                positions.push((offset + pos + 1, start..output.len()));
+                pos += 2;
+                continue;
+            }
+
+            let gaps = if config.gap_fillers_enabled() {
+                gap_count(token, dictionary, config.gap_token)
+            } else {
+                0
+            };
+            if gaps > 0 {
+                let end = (pos + 1 + gaps).min(body.len());
+                let start = output.len();
+                output.extend(expand_with_gaps(
+                    &body[pos..end],
+                    dictionary,
+                    config.gap_token,
+                    config.escape_token,
+                ));
+                positions.push((offset + pos, start..output.len()));
+                let wildcards = dictionary[&token].iter().enumerate();
+                let offsets = wildcards.filter(|(_, &t)| t == config.gap_token).map(|(i, _)| i);
+                for (index, filler) in (pos + 1..end).zip(offsets) {
+                    positions.push((offset + index, start + filler..start + filler + 1));
+                }
+                pos = end;
+                continue;
+            }
+
+            let start = output.len();
+            output.extend(expand_body(vec![token], dictionary, config.escape_token));
+            positions.push((offset + pos, start..output.len()));
+            pos += 1;
+        }
+    }
+}
+
+/// Expand body tokens against a dictionary mapping.
 ///
-/// Iteratively expands all meta-tokens until no more remain.
-pub fn decompress(
-    tokens: &[Token],
+/// Iteratively expands all meta-tokens until no more remain. Escaped
+/// literals are carried through every pass and unescaped at the end.
+pub fn expand_body(
+    body: Vec<Token>,
     dictionary: &HashMap<Token, Vec<Token>>,
-    config: &CompressionConfig,
+    escape_token: Token,
 ) -> Vec<Token> {
-    // First, extract body tokens (skip dictionary section)
-    let body = extract_body(tokens, config);
-
-    // Iteratively expand meta-tokens
     let mut result = body;
     let max_iterations = 100; // Prevent infinite loops
 
     for _ in 0..max_iterations {
-        let (expanded, changed) = expand_once(&result, dictionary);
+        let (expanded, changed) = expand_once(result, dictionary, escape_token);
+        result = expanded;
         if !changed {
             break;
         }
-        result = expanded;
     }
 
-    result
+    unescape(result, escape_token)
 }
 
-/// Extract body tokens from serialized output (after the dictionary section).
-fn extract_body(tokens: &[Token], config: &CompressionConfig) -> Vec<Token> {
-    let dict_end = config.dict_end_token;
+/// Append the expansion of `tokens` to `output`.
+///
+/// Gives the tokens `expand_body` gives for dictionaries whose definitions
+/// hold no escapes, expanding depth first down to the same nesting limit
+/// instead of building a buffer per pass.
+pub fn expand_into(
+    tokens: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    escape_token: Token,
+    output: &mut Vec<Token>,
+) {
+    expand_nested(tokens, dictionary, escape_token, 0, output);
+}
+
+fn expand_nested(
+    tokens: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    escape_token: Token,
+    depth: usize,
+    output: &mut Vec<Token>,
+) {
+    // Same limit as the passes of `expand_body`
+    const MAX_DEPTH: usize = 100;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        if token == escape_token {
+            output.push(tokens.get(i + 1).copied().unwrap_or(token));
+            i += 2;
+            continue;
+        }
+        match dictionary.get(&token) {
+            Some(definition) if depth < MAX_DEPTH => {
+                expand_nested(definition, dictionary, escape_token, depth + 1, output);
+            }
+            _ => output.push(token),
+        }
+        i += 1;
+    }
+}
 
-    // Find the end of the dictionary
-    if let Some(end_pos) = tokens.iter().position(|&t| t == dict_end) {
-        return tokens[end_pos + 1..].to_vec();
+/// Replace each `[escape_token, literal]` pair with `literal`.
+fn unescape(tokens: Vec<Token>, escape_token: Token) -> Vec<Token> {
+    if !tokens.contains(&escape_token) {
+        return tokens;
     }
 
-    // No dictionary section found - return all tokens
-    tokens.to_vec()
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter();
+    while let Some(token) = iter.next() {
+        match token {
+            t if t == escape_token => result.extend(iter.next().or(Some(t))),
+            t => result.push(t),
+        }
+    }
+    result
 }
 
-/// Expand meta-tokens one level.
-fn expand_once(tokens: &[Token], dictionary: &HashMap<Token, Vec<Token>>) -> (Vec<Token>, bool) {
+/// Expand meta-tokens one level, leaving escaped literals untouched.
+///
+/// Tokens with nothing to expand, as on the final pass, are returned as
+/// they are without copying.
+fn expand_once(
+    tokens: Vec<Token>,
+    dictionary: &HashMap<Token, Vec<Token>>,
+    escape_token: Token,
+) -> (Vec<Token>, bool) {
+    let mut escaped = false;
+    let expandable = tokens.iter().any(|&token| {
+        if escaped || token == escape_token {
+            escaped = !escaped;
+            return false;
+        }
+        dictionary.contains_key(&token)
+    });
+    if !expandable {
+        return (tokens, false);
+    }
+
     let mut result = Vec::with_capacity(tokens.len() * 2);
     let mut changed = false;
+    let mut escaped = false;
 
-    for &token in tokens {
-        if let Some(expansion) = dictionary.get(&token) {
+    for &token in &tokens {
+        if escaped || token == escape_token {
+            escaped = !escaped;
+            result.push(token);
+        } else if let Some(expansion) = dictionary.get(&token) {
             result.extend(expansion);
             changed = true;
         } else {
@@ -229,26 +1664,35 @@ fn expand_once(tokens: &[Token], dictionary: &HashMap<Token, Vec<Token>>) -> (Ve
 }
 
 /// Parse a serialized token sequence to extract the dictionary mapping.
+///
+/// Entries claiming a length above `config.dict_entry_length_limit()` are
+/// rejected rather than trusted, so a corrupted or hostile stream cannot
+/// force oversized definitions.
 pub fn parse_dictionary(
     tokens: &[Token],
     config: &CompressionConfig,
-) -> HashMap<Token, Vec<Token>> {
+) -> Result<HashMap<Token, Vec<Token>>, CompressionError> {
     let dict_start = config.dict_start_token;
     let dict_end = config.dict_end_token;
+    let max_length = config.dict_entry_length_limit();
 
     let mut dictionary = HashMap::new();
 
     // Find dictionary section
     let start_pos = match tokens.iter().position(|&t| t == dict_start) {
         Some(pos) => pos + 1,
-        None => return dictionary,
+        None => return Ok(dictionary),
     };
 
     let end_pos = match tokens[start_pos..].iter().position(|&t| t == dict_end) {
         Some(pos) => start_pos + pos,
-        None => return dictionary,
+        None => return Ok(dictionary),
     };
 
+    if config.dict_encoding == "trie" {
+        return parse_trie(&tokens[start_pos..end_pos], max_length);
+    }
+
     // Parse dictionary entries
     let mut pos = start_pos;
     while pos < end_pos {
@@ -256,6 +1700,13 @@ pub fn parse_dictionary(
         pos += 1;
 
         if pos >= end_pos {
+            if config.dict_length_enabled {
+                return Err(CompressionError::LikelyConfigMismatch(format!(
+                    "meta-token {} ends the dictionary without a length; it was likely \
+                     written with dict_length_enabled = false",
+                    meta_token
+                )));
+            }
             break;
         }
 
@@ -264,13 +1715,34 @@ pub fn parse_dictionary(
             pos += 1;
             len
         } else {
+            let section = &tokens[start_pos..end_pos];
+            if parse_with_lengths(section).is_some() && !reads_length_free(section, config) {
+                return Err(CompressionError::LikelyConfigMismatch(
+                    "dictionary parses as length-prefixed entries; it was likely written \
+                     with dict_length_enabled = true"
+                        .to_string(),
+                ));
+            }
             // Without length tokens, we need another way to determine entry length
             // For now, assume fixed-length or delimiter-based (not implemented)
             break;
         };
 
+        if length > max_length {
+            return Err(CompressionError::MalformedDictionary(format!(
+                "entry for meta-token {} claims length {} (limit {})",
+                meta_token, length, max_length
+            )));
+        }
+
         if pos + length > end_pos {
-            break;
+            return Err(CompressionError::LikelyConfigMismatch(format!(
+                "entry for meta-token {} claims length {} but only {} dictionary tokens \
+                 remain; it was likely written with dict_length_enabled = false",
+                meta_token,
+                length,
+                end_pos - pos
+            )));
         }
 
         let definition: Vec<Token> = tokens[pos..pos + length].to_vec();
@@ -279,7 +1751,159 @@ pub fn parse_dictionary(
         pos += length;
     }
 
-    dictionary
+    Ok(dictionary)
+}
+
+/// Whether `section` holds up as entries without length tokens.
+///
+/// Entries are delimited by consecutive meta-token ids, each running to
+/// where the next id first appears. The reading fails when an entry comes
+/// out empty, or when every definition opens with the number of tokens
+/// after it, which is how length-prefixed entries look when read this way.
+fn reads_length_free(section: &[Token], config: &CompressionConfig) -> bool {
+    let mut definitions = Vec::new();
+    let mut pos = 0;
+    while pos < section.len() {
+        let rest = &section[pos + 1..];
+        let end = config
+            .meta_token_direction
+            .step(section[pos], 1)
+            .and_then(|next| rest.iter().position(|&t| t == next))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return false;
+        }
+        definitions.push(&rest[..end]);
+        pos += 1 + end;
+    }
+
+    !definitions
+        .iter()
+        .all(|definition| definition[0] as usize == definition.len() - 1)
+}
+
+/// Parse trie-encoded entries (see `DICT_ENCODINGS`) between the delimiters.
+fn parse_trie(
+    section: &[Token],
+    max_length: usize,
+) -> Result<HashMap<Token, Vec<Token>>, CompressionError> {
+    let mut dictionary = HashMap::new();
+    let mut previous: Vec<Token> = Vec::new();
+    let mut pos = 0;
+
+    while pos + 3 <= section.len() {
+        let meta_token = section[pos];
+        let depth = section[pos + 1] as usize;
+        let suffix_len = section[pos + 2] as usize;
+        pos += 3;
+
+        if depth > previous.len() {
+            return Err(CompressionError::MalformedDictionary(format!(
+                "entry for meta-token {} branches at depth {} below a {}-token prefix",
+                meta_token,
+                depth,
+                previous.len()
+            )));
+        }
+        let length = depth.saturating_add(suffix_len);
+        if length > max_length {
+            return Err(CompressionError::MalformedDictionary(format!(
+                "entry for meta-token {} claims length {} (limit {})",
+                meta_token, length, max_length
+            )));
+        }
+        if pos + suffix_len > section.len() {
+            break;
+        }
+
+        previous.truncate(depth);
+        previous.extend_from_slice(&section[pos..pos + suffix_len]);
+        dictionary.insert(meta_token, previous.clone());
+        pos += suffix_len;
+    }
+
+    Ok(dictionary)
+}
+
+/// Check selected occurrences against the dictionary without decoding.
+///
+/// A cheap alternative to a full round trip: every occurrence must match
+/// its pattern in `tokens` (or the reversed pattern, when `allow_reversed`)
+/// once its patches are applied,
+/// the pattern must have a meta-token in `pattern_to_meta`, and that
+/// meta-token's definition must equal the pattern. Linear in the number of
+/// replaced tokens.
+pub fn verify_replacements(
+    tokens: &[Token],
+    selected: &[Occurrence],
+    dictionary: &Dictionary,
+    allow_reversed: bool,
+) -> Result<(), CompressionError> {
+    for occ in selected {
+        let pattern = occ.subsequence.as_slice();
+        let span = tokens.get(occ.start..occ.end()).unwrap_or_default();
+        let mut filled = occ.subsequence.to_vec();
+        for patch in &occ.patches {
+            match filled.get_mut(patch.index) {
+                Some(token) => *token = patch.token,
+                None => return Err(CompressionError::VerificationFailed),
+            }
+        }
+        if span != filled.as_slice() && !(allow_reversed && span.iter().rev().eq(pattern)) {
+            return Err(CompressionError::VerificationFailed);
+        }
+
+        let definition = dictionary
+            .pattern_to_meta
+            .get(pattern)
+            .and_then(|meta| dictionary.entries.get(meta));
+        if definition.map(Vec::as_slice) != Some(pattern) {
+            return Err(CompressionError::VerificationFailed);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a literal in an unframed stream would be decoded as something
+/// else: the escape token is unescaped, the dictionary delimiters open a
+/// frame and, with `detect_mirrored`, the reverse marker is dropped.
+pub fn is_raw_reserved(token: Token, config: &CompressionConfig) -> bool {
+    token == config.escape_token
+        || token == config.dict_start_token
+        || token == config.dict_end_token
+        || (config.detect_mirrored && token == config.reverse_marker_token)
+}
+
+/// Wrap uncompressed tokens in a frame with an empty dictionary section.
+///
+/// Literals are escaped as `build_body` escapes them for a dictionary
+/// starting at `next_meta_token`, along with any that `is_raw_reserved`
+/// flags, and the body is run-encoded when `body_runs` is set, so the frame
+/// decodes like a compressed one.
+pub fn frame_uncompressed(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> CompressionResult {
+    let dictionary = Dictionary {
+        entries: HashMap::new(),
+        tokens: vec![config.dict_start_token, config.dict_end_token],
+        pattern_to_meta: HashMap::new(),
+    };
+
+    let mut body = Vec::with_capacity(tokens.len());
+    for &literal in tokens {
+        if literal >= next_meta_token || is_raw_reserved(literal, config) {
+            body.push(config.escape_token);
+        }
+        body.push(literal);
+    }
+
+    let result = serialize_result(&dictionary, &body, tokens, config);
+    if config.body_runs {
+        return encode_body_runs(result, config);
+    }
+    result
 }
 
 /// Serialize compression result to final token sequence.
@@ -294,6 +1918,7 @@ pub fn serialize_result(
     serialized.extend(body);
 
     CompressionResult {
+        original_hash: hash_tokens(original),
         original_tokens: original.to_vec(),
         serialized_tokens: serialized.clone(),
         dictionary_tokens: dictionary.tokens.clone(),
@@ -302,13 +1927,15 @@ pub fn serialize_result(
         original_length: original.len(),
         compressed_length: serialized.len(),
         static_dictionary_id: None,
+        reason: None,
+        selection_trace: None,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::Occurrence;
+    use crate::types::{Occurrence, Patch};
 
     fn default_config() -> CompressionConfig {
         CompressionConfig::default()
@@ -319,12 +1946,54 @@ mod tests {
         Occurrence {
             start,
             length,
-            subsequence: subseq,
+            subsequence: subseq.into(),
             priority: 0,
             patches: vec![],
         }
     }
 
+    #[test]
+    fn test_dict_tie_break_orders_equal_patterns() {
+        // Both patterns are 3 tokens long and selected twice; [7, 8, 9]
+        // is used first but sorts after [1, 2, 3]
+        let selected = vec![
+            make_occurrence(vec![7, 8, 9], 0),
+            make_occurrence(vec![1, 2, 3], 5),
+            make_occurrence(vec![7, 8, 9], 10),
+            make_occurrence(vec![1, 2, 3], 15),
+        ];
+        let order = |tie_break: &str| {
+            let config = CompressionConfig {
+                dict_tie_break: tie_break.to_string(),
+                ..default_config()
+            };
+            assert!(config.validate().is_ok());
+            let dict = build_dictionary(&selected, &config, 1000);
+            let mut patterns: Vec<(Token, Vec<Token>)> = dict.entries.into_iter().collect();
+            patterns.sort_unstable();
+            patterns
+                .into_iter()
+                .map(|(_, pattern)| pattern)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(order("lexicographic"), vec![vec![1, 2, 3], vec![7, 8, 9]]);
+        assert_eq!(
+            order("first_occurrence"),
+            vec![vec![7, 8, 9], vec![1, 2, 3]]
+        );
+        assert_eq!(CompressionConfig::default().dict_tie_break, "lexicographic");
+
+        let typo = CompressionConfig {
+            dict_tie_break: "first".to_string(),
+            ..default_config()
+        };
+        assert!(matches!(
+            typo.validate(),
+            Err(CompressionError::InvalidConfig(_))
+        ));
+    }
+
     #[test]
     fn test_build_dictionary_empty() {
         let config = default_config();
@@ -354,6 +2023,165 @@ mod tests {
         assert_eq!(*dict.tokens.last().unwrap(), config.dict_end_token);
     }
 
+    #[test]
+    fn test_dictionary_overhead_matches_build_dictionary() {
+        let sets: Vec<Vec<Vec<Token>>> = vec![
+            vec![],
+            vec![vec![1, 2]],
+            vec![vec![1, 2, 3], vec![4, 5], vec![6, 7, 8, 9]],
+            // Repeated pattern counts once
+            vec![vec![1, 2], vec![3, 4], vec![1, 2]],
+            // [1, 2] is a sub-pattern of [1, 2, 3, 1, 2]
+            vec![vec![1, 2], vec![1, 2, 3, 1, 2]],
+            // Nested two deep, with an entry referring to the longest match
+            vec![vec![4, 5], vec![4, 5, 6], vec![4, 5, 6, 7, 4, 5, 6, 4, 5]],
+        ];
+
+        for dict_encoding in ["flat", "nested"] {
+            for dict_length_enabled in [true, false] {
+                let config = CompressionConfig {
+                    dict_length_enabled,
+                    dict_encoding: dict_encoding.to_string(),
+                    ..default_config()
+                };
+                for patterns in &sets {
+                    let occurrences: Vec<Occurrence> = patterns
+                        .iter()
+                        .enumerate()
+                        .map(|(i, p)| make_occurrence(p.clone(), i * 10))
+                        .collect();
+                    let dict = build_dictionary(&occurrences, &config, 1000);
+                    assert_eq!(dictionary_overhead(patterns, &config), dict.tokens.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_definitions_refer_to_sub_patterns() {
+        let patterns: Vec<Vec<Token>> =
+            vec![vec![4, 5], vec![4, 5, 6], vec![4, 5, 6, 7, 4, 5, 6, 4, 5]];
+        let occurrences: Vec<Occurrence> = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, p)| make_occurrence(p.clone(), i * 10))
+            .collect();
+        let flat = default_config();
+        let nested = CompressionConfig {
+            dict_encoding: "nested".to_string(),
+            ..default_config()
+        };
+
+        let dict = build_dictionary(&occurrences, &nested, 1000);
+        let parsed = parse_dictionary(&dict.tokens, &nested).unwrap();
+        assert_eq!(parsed[&1000], vec![4, 5]);
+        assert_eq!(parsed[&1001], vec![1000, 6]);
+        assert_eq!(parsed[&1002], vec![1001, 7, 1001, 1000]);
+        for (meta, pattern) in &dict.entries {
+            assert_eq!(&expand_body(vec![*meta], &parsed, nested.escape_token), pattern);
+        }
+
+        // The two longer definitions shrink from 3 and 9 tokens to 2 and 4
+        let flat_overhead = dictionary_overhead(&patterns, &flat);
+        assert_eq!(dictionary_overhead(&patterns, &nested), flat_overhead - 6);
+    }
+
+    #[test]
+    fn test_trie_dictionary_shares_prefixes() {
+        let patterns: Vec<Vec<Token>> = vec![
+            vec![1, 2, 3, 4, 5, 6],
+            vec![1, 2, 3, 4, 5, 7],
+            vec![1, 2, 3, 4, 8],
+            vec![1, 2, 3],
+            vec![9, 10, 11],
+        ];
+        let occurrences: Vec<Occurrence> = patterns
+            .iter()
+            .enumerate()
+            .map(|(i, p)| make_occurrence(p.clone(), i * 10))
+            .collect();
+        let flat = default_config();
+        let trie = CompressionConfig {
+            dict_encoding: "trie".to_string(),
+            ..default_config()
+        };
+
+        let flat_dict = build_dictionary(&occurrences, &flat, 1000);
+        let trie_dict = build_dictionary(&occurrences, &trie, 1000);
+        assert!(trie_dict.tokens.len() < flat_dict.tokens.len());
+        assert_eq!(trie_dict.entries, flat_dict.entries);
+        assert_eq!(parse_dictionary(&trie_dict.tokens, &trie).unwrap(), trie_dict.entries);
+        assert_eq!(dictionary_overhead(&patterns, &trie), trie_dict.tokens.len());
+
+        // A depth past the previous definition is rejected
+        let corrupt = [trie.dict_start_token, 1000, 0, 1, 1, 1001, 2, 1, 3, trie.dict_end_token];
+        assert!(matches!(
+            parse_dictionary(&corrupt, &trie),
+            Err(CompressionError::MalformedDictionary(_))
+        ));
+
+        // End to end with prefix-sharing phrases between unique tokens
+        let mut tokens: Vec<Token> = Vec::new();
+        for _ in 0..4 {
+            for pattern in &patterns[..3] {
+                tokens.extend(pattern);
+                tokens.push(100 + tokens.len() as Token);
+            }
+        }
+        let flat_result =
+            crate::compress_internal(&tokens, &flat, crate::DEFAULT_NEXT_META_TOKEN).unwrap();
+        let trie_result =
+            crate::compress_internal(&tokens, &trie, crate::DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(trie_result.compressed_length < flat_result.compressed_length);
+        let restored = crate::decompress_internal(
+            &trie_result.serialized_tokens,
+            &trie,
+            crate::DEFAULT_NEXT_META_TOKEN,
+        )
+        .unwrap();
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_compute_serialized_size_matches_build() {
+        // Period-6 cycle, shifted up by one in the second half
+        let tokens: Vec<Token> = (0..60)
+            .map(|i| [1, 2, 3, 4, 5, 6][i % 6] + (i / 30) as Token)
+            .collect();
+        let occurrences = |pattern: &[Token], starts: &[usize]| -> Vec<Occurrence> {
+            starts
+                .iter()
+                .map(|&start| make_occurrence(pattern.to_vec(), start))
+                .collect()
+        };
+        let mut gapped = make_occurrence(vec![1, CompressionConfig::default().gap_token, 3], 0);
+        gapped.patches = vec![Patch { index: 1, token: 2 }];
+
+        let selections: Vec<Vec<Occurrence>> = vec![
+            vec![],
+            occurrences(&[1, 2, 3], &[0, 6, 12]),
+            occurrences(&[1, 2, 3, 4, 5, 6], &[0, 6, 12, 18, 24]),
+            [occurrences(&[1, 2], &[0, 6]), occurrences(&[4, 5, 6], &[15, 21])].concat(),
+            [vec![gapped], occurrences(&[2, 3, 4], &[31, 37])].concat(),
+        ];
+
+        for dict_length_enabled in [true, false] {
+            let config = CompressionConfig {
+                dict_length_enabled,
+                ..default_config()
+            };
+            for selected in &selections {
+                let dict = build_dictionary(selected, &config, 1000);
+                let body =
+                    build_body(&tokens, selected, &dict.pattern_to_meta, config.escape_token);
+                assert_eq!(
+                    compute_serialized_size(selected, tokens.len(), &config),
+                    dict.tokens.len() + body.len()
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_build_body_simple() {
         let tokens = vec![1, 2, 3, 4, 1, 2, 5, 6];
@@ -362,12 +2190,44 @@ mod tests {
         let mut pattern_to_meta = HashMap::new();
         pattern_to_meta.insert(vec![1u32, 2u32], 1000u32);
 
-        let body = build_body(&tokens, &selected, &pattern_to_meta);
+        let body = build_body(&tokens, &selected, &pattern_to_meta, DEFAULT_ESCAPE_TOKEN);
 
         // Should be: [1000, 3, 4, 1000, 5, 6]
         assert_eq!(body, vec![1000, 3, 4, 1000, 5, 6]);
     }
 
+    #[test]
+    fn test_body_ops_materialize_to_build_body() {
+        let tokens: Vec<Token> = vec![9, 1, 2, 3, 7, 1, 2, 3, 8, 5, DEFAULT_ESCAPE_TOKEN, 4000];
+        let mut gapped = make_occurrence(vec![1, DEFAULT_GAP_TOKEN, 3], 5);
+        gapped.patches = vec![Patch { index: 1, token: 2 }];
+        let selected = vec![make_occurrence(vec![1, 2, 3], 1), gapped];
+
+        let mut pattern_to_meta = HashMap::new();
+        pattern_to_meta.insert(vec![1, 2, 3], 1000);
+        pattern_to_meta.insert(vec![1, DEFAULT_GAP_TOKEN, 3], 1001);
+        let needs_escape = |literal: Token| literal >= 1000;
+
+        let ops = build_body_ops(tokens.len(), &selected, &pattern_to_meta, 0.6).unwrap();
+        assert_eq!(ops.len(), 6);
+        assert_eq!(ops[1], BodyOp::Emit { meta: 1000 });
+        assert_eq!(ops[4], BodyOp::Fill { token: 2 });
+
+        let eager = build_body_with(
+            &tokens,
+            &selected,
+            &pattern_to_meta,
+            DEFAULT_ESCAPE_TOKEN,
+            needs_escape,
+        );
+        let lazy: Vec<Token> =
+            BodyOpsIter::new(&tokens, &ops, DEFAULT_ESCAPE_TOKEN, needs_escape).collect();
+        assert_eq!(lazy, eager);
+
+        // Half the input is covered, so an eager build is preferred
+        assert!(build_body_ops(tokens.len(), &selected, &pattern_to_meta, 0.5).is_none());
+    }
+
     #[test]
     fn test_decompress_simple() {
         let config = default_config();
@@ -408,12 +2268,144 @@ mod tests {
             4,
         ];
 
-        let dict = parse_dictionary(&tokens, &config);
+        let dict = parse_dictionary(&tokens, &config).unwrap();
 
         assert_eq!(dict.len(), 1);
         assert_eq!(dict.get(&1000), Some(&vec![1u32, 2u32]));
     }
 
+    #[test]
+    fn test_parse_dictionary_rejects_oversized_entry() {
+        let config = default_config();
+        let tokens = vec![
+            config.dict_start_token,
+            1000,        // meta-token
+            0x7FFF_FFFF, // absurd length
+            1,
+            2,
+            config.dict_end_token,
+            1000,
+        ];
+
+        let err = parse_dictionary(&tokens, &config).unwrap_err();
+
+        assert!(matches!(err, CompressionError::MalformedDictionary(_)));
+
+        // Longer entries are accepted once the limit is raised
+        let mut long_entry = vec![config.dict_start_token, 1000, 20];
+        long_entry.extend(1..=20);
+        long_entry.extend([config.dict_end_token, 1000]);
+        assert!(parse_dictionary(&long_entry, &config).is_err());
+
+        let relaxed = CompressionConfig {
+            max_dict_entry_length: 20,
+            ..default_config()
+        };
+        assert_eq!(parse_dictionary(&long_entry, &relaxed).unwrap()[&1000].len(), 20);
+    }
+
+    #[test]
+    fn test_parse_dictionary_hints_at_dict_length_mismatch() {
+        use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let with_lengths = default_config();
+        let without_lengths = CompressionConfig {
+            dict_length_enabled: false,
+            ..default_config()
+        };
+        let mismatch = |written: &CompressionConfig, read: &CompressionConfig| {
+            let tokens: Vec<Token> = [3, 4, 5, 6, 7].repeat(6);
+            let result = compress_internal(&tokens, written, DEFAULT_NEXT_META_TOKEN).unwrap();
+            let serialized = &result.serialized_tokens;
+            assert!(matches!(
+                parse_dictionary(serialized, read),
+                Err(CompressionError::LikelyConfigMismatch(_))
+            ));
+            decompress_internal(serialized, read, DEFAULT_NEXT_META_TOKEN).unwrap_err()
+        };
+
+        let err = mismatch(&with_lengths, &without_lengths);
+        assert!(err.to_string().contains("dict_length_enabled = true"));
+        // Read with lengths, the first definition token is taken as a
+        // length and the last one is left without one
+        let err = mismatch(&without_lengths, &with_lengths);
+        assert!(err.to_string().contains("dict_length_enabled = false"));
+
+        // A length running past the section hints the same way
+        let (start, end) = (with_lengths.dict_start_token, with_lengths.dict_end_token);
+        let tokens = vec![start, 1000, 5, 1, 2, end];
+        let err = parse_dictionary(&tokens, &with_lengths).unwrap_err();
+        assert!(matches!(err, CompressionError::LikelyConfigMismatch(_)));
+    }
+
+    #[test]
+    fn test_length_free_dictionary_that_parses_with_lengths_is_not_a_mismatch() {
+        let config = CompressionConfig {
+            dict_length_enabled: false,
+            ..default_config()
+        };
+        let (start, end) = (config.dict_start_token, config.dict_end_token);
+        // Read with lengths this is one entry of 7 tokens, but the next id
+        // splits it into two well-formed length-free entries
+        let tokens = vec![start, 0xFFFF0000, 7, 8, 9, 0xFFFF0001, 1, 2, 3, 4, end];
+        assert!(!matches!(
+            parse_dictionary(&tokens, &config),
+            Err(CompressionError::LikelyConfigMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_body_runs_round_trip() {
+        let config = CompressionConfig {
+            body_runs: true,
+            ..default_config()
+        };
+        let original = vec![1, 2, 3, 9, 1, 2, 3, 1, 2, 3, 7, 8, 1, 2, 3, 5];
+        let selected = vec![
+            make_occurrence(vec![1, 2, 3], 0),
+            make_occurrence(vec![1, 2, 3], 4),
+            make_occurrence(vec![1, 2, 3], 7),
+            make_occurrence(vec![1, 2, 3], 12),
+        ];
+
+        let dict = build_dictionary(&selected, &config, 1000);
+        let body = build_body(&original, &selected, &dict.pattern_to_meta, DEFAULT_ESCAPE_TOKEN);
+        let plain = serialize_result(&dict, &body, &original, &config);
+        let result = encode_body_runs(plain.clone(), &config);
+
+        let marker = config.literal_marker_token;
+        assert_eq!(
+            result.body_tokens,
+            vec![1000, marker, 1, 9, 1000, 1000, marker, 2, 7, 8, 1000, marker, 1, 5]
+        );
+        assert_eq!(result.compressed_length, result.serialized_tokens.len());
+
+        let restored = decompress(&result.serialized_tokens, &dict.entries, &config);
+        assert_eq!(restored, original);
+        let plain_restored = decompress(&plain.serialized_tokens, &dict.entries, &default_config());
+        assert_eq!(restored, plain_restored);
+    }
+
+    #[test]
+    fn test_run_encoded_body_expands_nested_entries() {
+        let config = CompressionConfig {
+            body_runs: true,
+            ..default_config()
+        };
+        let marker = config.literal_marker_token;
+        let escape = config.escape_token;
+        let dictionary: HashMap<Token, Vec<Token>> =
+            [(1000, vec![1, 2]), (1001, vec![1000, 3, 1000])].into_iter().collect();
+        let body = vec![1001, marker, 2, 9, 1001, 1000, 1001, 1002];
+
+        let mut table = ExpansionTable::new(&dictionary, escape);
+        assert_eq!(table.expansion(1001), Some(&[1, 2, 3, 1, 2][..]));
+        assert_eq!(table.expansion(1002), None);
+
+        let decoded = decode_body(&body, &dictionary, &config);
+        assert_eq!(decoded, [1, 2, 3, 1, 2, 9, 1001, 1, 2, 1, 2, 3, 1, 2, 1002]);
+    }
+
     #[test]
     fn test_round_trip() {
         let config = default_config();
@@ -428,7 +2420,7 @@ mod tests {
         let dict = build_dictionary(&selected, &config, 1000);
 
         // Build body
-        let body = build_body(&original, &selected, &dict.pattern_to_meta);
+        let body = build_body(&original, &selected, &dict.pattern_to_meta, DEFAULT_ESCAPE_TOKEN);
 
         // Serialize
         let result = serialize_result(&dict, &body, &original, &config);
@@ -438,4 +2430,309 @@ mod tests {
 
         assert_eq!(restored, original);
     }
+
+    #[test]
+    fn test_verify_replacements_catches_corrupted_mapping() {
+        let config = default_config();
+        let original = vec![1, 2, 3, 4, 5, 1, 2, 3, 4, 5];
+        let selected = vec![
+            make_occurrence(vec![1, 2, 3], 0),
+            make_occurrence(vec![4, 5], 3),
+            make_occurrence(vec![1, 2, 3], 5),
+            make_occurrence(vec![4, 5], 8),
+        ];
+        let dict = build_dictionary(&selected, &config, 1000);
+        assert!(verify_replacements(&original, &selected, &dict, false).is_ok());
+
+        // Swapped meta-tokens no longer expand to their patterns
+        let mut swapped = dict.clone();
+        let a = swapped.pattern_to_meta[&vec![1, 2, 3]];
+        let b = swapped.pattern_to_meta[&vec![4, 5]];
+        swapped.pattern_to_meta.insert(vec![1, 2, 3], b);
+        swapped.pattern_to_meta.insert(vec![4, 5], a);
+        assert_eq!(
+            verify_replacements(&original, &selected, &swapped, false),
+            Err(CompressionError::VerificationFailed)
+        );
+
+        // A missing pattern would silently fall back to literals
+        let mut missing = dict.clone();
+        missing.pattern_to_meta.remove(&vec![4, 5]);
+        assert_eq!(
+            verify_replacements(&original, &selected, &missing, false),
+            Err(CompressionError::VerificationFailed)
+        );
+
+        // An occurrence that does not match the input
+        let shifted = vec![make_occurrence(vec![1, 2, 3], 1)];
+        assert_eq!(
+            verify_replacements(&original, &shifted, &dict, false),
+            Err(CompressionError::VerificationFailed)
+        );
+    }
+
+    #[test]
+    fn test_decompress_with_positions() {
+        let config = default_config();
+        let original = vec![7, 1, 2, 3, 8, 9, 1, 2, 3, 4, 1, 2, 3];
+        let selected = vec![
+            make_occurrence(vec![1, 2, 3], 1),
+            make_occurrence(vec![1, 2, 3], 6),
+            make_occurrence(vec![1, 2, 3], 10),
+        ];
+
+        let dict = build_dictionary(&selected, &config, 1000);
+        let body = build_body(&original, &selected, &dict.pattern_to_meta, DEFAULT_ESCAPE_TOKEN);
+        let result = serialize_result(&dict, &body, &original, &config);
+
+        let (decoded, positions) =
+            decompress_with_positions(&result.serialized_tokens, &config).unwrap();
+
+        assert_eq!(decoded, original);
+        assert_eq!(positions.len(), body.len());
+        assert_eq!(positions[0], (0, 0..1));
+        assert_eq!(positions[1], (1, 1..4));
+
+        let mut next = 0;
+        for (i, (body_index, range)) in positions.iter().enumerate() {
+            assert_eq!(*body_index, i);
+            assert_eq!(range.start, next);
+            next = range.end;
+        }
+        assert_eq!(next, decoded.len());
+    }
+
+    #[test]
+    fn test_decompress_with_positions_across_frames() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..12 {
+            tokens.extend([1, 2, 3, 4, 5, 100 + i]);
+        }
+        let config = CompressionConfig {
+            max_single_pass_length: 36,
+            ..default_config()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(split_frames(&result.serialized_tokens, &config).len(), 2);
+
+        let (decoded, positions) =
+            decompress_with_positions(&result.serialized_tokens, &config).unwrap();
+
+        assert_eq!(decoded, tokens);
+        let mut next = 0;
+        for (body_index, range) in &positions {
+            assert_eq!(range.start, next);
+            // Indices past the second frame's dictionary still line up
+            if range.len() == 1 {
+                assert_eq!(result.body_tokens[*body_index], decoded[range.start]);
+            }
+            next = range.end;
+        }
+        assert_eq!(next, tokens.len());
+
+        // Only the unique tokens are left as literals, in both frames
+        let spans = result.uncompressed_spans(&config).unwrap();
+        let expected: Vec<(usize, usize)> = (0..12).map(|i| (6 * i + 5, 6 * i + 6)).collect();
+        assert_eq!(spans, expected);
+    }
+
+    #[test]
+    fn test_nested_dictionary_round_trips() {
+        let mut state: u64 = 898;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as Token % 3
+        };
+        let flat = default_config();
+        let nested = CompressionConfig {
+            dict_encoding: "nested".to_string(),
+            ..default_config()
+        };
+
+        let mut shrunk = 0;
+        for len in (40..400).step_by(20) {
+            let tokens: Vec<Token> = (0..len).map(|_| next()).collect();
+            let base = crate::DEFAULT_NEXT_META_TOKEN;
+            let result = crate::compress_internal(&tokens, &nested, base).unwrap();
+            let decoded =
+                crate::decompress_internal(&result.serialized_tokens, &nested, base).unwrap();
+            assert_eq!(decoded, tokens);
+
+            let flat_result = crate::compress_internal(&tokens, &flat, base).unwrap();
+            let (nested_len, flat_len) =
+                (result.serialized_tokens.len(), flat_result.serialized_tokens.len());
+            assert!(nested_len <= flat_len);
+            shrunk += usize::from(nested_len < flat_len);
+        }
+        assert!(shrunk > 0);
+    }
+
+    #[test]
+    fn test_rekey_meta_tokens() {
+        // Literals from 5000 up collide with the new pool and need escaping
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..12 {
+            tokens.extend([1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 6]);
+            tokens.extend([5000 + i, 9, 8, 7]);
+        }
+        let new_base = 5000;
+        let configs = [
+            default_config(),
+            CompressionConfig {
+                dict_encoding: "trie".to_string(),
+                ..default_config()
+            },
+            CompressionConfig {
+                body_runs: true,
+                ..default_config()
+            },
+            CompressionConfig {
+                dict_encoding: "nested".to_string(),
+                ..default_config()
+            },
+        ];
+
+        for config in configs {
+            let old_base = crate::DEFAULT_NEXT_META_TOKEN;
+            let result = crate::compress_internal(&tokens, &config, old_base).unwrap();
+            assert!(!result.dictionary_map.is_empty());
+            let original =
+                crate::decompress_internal(&result.serialized_tokens, &config, old_base).unwrap();
+
+            let rekeyed = rekey_meta_tokens(&result.serialized_tokens, &config, new_base).unwrap();
+            let ids: Vec<Token> =
+                parse_dictionary(&rekeyed, &config).unwrap().into_keys().collect();
+            assert_eq!(rekeyed.contains(&config.escape_token), !config.body_runs);
+            assert_eq!(ids.len(), result.dictionary_map.len());
+            assert!(ids.iter().all(|id| (new_base..new_base + 500).contains(id)));
+
+            let restored = crate::decompress_internal(&rekeyed, &config, new_base).unwrap();
+            assert_eq!(restored, original);
+            assert_eq!(restored, tokens);
+        }
+    }
+
+    #[test]
+    fn test_rekey_rejects_definition_literals_in_new_pool() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..12 {
+            tokens.extend([1, 2, 3, 4, 5, 6, 100 + i]);
+        }
+        let config = default_config();
+        let old_base = crate::DEFAULT_NEXT_META_TOKEN;
+        let result = crate::compress_internal(&tokens, &config, old_base).unwrap();
+        assert!(result.dictionary_map.values().any(|d| d.contains(&4)));
+
+        // Body literals could be escaped, but 4 sits inside a definition
+        let rekeyed = rekey_meta_tokens(&result.serialized_tokens, &config, 4);
+        assert!(matches!(rekeyed, Err(CompressionError::InvalidConfig(_))));
+
+        let rekeyed = rekey_meta_tokens(&result.serialized_tokens, &config, 200).unwrap();
+        let restored = crate::decompress_internal(&rekeyed, &config, 200).unwrap();
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_pattern_usage_counts_body_references() {
+        let config = default_config();
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 100 + i]);
+        }
+        for i in 0..3 {
+            tokens.extend([7, 8, 9, 200 + i]);
+        }
+        let selected: Vec<Occurrence> = (0..6)
+            .map(|i| make_occurrence(vec![1, 2, 3], i * 4))
+            .chain((0..3).map(|i| make_occurrence(vec![7, 8, 9], 24 + i * 4)))
+            .collect();
+        let dict = build_dictionary(&selected, &config, 1000);
+        let body = build_body(&tokens, &selected, &dict.pattern_to_meta, config.escape_token);
+        let result = serialize_result(&dict, &body, &tokens, &config);
+
+        let usage = pattern_usage(&result.serialized_tokens, &config).unwrap();
+        assert_eq!(usage, vec![(vec![1, 2, 3], 6), (vec![7, 8, 9], 3)]);
+        for (pattern, count) in &usage {
+            let meta = dict.pattern_to_meta[pattern];
+            assert_eq!(body.iter().filter(|&&t| t == meta).count(), *count);
+        }
+
+        // An entry defined through another counts its expansions as uses
+        let nested = vec![
+            config.dict_start_token,
+            1000,
+            2,
+            1,
+            2,
+            1001,
+            2,
+            1000,
+            3,
+            1002,
+            2,
+            8,
+            9,
+            config.dict_end_token,
+            1001,
+            1001,
+            1000,
+            5,
+        ];
+        let usage = pattern_usage(&nested, &config).unwrap();
+        assert_eq!(usage, vec![(vec![1, 2], 3), (vec![1, 2, 3], 2), (vec![8, 9], 0)]);
+    }
+
+    #[test]
+    fn test_headered_streams_are_read_with_their_delimiters() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            tokens.extend([1, 2, 3, 4, 100 + i]);
+        }
+        let config = CompressionConfig {
+            auto_delimiters: true,
+            ..default_config()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens[0], DELIMITER_HEADER_TOKEN);
+
+        let decoder = default_config();
+        let (decoded, positions) =
+            decompress_with_positions(&result.serialized_tokens, &decoder).unwrap();
+        assert_eq!(decoded, tokens);
+        assert_eq!(positions.len(), result.body_tokens.len());
+        for (i, (body_index, range)) in positions.iter().enumerate() {
+            assert_eq!(*body_index, i);
+            if range.len() == 1 {
+                assert_eq!(result.body_tokens[i], decoded[range.start]);
+            }
+        }
+
+        let usage = pattern_usage(&result.serialized_tokens, &decoder).unwrap();
+        assert_eq!(usage, vec![(vec![1, 2, 3, 4], 8)]);
+    }
+
+    #[test]
+    fn test_expand_once_returns_unexpandable_tokens_in_place() {
+        let escape = DEFAULT_ESCAPE_TOKEN;
+        let dictionary: HashMap<Token, Vec<Token>> =
+            [(1000, vec![1, 2]), (1001, vec![1000, 3])].into_iter().collect();
+
+        // An escaped meta-token is a literal, not something to expand
+        let literals = vec![5, 6, escape, 1000, 7];
+        let ptr = literals.as_ptr();
+        let (unchanged, changed) = expand_once(literals, &dictionary, escape);
+        assert!(!changed);
+        assert_eq!(unchanged.as_ptr(), ptr);
+        assert_eq!(unchanged, vec![5, 6, escape, 1000, 7]);
+
+        let (once, changed) = expand_once(vec![1001, 4, escape, 1001], &dictionary, escape);
+        assert!(changed);
+        assert_eq!(once, vec![1000, 3, 4, escape, 1001]);
+        let body = vec![1001, 4, escape, 1001, 1000];
+        assert_eq!(expand_body(body, &dictionary, escape), vec![1, 2, 3, 4, 1001, 1, 2]);
+    }
 }
@@ -5,9 +5,103 @@
 //!
 //! Port of `delta/discovery_sa.py`.
 
-use crate::suffix_array::{non_overlapping_positions, SuffixArray};
-use crate::types::{is_compressible, min_count_for_compressibility, Candidate, Token};
-use std::collections::HashMap;
+use crate::error::CompressionError;
+use crate::hash::{PatternMap, PatternSet};
+use crate::suffix_array::{
+    non_overlapping_positions, non_overlapping_positions_with, try_build_suffix_array_auto,
+    try_with_capacity, NonOverlapStrategy, SuffixArray,
+};
+use crate::types::{
+    compute_savings, is_compressible, min_count_for_compressibility, Candidate, CompressionConfig,
+    Pattern, Token,
+};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+
+/// How many occurrences a pattern needs before discovery reports it.
+///
+/// No pattern compresses with fewer than two occurrences, so
+/// `Compressibility` behaves like `Uniform(2)`, the default; it differs
+/// from larger uniform thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MinOccurrences {
+    /// At least `n` occurrences, and at least as many as the pattern's
+    /// length needs to be compressible.
+    Uniform(usize),
+    /// Only as many occurrences as the pattern's length needs to be
+    /// compressible, so long patterns qualify with fewer occurrences.
+    Compressibility,
+}
+
+impl MinOccurrences {
+    /// Minimum non-overlapping occurrences for a pattern of `length`.
+    pub fn min_count(self, length: usize, extra_cost: usize) -> usize {
+        let compressible = min_count_for_compressibility(length, extra_cost);
+        match self {
+            MinOccurrences::Uniform(n) => n.max(compressible),
+            MinOccurrences::Compressibility => compressible,
+        }
+    }
+
+    /// Fewest occurrences any pattern length can qualify with.
+    fn floor(self) -> usize {
+        match self {
+            MinOccurrences::Uniform(n) => n,
+            MinOccurrences::Compressibility => 2,
+        }
+    }
+}
+
+impl Default for MinOccurrences {
+    fn default() -> Self {
+        MinOccurrences::Uniform(2)
+    }
+}
+
+impl From<usize> for MinOccurrences {
+    fn from(n: usize) -> Self {
+        MinOccurrences::Uniform(n)
+    }
+}
+
+/// Order in which discovery returns candidates.
+///
+/// Selection consumes candidates in the order given, so a fixed order makes
+/// its input reproducible. Every order is total: ties fall back to the
+/// first occurrence, then the longer pattern, then the pattern's tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CandidateOrder {
+    /// Highest potential savings first, ties in suffix-array order.
+    #[default]
+    SavingsDesc,
+    /// Earliest first occurrence first.
+    PositionAsc,
+    /// Longest pattern first.
+    LengthDesc,
+}
+
+/// Reorder candidates as `order` describes.
+///
+/// `SavingsDesc` leaves them as they are, since discovery already returns
+/// them by savings.
+pub fn order_candidates(candidates: &mut [Candidate], order: CandidateOrder) {
+    let by_position = |a: &Candidate, b: &Candidate| {
+        a.positions()
+            .next()
+            .cmp(&b.positions().next())
+            .then_with(|| b.length.cmp(&a.length))
+            .then_with(|| a.subsequence.cmp(&b.subsequence))
+    };
+    match order {
+        CandidateOrder::SavingsDesc => {}
+        CandidateOrder::PositionAsc => candidates.sort_by(by_position),
+        CandidateOrder::LengthDesc => {
+            candidates.sort_by(|a, b| b.length.cmp(&a.length).then_with(|| by_position(a, b)))
+        }
+    }
+}
 
 /// Configuration for pattern discovery.
 #[derive(Debug, Clone)]
@@ -17,9 +111,68 @@ pub struct DiscoveryConfig {
     /// Maximum pattern length to consider
     pub max_length: usize,
     /// Minimum occurrences for a pattern to be considered
-    pub min_occurrences: usize,
+    pub min_occurrences: MinOccurrences,
     /// Extra cost per pattern (e.g., length token)
     pub extra_cost: usize,
+    /// Keep only the top-N candidates by potential savings (0 = unbounded)
+    pub max_candidates: usize,
+    /// Which occurrences to keep when a pattern overlaps itself
+    pub non_overlap: NonOverlapStrategy,
+    /// Build the suffix array in parallel for large inputs (only with the
+    /// `parallel` feature)
+    pub enable_parallel: bool,
+    /// Only discover patterns whose length is a multiple of this, such as
+    /// whole fixed-size records (1 = any length)
+    pub length_multiple_of: usize,
+    /// Treat the input as a ring, so patterns can wrap from its end to its
+    /// start; wrapped occurrences start `length` or fewer tokens before the
+    /// end
+    pub circular: bool,
+    /// Order of the returned candidates; `max_candidates` still keeps the
+    /// top-N by savings
+    pub candidate_order: CandidateOrder,
+}
+
+impl DiscoveryConfig {
+    /// Check that the length bounds describe a non-empty range of patterns
+    /// that can be compressed (length 2 or more).
+    pub fn validate(&self) -> Result<(), CompressionError> {
+        if self.min_length < 2 {
+            return Err(CompressionError::InvalidConfig(format!(
+                "min_length must be at least 2, got {}",
+                self.min_length
+            )));
+        }
+        if self.max_length < self.min_length {
+            return Err(CompressionError::InvalidConfig(format!(
+                "max_length ({}) is less than min_length ({})",
+                self.max_length, self.min_length
+            )));
+        }
+        if self.length_multiple_of == 0 {
+            return Err(CompressionError::InvalidConfig(
+                "length_multiple_of must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Derive the discovery settings used by the compression pipeline.
+    pub fn from_compression_config(config: &CompressionConfig) -> Self {
+        Self {
+            min_length: config.min_subsequence_length,
+            max_length: config.max_subsequence_length,
+            min_occurrences: config.min_occurrences,
+            extra_cost: config.extra_cost(),
+            max_candidates: config.max_candidates,
+            non_overlap: config.non_overlap,
+            enable_parallel: config.enable_parallel,
+            length_multiple_of: config.length_multiple_of,
+            // Later passes discover over a body, which is always linear
+            circular: false,
+            candidate_order: CandidateOrder::default(),
+        }
+    }
 }
 
 impl Default for DiscoveryConfig {
@@ -27,8 +180,14 @@ impl Default for DiscoveryConfig {
         Self {
             min_length: 2,
             max_length: 8,
-            min_occurrences: 2,
+            min_occurrences: MinOccurrences::default(),
             extra_cost: 1,
+            max_candidates: 0,
+            non_overlap: NonOverlapStrategy::default(),
+            enable_parallel: true,
+            length_multiple_of: 1,
+            circular: false,
+            candidate_order: CandidateOrder::default(),
         }
     }
 }
@@ -37,53 +196,125 @@ impl Default for DiscoveryConfig {
 ///
 /// Uses LCP intervals to efficiently find all repeated subsequences.
 pub fn discover_candidates(tokens: &[Token], config: &DiscoveryConfig) -> Vec<Candidate> {
+    try_discover_candidates(tokens, config).expect("discovery allocation")
+}
+
+/// `discover_candidates`, returning `OutOfMemory` instead of aborting when
+/// the suffix array or its working buffers cannot be allocated.
+pub fn try_discover_candidates(
+    tokens: &[Token],
+    config: &DiscoveryConfig,
+) -> Result<Vec<Candidate>, CompressionError> {
     if tokens.len() < config.min_length * 2 {
-        return Vec::new();
+        return Ok(Vec::new());
     }
 
-    let sa = SuffixArray::build(tokens);
-    let intervals = sa.lcp_intervals(config.min_length);
+    // A ring is searched as the input followed by the start of it again,
+    // counting only the suffixes that start in the input itself
+    let len = tokens.len();
+    let ring;
+    let tokens = if config.circular {
+        let wrap = config.max_length.saturating_sub(1).min(len);
+        ring = {
+            let mut ring = try_with_capacity(len + wrap)?;
+            ring.extend_from_slice(tokens);
+            ring.extend_from_slice(&tokens[..wrap]);
+            ring
+        };
+        &ring[..]
+    } else {
+        tokens
+    };
 
-    let mut candidates: Vec<Candidate> = Vec::new();
-    let mut seen: HashMap<Vec<Token>, usize> = HashMap::new(); // pattern -> candidate index
+    let sa = try_build_suffix_array_auto(tokens, config.enable_parallel)?;
+    candidates_from_suffix_array(tokens, len, &sa, config)
+}
 
-    for (start_idx, end_idx, lcp_len) in intervals {
-        // Number of suffixes in this interval
-        let count = end_idx - start_idx + 1;
-        if count < config.min_occurrences {
+/// `discover_candidates` over a suffix array the caller already built for
+/// exactly `tokens`.
+///
+/// Fails with `InvalidConfig` if the array's length differs from the
+/// input's, or if `config.circular` is set, since a ring needs the array of
+/// a longer sequence.
+pub fn discover_with_suffix_array(
+    tokens: &[Token],
+    sa: &SuffixArray,
+    config: &DiscoveryConfig,
+) -> Result<Vec<Candidate>, CompressionError> {
+    if sa.suffix_array.len() != tokens.len() {
+        return Err(CompressionError::InvalidConfig(format!(
+            "suffix array covers {} tokens but the input has {}",
+            sa.suffix_array.len(),
+            tokens.len()
+        )));
+    }
+    if config.circular {
+        return Err(CompressionError::InvalidConfig(
+            "circular discovery cannot use a prebuilt suffix array".to_string(),
+        ));
+    }
+    if tokens.len() < config.min_length * 2 {
+        return Ok(Vec::new());
+    }
+    candidates_from_suffix_array(tokens, tokens.len(), sa, config)
+}
+
+/// Turn the LCP intervals of `sa` into candidates, keeping positions below
+/// `len` (the input's length when `tokens` is a ring).
+fn candidates_from_suffix_array(
+    tokens: &[Token],
+    len: usize,
+    sa: &SuffixArray,
+    config: &DiscoveryConfig,
+) -> Result<Vec<Candidate>, CompressionError> {
+    let min_count = config.min_occurrences.floor();
+    let intervals = sa.try_lcp_intervals_with_parent(config.min_length, min_count)?;
+
+    let mut candidates = CandidateSink::new(config.max_candidates, config.extra_cost);
+    let mut positions: Vec<usize> = Vec::new();
+    let multiple = config.length_multiple_of.max(1);
+    // Every candidate's pattern is a slice of one copy of the input
+    let arena = Pattern::from_slice(tokens);
+
+    for (start_idx, end_idx, lcp_len, parent_lcp) in intervals {
+        // Every length shares the interval's positions, sorted once
+        positions.clear();
+        positions
+            .try_reserve(end_idx - start_idx + 1)
+            .map_err(|_| CompressionError::OutOfMemory)?;
+        positions.extend(sa.suffix_array[start_idx..=end_idx].iter().filter(|&&p| p < len));
+        let Some(&first_pos) = positions.first() else {
             continue;
-        }
+        };
+        positions.sort_unstable();
 
-        // Extract positions from suffix array
-        let positions: Vec<usize> = sa.suffix_array[start_idx..=end_idx].to_vec();
+        // Number of occurrences in this interval
+        let count = positions.len();
+
+        // Only the lengths this interval is the widest one for; shorter
+        // prefixes occur at more positions and come from the parent
+        let shortest = config.min_length.max(parent_lcp + 1);
+        for length in shortest..=lcp_len.min(config.max_length) {
+            if length % multiple != 0 {
+                continue;
+            }
 
-        // Try different lengths up to lcp_len
-        for length in config.min_length..=lcp_len.min(config.max_length) {
             // Check if this length could be compressible
-            let min_count = min_count_for_compressibility(length, config.extra_cost);
+            let min_count = config.min_occurrences.min_count(length, config.extra_cost);
             if count < min_count {
                 continue;
             }
 
             // Extract the pattern
-            if positions.is_empty() {
-                continue;
-            }
-            let first_pos = positions[0];
             if first_pos + length > tokens.len() {
                 continue;
             }
-            let pattern: Vec<Token> = tokens[first_pos..first_pos + length].to_vec();
-
-            // Skip if we've already seen this pattern
-            if seen.contains_key(&pattern) {
-                continue;
-            }
-
             // Get non-overlapping positions
-            let mut sorted_positions = positions.clone();
-            sorted_positions.sort_unstable();
-            let non_overlapping = non_overlapping_positions(&sorted_positions, length);
+            let mut non_overlapping =
+                non_overlapping_positions_with(&positions, length, config.non_overlap);
+            if config.circular {
+                drop_ring_overlap(&mut non_overlapping, length, len);
+            }
 
             // Check if still compressible after removing overlaps
             if non_overlapping.len() < min_count {
@@ -96,34 +327,114 @@ pub fn discover_candidates(tokens: &[Token], config: &DiscoveryConfig) -> Vec<Ca
             }
 
             // Create candidate
-            let candidate = Candidate::new(pattern.clone(), non_overlapping);
-            seen.insert(pattern, candidates.len());
-            candidates.push(candidate);
+            let pattern = arena.slice(first_pos..first_pos + length);
+            candidates.push(Candidate::new(pattern, non_overlapping));
         }
     }
 
     // Sort candidates by potential savings (higher first)
-    candidates.sort_by(|a, b| {
-        let savings_a = compute_potential_savings(a, config.extra_cost);
-        let savings_b = compute_potential_savings(b, config.extra_cost);
-        savings_b.cmp(&savings_a)
-    });
+    let mut candidates = candidates.into_sorted_vec();
+    order_candidates(&mut candidates, config.candidate_order);
+    Ok(candidates)
+}
 
-    candidates
+/// Drop the last occurrence when it wraps around onto the first one.
+fn drop_ring_overlap(positions: &mut Vec<usize>, length: usize, len: usize) {
+    if let (Some(&first), Some(&last)) = (positions.first(), positions.last()) {
+        if positions.len() > 1 && last + length > len + first {
+            positions.pop();
+        }
+    }
 }
 
-/// Compute potential savings for a candidate.
-fn compute_potential_savings(candidate: &Candidate, extra_cost: usize) -> i64 {
-    let count = candidate.positions.len();
-    let length = candidate.length;
+/// Candidate ranked by potential savings, then by discovery order.
+///
+/// Ordering is "worse first": lower savings, and among equal savings the
+/// later-discovered candidate, compare as smaller.
+struct RankedCandidate {
+    savings: i64,
+    order: usize,
+    candidate: Candidate,
+}
+
+impl PartialEq for RankedCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedCandidate {}
+
+impl PartialOrd for RankedCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.savings
+            .cmp(&other.savings)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+/// Collects discovered candidates, optionally bounded to the top-N.
+///
+/// When bounded, a min-heap holds the best candidates seen so far and
+/// weaker ones are dropped as soon as they are generated.
+pub(crate) struct CandidateSink {
+    max_candidates: usize,
+    extra_cost: usize,
+    heap: BinaryHeap<Reverse<RankedCandidate>>,
+    next_order: usize,
+}
+
+impl CandidateSink {
+    pub(crate) fn new(max_candidates: usize, extra_cost: usize) -> Self {
+        Self {
+            max_candidates,
+            extra_cost,
+            heap: BinaryHeap::new(),
+            next_order: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, candidate: Candidate) {
+        let entry = RankedCandidate {
+            savings: compute_potential_savings(&candidate, self.extra_cost),
+            order: self.next_order,
+            candidate,
+        };
+        self.next_order += 1;
+
+        if self.max_candidates == 0 || self.heap.len() < self.max_candidates {
+            self.heap.push(Reverse(entry));
+            return;
+        }
 
-    if count == 0 {
-        return 0;
+        if let Some(Reverse(weakest)) = self.heap.peek() {
+            if entry > *weakest {
+                self.heap.pop();
+                self.heap.push(Reverse(entry));
+            }
+        }
     }
 
-    let original = (length * count) as i64;
-    let compressed = (1 + length + count + extra_cost) as i64; // meta-token + definition + count replacements + extra
-    (original - compressed).max(0)
+    /// Candidates by savings (highest first), ties in discovery order.
+    pub(crate) fn into_sorted_vec(self) -> Vec<Candidate> {
+        // Ascending order of `Reverse` is descending order of rank.
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(entry)| entry.candidate)
+            .collect()
+    }
+}
+
+/// Compute potential savings for a candidate.
+fn compute_potential_savings(candidate: &Candidate, extra_cost: usize) -> i64 {
+    compute_savings(candidate.length, candidate.count(), extra_cost)
 }
 
 /// Discover patterns optimized for hierarchical compression.
@@ -167,16 +478,212 @@ pub fn discover_fixed_length(
         return Vec::new();
     }
 
-    let min_count = min_count_for_compressibility(length, extra_cost);
-
     // Count occurrences of each n-gram
-    let mut pattern_positions: HashMap<Vec<Token>, Vec<usize>> = HashMap::new();
+    let mut pattern_positions: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
+    let arena = Pattern::from_slice(tokens);
 
     for i in 0..=tokens.len() - length {
-        let pattern: Vec<Token> = tokens[i..i + length].to_vec();
+        let pattern = arena.slice(i..i + length);
         pattern_positions.entry(pattern).or_default().push(i);
     }
 
+    fixed_length_candidates(pattern_positions, length, extra_cost)
+}
+
+/// Fixed-length discovery over a token iterator.
+///
+/// Produces the same candidates as `discover_fixed_length` while holding
+/// only a `length`-token window of the input; memory grows with the number
+/// of distinct n-grams and their positions, not with the sequence.
+pub fn discover_fixed_length_iter<I>(iter: I, length: usize, extra_cost: usize) -> Vec<Candidate>
+where
+    I: IntoIterator<Item = Token>,
+{
+    if length == 0 {
+        return Vec::new();
+    }
+
+    let mut pattern_positions: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
+    let mut window: VecDeque<Token> = VecDeque::with_capacity(length);
+    let mut ngram: Vec<Token> = Vec::with_capacity(length);
+
+    for (i, token) in iter.into_iter().enumerate() {
+        if window.len() == length {
+            window.pop_front();
+        }
+        window.push_back(token);
+
+        if window.len() == length {
+            ngram.clear();
+            ngram.extend(&window);
+            push_position(&mut pattern_positions, &ngram, i + 1 - length);
+        }
+    }
+
+    fixed_length_candidates(pattern_positions, length, extra_cost)
+}
+
+/// Multiplier for the polynomial rolling hash (odd, so invertible mod 2^64).
+const ROLLING_HASH_BASE: u64 = 0x100_0000_01b3;
+
+/// Windowed fixed-length discovery fed one token at a time.
+///
+/// N-grams are indexed by a Rabin-Karp rolling hash as tokens arrive, and
+/// only the most recent `window` tokens are kept, so memory stays bounded
+/// however long the stream runs. `candidates` reports what
+/// `discover_fixed_length` would find in that window, with positions
+/// counted from the start of the stream. Repeats further apart than the
+/// window are missed.
+#[derive(Debug, Clone)]
+pub struct RollingDiscovery {
+    length: usize,
+    window: usize,
+    /// `ROLLING_HASH_BASE^(length - 1)`, to remove the outgoing token.
+    outgoing_factor: u64,
+    hash: u64,
+    /// The last `window` tokens; `buffer[0]` is stream position `base`.
+    buffer: VecDeque<Token>,
+    base: usize,
+    consumed: usize,
+    /// N-gram starts in the window, oldest first, with their hashes.
+    ngrams: VecDeque<(usize, u64)>,
+    positions: PatternMap<u64, VecDeque<usize>>,
+}
+
+impl RollingDiscovery {
+    /// Track n-grams of `length` tokens within the last `window` tokens.
+    ///
+    /// Fails if `length` is 0 or `window` is shorter than `length`.
+    pub fn new(length: usize, window: usize) -> Result<Self, CompressionError> {
+        if length == 0 {
+            return Err(CompressionError::InvalidConfig(
+                "rolling discovery length must be at least 1".to_string(),
+            ));
+        }
+        if window < length {
+            return Err(CompressionError::InvalidConfig(format!(
+                "rolling discovery window ({}) must be at least length ({})",
+                window, length
+            )));
+        }
+
+        let outgoing_factor = (1..length).fold(1u64, |acc, _| acc.wrapping_mul(ROLLING_HASH_BASE));
+        Ok(Self {
+            length,
+            window,
+            outgoing_factor,
+            hash: 0,
+            buffer: VecDeque::with_capacity(window + 1),
+            base: 0,
+            consumed: 0,
+            ngrams: VecDeque::new(),
+            positions: PatternMap::default(),
+        })
+    }
+
+    /// Add the next token of the stream.
+    pub fn push(&mut self, token: Token) {
+        if self.consumed >= self.length {
+            let outgoing = self.buffer[self.buffer.len() - self.length] as u64;
+            self.hash = self
+                .hash
+                .wrapping_sub(outgoing.wrapping_mul(self.outgoing_factor));
+        }
+        self.hash = self
+            .hash
+            .wrapping_mul(ROLLING_HASH_BASE)
+            .wrapping_add(token as u64);
+        self.buffer.push_back(token);
+        self.consumed += 1;
+
+        if self.consumed >= self.length {
+            let start = self.consumed - self.length;
+            self.positions.entry(self.hash).or_default().push_back(start);
+            self.ngrams.push_back((start, self.hash));
+        }
+
+        if self.buffer.len() > self.window {
+            self.buffer.pop_front();
+            self.base += 1;
+            self.evict_before(self.base);
+        }
+    }
+
+    /// Add a chunk of the stream, such as one passed to
+    /// `StreamingCompressor::add_chunk`.
+    pub fn extend(&mut self, tokens: &[Token]) {
+        for &token in tokens {
+            self.push(token);
+        }
+    }
+
+    /// Number of tokens consumed so far.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Compressible n-grams in the current window, ordered like
+    /// `discover_fixed_length`.
+    pub fn candidates(&self, extra_cost: usize) -> Vec<Candidate> {
+        let min_count = min_count_for_compressibility(self.length, extra_cost);
+
+        // Group by pattern as well as hash so collisions stay apart
+        let mut pattern_positions: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
+        let mut ngram: Vec<Token> = Vec::with_capacity(self.length);
+        for starts in self.positions.values() {
+            if starts.len() < min_count {
+                continue;
+            }
+            for &start in starts {
+                let offset = start - self.base;
+                ngram.clear();
+                ngram.extend(self.buffer.range(offset..offset + self.length));
+                push_position(&mut pattern_positions, &ngram, start);
+            }
+        }
+
+        fixed_length_candidates(pattern_positions, self.length, extra_cost)
+    }
+
+    /// Forget n-grams starting before stream position `base`.
+    fn evict_before(&mut self, base: usize) {
+        while let Some(&(start, hash)) = self.ngrams.front() {
+            if start >= base {
+                break;
+            }
+            self.ngrams.pop_front();
+            if let Some(starts) = self.positions.get_mut(&hash) {
+                starts.pop_front();
+                if starts.is_empty() {
+                    self.positions.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+/// Record `position` for `ngram`, copying the n-gram only when it is new.
+fn push_position(
+    pattern_positions: &mut PatternMap<Pattern, Vec<usize>>,
+    ngram: &[Token],
+    position: usize,
+) {
+    match pattern_positions.get_mut(ngram) {
+        Some(positions) => positions.push(position),
+        None => {
+            pattern_positions.insert(Pattern::from_slice(ngram), vec![position]);
+        }
+    }
+}
+
+/// Turn n-gram positions into sorted, compressible candidates.
+fn fixed_length_candidates(
+    pattern_positions: PatternMap<Pattern, Vec<usize>>,
+    length: usize,
+    extra_cost: usize,
+) -> Vec<Candidate> {
+    let min_count = min_count_for_compressibility(length, extra_cost);
+
     // Filter and create candidates
     let mut candidates = Vec::new();
 
@@ -200,26 +707,78 @@ pub fn discover_fixed_length(
     candidates.sort_by(|a, b| {
         let savings_a = compute_potential_savings(a, extra_cost);
         let savings_b = compute_potential_savings(b, extra_cost);
-        savings_b.cmp(&savings_a)
+        savings_b
+            .cmp(&savings_a)
+            .then_with(|| a.subsequence.cmp(&b.subsequence))
     });
 
     candidates
 }
 
+/// Build candidates from externally supplied occurrence hints.
+///
+/// Each hint is a pattern with its known positions. Positions are sorted,
+/// and every position must lie within the input, match the pattern, and not
+/// overlap the previous position of the same hint.
+pub fn candidates_from_hints(
+    tokens: &[Token],
+    hints: Vec<(Vec<Token>, Vec<usize>)>,
+) -> Result<Vec<Candidate>, CompressionError> {
+    let mut candidates = Vec::with_capacity(hints.len());
+
+    for (hint, (pattern, mut positions)) in hints.into_iter().enumerate() {
+        let invalid = |reason: String| CompressionError::InvalidHint { hint, reason };
+
+        if pattern.is_empty() {
+            return Err(invalid("pattern is empty".to_string()));
+        }
+
+        positions.sort_unstable();
+        let mut next_free = 0;
+
+        for &pos in &positions {
+            if pos < next_free {
+                return Err(invalid(format!(
+                    "position {} overlaps the previous occurrence",
+                    pos
+                )));
+            }
+            let end = match pos.checked_add(pattern.len()) {
+                Some(end) if end <= tokens.len() => end,
+                _ => {
+                    return Err(invalid(format!(
+                        "position {} runs past the end of the input",
+                        pos
+                    )))
+                }
+            };
+            if tokens[pos..end] != pattern[..] {
+                return Err(invalid(format!(
+                    "tokens at position {} do not match the pattern",
+                    pos
+                )));
+            }
+            next_free = end;
+        }
+
+        candidates.push(Candidate::new(pattern, positions));
+    }
+
+    Ok(candidates)
+}
+
 /// Deduplicate candidates that have the same subsequence.
 pub fn deduplicate_candidates(candidates: Vec<Candidate>) -> Vec<Candidate> {
-    let mut seen: HashMap<Vec<Token>, usize> = HashMap::new();
+    let mut seen: PatternMap<Pattern, usize> = PatternMap::default();
     let mut result: Vec<Candidate> = Vec::new();
 
     for candidate in candidates {
         if let Some(&idx) = seen.get(&candidate.subsequence) {
             // Merge positions
             let existing: &mut Candidate = &mut result[idx];
-            let mut all_positions: Vec<usize> = existing.positions.clone();
-            all_positions.extend(&candidate.positions);
-            all_positions.sort_unstable();
-            all_positions.dedup();
-            existing.positions = all_positions;
+            let mut all_positions: Vec<usize> = existing.positions().collect();
+            all_positions.extend(candidate.positions());
+            existing.set_positions(&all_positions);
         } else {
             seen.insert(candidate.subsequence.clone(), result.len());
             result.push(candidate);
@@ -229,6 +788,73 @@ pub fn deduplicate_candidates(candidates: Vec<Candidate>) -> Vec<Candidate> {
     result
 }
 
+/// Drop candidates whose tokens are not all of one type.
+///
+/// `token_type` partitions the vocabulary, e.g. into structural and
+/// content tokens. Unlike segment boundaries, which only split the input,
+/// this constrains every token of a pattern, so no dictionary entry mixes
+/// types.
+pub fn retain_single_type(candidates: &mut Vec<Candidate>, token_type: impl Fn(Token) -> u8) {
+    candidates.retain(|candidate| {
+        let mut types = candidate.subsequence.iter().map(|&t| token_type(t));
+        let first = types.next();
+        types.all(|t| Some(t) == first)
+    });
+}
+
+/// Drop blacklisted candidates and every position overlapping a
+/// blacklisted region, then candidates no longer worth compressing.
+///
+/// Each occurrence of a blacklisted sequence in `tokens` must stay
+/// verbatim, so no selected occurrence may cover any of its tokens.
+pub fn exclude_blacklisted(
+    tokens: &[Token],
+    candidates: Vec<Candidate>,
+    blacklist: &[Vec<Token>],
+    config: &DiscoveryConfig,
+) -> Vec<Candidate> {
+    if blacklist.is_empty() {
+        return candidates;
+    }
+
+    let mut blocked = vec![false; tokens.len()];
+    for pattern in blacklist.iter().filter(|p| !p.is_empty()) {
+        for (start, window) in tokens.windows(pattern.len()).enumerate() {
+            if window == pattern.as_slice() {
+                blocked[start..start + pattern.len()].fill(true);
+            }
+        }
+    }
+
+    // blocked_before[i] = blocked tokens in tokens[..i]
+    let mut blocked_before = Vec::with_capacity(tokens.len() + 1);
+    blocked_before.push(0);
+    for &b in &blocked {
+        blocked_before.push(blocked_before.last().unwrap() + usize::from(b));
+    }
+
+    let banned: PatternSet<&[Token]> = blacklist.iter().map(Vec::as_slice).collect();
+    candidates
+        .into_iter()
+        .filter(|candidate| !banned.contains(candidate.subsequence.as_slice()))
+        .filter_map(|mut candidate| {
+            let length = candidate.length;
+            let positions: Vec<usize> = candidate
+                .positions()
+                .filter(|&pos| blocked_before[pos + length] == blocked_before[pos])
+                .collect();
+            candidate.set_positions(&positions);
+
+            let min_count = config.min_occurrences.min_count(length, config.extra_cost);
+            let count = positions.len();
+            if count < min_count || !is_compressible(length, count, config.extra_cost) {
+                return None;
+            }
+            Some(candidate)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,8 +879,9 @@ mod tests {
         let config = DiscoveryConfig {
             min_length: 2,
             max_length: 4,
-            min_occurrences: 2,
+            min_occurrences: MinOccurrences::Uniform(2),
             extra_cost: 1,
+            ..Default::default()
         };
 
         let candidates = discover_candidates(&tokens, &config);
@@ -266,7 +893,7 @@ mod tests {
         for cand in &candidates {
             assert!(is_compressible(
                 cand.length,
-                cand.positions.len(),
+                cand.count(),
                 config.extra_cost
             ));
         }
@@ -279,10 +906,209 @@ mod tests {
 
         // Should find [1, 2] pattern
         assert!(!candidates.is_empty());
-        let found = candidates.iter().any(|c| c.subsequence == vec![1, 2]);
+        let found = candidates.iter().any(|c| c.subsequence[..] == [1, 2]);
         assert!(found);
     }
 
+    #[test]
+    fn test_discover_fixed_length_iter_matches_slice() {
+        let tokens: Vec<Token> = (0..500u32).map(|i| (i * 7 % 13) % 5).collect();
+
+        for length in [2, 3, 5] {
+            let from_slice = discover_fixed_length(&tokens, length, 1);
+            let from_iter = discover_fixed_length_iter(tokens.iter().copied(), length, 1);
+            assert!(!from_slice.is_empty());
+            assert_eq!(from_iter, from_slice);
+        }
+
+        assert!(discover_fixed_length_iter(tokens[..3].iter().copied(), 4, 1).is_empty());
+    }
+
+    #[test]
+    fn test_rolling_discovery_matches_fixed_length() {
+        let tokens: Vec<Token> = (0..500u32).map(|i| (i * 7 % 13) % 5).collect();
+
+        for length in [2, 3, 5] {
+            // A window covering the whole input finds the same candidates
+            let mut rolling = RollingDiscovery::new(length, tokens.len()).unwrap();
+            for chunk in tokens.chunks(37) {
+                rolling.extend(chunk);
+            }
+            let expected = discover_fixed_length(&tokens, length, 1);
+            assert!(!expected.is_empty());
+            assert_eq!(rolling.candidates(1), expected);
+
+            // A smaller window sees only the most recent tokens
+            let window = 60;
+            let mut rolling = RollingDiscovery::new(length, window).unwrap();
+            rolling.extend(&tokens);
+            let offset = tokens.len() - window;
+            let expected: Vec<Candidate> = discover_fixed_length(&tokens[offset..], length, 1)
+                .into_iter()
+                .map(|c| {
+                    let positions = c.positions().map(|p| p + offset).collect();
+                    Candidate::new(c.subsequence, positions)
+                })
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(rolling.candidates(1), expected);
+            assert_eq!(rolling.consumed(), tokens.len());
+        }
+
+        assert!(RollingDiscovery::new(0, 10).is_err());
+        assert!(RollingDiscovery::new(4, 3).is_err());
+    }
+
+    #[test]
+    fn test_compressibility_policy_admits_long_pairs() {
+        // [1..=8] and [20, 21] each occur exactly twice
+        let mut tokens: Vec<Token> = Vec::new();
+        for sep in [100, 101] {
+            tokens.extend(1..=8);
+            tokens.extend([sep, 20, 21, sep + 10]);
+        }
+        let config = DiscoveryConfig {
+            min_length: 2,
+            max_length: 8,
+            min_occurrences: MinOccurrences::Compressibility,
+            ..Default::default()
+        };
+
+        let candidates = discover_candidates(&tokens, &config);
+        let has = |pattern: &[Token]| candidates.iter().any(|c| c.subsequence[..] == *pattern);
+        assert!(has(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert!(!has(&[20, 21]));
+
+        let uniform = DiscoveryConfig {
+            min_occurrences: MinOccurrences::Uniform(3),
+            ..config
+        };
+        assert!(discover_candidates(&tokens, &uniform).is_empty());
+    }
+
+    #[test]
+    fn test_validate_length_bounds() {
+        assert!(DiscoveryConfig::default().validate().is_ok());
+
+        let too_short = DiscoveryConfig {
+            min_length: 1,
+            ..Default::default()
+        };
+        assert!(matches!(too_short.validate(), Err(CompressionError::InvalidConfig(_))));
+
+        let inverted = DiscoveryConfig {
+            min_length: 4,
+            max_length: 3,
+            ..Default::default()
+        };
+        assert!(matches!(inverted.validate(), Err(CompressionError::InvalidConfig(_))));
+
+        let no_multiple = DiscoveryConfig {
+            length_multiple_of: 0,
+            ..Default::default()
+        };
+        assert!(matches!(no_multiple.validate(), Err(CompressionError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_length_multiple_of_keeps_whole_records() {
+        // Three-token records: a repeated run of three records, each run
+        // followed by a unique record
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 4, 5, 6, 7, 8, 9]);
+            tokens.extend([100 + i, 200 + i, 300 + i]);
+        }
+        let any_length = DiscoveryConfig {
+            max_length: 9,
+            ..Default::default()
+        };
+        let records = DiscoveryConfig {
+            length_multiple_of: 3,
+            ..any_length.clone()
+        };
+
+        let has = |candidates: &[Candidate], pattern: &[Token]| {
+            candidates.iter().any(|c| c.subsequence[..] == *pattern)
+        };
+        assert!(has(&discover_candidates(&tokens, &any_length), &[1, 2, 3, 4]));
+
+        let candidates = discover_candidates(&tokens, &records);
+        let mut lengths: Vec<usize> = candidates.iter().map(|c| c.length).collect();
+        lengths.sort_unstable();
+        lengths.dedup();
+        assert_eq!(lengths, vec![3, 6, 9]);
+        assert!(!has(&candidates, &[1, 2, 3, 4]));
+        assert!(has(&candidates, &[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+    }
+
+    #[test]
+    fn test_prefix_of_longer_repeat_keeps_every_position() {
+        // [1, 2] starts five copies of [1, 2, 3, 4] and three other
+        // places. Its candidate comes from the widest interval, not from
+        // the narrower [1, 2, 3, 4] one, so it carries all eight positions.
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..5 {
+            tokens.extend([1, 2, 3, 4, 100 + i]);
+        }
+        for i in 0..3 {
+            tokens.extend([1, 2, 200 + i]);
+        }
+
+        let candidates = discover_candidates(&tokens, &DiscoveryConfig::default());
+        let prefix = candidates.iter().find(|c| c.subsequence() == [1, 2]).unwrap();
+
+        assert_eq!(prefix.positions().collect::<Vec<_>>(), [0, 5, 10, 15, 20, 25, 28, 31]);
+    }
+
+    #[test]
+    fn test_candidates_match_brute_force() {
+        // Every repeated substring, counted directly, against the patterns
+        // discovery reports from the LCP intervals
+        let mut state: u32 = 7;
+        let random: Vec<Token> = (0..120)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) % 4
+            })
+            .collect();
+        let cyclic: Vec<Token> = (0..60).map(|i| i % 3).collect();
+        let mut nested: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            nested.extend([1, 2, 3, 4, 5]);
+            nested.resize(nested.len() + i % 4, 9);
+        }
+
+        let config = DiscoveryConfig {
+            max_length: 10,
+            ..Default::default()
+        };
+        for tokens in [random, cyclic, nested] {
+            let mut expected: Vec<(Vec<Token>, Vec<usize>)> = Vec::new();
+            for length in config.min_length..=config.max_length {
+                let mut by_pattern: PatternMap<&[Token], Vec<usize>> = PatternMap::default();
+                for (pos, window) in tokens.windows(length).enumerate() {
+                    by_pattern.entry(window).or_default().push(pos);
+                }
+                let min_count = config.min_occurrences.min_count(length, config.extra_cost);
+                for (pattern, positions) in by_pattern {
+                    let kept = non_overlapping_positions(&positions, length);
+                    if kept.len() >= min_count && is_compressible(length, kept.len(), 1) {
+                        expected.push((pattern.to_vec(), kept));
+                    }
+                }
+            }
+            expected.sort();
+
+            let mut found: Vec<(Vec<Token>, Vec<usize>)> = discover_candidates(&tokens, &config)
+                .into_iter()
+                .map(|c| (c.subsequence.to_vec(), c.positions().collect()))
+                .collect();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
     #[test]
     fn test_deduplicate_candidates() {
         let c1 = Candidate::new(vec![1, 2], vec![0, 4, 8]);
@@ -294,8 +1120,59 @@ mod tests {
         assert_eq!(result.len(), 2);
 
         // First candidate should have merged positions
-        let merged = result.iter().find(|c| c.subsequence == vec![1, 2]).unwrap();
-        assert_eq!(merged.positions.len(), 6);
+        let merged = result.iter().find(|c| c.subsequence[..] == [1, 2]).unwrap();
+        assert_eq!(merged.count(), 6);
+    }
+
+    #[test]
+    fn test_max_candidates_keeps_highest_savings() {
+        // Small alphabet pseudo-random stream: many distinct short repeats
+        let mut state: u32 = 12345;
+        let tokens: Vec<Token> = (0..4000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) % 6
+            })
+            .collect();
+
+        let unbounded = discover_candidates(&tokens, &DiscoveryConfig::default());
+        assert!(unbounded.len() > 1000, "expected thousands of candidates");
+
+        let config = DiscoveryConfig {
+            max_candidates: 50,
+            ..Default::default()
+        };
+        let bounded = discover_candidates(&tokens, &config);
+        assert_eq!(bounded.len(), 50);
+
+        let bounded_savings: Vec<i64> = bounded
+            .iter()
+            .map(|c| compute_potential_savings(c, config.extra_cost))
+            .collect();
+        let top_savings: Vec<i64> = unbounded
+            .iter()
+            .take(50)
+            .map(|c| compute_potential_savings(c, config.extra_cost))
+            .collect();
+        assert_eq!(bounded_savings, top_savings);
+
+        // Survivors are the same candidates the unbounded run ranks first
+        assert_eq!(bounded, unbounded[..50].to_vec());
+    }
+
+    #[test]
+    fn test_candidates_from_hints() {
+        let tokens = vec![1, 2, 9, 1, 2, 9, 1, 2];
+
+        let candidates = candidates_from_hints(&tokens, vec![(vec![1, 2], vec![6, 0, 3])]).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert!(candidates[0].positions().eq([0, 3, 6]));
+
+        let overlap = candidates_from_hints(&tokens, vec![(vec![1, 2, 9], vec![0, 2])]);
+        assert!(matches!(overlap, Err(CompressionError::InvalidHint { hint: 0, .. })));
+
+        let out_of_bounds = candidates_from_hints(&tokens, vec![(vec![1, 2, 9], vec![6])]);
+        assert!(matches!(out_of_bounds, Err(CompressionError::InvalidHint { hint: 0, .. })));
     }
 
     #[test]
@@ -315,10 +1192,96 @@ mod tests {
         for cand in &candidates {
             // Verify positions don't overlap
             let mut prev_end = 0;
-            for &pos in &cand.positions {
+            for pos in cand.positions() {
                 assert!(pos >= prev_end, "Positions should not overlap");
                 prev_end = pos + cand.length;
             }
         }
     }
+
+    #[test]
+    fn test_non_overlap_strategy_from_compression_config() {
+        // Runs of five 7s fit [7, 7] twice each, at offsets 0 and 2 or 1 and 3
+        let mut tokens: Vec<Token> = Vec::new();
+        for sep in 100..104 {
+            tokens.extend([7; 5]);
+            tokens.push(sep);
+        }
+        let positions = |non_overlap| {
+            let config = CompressionConfig {
+                non_overlap,
+                ..Default::default()
+            };
+            let config = DiscoveryConfig::from_compression_config(&config);
+            let candidates = discover_candidates(&tokens, &config);
+            let pair = candidates.iter().find(|c| c.subsequence() == [7, 7]).unwrap();
+            pair.positions().collect::<Vec<usize>>()
+        };
+
+        let earliest = [0, 2, 6, 8, 12, 14, 18, 20];
+        assert_eq!(positions(NonOverlapStrategy::EarliestFirst), earliest);
+        assert_eq!(positions(NonOverlapStrategy::LatestFirst), [1, 3, 7, 9, 13, 15, 19, 21]);
+        assert_eq!(positions(NonOverlapStrategy::MaxCount), earliest);
+    }
+
+    #[test]
+    fn test_min_occurrences_from_compression_config() {
+        // [1..=8] occurs twice: enough for its length, short of a uniform 3
+        let mut tokens: Vec<Token> = Vec::new();
+        for sep in [100, 101] {
+            tokens.extend(1..=8);
+            tokens.extend([sep, 20, 21, sep + 10]);
+        }
+        let candidates = |min_occurrences| {
+            let config = CompressionConfig {
+                min_occurrences,
+                ..Default::default()
+            };
+            discover_candidates(&tokens, &DiscoveryConfig::from_compression_config(&config))
+        };
+
+        let compressibility = candidates(MinOccurrences::Compressibility);
+        assert!(compressibility.iter().any(|c| c.subsequence() == [1, 2, 3, 4, 5, 6, 7, 8]));
+        assert!(candidates(MinOccurrences::Uniform(3)).is_empty());
+        assert_eq!(candidates(MinOccurrences::Uniform(2)), compressibility);
+    }
+
+    #[test]
+    fn test_candidate_order_sorts_candidates() {
+        use crate::selection::select_greedy;
+
+        let tokens: Vec<Token> = [7, 8, 1, 2, 3, 4, 5, 6, 9].repeat(6);
+        let discover = |candidate_order| {
+            let config = DiscoveryConfig {
+                candidate_order,
+                ..Default::default()
+            };
+            discover_candidates(&tokens, &config)
+        };
+        let key = |c: &Candidate| (c.positions().next().unwrap(), c.length);
+
+        let by_savings = discover(CandidateOrder::SavingsDesc);
+        assert!(by_savings.windows(2).all(|w| {
+            compute_potential_savings(&w[0], 1) >= compute_potential_savings(&w[1], 1)
+        }));
+
+        let by_position = discover(CandidateOrder::PositionAsc);
+        assert_eq!(by_position.len(), by_savings.len());
+        assert!(by_position.windows(2).all(|w| {
+            let (a, b) = (key(&w[0]), key(&w[1]));
+            a.0 < b.0 || (a.0 == b.0 && a.1 >= b.1)
+        }));
+
+        let by_length = discover(CandidateOrder::LengthDesc);
+        assert!(by_length.windows(2).all(|w| {
+            let (a, b) = (key(&w[0]), key(&w[1]));
+            a.1 > b.1 || (a.1 == b.1 && a.0 <= b.0)
+        }));
+
+        // A fixed order gives the same selection every time
+        let first = select_greedy(&by_position, 1).selected;
+        let again = select_greedy(&discover(CandidateOrder::PositionAsc), 1).selected;
+        assert!(!first.is_empty());
+        assert_eq!(first, again);
+    }
 }
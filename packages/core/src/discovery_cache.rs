@@ -0,0 +1,286 @@
+//! Reuse of discovery results across overlapping windows.
+//!
+//! Sliding-window workloads compress consecutive windows that share most
+//! of their content. `DiscoveryCache` remembers the candidates found for
+//! the previous window; when the next window starts with a long enough
+//! suffix of it, only patterns reaching into the new tail are searched for
+//! and the remembered patterns are recounted against the new window.
+
+use crate::discovery::{discover_candidates, order_candidates, CandidateSink, DiscoveryConfig};
+use crate::hash::{PatternMap, PatternSet};
+use crate::suffix_array::non_overlapping_positions_with;
+use crate::types::{is_compressible, Candidate, Pattern, Token};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Default fraction of a window that must be shared with the cached one.
+pub const DEFAULT_MIN_OVERLAP: f64 = 0.5;
+
+/// Candidates discovered for the most recent window.
+///
+/// A pattern of the new window either lies wholly in the shared region,
+/// where it occurs at least as often as in the cached window and so was a
+/// cached candidate, or has an occurrence starting at most
+/// `max_length - 1` tokens before the tail. Recounting both sets yields
+/// the same candidates as a fresh discovery. With `max_candidates` or
+/// `circular` set the first argument does not hold, so every window is
+/// discovered afresh.
+#[derive(Debug, Clone)]
+pub struct DiscoveryCache {
+    config: DiscoveryConfig,
+    min_overlap: f64,
+    window: Vec<Token>,
+    window_hash: u64,
+    candidates: Vec<Candidate>,
+    discovered_tokens: usize,
+}
+
+impl DiscoveryCache {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            min_overlap: DEFAULT_MIN_OVERLAP,
+            window: Vec::new(),
+            window_hash: 0,
+            candidates: Vec::new(),
+            discovered_tokens: 0,
+        }
+    }
+
+    /// Set the fraction of a new window that must overlap the cached one
+    /// before cached candidates are reused.
+    pub fn with_min_overlap(mut self, min_overlap: f64) -> Self {
+        self.min_overlap = min_overlap;
+        self
+    }
+
+    /// Total number of tokens searched for new patterns.
+    pub fn discovered_tokens(&self) -> usize {
+        self.discovered_tokens
+    }
+
+    /// Forget the cached window.
+    pub fn invalidate(&mut self) {
+        self.window.clear();
+        self.window_hash = 0;
+        self.candidates.clear();
+    }
+
+    /// Discover candidates for `tokens`, reusing the cached window if possible.
+    pub fn discover(&mut self, tokens: &[Token]) -> Vec<Candidate> {
+        let hash = content_hash(tokens);
+        if hash == self.window_hash && tokens == self.window.as_slice() {
+            return self.candidates.clone();
+        }
+
+        let reusable = self.config.max_candidates == 0 && !self.config.circular;
+        let candidates = match self.shared_prefix_len(tokens).filter(|_| reusable) {
+            Some(shared) => self.discover_incremental(tokens, shared),
+            None => {
+                self.discovered_tokens += tokens.len();
+                discover_candidates(tokens, &self.config)
+            }
+        };
+
+        self.window = tokens.to_vec();
+        self.window_hash = hash;
+        self.candidates = candidates.clone();
+        candidates
+    }
+
+    /// Length of the longest suffix of the cached window that prefixes
+    /// `tokens`, if it covers at least `min_overlap` of `tokens`.
+    fn shared_prefix_len(&self, tokens: &[Token]) -> Option<usize> {
+        if self.window.is_empty() || tokens.is_empty() {
+            return None;
+        }
+
+        let required = ((tokens.len() as f64 * self.min_overlap).ceil() as usize).max(1);
+        for shift in 0..self.window.len() {
+            let shared = self.window.len() - shift;
+            if shared < required {
+                break;
+            }
+            if shared <= tokens.len() && self.window[shift..] == tokens[..shared] {
+                return Some(shared);
+            }
+        }
+
+        None
+    }
+
+    fn discover_incremental(&mut self, tokens: &[Token], shared: usize) -> Vec<Candidate> {
+        // Every occurrence reaching into the tail starts in this window
+        let tail_start = shared.saturating_sub(self.config.max_length.saturating_sub(1));
+        let tail = &tokens[tail_start..];
+        self.discovered_tokens += tail.len();
+
+        let mut patterns: PatternSet<Pattern> = PatternSet::default();
+        patterns.extend(self.candidates.iter().map(|c| c.subsequence.clone()));
+        let arena = Pattern::from_slice(tail);
+        let multiple = self.config.length_multiple_of.max(1);
+        for length in self.config.min_length..=self.config.max_length.min(tail.len()) {
+            if length % multiple == 0 {
+                let windows = 0..=tail.len() - length;
+                patterns.extend(windows.map(|start| arena.slice(start..start + length)));
+            }
+        }
+
+        let mut patterns: Vec<Pattern> = patterns.into_iter().collect();
+        patterns.sort_unstable();
+        count_candidates(tokens, patterns, &self.config)
+    }
+}
+
+/// Locate every pattern in `tokens` and keep the compressible ones.
+fn count_candidates(
+    tokens: &[Token],
+    patterns: Vec<Pattern>,
+    config: &DiscoveryConfig,
+) -> Vec<Candidate> {
+    let mut by_length: PatternMap<usize, PatternMap<Pattern, Vec<usize>>> = PatternMap::default();
+    for pattern in &patterns {
+        by_length
+            .entry(pattern.len())
+            .or_default()
+            .insert(pattern.clone(), Vec::new());
+    }
+
+    for (&length, positions) in by_length.iter_mut() {
+        if length == 0 || length > tokens.len() {
+            continue;
+        }
+        for (i, window) in tokens.windows(length).enumerate() {
+            if let Some(found) = positions.get_mut(window) {
+                found.push(i);
+            }
+        }
+    }
+
+    let mut sink = CandidateSink::new(config.max_candidates, config.extra_cost);
+    for pattern in patterns {
+        let length = pattern.len();
+        let positions = match by_length.get(&length).and_then(|p| p.get(&pattern)) {
+            Some(found) => non_overlapping_positions_with(found, length, config.non_overlap),
+            None => continue,
+        };
+
+        let min_count = config.min_occurrences.min_count(length, config.extra_cost);
+        if positions.len() < min_count
+            || !is_compressible(length, positions.len(), config.extra_cost)
+        {
+            continue;
+        }
+
+        sink.push(Candidate::new(pattern, positions));
+    }
+
+    let mut candidates = sink.into_sorted_vec();
+    order_candidates(&mut candidates, config.candidate_order);
+    candidates
+}
+
+fn content_hash(tokens: &[Token]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tokens.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sliding_windows() -> (Vec<Token>, Vec<Token>) {
+        let mut stream: Vec<Token> = Vec::new();
+        for i in 0..30 {
+            stream.extend([1, 2, 3, 4]);
+            stream.push(100 + i);
+            stream.extend([5, 6, 7]);
+        }
+        (stream[0..160].to_vec(), stream[40..200].to_vec())
+    }
+
+    /// Candidates sorted by pattern; ties in savings may come back in a
+    /// different order from the two paths.
+    fn by_pattern(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+        candidates.sort_by(|a, b| a.subsequence.cmp(&b.subsequence));
+        candidates
+    }
+
+    #[test]
+    fn test_reuses_overlapping_window() {
+        let (first, second) = sliding_windows();
+        let mut cache = DiscoveryCache::new(DiscoveryConfig::default());
+
+        cache.discover(&first);
+        let after_first = cache.discovered_tokens();
+        let cached = cache.discover(&second);
+        let fresh = discover_candidates(&second, &DiscoveryConfig::default());
+
+        assert_eq!(after_first, first.len());
+        assert!(cache.discovered_tokens() - after_first < second.len());
+
+        assert_eq!(by_pattern(cached), by_pattern(fresh));
+    }
+
+    #[test]
+    fn test_finds_patterns_straddling_the_tail() {
+        // 50..58 occurs once in the shared region and once across the
+        // start of the new tail, and nowhere else
+        let pattern: Vec<Token> = (50..58).collect();
+        let mut stream: Vec<Token> = (1000..1100).collect();
+        stream[20..28].copy_from_slice(&pattern);
+        stream[96..].copy_from_slice(&pattern[..4]);
+        stream.extend(&pattern[4..]);
+        stream.extend(2000..2040);
+        let (first, second) = (&stream[..100], &stream[10..]);
+        let mut cache = DiscoveryCache::new(DiscoveryConfig::default());
+
+        cache.discover(first);
+        let cached = cache.discover(second);
+        let fresh = discover_candidates(second, &DiscoveryConfig::default());
+
+        assert!(fresh.iter().any(|c| c.subsequence() == pattern));
+        assert_eq!(by_pattern(cached), by_pattern(fresh));
+        assert!(cache.discovered_tokens() < first.len() + second.len());
+    }
+
+    #[test]
+    fn test_top_n_rediscovers() {
+        let (first, second) = sliding_windows();
+        let config = DiscoveryConfig {
+            max_candidates: 3,
+            ..Default::default()
+        };
+        let mut cache = DiscoveryCache::new(config.clone());
+
+        cache.discover(&first);
+        let cached = cache.discover(&second);
+
+        assert_eq!(cached, discover_candidates(&second, &config));
+        assert_eq!(cache.discovered_tokens(), first.len() + second.len());
+    }
+
+    #[test]
+    fn test_small_overlap_rediscovers() {
+        let (first, second) = sliding_windows();
+        let mut cache = DiscoveryCache::new(DiscoveryConfig::default()).with_min_overlap(0.9);
+
+        cache.discover(&first);
+        cache.discover(&second);
+
+        assert_eq!(cache.discovered_tokens(), first.len() + second.len());
+    }
+
+    #[test]
+    fn test_identical_window_hits_cache() {
+        let (first, _) = sliding_windows();
+        let mut cache = DiscoveryCache::new(DiscoveryConfig::default());
+
+        let fresh = cache.discover(&first);
+        let cached = cache.discover(&first);
+
+        assert_eq!(fresh, cached);
+        assert_eq!(cache.discovered_tokens(), first.len());
+    }
+}
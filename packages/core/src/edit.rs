@@ -0,0 +1,283 @@
+//! In-place edits of compressed output.
+//!
+//! An edit replaces a range of the original tokens. Only the body units
+//! covering that range are decoded; the edited region is recompressed on
+//! its own and spliced back between the untouched units, with any new
+//! dictionary entries appended to the existing dictionary. Results are
+//! assumed to number meta-tokens from `DEFAULT_NEXT_META_TOKEN`, as
+//! `compress_with_hints` and `compress_with_cache` do.
+
+use crate::dictionary::{
+    body_unit_len, decode_body, is_reserved_token, ROTATION_HEADER_TOKEN,
+};
+use crate::error::CompressionError;
+use crate::hash::hash_tokens;
+use crate::types::{CompressionConfig, CompressionResult, Token};
+use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Replace `edit_len` original tokens at `edit_start` with `new_tokens`.
+///
+/// Results without a dictionary, and rings compressed from a rotation
+/// (see `circular`), are recompressed whole. Otherwise the edited region
+/// is recompressed with new meta-tokens continuing after the existing
+/// ones, within what remains of `meta_token_pool_size`; when the pool is
+/// used up, or the region holds tokens that would need escaping, it is
+/// written as literals. Existing entries are kept even if the edit
+/// removed their last use.
+///
+/// Fails with `InvalidConfig` on compressed channel results (see
+/// `channels`), whose body does not follow the original token order.
+pub fn apply_edit(
+    result: &CompressionResult,
+    edit_start: usize,
+    edit_len: usize,
+    new_tokens: &[Token],
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let edit_end = edit_start.saturating_add(edit_len);
+    if edit_end > result.original_length {
+        return Err(CompressionError::InvalidConfig(format!(
+            "edit {}..{} is outside the {} original tokens",
+            edit_start, edit_end, result.original_length
+        )));
+    }
+
+    let mut original = result.original_tokens.clone();
+    original.splice(edit_start..edit_end, new_tokens.iter().copied());
+    // A rotated ring's body does not follow the original token order
+    let header = result.dictionary_tokens.first();
+    let rotated = config.circular && header == Some(&ROTATION_HEADER_TOKEN);
+    if result.dictionary_map.is_empty() || result.body_tokens.is_empty() || rotated {
+        return compress_internal(&original, config, DEFAULT_NEXT_META_TOKEN);
+    }
+    if config.channels > 1 {
+        return Err(CompressionError::InvalidConfig(
+            "channel bodies cannot be edited in place".to_string(),
+        ));
+    }
+
+    // Units covering the edit; an insertion falls in the unit it precedes
+    let units = body_units(&result.body_tokens, &result.dictionary_map, config);
+    let touches = |output: &Range<usize>| {
+        output.start < edit_end.max(edit_start + 1) && edit_start < output.end
+    };
+    let first = units
+        .iter()
+        .position(|(_, output)| touches(output))
+        .unwrap_or(units.len() - 1);
+    let last = units
+        .iter()
+        .rposition(|(_, output)| touches(output))
+        .unwrap_or(first);
+    let body_range = units[first].0.start..units[last].0.end;
+    let output_start = units[first].1.start;
+
+    let mut region = decode_body(
+        &result.body_tokens[body_range.clone()],
+        &result.dictionary_map,
+        config,
+    );
+    let offset = edit_start - output_start;
+    region.splice(offset..offset + edit_len, new_tokens.iter().copied());
+
+    let (region_body, new_entries) = recompress_region(&region, result, config)?;
+
+    let mut body = result.body_tokens[..body_range.start].to_vec();
+    body.extend(region_body);
+    body.extend_from_slice(&result.body_tokens[body_range.end..]);
+
+    let mut dictionary_tokens = result.dictionary_tokens.clone();
+    let mut dictionary_map = result.dictionary_map.clone();
+    if let Some(entries) = new_entries {
+        // Append the region's entries before the existing end delimiter
+        if let Some(pos) = dictionary_tokens
+            .iter()
+            .rposition(|&t| t == config.dict_end_token)
+        {
+            dictionary_tokens.truncate(pos);
+        }
+        let section = &entries.dictionary_tokens;
+        let start = section
+            .iter()
+            .position(|&t| t != config.dict_start_token)
+            .unwrap_or(0);
+        dictionary_tokens.extend_from_slice(&section[start..]);
+        dictionary_map.extend(entries.dictionary_map);
+    }
+
+    let mut serialized = dictionary_tokens.clone();
+    serialized.extend(&body);
+
+    Ok(CompressionResult {
+        original_hash: hash_tokens(&original),
+        original_length: original.len(),
+        original_tokens: original,
+        compressed_length: serialized.len(),
+        serialized_tokens: serialized,
+        dictionary_tokens,
+        body_tokens: body,
+        dictionary_map,
+        static_dictionary_id: result.static_dictionary_id.clone(),
+        reason: None,
+        selection_trace: None,
+    })
+}
+
+/// Each body unit's range in the body and in the decoded output.
+fn body_units(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut expanded_len: HashMap<Token, usize> = HashMap::new();
+    let mut units = Vec::new();
+    let mut pos = 0;
+    let mut output = 0;
+
+    while pos < body.len() {
+        let end = pos + body_unit_len(body, pos, dictionary, config);
+        let unit = &body[pos..end];
+        let len = match unit {
+            [token] if dictionary.contains_key(token) => *expanded_len
+                .entry(*token)
+                .or_insert_with(|| decode_body(unit, dictionary, config).len()),
+            _ => decode_body(unit, dictionary, config).len(),
+        };
+        units.push((pos..end, output..output + len));
+        pos = end;
+        output += len;
+    }
+
+    units
+}
+
+/// Body for an edited region, plus the result holding its new entries.
+fn recompress_region(
+    region: &[Token],
+    result: &CompressionResult,
+    config: &CompressionConfig,
+) -> Result<(Vec<Token>, Option<CompressionResult>), CompressionError> {
+    let needs_escape = |t: Token| {
+        is_reserved_token(t, config, DEFAULT_NEXT_META_TOKEN)
+            || (config.body_runs && t == config.literal_marker_token)
+            || (config.detect_mirrored && t == config.reverse_marker_token)
+    };
+    let pool_left = config
+        .meta_token_pool_size
+        .saturating_sub(result.dictionary_map.len());
+
+    if pool_left > 0 && !region.iter().any(|&t| needs_escape(t)) {
+        let region_config = CompressionConfig {
+            meta_token_pool_size: pool_left,
+            always_framed: false,
+            circular: false,
+            ..config.clone()
+        };
+        let direction = config.meta_token_direction;
+        let used = result.dictionary_map.len();
+        let next_meta_token = direction.step(DEFAULT_NEXT_META_TOKEN, used).ok_or_else(|| {
+            CompressionError::InvalidConfig(format!("no meta-token ids remain after {}", used))
+        })?;
+        let compressed = compress_internal(region, &region_config, next_meta_token)?;
+
+        if compressed.dictionary_map.is_empty() {
+            return Ok((compressed.body_tokens, None));
+        }
+        if compressed.dictionary_map.len() <= pool_left {
+            return Ok((compressed.body_tokens.clone(), Some(compressed)));
+        }
+    }
+
+    // Literal runs are copied verbatim; elsewhere reserved literals are escaped
+    if config.body_runs {
+        let mut body = vec![config.literal_marker_token, region.len() as Token];
+        body.extend_from_slice(region);
+        return Ok((body, None));
+    }
+    let mut body = Vec::with_capacity(region.len());
+    for &literal in region {
+        if needs_escape(literal) {
+            body.push(config.escape_token);
+        }
+        body.push(literal);
+    }
+    Ok((body, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress_internal;
+
+    fn document() -> Vec<Token> {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..30 {
+            tokens.extend([1, 2, 3, 4, 100 + i, 5, 6, 7]);
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_edit_middle_of_compressed_document() {
+        let tokens = document();
+        let config = CompressionConfig::default();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(!result.dictionary_map.is_empty());
+
+        let edits: [(usize, usize, Vec<Token>); 4] = [
+            (120, 3, vec![900, 901, 902, 903]),
+            (118, 10, vec![]),
+            (
+                121,
+                0,
+                vec![1, 2, 3, 4, 5, 6, 7, 1, 2, 3, 4, 5, 6, 7, 1, 2, 3, 4],
+            ),
+            (0, 1, vec![DEFAULT_NEXT_META_TOKEN]),
+        ];
+        for (start, len, new_tokens) in edits {
+            let edited = apply_edit(&result, start, len, &new_tokens, &config).unwrap();
+
+            let mut expected = tokens.clone();
+            expected.splice(start..start + len, new_tokens.iter().copied());
+            assert_eq!(edited.original_tokens, expected);
+            assert_eq!(edited.original_length, expected.len());
+            let decoded =
+                decompress_internal(&edited.serialized_tokens, &config, DEFAULT_NEXT_META_TOKEN)
+                    .unwrap();
+            assert_eq!(decoded, expected);
+
+            // Units well before and after the edit are left untouched
+            if start > 0 {
+                let (body, edited_body) = (&result.body_tokens, &edited.body_tokens);
+                assert_eq!(edited_body[..10], body[..10]);
+                assert_eq!(
+                    edited_body[edited_body.len() - 10..],
+                    body[body.len() - 10..]
+                );
+            }
+        }
+
+        assert!(apply_edit(&result, tokens.len(), 1, &[], &config).is_err());
+    }
+
+    #[test]
+    fn test_edit_in_run_encoded_body() {
+        let tokens = document();
+        let config = CompressionConfig {
+            body_runs: true,
+            ..Default::default()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        let edited =
+            apply_edit(&result, 60, 2, &[config.literal_marker_token, 8], &config).unwrap();
+        let mut expected = tokens.clone();
+        expected.splice(60..62, [config.literal_marker_token, 8]);
+        let decoded =
+            decompress_internal(&edited.serialized_tokens, &config, DEFAULT_NEXT_META_TOKEN)
+                .unwrap();
+        assert_eq!(decoded, expected);
+    }
+}
@@ -0,0 +1,164 @@
+//! Token equivalence classes for pattern discovery.
+//!
+//! Tokens in one class, such as separators that mean the same thing in
+//! different contexts, are replaced by the class representative (its first
+//! token) before discovery, so a pattern can match across members. Where a
+//! pattern's occurrences hold different members, the definition holds
+//! `gap_token` and each occurrence keeps its own member as a patch, written
+//! to the body after the meta-token like a skip-gram gap filler.
+
+use crate::discovery::{try_discover_candidates, DiscoveryConfig};
+use crate::error::CompressionError;
+use crate::hash::PatternMap;
+use crate::types::{Candidate, CompressionConfig, Patch, Token};
+use std::collections::HashMap;
+
+/// Replace every class member with its class representative.
+///
+/// A token listed in several classes takes the representative of the
+/// first. Tokens outside every class are left unchanged.
+pub fn canonicalize(tokens: &[Token], classes: &[Vec<Token>]) -> Vec<Token> {
+    let mut representatives: PatternMap<Token, Token> = PatternMap::default();
+    for class in classes {
+        if let Some(&representative) = class.first() {
+            for &member in class {
+                representatives.entry(member).or_insert(representative);
+            }
+        }
+    }
+
+    tokens
+        .iter()
+        .map(|t| representatives.get(t).copied().unwrap_or(*t))
+        .collect()
+}
+
+/// Discover candidates over canonicalized tokens, restoring the originals.
+///
+/// Returns `OutOfMemory` as `try_discover_candidates` does.
+pub fn discover_with_classes(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    discovery_config: &DiscoveryConfig,
+) -> Result<Vec<Candidate>, CompressionError> {
+    let canonical = canonicalize(tokens, &config.equivalence_classes);
+    let candidates = try_discover_candidates(&canonical, discovery_config)?;
+    Ok(candidates
+        .into_iter()
+        .filter_map(|c| {
+            restore_candidate(tokens, &c, config.gap_token, discovery_config.extra_cost)
+        })
+        .collect())
+}
+
+/// Rewrite a candidate found in canonical tokens against the originals.
+///
+/// Positions whose original tokens agree keep them in the definition; the
+/// rest become gaps filled per occurrence. Returns `None` when the
+/// fillers cost more than the pattern saves.
+fn restore_candidate(
+    tokens: &[Token],
+    candidate: &Candidate,
+    gap_token: Token,
+    extra_cost: usize,
+) -> Option<Candidate> {
+    let positions: Vec<usize> = candidate.positions().collect();
+    let length = candidate.length;
+    let first = *positions.first()?;
+
+    let mut definition = tokens[first..first + length].to_vec();
+    if definition.contains(&gap_token) {
+        return None;
+    }
+    let gap_indices: Vec<usize> = (0..length)
+        .filter(|&i| {
+            positions
+                .iter()
+                .any(|&pos| tokens[pos + i] != definition[i])
+        })
+        .collect();
+    if gap_indices.is_empty() {
+        return Some(Candidate::new(definition, positions));
+    }
+
+    // Each occurrence costs its meta-token plus one token per gap
+    let count = positions.len();
+    let original = length * count;
+    let compressed = count * (1 + gap_indices.len()) + 1 + extra_cost + length;
+    if length - gap_indices.len() < 2 || original <= compressed {
+        return None;
+    }
+
+    let patches: HashMap<usize, Vec<Patch>> = positions
+        .iter()
+        .map(|&pos| {
+            let patches = gap_indices
+                .iter()
+                .map(|&index| Patch {
+                    index,
+                    token: tokens[pos + index],
+                })
+                .collect();
+            (pos, patches)
+        })
+        .collect();
+    for &index in &gap_indices {
+        definition[index] = gap_token;
+    }
+
+    let mut restored = Candidate::new(definition, positions);
+    restored.patches = patches;
+    Some(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+
+    #[test]
+    fn test_canonicalize() {
+        let classes = vec![vec![7, 8, 9], vec![]];
+        assert_eq!(canonicalize(&[1, 8, 9, 7, 2], &classes), [1, 7, 7, 7, 2]);
+    }
+
+    #[test]
+    fn test_equivalent_separators_share_pattern() {
+        // One record uses a rarer separator meaning the same thing
+        let (comma, semicolon) = (44, 59);
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..12 {
+            let separator = if i == 5 { semicolon } else { comma };
+            tokens.extend([10, 11, separator, 12, 13, 1000 + i]);
+        }
+
+        let plain = CompressionConfig {
+            min_subsequence_length: 5,
+            max_subsequence_length: 5,
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+        let classes = CompressionConfig {
+            equivalence_classes: vec![vec![comma, semicolon]],
+            verify_full: true,
+            ..plain.clone()
+        };
+
+        let without = compress_internal(&tokens, &plain, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let with = compress_internal(&tokens, &classes, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        let gap = classes.gap_token;
+        let spanning = [10, 11, gap, 12, 13];
+        assert_eq!(with.dictionary_map.len(), 1);
+        assert!(with.dictionary_map.values().all(|def| def[..] == spanning));
+        let is_meta = |t: &&Token| with.dictionary_map.contains_key(t);
+        assert_eq!(with.body_tokens.iter().filter(is_meta).count(), 12);
+        assert!(!without.body_tokens.contains(&gap));
+        assert!(without.body_tokens.contains(&semicolon));
+
+        let restored =
+            decompress_internal(&with.serialized_tokens, &classes, DEFAULT_NEXT_META_TOKEN)
+                .unwrap();
+        assert_eq!(restored, tokens);
+    }
+}
@@ -0,0 +1,75 @@
+//! Error types for the compression pipeline.
+//!
+//! Native entry points return `CompressionError`; the WASM bindings convert
+//! it into a `JsValue` carrying the display message.
+
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Errors produced by compression and decompression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionError {
+    /// An occurrence hint does not describe the input.
+    InvalidHint {
+        /// Index of the offending hint
+        hint: usize,
+        /// Why the hint was rejected
+        reason: String,
+    },
+    /// Incremental or round-trip verification found output that would not
+    /// reproduce the input.
+    VerificationFailed,
+    /// A configuration value is out of range.
+    InvalidConfig(String),
+    /// The serialized dictionary section cannot be decoded.
+    MalformedDictionary(String),
+    /// The body references meta-tokens the dictionary does not define.
+    UnresolvedMetaTokens(Vec<u32>),
+    /// A decoded token is not a Unicode scalar value, so the output is
+    /// not text.
+    InvalidCharacter(u32),
+    /// The stream does not parse under the given config but looks like it
+    /// was written with a different setting, named in the message.
+    LikelyConfigMismatch(String),
+    /// A suffix array or discovery buffer could not be allocated.
+    OutOfMemory,
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::InvalidHint { hint, reason } => {
+                write!(f, "Invalid occurrence hint {}: {}", hint, reason)
+            }
+            CompressionError::VerificationFailed => {
+                write!(f, "Compression verification failed: round-trip mismatch")
+            }
+            CompressionError::InvalidConfig(reason) => {
+                write!(f, "Invalid configuration: {}", reason)
+            }
+            CompressionError::MalformedDictionary(reason) => {
+                write!(f, "Malformed dictionary: {}", reason)
+            }
+            CompressionError::UnresolvedMetaTokens(tokens) => {
+                write!(f, "Unresolved meta-tokens: {:?}", tokens)
+            }
+            CompressionError::InvalidCharacter(token) => {
+                write!(f, "Token {} is not a Unicode scalar value", token)
+            }
+            CompressionError::LikelyConfigMismatch(hint) => {
+                write!(f, "Likely config mismatch: {}", hint)
+            }
+            CompressionError::OutOfMemory => {
+                write!(f, "Out of memory: input too large to compress")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {}
+
+impl From<CompressionError> for JsValue {
+    fn from(error: CompressionError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
@@ -0,0 +1,50 @@
+//! Hash maps used for internal pattern bookkeeping, and a stable hash of
+//! token sequences.
+//!
+//! Keys are token patterns and positions taken from the input being
+//! compressed, never user-chosen map keys, so DoS resistance buys little.
+//! With the `fast-hash` feature these maps use FxHash instead of SipHash.
+//!
+//! Code that iterates these maps must not let iteration order leak into the
+//! output; sort with a full tie-break where order matters.
+
+use crate::types::Token;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "fast-hash")]
+pub type PatternHasher = std::hash::BuildHasherDefault<rustc_hash::FxHasher>;
+
+#[cfg(not(feature = "fast-hash"))]
+pub type PatternHasher = std::collections::hash_map::RandomState;
+
+/// Name of the hasher selected at compile time, for labelling benchmarks.
+#[cfg(feature = "fast-hash")]
+pub const PATTERN_HASHER: &str = "fxhash";
+
+/// Name of the hasher selected at compile time, for labelling benchmarks.
+#[cfg(not(feature = "fast-hash"))]
+pub const PATTERN_HASHER: &str = "siphash";
+
+/// Hash map with the crate's pattern hasher.
+pub type PatternMap<K, V> = HashMap<K, V, PatternHasher>;
+
+/// Hash set with the crate's pattern hasher.
+pub type PatternSet<K> = HashSet<K, PatternHasher>;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hash of `tokens`, fed each token's little-endian bytes.
+///
+/// Unlike the map hashers it is fixed across runs, platforms and features,
+/// so a value stored alongside compressed output can check a later decode.
+pub fn hash_tokens(tokens: &[Token]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for token in tokens {
+        for byte in token.to_le_bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
@@ -0,0 +1,185 @@
+//! Pipeline invariant checks enabled by `CompressionConfig::debug_checks`.
+//!
+//! Each check panics at the point of violation, naming the invariant, so
+//! a compression bug surfaces where it happens rather than as a wrong
+//! result further down the pipeline.
+
+use crate::dictionary::{unresolved_meta_tokens, Dictionary};
+use crate::types::{CompressionConfig, Occurrence, Token};
+use std::collections::HashMap;
+
+/// Selected occurrences lie within the input and do not overlap.
+pub fn check_selection(selected: &[Occurrence], original_len: usize) {
+    let mut spans: Vec<(usize, usize)> =
+        selected.iter().map(|occ| (occ.start, occ.end())).collect();
+    spans.sort_unstable();
+
+    for &(start, end) in &spans {
+        if end > original_len {
+            panic!(
+                "invariant violated: selected occurrence {}..{} is past input end {}",
+                start, end, original_len
+            );
+        }
+    }
+    for pair in spans.windows(2) {
+        if pair[1].0 < pair[0].1 {
+            panic!(
+                "invariant violated: selected occurrences {}..{} and {}..{} overlap",
+                pair[0].0, pair[0].1, pair[1].0, pair[1].1
+            );
+        }
+    }
+}
+
+/// Every pattern's meta-token has a dictionary entry for that pattern.
+pub fn check_dictionary(dict: &Dictionary) {
+    for (pattern, meta) in &dict.pattern_to_meta {
+        if dict.entries.get(meta) != Some(pattern) {
+            panic!(
+                "invariant violated: meta-token {} has no dictionary entry for {:?}",
+                meta, pattern
+            );
+        }
+    }
+}
+
+/// The body is the input with each occurrence shortened to its meta-token
+/// and gap fillers, plus one escape per escaped literal.
+pub fn check_body_length(
+    body: &[Token],
+    selected: &[Occurrence],
+    original_len: usize,
+    escape_token: Token,
+) {
+    let mut escapes = 0;
+    let mut pos = 0;
+    while pos < body.len() {
+        if body[pos] == escape_token {
+            escapes += 1;
+            pos += 1;
+        }
+        pos += 1;
+    }
+
+    let covered: usize = selected.iter().map(|occ| occ.length - 1).sum();
+    let fillers: usize = selected.iter().map(|occ| occ.patches.len()).sum();
+    let expected = (original_len + fillers + escapes).saturating_sub(covered);
+    if body.len() != expected {
+        panic!(
+            "invariant violated: body has {} tokens, expected {} for {} selections",
+            body.len(),
+            expected,
+            selected.len()
+        );
+    }
+}
+
+/// Every meta-token referenced by the body of `serialized` is defined.
+pub fn check_meta_tokens_defined(
+    serialized: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) {
+    let unresolved = unresolved_meta_tokens(serialized, dictionary, config, next_meta_token);
+    if !unresolved.is_empty() {
+        panic!(
+            "invariant violated: body references undefined meta-tokens {:?}",
+            unresolved
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::{build_body, build_dictionary};
+    use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+    fn occurrence(pattern: &[Token], start: usize) -> Occurrence {
+        Occurrence {
+            start,
+            length: pattern.len(),
+            subsequence: pattern.into(),
+            priority: 0,
+            patches: vec![],
+        }
+    }
+
+    fn tokens() -> Vec<Token> {
+        [1, 2, 3, 9, 1, 2, 3, 8, 1, 2, 3].to_vec()
+    }
+
+    #[test]
+    fn test_debug_checks_pass_on_valid_pipeline() {
+        let mut escaped = [4, 5, 6, 7].repeat(12);
+        escaped.insert(5, DEFAULT_NEXT_META_TOKEN);
+        let gapped: Vec<Token> = (0..8).flat_map(|i| [10, 100 + i, 20, 500 + i]).collect();
+        let inputs = [tokens(), escaped, gapped, (0..50).collect()];
+
+        for max_gaps in [0, 1] {
+            let config = CompressionConfig {
+                debug_checks: true,
+                max_gaps,
+                ..Default::default()
+            };
+            for input in &inputs {
+                compress_internal(input, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "invariant violated: selected occurrences 0..3 and 2..5 overlap")]
+    fn test_check_selection_catches_overlap() {
+        check_selection(&[occurrence(&[1, 2, 3], 0), occurrence(&[3, 9, 1], 2)], 11);
+    }
+
+    #[test]
+    #[should_panic(expected = "selected occurrence 10..13 is past input end 11")]
+    fn test_check_selection_catches_out_of_bounds() {
+        check_selection(&[occurrence(&[1, 2, 3], 10)], 11);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no dictionary entry for [1, 2, 3]")]
+    fn test_check_dictionary_catches_missing_entry() {
+        let config = CompressionConfig::default();
+        let selected = [occurrence(&[1, 2, 3], 0), occurrence(&[1, 2, 3], 4)];
+        let mut dict = build_dictionary(&selected, &config, DEFAULT_NEXT_META_TOKEN);
+        dict.entries.clear();
+        check_dictionary(&dict);
+    }
+
+    #[test]
+    #[should_panic(expected = "invariant violated: body has 7 tokens, expected 5")]
+    fn test_check_body_length_catches_dropped_occurrence() {
+        let config = CompressionConfig::default();
+        let selected: Vec<Occurrence> = [0, 4, 8]
+            .iter()
+            .map(|&start| occurrence(&[1, 2, 3], start))
+            .collect();
+        let dict = build_dictionary(&selected, &config, DEFAULT_NEXT_META_TOKEN);
+        let body = build_body(
+            &tokens(),
+            &selected[..2],
+            &dict.pattern_to_meta,
+            config.escape_token,
+        );
+        check_body_length(&body, &selected, tokens().len(), config.escape_token);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invariant violated: body references undefined meta-tokens [4294901761]"
+    )]
+    fn test_check_meta_tokens_defined_catches_undefined_reference() {
+        let config = CompressionConfig::default();
+        let selected = [occurrence(&[1, 2, 3], 0), occurrence(&[1, 2, 3], 4)];
+        let dict = build_dictionary(&selected, &config, DEFAULT_NEXT_META_TOKEN);
+        let mut serialized = dict.tokens.clone();
+        serialized.extend([DEFAULT_NEXT_META_TOKEN, 9, DEFAULT_NEXT_META_TOKEN + 1]);
+        check_meta_tokens_defined(&serialized, &dict.entries, &config, DEFAULT_NEXT_META_TOKEN);
+    }
+}
@@ -14,21 +14,76 @@
 //! const result = await compress(tokens);
 //! const restored = await decompress(result.serializedTokens);
 //! ```
-
+//!
+//! # Degenerate inputs
+//!
+//! Every entry point accepts empty and very short inputs. Inputs too short
+//! to contain a compressible pattern (fewer than two copies of the minimum
+//! pattern length) are returned unchanged by `compress` and
+//! `StreamingCompressor::finish`, `decompress` returns a stream without a
+//! dictionary as-is, and `discover_patterns` returns no patterns. Only
+//! invalid pattern length bounds are reported as errors.
+
+pub mod analysis;
+pub mod batch;
+pub mod channels;
 pub mod config;
 pub mod dictionary;
 pub mod discovery;
+pub mod discovery_cache;
+pub mod edit;
+pub mod equivalence;
+pub mod error;
+pub mod hash;
+pub mod invariants;
+pub mod mirror;
+pub mod python_compat;
+pub mod python_parity;
 pub mod selection;
+pub mod skipgram;
+pub mod sliding;
+pub mod sniff;
+pub mod spans;
+pub mod static_dictionary;
 pub mod suffix_array;
 #[cfg(feature = "parallel")]
 pub mod suffix_array_parallel;
+pub mod text;
 pub mod types;
 
+use channels::{compress_channels, interleave_tail};
 use config::JsCompressionConfig;
-use dictionary::{build_body, build_dictionary, decompress as dict_decompress, parse_dictionary, serialize_result};
-use discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
-use selection::select_occurrences;
-use types::{CompressionConfig, CompressionResult, Token};
+use dictionary::{
+    build_body, build_body_with, build_dictionary, compute_serialized_size,
+    decompress as dict_decompress, decompress_into as dict_decompress_into, encode_body_runs,
+    ensure_ids_fit, frame_uncompressed, is_raw_reserved, is_reserved_token, meta_token_pool,
+    parse_dictionary, serialize_result, split_frames, trim_onto_dictionary, trim_to_dict_fraction,
+    trim_to_pool, unresolved_meta_tokens, verify_replacements, DelimiterHeader, Dictionary,
+    DELIMITER_HEADER_TOKEN, ROTATION_HEADER_TOKEN,
+};
+use discovery::{
+    candidates_from_hints, deduplicate_candidates, discover_candidates, discover_with_suffix_array,
+    exclude_blacklisted, retain_single_type, try_discover_candidates, DiscoveryConfig,
+};
+use discovery_cache::DiscoveryCache;
+use equivalence::discover_with_classes;
+use error::CompressionError;
+use hash::hash_tokens;
+use invariants::{
+    check_body_length, check_dictionary, check_meta_tokens_defined, check_selection,
+};
+use mirror::{build_body_mirrored, discover_mirrored, MirroredCandidate};
+use selection::{
+    cap_occurrences, prefer_aligned, run_super_candidates, select_occurrences_traced,
+    select_occurrences_with, DefaultScorer, SelectionTrace,
+};
+use skipgram::{discover_skip_grams, retain_gap_free, SkipGramConfig};
+use std::collections::HashSet;
+use suffix_array::SuffixArray;
+use types::{
+    Candidate, CompressionConfig, CompressionResult, MetaTokenDirection, NoCompressionReason,
+    Occurrence, Token,
+};
 use wasm_bindgen::prelude::*;
 
 /// Initialize panic hook for better error messages in WASM.
@@ -57,129 +112,759 @@ pub fn compress(tokens: &[u32], config: JsValue) -> Result<CompressionResult, Js
     };
 
     let compression_config = js_config.merge_with_defaults();
-    let next_meta_token = js_config.next_meta_token.unwrap_or(0xFFFF0000);
+    let next_meta_token = js_config.next_meta_token.unwrap_or(DEFAULT_NEXT_META_TOKEN);
+
+    compress_internal(tokens, &compression_config, next_meta_token).map_err(JsValue::from)
+}
 
-    compress_internal(tokens, &compression_config, next_meta_token)
+/// Native counterpart of `compress`, with the default first meta-token.
+///
+/// Returns `OutOfMemory` rather than aborting when a suffix array cannot
+/// be built, for the input or for the body a hierarchical pass searches.
+pub fn compress_tokens(
+    tokens: &[Token],
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    compress_internal(tokens, config, DEFAULT_NEXT_META_TOKEN)
 }
 
+/// First meta-token ID assigned when the caller does not provide one.
+pub const DEFAULT_NEXT_META_TOKEN: Token = 0xFFFF0000;
+
+/// Default `max_single_pass_length`: about 4M tokens, whose suffix array
+/// and LCP arrays stay well within wasm32 memory.
+pub const DEFAULT_MAX_SINGLE_PASS_LENGTH: usize = 1 << 22;
+
 /// Internal compression implementation.
 fn compress_internal(
     tokens: &[Token],
     config: &CompressionConfig,
     next_meta_token: Token,
-) -> Result<CompressionResult, JsValue> {
+) -> Result<CompressionResult, CompressionError> {
+    if config.circular {
+        return compress_circular(tokens, config, next_meta_token);
+    }
+    if tokens.len() > config.max_single_pass_length {
+        return compress_segmented(tokens, config, next_meta_token);
+    }
+    if config.auto_delimiters {
+        if let Some(header) = DelimiterHeader::above(tokens, config) {
+            let compress = |config: &_, next| compress_unframed(tokens, config, next);
+            return compress_auto_delimited(tokens, config, header, compress);
+        }
+    }
+    let result = compress_unframed(tokens, config, next_meta_token)?;
+    Ok(frame_if_required(result, config, next_meta_token))
+}
+
+/// Compress the input as a ring, opened where `ring_cut` says.
+///
+/// The rotated input is compressed as linear input behind a rotation
+/// header (see `ROTATION_HEADER_TOKEN`), kept only when shorter than
+/// compressing the input as is.
+fn compress_circular(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<CompressionResult, CompressionError> {
+    let linear_config = CompressionConfig {
+        circular: false,
+        ..config.clone()
+    };
+    let linear = compress_internal(tokens, &linear_config, next_meta_token)?;
+    let linear = frame_if_required(linear, config, next_meta_token);
+    let Some(cut) = ring_cut(tokens, config)? else {
+        return Ok(linear);
+    };
+
+    let rotated = [&tokens[cut..], &tokens[..cut]].concat();
+    let result = compress_internal(&rotated, &linear_config, next_meta_token)?;
+    let header = [ROTATION_HEADER_TOKEN, cut as Token];
+    if result.compressed_length + header.len() >= linear.compressed_length {
+        return Ok(linear);
+    }
+
+    let mut dictionary_tokens = header.to_vec();
+    dictionary_tokens.extend(&result.dictionary_tokens);
+    let mut serialized_tokens = header.to_vec();
+    serialized_tokens.extend(&result.serialized_tokens);
+    Ok(CompressionResult {
+        original_hash: hash_tokens(tokens),
+        original_tokens: tokens.to_vec(),
+        compressed_length: serialized_tokens.len(),
+        serialized_tokens,
+        dictionary_tokens,
+        ..result
+    })
+}
+
+/// Where to open the ring: just past the wrapped occurrence of the
+/// candidate saving the most, so that occurrence lies within the rotated
+/// input. `None` when no candidate wraps.
+fn ring_cut(
+    tokens: &[Token],
+    config: &CompressionConfig,
+) -> Result<Option<usize>, CompressionError> {
+    if tokens.len() < config.input_length_threshold() || tokens.len() > Token::MAX as usize {
+        return Ok(None);
+    }
+    let ring = DiscoveryConfig {
+        circular: true,
+        ..DiscoveryConfig::from_compression_config(config)
+    };
+    let len = tokens.len();
+    let best = try_discover_candidates(tokens, &ring)?
+        .into_iter()
+        .filter_map(|candidate| {
+            let wrapped = candidate.positions().find(|&pos| pos + candidate.length > len)?;
+            let savings = config.savings_for(candidate.length, candidate.count());
+            Some((savings, wrapped + candidate.length - len))
+        })
+        .max_by_key(|&(savings, _)| savings);
+    Ok(best.map(|(_, cut)| cut))
+}
+
+/// Compress `max_single_pass_length` pieces of the input as concatenated
+/// frames, each with its own dictionary (see `split_frames`).
+///
+/// Pieces are always framed, so a piece left as is cannot be read as part
+/// of the body before it, and keep the configured delimiters. The result
+/// carries the first frame's dictionary; the later frames are part of its
+/// body.
+fn compress_segmented(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<CompressionResult, CompressionError> {
+    let mut segment_config = CompressionConfig {
+        always_framed: true,
+        auto_delimiters: false,
+        ..config.clone()
+    };
+
+    let mut serialized = Vec::new();
+    let mut first: Option<CompressionResult> = None;
+    let segment_length = config.max_single_pass_length.max(1);
+    for (i, segment) in tokens.chunks(segment_length).enumerate() {
+        // Boundaries are positions in the whole input
+        let offset = i * segment_length;
+        segment_config.merge_boundaries = config
+            .merge_boundaries
+            .iter()
+            .filter(|&&pos| pos >= offset && pos <= offset + segment.len())
+            .map(|&pos| pos - offset)
+            .collect();
+        let result = compress_internal(segment, &segment_config, next_meta_token)?;
+        serialized.extend(&result.serialized_tokens);
+        first.get_or_insert(result);
+    }
+
+    Ok(stitch_frames(tokens, serialized, first, config, next_meta_token))
+}
+
+/// Result for concatenated frames `serialized`, carrying the first frame's
+/// dictionary, or the input left as is if the frames are no shorter.
+fn stitch_frames(
+    tokens: &[Token],
+    serialized: Vec<Token>,
+    first: Option<CompressionResult>,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> CompressionResult {
+    let first = match first {
+        Some(first) if config.keeps_compressed(serialized.len(), tokens.len()) => first,
+        _ => {
+            let reason = NoCompressionReason::NotBeneficial;
+            let result = CompressionResult::no_compression(tokens.to_vec(), reason);
+            return frame_if_required(result, config, next_meta_token);
+        }
+    };
+    let body_tokens = serialized[first.dictionary_tokens.len()..].to_vec();
+    CompressionResult {
+        original_tokens: tokens.to_vec(),
+        compressed_length: serialized.len(),
+        serialized_tokens: serialized,
+        dictionary_tokens: first.dictionary_tokens,
+        body_tokens,
+        dictionary_map: first.dictionary_map,
+        original_length: tokens.len(),
+        static_dictionary_id: None,
+        reason: None,
+        selection_trace: None,
+        original_hash: hash_tokens(tokens),
+    }
+}
+
+/// Compress each segment of the input, up to and including a
+/// `segment_token`, as its own frame.
+///
+/// Every frame opens with a delimiter header (see `DelimiterHeader`) giving
+/// the segment its own meta-token range: `meta_token_pool_size` ids
+/// ascending from `DEFAULT_NEXT_META_TOKEN`, less one pool per earlier
+/// segment. The stream decodes with `decompress`. Fails with
+/// `InvalidConfig` if the input holds `DELIMITER_HEADER_TOKEN` or the
+/// ranges run out of ids.
+pub fn compress_segments(
+    tokens: &[Token],
+    segment_token: Token,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let frames = split_segments(tokens, segment_token, config)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, segment)| compress_segment(segment, i, config))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(stitch_segments(tokens, frames, config))
+}
+
+/// `compress_segments`, compressing the segments concurrently.
+///
+/// Segments are independent, so the output is the same as
+/// `compress_segments`.
+#[cfg(feature = "parallel")]
+pub fn compress_segmented_parallel(
+    tokens: &[Token],
+    segment_token: Token,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    use rayon::prelude::*;
+
+    let segments = split_segments(tokens, segment_token, config)?;
+    let frames = segments
+        .par_iter()
+        .enumerate()
+        .map(|(i, segment)| compress_segment(segment, i, config))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(stitch_segments(tokens, frames, config))
+}
+
+/// Split the input after each `segment_token`, checking every segment can
+/// be given its own meta-token range.
+fn split_segments<'a>(
+    tokens: &'a [Token],
+    segment_token: Token,
+    config: &CompressionConfig,
+) -> Result<Vec<&'a [Token]>, CompressionError> {
+    if tokens.contains(&DELIMITER_HEADER_TOKEN) {
+        return Err(CompressionError::InvalidConfig(
+            "segmented input cannot hold the delimiter header token".to_string(),
+        ));
+    }
+    let segments: Vec<&[Token]> = tokens.split_inclusive(|&t| t == segment_token).collect();
+    let ids = segments.len().saturating_mul(config.meta_token_pool_size);
+    if ids > DEFAULT_NEXT_META_TOKEN as usize {
+        return Err(CompressionError::InvalidConfig(format!(
+            "{} segments do not fit disjoint meta-token ranges",
+            segments.len()
+        )));
+    }
+    Ok(segments)
+}
+
+/// Compress segment `index` as a headered frame in its meta-token range.
+fn compress_segment(
+    segment: &[Token],
+    index: usize,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let header = DelimiterHeader {
+        dict_start_token: config.dict_start_token,
+        dict_end_token: config.dict_end_token,
+        next_meta_token: DEFAULT_NEXT_META_TOKEN - (index * config.meta_token_pool_size) as Token,
+    };
+    let segment_config = CompressionConfig {
+        always_framed: true,
+        ..header.apply(config)
+    };
+    let result = compress_internal(segment, &segment_config, header.next_meta_token)?;
+    Ok(header.prepend(result))
+}
+
+/// Concatenate segment frames into one result, as `stitch_frames` does.
+fn stitch_segments(
+    tokens: &[Token],
+    frames: Vec<CompressionResult>,
+    config: &CompressionConfig,
+) -> CompressionResult {
+    let serialized: Vec<Token> = frames.iter().flat_map(|f| f.serialized_tokens.clone()).collect();
+    let first = frames.into_iter().next();
+    stitch_frames(tokens, serialized, first, config, DEFAULT_NEXT_META_TOKEN)
+}
+
+/// Compress with the delimiters in `header`, writing it ahead of the
+/// dictionary. Results without a dictionary section carry no header.
+///
+/// `compress` produces the unframed result for a config and first
+/// meta-token.
+fn compress_auto_delimited(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    header: DelimiterHeader,
+    compress: impl FnOnce(&CompressionConfig, Token) -> Result<CompressionResult, CompressionError>,
+) -> Result<CompressionResult, CompressionError> {
+    let config = header.apply(config);
+    let next_meta_token = header.next_meta_token;
+
+    let mut result = compress(&config, next_meta_token)?;
+    let has_dictionary = !result.dictionary_tokens.is_empty();
+    let headered_length = result.compressed_length + DelimiterHeader::LEN;
+    if has_dictionary && !config.keeps_compressed(headered_length, tokens.len()) {
+        let reason = NoCompressionReason::NotBeneficial;
+        result = CompressionResult::no_compression(tokens.to_vec(), reason);
+    }
+
+    let result = frame_if_required(result, &config, next_meta_token);
+    if result.dictionary_tokens.is_empty() {
+        return Ok(result);
+    }
+    Ok(header.prepend(result))
+}
+
+/// Frame a result that fell back to raw tokens when `always_framed` is set,
+/// when its first token would be read as a delimiter header, or when it
+/// holds a token the decoder would not read as a literal (see
+/// `is_raw_reserved`).
+fn frame_if_required(
+    result: CompressionResult,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> CompressionResult {
+    let headerlike = match result.serialized_tokens.first() {
+        Some(&DELIMITER_HEADER_TOKEN) => true,
+        Some(&ROTATION_HEADER_TOKEN) => config.circular,
+        _ => false,
+    };
+    let has_reserved = result.serialized_tokens.iter().any(|&t| is_raw_reserved(t, config));
+    if config.always_framed || headerlike || has_reserved {
+        return frame_raw(result, config, next_meta_token);
+    }
+    result
+}
+
+/// Give a result stored without compression an empty dictionary section.
+fn frame_raw(
+    result: CompressionResult,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> CompressionResult {
+    if result.dictionary_tokens.is_empty() {
+        let framed = frame_uncompressed(&result.original_tokens, config, next_meta_token);
+        return CompressionResult {
+            reason: result.reason,
+            selection_trace: result.selection_trace,
+            original_hash: result.original_hash,
+            ..framed
+        };
+    }
+    result
+}
+
+/// Compression that may return the input unchanged.
+fn compress_unframed(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<CompressionResult, CompressionError> {
+    if config.strict_python_parity {
+        return python_parity::compress_python_parity(tokens, config, next_meta_token);
+    }
+    if config.channels > 1 {
+        return compress_channels(tokens, config, next_meta_token);
+    }
+
     // Handle empty or small inputs
-    if tokens.len() < config.min_subsequence_length * 2 {
-        return Ok(CompressionResult::no_compression(tokens.to_vec()));
+    if tokens.len() < config.input_length_threshold() {
+        let reason = NoCompressionReason::TooSmall;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     // Discovery configuration
-    let discovery_config = DiscoveryConfig {
-        min_length: config.min_subsequence_length,
-        max_length: config.max_subsequence_length,
-        min_occurrences: 2,
-        extra_cost: if config.dict_length_enabled { 1 } else { 0 },
+    let discovery_config = DiscoveryConfig::from_compression_config(config);
+
+    // Discover candidate patterns, across equivalent tokens if configured
+    let candidates = if !config.equivalence_classes.is_empty() && config.gap_fillers_enabled() {
+        discover_with_classes(tokens, config, &discovery_config)?
+    } else {
+        try_discover_candidates(tokens, &discovery_config)?
+    };
+
+    compress_candidates(tokens, candidates, config, next_meta_token)
+}
+
+/// Compress using pattern hints instead of suffix-array discovery.
+///
+/// Each hint is a pattern and the positions where it occurs. Hints are
+/// validated against the input (in bounds, matching tokens, no overlapping
+/// positions within a hint) and then go through the normal selection and
+/// dictionary-building steps. Patterns longer than the decoder's dictionary
+/// entry limit are rejected, since the output could not be decoded.
+pub fn compress_with_hints(
+    tokens: &[Token],
+    hints: Vec<(Vec<Token>, Vec<usize>)>,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let max_length = config.dict_entry_length_limit();
+    if let Some(hint) = hints.iter().position(|(pattern, _)| pattern.len() > max_length) {
+        return Err(CompressionError::InvalidHint {
+            hint,
+            reason: format!("pattern is longer than the entry limit of {}", max_length),
+        });
+    }
+
+    let candidates = candidates_from_hints(tokens, hints)?;
+    let result = compress_candidates(tokens, candidates, config, DEFAULT_NEXT_META_TOKEN)?;
+    Ok(frame_if_required(result, config, DEFAULT_NEXT_META_TOKEN))
+}
+
+/// Compress a window, reusing discovery results cached for the previous one.
+///
+/// The cache should be created from the same settings as `config` (see
+/// `DiscoveryConfig::from_compression_config`).
+pub fn compress_with_cache(
+    tokens: &[Token],
+    cache: &mut DiscoveryCache,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let result = if tokens.len() < config.input_length_threshold() {
+        CompressionResult::no_compression(tokens.to_vec(), NoCompressionReason::TooSmall)
+    } else {
+        let candidates = cache.discover(tokens);
+        compress_candidates(tokens, candidates, config, DEFAULT_NEXT_META_TOKEN)?
+    };
+    Ok(frame_if_required(result, config, DEFAULT_NEXT_META_TOKEN))
+}
+
+/// Compress using a suffix array the caller already built for `tokens`.
+///
+/// Skips building the array during discovery, and otherwise matches
+/// `compress` with the default first meta-token. Inputs too small to
+/// compress are returned as is without reading `sa`; otherwise fails with
+/// `InvalidConfig` if `sa` was not built for an input of this length.
+/// Settings that discover over a transformed input (channels, equivalence
+/// classes, circular, Python parity, segmenting) fail with `InvalidConfig`
+/// rather than being ignored.
+pub fn compress_with_suffix_array(
+    tokens: &[Token],
+    sa: &SuffixArray,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let unsupported = [
+        (config.circular, "circular"),
+        (config.channels > 1, "channels"),
+        (config.strict_python_parity, "strict_python_parity"),
+        (
+            !config.equivalence_classes.is_empty() && config.gap_fillers_enabled(),
+            "equivalence_classes",
+        ),
+        (tokens.len() > config.max_single_pass_length, "max_single_pass_length"),
+    ];
+    if let Some((_, setting)) = unsupported.iter().find(|(applies, _)| *applies) {
+        return Err(CompressionError::InvalidConfig(format!(
+            "{} cannot be used with a prebuilt suffix array",
+            setting
+        )));
+    }
+
+    let compress = |config: &CompressionConfig, next_meta_token| {
+        if tokens.len() < config.input_length_threshold() {
+            let reason = NoCompressionReason::TooSmall;
+            return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+        }
+        let discovery_config = DiscoveryConfig::from_compression_config(config);
+        let candidates = discover_with_suffix_array(tokens, sa, &discovery_config)?;
+        compress_candidates(tokens, candidates, config, next_meta_token)
+    };
+    if config.auto_delimiters {
+        if let Some(header) = DelimiterHeader::above(tokens, config) {
+            return compress_auto_delimited(tokens, config, header, compress);
+        }
+    }
+    let result = compress(config, DEFAULT_NEXT_META_TOKEN)?;
+    Ok(frame_if_required(result, config, DEFAULT_NEXT_META_TOKEN))
+}
+
+/// Run selection, dictionary building and serialization over candidates,
+/// attaching the selection trace when `selection_trace` is set.
+fn compress_candidates(
+    tokens: &[Token],
+    candidates: Vec<Candidate>,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<CompressionResult, CompressionError> {
+    let mut trace = config.selection_trace.then(SelectionTrace::default);
+    let mut result =
+        compress_selected(tokens, candidates, config, next_meta_token, trace.as_mut())?;
+    result.selection_trace = trace;
+    Ok(result)
+}
+
+fn compress_selected(
+    tokens: &[Token],
+    candidates: Vec<Candidate>,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+    trace: Option<&mut SelectionTrace>,
+) -> Result<CompressionResult, CompressionError> {
+    let discovery_config = DiscoveryConfig::from_compression_config(config);
+
+    // Let mirrored repeats compete with regular patterns
+    let mut candidates = candidates;
+    if config.detect_mirrored {
+        let mirrored = discover_mirrored(tokens, &discovery_config);
+        candidates.extend(mirrored.iter().map(MirroredCandidate::to_candidate));
+    }
+
+    // Skip-grams compete too, carrying their gap fillers as patches
+    if config.skip_grams_enabled() {
+        let skip_grams = SkipGramConfig {
+            max_gaps: config.max_gaps,
+            gap_len: config.gap_len,
+            gap_token: config.gap_token,
+        };
+        candidates.extend(discover_skip_grams(tokens, &discovery_config, &skip_grams));
+    }
+
+    // Literals that collide with meta-tokens or the reverse marker are
+    // escaped in the body, but dictionary definitions cannot carry escapes
+    let reserved = |t: &Token| {
+        is_reserved_token(*t, config, next_meta_token)
+            || (config.detect_mirrored && *t == config.reverse_marker_token)
     };
+    let has_reserved = tokens.iter().any(reserved);
+    if has_reserved {
+        candidates.retain(|c| !c.subsequence.iter().any(reserved));
+    }
+    if config.gap_fillers_enabled() {
+        retain_gap_free(&mut candidates, config.gap_token);
+    }
+    if !config.token_types.is_empty() {
+        retain_single_type(&mut candidates, |t| config.token_type(t));
+    }
+
+    // Blacklisted sequences stay verbatim in the body
+    let blacklist = &config.blacklist_patterns;
+    let candidates = exclude_blacklisted(tokens, candidates, blacklist, &discovery_config);
 
-    // Discover candidate patterns
-    let candidates = discover_candidates(tokens, &discovery_config);
     if candidates.is_empty() {
-        return Ok(CompressionResult::no_compression(tokens.to_vec()));
+        let reason = NoCompressionReason::NoPatterns;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     // Deduplicate candidates
-    let candidates = deduplicate_candidates(candidates);
+    let mut candidates = deduplicate_candidates(candidates);
+    cap_occurrences(&mut candidates, config.max_occurrences_per_pattern);
+    prefer_aligned(&mut candidates, &config.merge_boundaries);
+
+    // Select non-overlapping occurrences; exact selection only on small inputs
+    let mode = match config.selection_mode.as_str() {
+        "exact" if tokens.len() > config.exact_max_input_length => "optimal",
+        mode => mode,
+    };
+    let extra_cost = discovery_config.extra_cost;
+    let max_iterations = config.selection_max_iterations;
+    let selection_result = match trace {
+        Some(trace) => select_occurrences_traced(
+            &candidates,
+            mode,
+            extra_cost,
+            max_iterations,
+            &DefaultScorer,
+            trace,
+        ),
+        None => {
+            select_occurrences_with(&candidates, mode, extra_cost, max_iterations, &DefaultScorer)
+        }
+    };
+
+    let mut selected = selection_result.selected;
+    if config.coalesce_runs && !config.detect_mirrored {
+        selected = coalesce_runs(&candidates, selected, tokens.len(), config);
+    }
+
+    // Keep the dictionary within its share of the output and its pool
+    let selected = trim_to_dict_fraction(selected, tokens.len(), config);
+    let selected = trim_to_pool(selected, config.meta_token_pool_size, config);
+    if selected.is_empty() {
+        let reason = NoCompressionReason::NoViableSelection;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
 
-    // Select non-overlapping occurrences
-    let selection_result = select_occurrences(
-        &candidates,
-        &config.selection_mode,
-        discovery_config.extra_cost,
-    );
+    if config.debug_checks {
+        check_selection(&selected, tokens.len());
+    }
+
+    // Every pattern needs an id within the token range
+    ensure_ids_fit(&selected, config, next_meta_token)?;
 
-    if selection_result.selected.is_empty() {
-        return Ok(CompressionResult::no_compression(tokens.to_vec()));
+    // Skip building output that cannot beat the input even unescaped
+    let lower_bound = compute_serialized_size(&selected, tokens.len(), config);
+    if !config.detect_mirrored && !config.keeps_compressed(lower_bound, tokens.len()) {
+        let reason = NoCompressionReason::NotBeneficial;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     // Build dictionary
-    let dict = build_dictionary(&selection_result.selected, config, next_meta_token);
+    let dict = build_dictionary(&selected, config, next_meta_token);
     if dict.entries.is_empty() {
-        return Ok(CompressionResult::no_compression(tokens.to_vec()));
+        let reason = NoCompressionReason::NoViableSelection;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
+    if config.debug_checks {
+        check_dictionary(&dict);
     }
 
     // Build body with replacements
-    let body = build_body(tokens, &selection_result.selected, &dict.pattern_to_meta);
+    let escape = config.escape_token;
+    let body = if config.detect_mirrored {
+        let marker = config.reverse_marker_token;
+        build_body_mirrored(tokens, &selected, &dict.pattern_to_meta, marker, escape)
+    } else {
+        build_body_in_pool(tokens, &selected, &dict, config, next_meta_token)
+    };
+    if config.verify {
+        verify_replacements(tokens, &selected, &dict, config.detect_mirrored)?;
+    }
+    // Mirrored bodies also carry reverse markers
+    if config.debug_checks && !config.detect_mirrored {
+        check_body_length(&body, &selected, tokens.len(), escape);
+    }
+
+    // Escape pairs must stay together, which later passes cannot ensure
+    let has_escapes = body.contains(&escape);
 
     // Check if compression is beneficial
     let compressed_len = dict.tokens.len() + body.len();
-    if compressed_len >= tokens.len() {
-        return Ok(CompressionResult::no_compression(tokens.to_vec()));
+    if !config.keeps_compressed(compressed_len, tokens.len()) {
+        let reason = NoCompressionReason::NotBeneficial;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     // Serialize result
     let mut result = serialize_result(&dict, &body, tokens, config);
+    if config.debug_checks {
+        let serialized = &result.serialized_tokens;
+        check_meta_tokens_defined(serialized, &dict.entries, config, next_meta_token);
+    }
 
-    // Verify if requested
-    if config.verify {
-        let restored = dict_decompress(&result.serialized_tokens, &dict.entries, config);
-        if restored != tokens {
-            return Err(JsValue::from_str(
-                "Compression verification failed: round-trip mismatch",
-            ));
+    // Reverse markers and gap fillers must stay next to their meta-token,
+    // so such bodies skip the passes that regroup body tokens
+    let has_gaps = selected.iter().any(|occ| !occ.patches.is_empty());
+    if !config.detect_mirrored && !has_gaps {
+        // Hierarchical compression; escaped literals would be regrouped with
+        // their escape, so bodies holding escapes stay single-level. No ids
+        // remain if the first pass took the last one.
+        let direction = config.meta_token_direction;
+        let next_meta_token = direction.step(next_meta_token, dict.entries.len());
+        let hierarchical = config.hierarchical_enabled && config.hierarchical_max_depth > 1;
+        if let Some(next_meta_token) = next_meta_token.filter(|_| hierarchical && !has_escapes) {
+            result = apply_hierarchical(result, config, next_meta_token)?;
+        }
+
+        // Length-prefix literal runs once the final body is known
+        if config.body_runs {
+            result = encode_body_runs(result, config);
         }
     }
 
-    // Hierarchical compression
-    if config.hierarchical_enabled && config.hierarchical_max_depth > 1 {
-        result = apply_hierarchical(result, config, next_meta_token + dict.entries.len() as Token)?;
+    // Run prefixes can make the final stream longer than the input
+    if !config.keeps_compressed(result.compressed_length, tokens.len()) {
+        let reason = NoCompressionReason::NotBeneficial;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
+
+    // Full round trip of the final stream if requested
+    if config.verify_full {
+        let serialized = &result.serialized_tokens;
+        let restored = dict_decompress(serialized, &result.dictionary_map, config);
+        if restored != tokens {
+            return Err(CompressionError::VerificationFailed);
+        }
     }
 
     Ok(result)
 }
 
 /// Apply hierarchical compression passes.
+///
+/// All passes share the `meta_token_pool_size` ids reserved for the
+/// dictionary; hierarchical compression stops once a pass would need more
+/// ids than remain in the pool.
 fn apply_hierarchical(
     mut result: CompressionResult,
     config: &CompressionConfig,
     mut next_meta_token: Token,
-) -> Result<CompressionResult, JsValue> {
+) -> Result<CompressionResult, CompressionError> {
     let min_improvement = 0.02; // 2% improvement threshold
 
     for _depth in 1..config.hierarchical_max_depth {
+        let pool_remaining = config
+            .meta_token_pool_size
+            .saturating_sub(result.dictionary_map.len())
+            .min(config.meta_token_direction.capacity(next_meta_token));
+        if pool_remaining == 0 {
+            break;
+        }
+
         let body = result.body_tokens.clone();
         if body.len() < config.min_subsequence_length * 2 {
             break;
         }
 
-        let discovery_config = DiscoveryConfig {
-            min_length: config.min_subsequence_length,
-            max_length: config.max_subsequence_length,
-            min_occurrences: 2,
-            extra_cost: if config.dict_length_enabled { 1 } else { 0 },
-        };
+        let discovery_config = DiscoveryConfig::from_compression_config(config);
 
-        let candidates = discover_candidates(&body, &discovery_config);
+        let mut candidates = try_discover_candidates(&body, &discovery_config)?;
+        if config.gap_fillers_enabled() {
+            retain_gap_free(&mut candidates, config.gap_token);
+        }
+        if !config.token_types.is_empty() {
+            // A meta-token has the type of the pattern it stands for
+            let map = &result.dictionary_map;
+            retain_single_type(&mut candidates, |mut t| {
+                while let Some(pattern) = map.get(&t) {
+                    t = pattern[0];
+                }
+                config.token_type(t)
+            });
+        }
+        let blacklist = &config.blacklist_patterns;
+        let candidates = exclude_blacklisted(&body, candidates, blacklist, &discovery_config);
         if candidates.is_empty() {
             break;
         }
 
-        let candidates = deduplicate_candidates(candidates);
-        let selection_result = select_occurrences(
+        let mut candidates = deduplicate_candidates(candidates);
+        cap_occurrences(&mut candidates, config.max_occurrences_per_pattern);
+        let selection_result = select_occurrences_with(
             &candidates,
             &config.selection_mode,
             discovery_config.extra_cost,
+            config.selection_max_iterations,
+            &DefaultScorer,
         );
 
-        if selection_result.selected.is_empty() {
+        // The merged dictionary must still fit `max_dict_fraction`
+        let dict_len = result.dictionary_tokens.len();
+        let selected = selection_result.selected;
+        let selected = trim_onto_dictionary(selected, body.len(), dict_len, config);
+        if selected.is_empty() {
             break;
         }
 
-        let new_dict = build_dictionary(&selection_result.selected, config, next_meta_token);
+        // Stop once a pass needs more ids than remain in the pool
+        let patterns: HashSet<&[Token]> =
+            selected.iter().map(|occ| occ.subsequence.as_slice()).collect();
+        if patterns.len() > pool_remaining {
+            break;
+        }
+        let new_dict = build_dictionary(&selected, config, next_meta_token);
         if new_dict.entries.is_empty() {
             break;
         }
 
-        let new_body = build_body(&body, &selection_result.selected, &new_dict.pattern_to_meta);
+        let new_body = build_body_in_pool(&body, &selected, &new_dict, config, next_meta_token);
+        if config.verify {
+            verify_replacements(&body, &selected, &new_dict, false)?;
+        }
         let new_compressed_len = result.dictionary_tokens.len() + new_dict.tokens.len() + new_body.len();
 
         let improvement = 1.0 - (new_compressed_len as f64 / result.compressed_length as f64);
@@ -208,8 +893,8 @@ fn apply_hierarchical(
         let mut serialized = merged_dict_tokens.clone();
         serialized.extend(&new_body);
 
-        result = CompressionResult {
-            original_tokens: result.original_tokens,
+        let merged = CompressionResult {
+            original_tokens: result.original_tokens.clone(),
             serialized_tokens: serialized.clone(),
             dictionary_tokens: merged_dict_tokens,
             body_tokens: new_body,
@@ -217,14 +902,169 @@ fn apply_hierarchical(
             original_length: result.original_length,
             compressed_length: serialized.len(),
             static_dictionary_id: None,
+            reason: None,
+            selection_trace: None,
+            original_hash: result.original_hash,
         };
 
-        next_meta_token += new_entries_len as Token;
+        // Keep the previous pass unless the merged output is really smaller
+        // and its dictionary fits, which the size estimate does not promise
+        let dict_limit = config.max_dict_fraction * merged.compressed_length as f64;
+        if merged.compressed_length >= result.compressed_length
+            || merged.dictionary_tokens.len() as f64 > dict_limit
+        {
+            break;
+        }
+        result = merged;
+
+        match config.meta_token_direction.step(next_meta_token, new_entries_len) {
+            Some(next) => next_meta_token = next,
+            None => break,
+        }
     }
 
     Ok(result)
 }
 
+/// Reselect with doubled or quadrupled super-patterns for runs in
+/// `selected`, keeping whichever selection serializes shortest.
+fn coalesce_runs(
+    candidates: &[Candidate],
+    selected: Vec<Occurrence>,
+    original_len: usize,
+    config: &CompressionConfig,
+) -> Vec<Occurrence> {
+    let max_length = config.dict_entry_length_limit();
+    let mut best_size = compute_serialized_size(&selected, original_len, config);
+    let mut best = None;
+
+    for multiplier in [2, 4] {
+        let super_candidates = run_super_candidates(&selected, multiplier, max_length);
+        if super_candidates.is_empty() {
+            continue;
+        }
+
+        let mut augmented = candidates.to_vec();
+        augmented.extend(super_candidates);
+        let reselected = select_occurrences_with(
+            &augmented,
+            &config.selection_mode,
+            config.extra_cost(),
+            config.selection_max_iterations,
+            &DefaultScorer,
+        )
+        .selected;
+
+        let size = compute_serialized_size(&reselected, original_len, config);
+        if size < best_size {
+            best_size = size;
+            best = Some(reselected);
+        }
+    }
+
+    best.unwrap_or(selected)
+}
+
+/// Build a body whose meta-tokens were assigned from `next_meta_token`.
+///
+/// Descending ids put earlier passes' meta-tokens above the new ones, so
+/// instead of every literal from the first new id up, only literals in the
+/// pool still to be assigned are escaped, along with `dict_start_token`,
+/// which would otherwise open a frame (see `split_frames`).
+fn build_body_in_pool(
+    tokens: &[Token],
+    selected: &[Occurrence],
+    dict: &Dictionary,
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Vec<Token> {
+    let escape = config.escape_token;
+    match config.meta_token_direction {
+        MetaTokenDirection::Ascending => {
+            build_body(tokens, selected, &dict.pattern_to_meta, escape)
+        }
+        MetaTokenDirection::Descending => {
+            let pool = meta_token_pool(config, next_meta_token);
+            let meta = &dict.pattern_to_meta;
+            let start = config.dict_start_token;
+            build_body_with(tokens, selected, meta, escape, |literal| {
+                pool.contains(&literal) || literal == start
+            })
+        }
+    }
+}
+
+/// Estimate peak memory, in bytes, needed to compress `tokens_len` tokens.
+///
+/// Lets a caller choose between whole-buffer compression and
+/// `StreamingCompressor` before committing. See
+/// `CompressionConfig::estimate_memory`.
+#[wasm_bindgen]
+pub fn estimate_memory(tokens_len: usize, config: JsValue) -> Result<usize, JsValue> {
+    let js_config: JsCompressionConfig = if config.is_undefined() || config.is_null() {
+        JsCompressionConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    Ok(js_config.merge_with_defaults().estimate_memory(tokens_len))
+}
+
+/// Check a configuration up front. See `CompressionConfig::validate`.
+#[wasm_bindgen]
+pub fn validate_config(config: JsValue) -> Result<(), JsValue> {
+    let js_config: JsCompressionConfig = if config.is_undefined() || config.is_null() {
+        JsCompressionConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    js_config.merge_with_defaults().validate().map_err(JsValue::from)
+}
+
+/// Compress `tokens` under each config in the array `configs`, returning
+/// an array of comparisons, best ratio first. See
+/// `analysis::compare_configs`.
+#[wasm_bindgen]
+pub fn compare_configs(tokens: &[u32], configs: JsValue) -> Result<JsValue, JsValue> {
+    let js_configs: Vec<JsCompressionConfig> =
+        serde_wasm_bindgen::from_value(configs).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let configs = js_configs
+        .into_iter()
+        .map(|config| config.merge_with_defaults())
+        .collect();
+
+    let comparisons = analysis::compare_configs(tokens, configs).map_err(JsValue::from)?;
+    serde_wasm_bindgen::to_value(&comparisons).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Occurrences a pattern of `length` tokens needs to be worth compressing
+/// under `config`. See `CompressionConfig::min_occurrences_for`.
+#[wasm_bindgen]
+pub fn min_occurrences_for(length: usize, config: JsValue) -> Result<usize, JsValue> {
+    let js_config: JsCompressionConfig = if config.is_undefined() || config.is_null() {
+        JsCompressionConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    Ok(js_config.merge_with_defaults().min_occurrences_for(length))
+}
+
+/// Net tokens saved by compressing `count` occurrences of a pattern of
+/// `length` tokens; negative when not worthwhile. See
+/// `CompressionConfig::savings_for`.
+#[wasm_bindgen]
+pub fn savings_for(length: usize, count: usize, config: JsValue) -> Result<i64, JsValue> {
+    let js_config: JsCompressionConfig = if config.is_undefined() || config.is_null() {
+        JsCompressionConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    Ok(js_config.merge_with_defaults().savings_for(length, count))
+}
+
 /// Decompress a compressed token sequence.
 ///
 /// # Arguments
@@ -234,7 +1074,8 @@ fn apply_hierarchical(
 ///
 /// # Returns
 ///
-/// The original token sequence.
+/// The original token sequence. Fails if the body references a meta-token
+/// the dictionary does not define; see `decompress_lenient`.
 #[wasm_bindgen]
 pub fn decompress(tokens: &[u32], config: JsValue) -> Result<Vec<u32>, JsValue> {
     let js_config: JsCompressionConfig = if config.is_undefined() || config.is_null() {
@@ -243,15 +1084,152 @@ pub fn decompress(tokens: &[u32], config: JsValue) -> Result<Vec<u32>, JsValue>
         serde_wasm_bindgen::from_value(config).map_err(|e| JsValue::from_str(&e.to_string()))?
     };
 
+    let next_meta_token = js_config.next_meta_token.unwrap_or(DEFAULT_NEXT_META_TOKEN);
     let compression_config = js_config.merge_with_defaults();
 
-    // Parse dictionary from tokens
-    let dictionary = parse_dictionary(tokens, &compression_config);
-
-    // Decompress
-    let result = dict_decompress(tokens, &dictionary, &compression_config);
+    decompress_internal(tokens, &compression_config, next_meta_token).map_err(JsValue::from)
+}
 
-    Ok(result)
+/// Decompress, leaving meta-tokens without a definition in place.
+///
+/// For streams from an encoder using dictionary features this decoder
+/// does not model. Returns `{ tokens, unresolved }`: the best-effort
+/// decode and the unresolved meta-tokens in order of first reference, so
+/// callers can detect a partial decode.
+#[wasm_bindgen]
+pub fn decompress_lenient(tokens: &[u32], config: JsValue) -> Result<JsValue, JsValue> {
+    let js_config: JsCompressionConfig = if config.is_undefined() || config.is_null() {
+        JsCompressionConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    let next_meta_token = js_config.next_meta_token.unwrap_or(DEFAULT_NEXT_META_TOKEN);
+    let compression_config = js_config.merge_with_defaults();
+
+    let (output, unresolved) =
+        decompress_lenient_internal(tokens, &compression_config, next_meta_token)?;
+    let result = serde_json::json!({ "tokens": output, "unresolved": unresolved });
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Internal decompression implementation.
+///
+/// Errors with `UnresolvedMetaTokens` where the lenient decode would
+/// leave meta-tokens unexpanded.
+fn decompress_internal(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<Vec<Token>, CompressionError> {
+    let (output, unresolved) = decompress_lenient_internal(tokens, config, next_meta_token)?;
+    if !unresolved.is_empty() {
+        return Err(CompressionError::UnresolvedMetaTokens(unresolved));
+    }
+    Ok(output)
+}
+
+/// Decompress into `out`, reusing its capacity.
+///
+/// `out` is cleared first, so a buffer kept across calls stops allocating
+/// once it is large enough for the longest stream. On error `out` is left
+/// empty.
+pub fn decompress_into(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    out: &mut Vec<Token>,
+) -> Result<(), CompressionError> {
+    out.clear();
+    let unresolved = decompress_lenient_into(tokens, config, DEFAULT_NEXT_META_TOKEN, out)
+        .inspect_err(|_| out.clear())?;
+    if !unresolved.is_empty() {
+        out.clear();
+        return Err(CompressionError::UnresolvedMetaTokens(unresolved));
+    }
+    Ok(())
+}
+
+/// Internal lenient decompression implementation.
+fn decompress_lenient_internal(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<(Vec<Token>, Vec<Token>), CompressionError> {
+    let mut output = Vec::with_capacity(tokens.len() * 2);
+    let unresolved = decompress_lenient_into(tokens, config, next_meta_token, &mut output)?;
+    Ok((output, unresolved))
+}
+
+/// Append the lenient decode of `tokens` to `output`, returning the
+/// unresolved meta-tokens.
+///
+/// Each frame (see `split_frames`) is decoded against its own dictionary.
+/// A stream opening with a delimiter header is decoded with the header's
+/// delimiters up to the next header, whatever `config` says.
+fn decompress_lenient_into(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+    output: &mut Vec<Token>,
+) -> Result<Vec<Token>, CompressionError> {
+    if let (true, [ROTATION_HEADER_TOKEN, cut, rest @ ..]) = (config.circular, tokens) {
+        let linear_config = CompressionConfig {
+            circular: false,
+            ..config.clone()
+        };
+        let from = output.len();
+        let unresolved = decompress_lenient_into(rest, &linear_config, next_meta_token, output)?;
+        let rotated = &mut output[from..];
+        let cut = *cut as usize;
+        if cut > rotated.len() {
+            return Err(CompressionError::MalformedDictionary(format!(
+                "rotation header cuts the ring at {} but it decodes to {} tokens",
+                cut,
+                rotated.len()
+            )));
+        }
+        rotated.rotate_right(cut);
+        return Ok(unresolved);
+    }
+    if let Some((header, rest)) = DelimiterHeader::read(tokens) {
+        // Headered streams hold no literals as large as the header token
+        let end = rest
+            .iter()
+            .position(|&t| t == DELIMITER_HEADER_TOKEN)
+            .unwrap_or(rest.len());
+        let header_config = header.apply(config);
+        let mut unresolved =
+            decompress_lenient_into(&rest[..end], &header_config, header.next_meta_token, output)?;
+        let more_unresolved =
+            decompress_lenient_into(&rest[end..], config, next_meta_token, output)?;
+        for token in more_unresolved {
+            if !unresolved.contains(&token) {
+                unresolved.push(token);
+            }
+        }
+        return Ok(unresolved);
+    }
+
+    let mut unresolved = Vec::new();
+    for frame in split_frames(tokens, config) {
+        // Parse dictionary from tokens
+        let dictionary = parse_dictionary(frame, config)?;
+
+        for token in unresolved_meta_tokens(frame, &dictionary, config, next_meta_token) {
+            if !unresolved.contains(&token) {
+                unresolved.push(token);
+            }
+        }
+
+        // Decompress; channel bodies decode back to back, while frames
+        // without a dictionary hold the interleaved input as is
+        let from = output.len();
+        dict_decompress_into(frame, &dictionary, config, output);
+        if config.channels > 1 && !dictionary.is_empty() {
+            interleave_tail(output, from, config.channels);
+        }
+    }
+    Ok(unresolved)
 }
 
 /// Streaming compressor for large inputs.
@@ -260,6 +1238,8 @@ pub struct StreamingCompressor {
     chunks: Vec<Vec<Token>>,
     config: CompressionConfig,
     next_meta_token: Token,
+    /// Whether an earlier flush emitted any tokens
+    flushed: bool,
 }
 
 #[wasm_bindgen]
@@ -276,7 +1256,8 @@ impl StreamingCompressor {
         Ok(Self {
             chunks: Vec::new(),
             config: js_config.merge_with_defaults(),
-            next_meta_token: js_config.next_meta_token.unwrap_or(0xFFFF0000),
+            next_meta_token: js_config.next_meta_token.unwrap_or(DEFAULT_NEXT_META_TOKEN),
+            flushed: false,
         })
     }
 
@@ -286,56 +1267,191 @@ impl StreamingCompressor {
     }
 
     /// Finish streaming and produce compressed result.
+    ///
+    /// With no chunks (or only empty ones) the result is an uncompressed
+    /// empty sequence.
     pub fn finish(self) -> Result<CompressionResult, JsValue> {
+        self.finish_internal().map_err(JsValue::from)
+    }
+
+    /// Compress everything buffered so far as a self-contained frame.
+    ///
+    /// The buffer is cleared, so later chunks go into a new, independent
+    /// frame with its own dictionary. Concatenated frames decode with
+    /// `decompress`: input left uncompressed after the first frame is
+    /// framed with an empty dictionary section.
+    pub fn flush(&mut self) -> Result<CompressionResult, JsValue> {
+        self.flush_internal().map_err(JsValue::from)
+    }
+
+    /// Get approximate memory usage.
+    pub fn memory_usage(&self) -> usize {
+        self.chunks.iter().map(|c| c.len() * 4).sum()
+    }
+}
+
+impl StreamingCompressor {
+    fn finish_internal(mut self) -> Result<CompressionResult, CompressionError> {
+        self.flush_internal()
+    }
+
+    /// Buffer `tokens`, appending a frame to `out` each time `window`
+    /// tokens are buffered.
+    fn push_windowed(
+        &mut self,
+        mut tokens: &[Token],
+        window: usize,
+        out: &mut Vec<Token>,
+    ) -> Result<(), CompressionError> {
+        while !tokens.is_empty() {
+            let buffered: usize = self.chunks.iter().map(|c| c.len()).sum();
+            let (chunk, rest) = tokens.split_at((window - buffered).min(tokens.len()));
+            self.chunks.push(chunk.to_vec());
+            tokens = rest;
+            if buffered + chunk.len() == window {
+                out.extend(self.flush_internal()?.serialized_tokens);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_internal(&mut self) -> Result<CompressionResult, CompressionError> {
         // Concatenate all chunks
-        let total_len: usize = self.chunks.iter().map(|c| c.len()).sum();
+        let chunks = std::mem::take(&mut self.chunks);
+        let total_len: usize = chunks.iter().map(|c| c.len()).sum();
         let mut all_tokens = Vec::with_capacity(total_len);
-        for chunk in &self.chunks {
+        for chunk in &chunks {
             all_tokens.extend(chunk);
         }
 
         // Compress the full sequence
-        compress_internal(&all_tokens, &self.config, self.next_meta_token)
+        let result = compress_internal(&all_tokens, &self.config, self.next_meta_token)?;
+
+        // Raw tokens after an earlier frame would read as part of its body,
+        // so only the first frame may be left without a dictionary section
+        let result = if self.flushed && !all_tokens.is_empty() {
+            frame_raw(result, &self.config, self.next_meta_token)
+        } else {
+            result
+        };
+        self.flushed |= !result.serialized_tokens.is_empty();
+        Ok(result)
     }
+}
 
-    /// Get approximate memory usage.
-    pub fn memory_usage(&self) -> usize {
-        self.chunks.iter().map(|c| c.len() * 4).sum()
+/// Compress a `ReadableStream` of `Uint32Array` chunks as it is read.
+///
+/// Every `max_single_pass_length` tokens are compressed as a frame with
+/// its own dictionary (see `StreamingCompressor::flush`), so at most one
+/// window of input is held at a time. Resolves to the concatenated frames,
+/// which decode with `decompress`.
+#[wasm_bindgen]
+pub async fn compress_stream(
+    readable: JsValue,
+    config: JsValue,
+) -> Result<js_sys::Uint32Array, JsValue> {
+    let mut compressor = StreamingCompressor::new(config)?;
+    // Pieces must be framed to be read back to back
+    compressor.config.always_framed = true;
+    let window = compressor.config.max_single_pass_length.max(1);
+
+    let method = |target: &JsValue, name: &str| -> Result<js_sys::Function, JsValue> {
+        js_sys::Reflect::get(target, &JsValue::from_str(name))?.dyn_into()
+    };
+    let reader = method(&readable, "getReader")?.call0(&readable)?;
+    let read = method(&reader, "read")?;
+
+    let mut out = Vec::new();
+    loop {
+        let promise: js_sys::Promise = read.call0(&reader)?.dyn_into()?;
+        let step = wasm_bindgen_futures::JsFuture::from(promise).await?;
+        if js_sys::Reflect::get(&step, &JsValue::from_str("done"))?.is_truthy() {
+            break;
+        }
+        let value = js_sys::Reflect::get(&step, &JsValue::from_str("value"))?;
+        let chunk = js_sys::Uint32Array::new(&value).to_vec();
+        compressor.push_windowed(&chunk, window, &mut out)?;
     }
+    if compressor.memory_usage() > 0 {
+        out.extend(compressor.finish_internal()?.serialized_tokens);
+    }
+
+    Ok(js_sys::Uint32Array::from(&out[..]))
 }
 
 /// Discover patterns without compressing.
 ///
-/// Useful for analysis and building static dictionaries.
+/// Useful for analysis and building static dictionaries. Fails if
+/// `min_length` is below 2 or `max_length` is below `min_length`.
 #[wasm_bindgen]
 pub fn discover_patterns(
     tokens: &[u32],
     min_length: usize,
     max_length: usize,
 ) -> Result<JsValue, JsValue> {
+    // Convert to JS-friendly format
+    let mut result: Vec<serde_json::Value> = Vec::new();
+    for_each_pattern::<JsValue>(tokens, min_length, max_length, |candidate| {
+        result.push(pattern_json(candidate));
+        Ok(())
+    })?;
+
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Discover patterns, passing each one to a callback as it is converted.
+///
+/// Each pattern is handed to `on_pattern` as the same object
+/// `discover_patterns` returns in its array, so large results can be
+/// written out incrementally instead of materialized as one JS array.
+/// Returns the number of patterns reported; an exception thrown by the
+/// callback stops discovery and is returned as the error.
+#[wasm_bindgen]
+pub fn discover_patterns_streaming(
+    tokens: &[u32],
+    min_length: usize,
+    max_length: usize,
+    on_pattern: &js_sys::Function,
+) -> Result<usize, JsValue> {
+    for_each_pattern(tokens, min_length, max_length, |candidate| {
+        let value = serde_wasm_bindgen::to_value(&pattern_json(candidate))
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        on_pattern.call1(&JsValue::NULL, &value).map(|_| ())
+    })
+}
+
+/// Run discovery and invoke `on_pattern` for each candidate in order.
+fn for_each_pattern<E: From<CompressionError>>(
+    tokens: &[Token],
+    min_length: usize,
+    max_length: usize,
+    mut on_pattern: impl FnMut(&Candidate) -> Result<(), E>,
+) -> Result<usize, E> {
     let config = DiscoveryConfig {
         min_length,
         max_length,
-        min_occurrences: 2,
+        min_occurrences: discovery::MinOccurrences::Uniform(2),
         extra_cost: 1,
+        ..Default::default()
     };
+    config.validate()?;
 
     let candidates = discover_candidates(tokens, &config);
+    for candidate in &candidates {
+        on_pattern(candidate)?;
+    }
 
-    // Convert to JS-friendly format
-    let result: Vec<serde_json::Value> = candidates
-        .iter()
-        .map(|c| {
-            serde_json::json!({
-                "pattern": c.subsequence,
-                "length": c.length,
-                "positions": c.positions,
-                "count": c.positions.len(),
-            })
-        })
-        .collect();
+    Ok(candidates.len())
+}
 
-    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+/// JSON description of a discovered pattern.
+fn pattern_json(candidate: &Candidate) -> serde_json::Value {
+    serde_json::json!({
+        "pattern": candidate.subsequence.as_slice(),
+        "length": candidate.length,
+        "positions": candidate.positions().collect::<Vec<_>>(),
+        "count": candidate.count(),
+    })
 }
 
 /// Get version information.
@@ -351,6 +1467,10 @@ pub const FORMAT_VERSION: u32 = 1;
 mod tests {
     use super::*;
 
+    fn decode(tokens: &[Token], config: &CompressionConfig) -> Vec<Token> {
+        decompress_internal(tokens, config, DEFAULT_NEXT_META_TOKEN).unwrap()
+    }
+
     #[test]
     fn test_compress_simple() {
         // Use larger input to overcome dictionary overhead
@@ -377,7 +1497,7 @@ mod tests {
         let result = compress_internal(&tokens, &config, 0xFFFF0000).unwrap();
 
         // Parse dictionary and decompress
-        let dictionary = parse_dictionary(&result.serialized_tokens, &config);
+        let dictionary = parse_dictionary(&result.serialized_tokens, &config).unwrap();
         let restored = dict_decompress(&result.serialized_tokens, &dictionary, &config);
 
         assert_eq!(restored, tokens);
@@ -395,6 +1515,462 @@ mod tests {
         assert_eq!(result.compression_ratio(), 1.0);
     }
 
+    #[test]
+    fn test_pattern_rich_output_is_stable() {
+        // Many short overlapping patterns, as in `benches/pipeline.rs`;
+        // the expected output predates slice-keyed pattern bookkeeping
+        let mut tokens: Vec<Token> = Vec::new();
+        let (mut filler, mut i) = (100_000, 0u32);
+        while tokens.len() < 5_000 {
+            let p = i % 32;
+            tokens.extend((0..2 + p % 7).map(|t| p * 16 + t));
+            tokens.push(filler);
+            filler += 1;
+            i = i.wrapping_mul(31).wrapping_add(7) % 1009;
+        }
+
+        let checksum = |tokens: &[Token]| {
+            tokens
+                .iter()
+                .fold(0u64, |acc, &t| acc.wrapping_mul(1_000_003).wrapping_add(t as u64))
+        };
+        let config = CompressionConfig::default();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.compressed_length, 1950);
+        assert_eq!(checksum(&result.serialized_tokens), 16274616001026467631);
+    }
+
+    #[test]
+    fn test_coalesce_runs_adds_super_pattern() {
+        let motif: [Token; 5] = [11, 12, 13, 14, 15];
+        let mut tokens: Vec<Token> = vec![1, 2, 3];
+        tokens.extend(motif.repeat(16));
+        tokens.extend([4, 5, 6]);
+
+        // Hints give the per-occurrence selection discovery would aim for
+        let positions: Vec<usize> = (0..16).map(|i| 3 + i * motif.len()).collect();
+        let hints = || vec![(motif.to_vec(), positions.clone())];
+        let naive = CompressionConfig {
+            hierarchical_enabled: false,
+            max_dict_entry_length: 20,
+            ..Default::default()
+        };
+        let coalesced = CompressionConfig {
+            coalesce_runs: true,
+            verify_full: true,
+            ..naive.clone()
+        };
+        let plain = compress_with_hints(&tokens, hints(), &naive).unwrap();
+        let result = compress_with_hints(&tokens, hints(), &coalesced).unwrap();
+
+        assert_eq!(plain.dictionary_map.len(), 1);
+        assert!(result.dictionary_map.values().any(|def| def.len() > motif.len()));
+        assert!(result.compression_ratio() < plain.compression_ratio());
+
+        let restored =
+            decompress_internal(&result.serialized_tokens, &coalesced, DEFAULT_NEXT_META_TOKEN)
+                .unwrap();
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_auto_delimiters_sit_above_input() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..40 {
+            tokens.extend([1000, 7, 500, 42, i * 20]);
+        }
+        let config = CompressionConfig {
+            auto_delimiters: true,
+            ..Default::default()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        let header = [DELIMITER_HEADER_TOKEN, 1001, 1002, 1003];
+        assert_eq!(result.serialized_tokens[..4], header);
+        assert_eq!(result.serialized_tokens[4], 1001);
+        assert!(result.dictionary_map.keys().all(|&meta| meta >= 1003));
+        assert!(result.compressed_length < tokens.len());
+
+        // An unconfigured decoder reads the delimiters from the header
+        let decode = CompressionConfig::default();
+        let serialized = &result.serialized_tokens;
+        let restored = decompress_internal(serialized, &decode, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(restored, tokens);
+
+        // Each headered frame keeps its own delimiters
+        let low: Vec<Token> = [3, 1, 4, 1, 5].repeat(10);
+        let second = compress_internal(&low, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        // The input length exceeds its tokens, so the delimiters sit above it
+        assert_eq!(second.serialized_tokens[1], 51);
+        let mut stream = result.serialized_tokens.clone();
+        stream.extend(&second.serialized_tokens);
+        let restored = decompress_internal(&stream, &decode, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(restored, [tokens, low].concat());
+    }
+
+    #[test]
+    fn test_auto_delimiters_sit_above_lengths_and_run_counts() {
+        let tokens = vec![
+            1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 0, 1, 0, 0, 0, 1, 0,
+            0, 1,
+        ];
+        for body_runs in [false, true] {
+            let config = CompressionConfig {
+                auto_delimiters: true,
+                body_runs,
+                ..Default::default()
+            };
+            let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+        }
+    }
+
+    #[test]
+    fn test_raw_stream_opening_with_header_token_is_framed() {
+        let config = CompressionConfig::default();
+        let tokens = vec![DELIMITER_HEADER_TOKEN, 1, 2, 3, 4];
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(result.dictionary_map.is_empty());
+        assert_eq!(result.serialized_tokens[0], config.dict_start_token);
+        let serialized = &result.serialized_tokens;
+        let restored = decompress_internal(serialized, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(restored, tokens);
+
+        // Elsewhere in a raw stream the token is an ordinary literal
+        let tokens = vec![1, DELIMITER_HEADER_TOKEN, 2, 3, 4];
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens, tokens);
+    }
+
+    #[test]
+    fn test_no_compression_reasons() {
+        let compress = |tokens: &[Token], config: &CompressionConfig| {
+            compress_internal(tokens, config, DEFAULT_NEXT_META_TOKEN).unwrap()
+        };
+        let config = CompressionConfig::default();
+
+        let result = compress(&[1, 2, 3], &config);
+        assert_eq!(result.reason, Some(NoCompressionReason::TooSmall));
+
+        // Seven tokens fall under the default threshold of twice min_len 4
+        let tokens = [1, 2, 3, 4, 1, 2, 3];
+        let min_len_4 = CompressionConfig {
+            min_subsequence_length: 4,
+            ..Default::default()
+        };
+        let result = compress(&tokens, &min_len_4);
+        assert_eq!(result.reason, Some(NoCompressionReason::TooSmall));
+        let lowered = CompressionConfig {
+            min_input_length: 4,
+            ..min_len_4
+        };
+        let result = compress(&tokens, &lowered);
+        assert_ne!(result.reason, Some(NoCompressionReason::TooSmall));
+
+        let unique: Vec<Token> = (1..100).collect();
+        let result = compress(&unique, &config);
+        assert_eq!(result.reason, Some(NoCompressionReason::NoPatterns));
+
+        // The dictionary cap leaves no pattern to select
+        let repeated: Vec<Token> = [1, 2, 3, 4].repeat(4);
+        let capped = CompressionConfig {
+            max_dict_fraction: 0.01,
+            ..Default::default()
+        };
+        let result = compress(&repeated, &capped);
+        assert_eq!(result.reason, Some(NoCompressionReason::NoViableSelection));
+
+        // Escaping meta-token-range literals outweighs the savings
+        let mut escaped = repeated.clone();
+        escaped.extend((0..20).map(|i| DEFAULT_NEXT_META_TOKEN + i));
+        let result = compress(&escaped, &config);
+        assert_eq!(result.reason, Some(NoCompressionReason::NotBeneficial));
+        assert_eq!(result.serialized_tokens, escaped);
+
+        let result = compress(&repeated, &config);
+        assert!(!result.dictionary_map.is_empty());
+        assert_eq!(result.reason, None);
+    }
+
+    #[test]
+    fn test_hierarchical_never_grows_depth_one_result() {
+        let mut state: u32 = 7;
+        let mut next = move |range: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % range
+        };
+
+        let mut inputs: Vec<Vec<Token>> = vec![
+            [1, 2, 3, 4, 1, 2, 3, 5].repeat(20),
+            [[1, 2, 3].repeat(3), vec![9]].concat().repeat(12),
+        ];
+        for alphabet in [3, 6, 20] {
+            inputs.push((0..400).map(|_| next(alphabet)).collect());
+        }
+        let phrases: Vec<Vec<Token>> = (0..5).map(|_| (0..4).map(|_| next(50)).collect()).collect();
+        inputs.push((0..120).flat_map(|_| phrases[next(5) as usize].clone()).collect());
+
+        for tokens in &inputs {
+            let single = CompressionConfig {
+                hierarchical_max_depth: 1,
+                ..Default::default()
+            };
+            let depth_one = compress_internal(tokens, &single, DEFAULT_NEXT_META_TOKEN).unwrap();
+            for depth in 2..5 {
+                let config = CompressionConfig {
+                    hierarchical_max_depth: depth,
+                    verify_full: true,
+                    ..Default::default()
+                };
+                let result = compress_internal(tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+                assert!(result.compressed_length <= depth_one.compressed_length);
+                assert_eq!(result.serialized_tokens.len(), result.compressed_length);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_output_is_stable() {
+        // Guards the serialized format against changes in pattern storage.
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..4 {
+            tokens.extend(1..=10);
+            tokens.push(100 + i);
+        }
+        for i in 0..6 {
+            tokens.extend([20, 21, 22]);
+            tokens.push(200 + i);
+        }
+        let config = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        let (a, b) = (DEFAULT_NEXT_META_TOKEN, DEFAULT_NEXT_META_TOKEN + 1);
+        let (start, end) = (config.dict_start_token, config.dict_end_token);
+        let expected: Vec<Token> = vec![
+            start, a, 3, 20, 21, 22, b, 8, 1, 2, 3, 4, 5, 6, 7, 8, end,
+            b, 9, 10, 100, b, 9, 10, 101, b, 9, 10, 102, b, 9, 10, 103,
+            a, 200, a, 201, a, 202, a, 203, a, 204, a, 205,
+        ];
+        assert_eq!(result.serialized_tokens, expected);
+    }
+
+    #[test]
+    fn test_compress_output_independent_of_hasher() {
+        // Patterns of equal length and count tie on every ranking key, so
+        // their meta-token assignment must not follow map iteration order.
+        // The expected output is the same with and without `fast-hash`.
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..4 {
+            for pattern in [[7, 8, 9], [4, 5, 6], [1, 2, 3]] {
+                tokens.extend(pattern);
+                tokens.push(100 + i);
+            }
+        }
+        let config = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        let (a, b, c) = (
+            DEFAULT_NEXT_META_TOKEN,
+            DEFAULT_NEXT_META_TOKEN + 1,
+            DEFAULT_NEXT_META_TOKEN + 2,
+        );
+        let mut expected: Vec<Token> = vec![config.dict_start_token];
+        expected.extend([a, 3, 1, 2, 3, b, 3, 4, 5, 6, c, 3, 7, 8, 9]);
+        expected.push(config.dict_end_token);
+        for i in 0..4 {
+            expected.extend([c, 100 + i, b, 100 + i, a, 100 + i]);
+        }
+        assert_eq!(result.serialized_tokens, expected);
+    }
+
+    #[test]
+    fn test_first_pass_respects_meta_token_pool() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            for j in 0..4 {
+                tokens.extend([10 * i + 1, 10 * i + 2, 10 * i + 3, 10 * i + 4, 1000 + j]);
+            }
+        }
+        let config = CompressionConfig {
+            hierarchical_enabled: false,
+            verify: true,
+            ..Default::default()
+        };
+        let unbounded = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(unbounded.dictionary_map.len(), 6);
+
+        let config = CompressionConfig {
+            meta_token_pool_size: 3,
+            ..config
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(result.dictionary_map.len() <= 3);
+        assert!(!result.dictionary_map.is_empty());
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_hierarchical_respects_meta_token_pool() {
+        let tokens: Vec<Token> = (1..=8).cycle().take(8 * 20).collect();
+        let config = CompressionConfig {
+            max_subsequence_length: 4,
+            hierarchical_max_depth: 3,
+            verify: true,
+            ..Default::default()
+        };
+
+        // Unbounded, the extra passes add entries beyond the first two.
+        let unbounded = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(unbounded.dictionary_map.len() > 2);
+
+        let config = CompressionConfig {
+            meta_token_pool_size: 2,
+            ..config
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert_eq!(result.dictionary_map.len(), 2);
+        let pool = DEFAULT_NEXT_META_TOKEN..DEFAULT_NEXT_META_TOKEN + 2;
+        assert!(result.dictionary_map.keys().all(|id| pool.contains(id)));
+
+        let dictionary = parse_dictionary(&result.serialized_tokens, &config).unwrap();
+        let restored = dict_decompress(&result.serialized_tokens, &dictionary, &config);
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_hierarchical_stops_at_end_of_token_range() {
+        let tokens: Vec<Token> = (11..=18).cycle().take(8 * 20).collect();
+        let config = CompressionConfig {
+            max_subsequence_length: 4,
+            hierarchical_max_depth: 3,
+            meta_token_direction: MetaTokenDirection::Descending,
+            verify: true,
+            ..Default::default()
+        };
+
+        // The first pass takes ids 1 and 0, leaving none to step to
+        let result = compress_internal(&tokens, &config, 1).unwrap();
+
+        let mut ids: Vec<Token> = result.dictionary_map.keys().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [0, 1]);
+        let restored = decompress_internal(&result.serialized_tokens, &config, 1).unwrap();
+        assert_eq!(restored, tokens);
+
+        assert_eq!(MetaTokenDirection::Descending.step(1, 2), None);
+        assert_eq!(MetaTokenDirection::Ascending.step(Token::MAX, 1), None);
+        assert_eq!(MetaTokenDirection::Ascending.step(Token::MAX - 1, 1), Some(Token::MAX));
+    }
+
+    #[test]
+    fn test_hierarchical_keeps_escaped_literals_above_pool() {
+        // 0xFFFFFFF9 lies above the meta-token pool but is still escaped in
+        // the body; the copy outside [_, 0xFFFFFFF9, 7, _] stays a literal
+        let above_pool: Token = 0xFFFFFFF9;
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..10 {
+            tokens.extend([1, 2, 3, 4, 100 + i, above_pool, 7, 200 + i]);
+        }
+        tokens.extend([300, above_pool, 301]);
+        let config = CompressionConfig {
+            min_subsequence_length: 3,
+            ..Default::default()
+        };
+        assert!(config.hierarchical_enabled);
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(result.compressed_length < tokens.len());
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_compress_with_cache_matches_fresh() {
+        let mut stream: Vec<Token> = Vec::new();
+        for i in 0..40 {
+            stream.extend([1, 2, 3, 4, 5]);
+            stream.push(100 + i);
+            stream.extend([6, 7, 8]);
+        }
+        // Irregular repeats, so patterns straddle every window boundary
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        for _ in 0..200 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            stream.push((state >> 60) as Token);
+        }
+        let windows = [
+            &stream[0..240],
+            &stream[60..300],
+            &stream[200..400],
+            &stream[250..460],
+        ];
+        let config = CompressionConfig::default();
+        let mut cache = DiscoveryCache::new(DiscoveryConfig::from_compression_config(&config));
+
+        for (i, window) in windows.iter().enumerate() {
+            let before = cache.discovered_tokens();
+            let cached = compress_with_cache(window, &mut cache, &config).unwrap();
+            let fresh = compress_internal(window, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+            assert_eq!(cached.serialized_tokens, fresh.serialized_tokens);
+            if i > 0 {
+                assert!(cache.discovered_tokens() - before < window.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_with_hints_matches_discovery() {
+        let pattern = vec![1, 2, 3, 4, 5, 6];
+        let mut tokens: Vec<Token> = Vec::new();
+        for filler in [100, 101, 102] {
+            tokens.extend(&pattern);
+            tokens.push(filler);
+        }
+        let config = CompressionConfig {
+            max_subsequence_length: 6,
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+
+        let expected = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let hinted =
+            compress_with_hints(&tokens, vec![(pattern, vec![0, 7, 14])], &config).unwrap();
+
+        assert!(hinted.compressed_length < hinted.original_length);
+        assert_eq!(hinted.serialized_tokens, expected.serialized_tokens);
+    }
+
+    #[test]
+    fn test_compress_with_hints_rejects_mismatch() {
+        let tokens: Vec<Token> = vec![1, 2, 3, 1, 2, 3, 1, 2, 4];
+        let config = CompressionConfig::default();
+
+        let result = compress_with_hints(&tokens, vec![(vec![1, 2, 3], vec![0, 3, 6])], &config);
+
+        match result {
+            Err(CompressionError::InvalidHint { hint, reason }) => {
+                assert_eq!(hint, 0);
+                assert!(reason.contains("position 6"));
+            }
+            other => panic!("expected InvalidHint, got {:?}", other.map(|r| r.serialized_tokens)),
+        }
+    }
+
     #[test]
     fn test_streaming_compressor() {
         let config = CompressionConfig::default();
@@ -403,6 +1979,7 @@ mod tests {
             chunks: Vec::new(),
             config,
             next_meta_token: 0xFFFF0000,
+            flushed: false,
         };
 
         compressor.add_chunk(&[1, 2, 3, 1, 2, 3, 1, 2, 3]);
@@ -413,4 +1990,832 @@ mod tests {
         // Should compress combined input
         assert!(result.original_length == 15);
     }
+
+    #[test]
+    fn test_degenerate_inputs() {
+        let config = CompressionConfig::default();
+        let inputs: [&[Token]; 3] = [&[], &[7], &[7, 7]];
+
+        for tokens in inputs {
+            let result = compress_internal(tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert_eq!(result.serialized_tokens, tokens);
+            assert_eq!(result.compressed_length, tokens.len());
+            let restored = decode(&result.serialized_tokens, &config);
+            assert_eq!(restored, tokens);
+
+            let mut compressor = StreamingCompressor {
+                chunks: Vec::new(),
+                config: config.clone(),
+                next_meta_token: DEFAULT_NEXT_META_TOKEN,
+                flushed: false,
+            };
+            compressor.add_chunk(tokens);
+            let streamed = compressor.finish_internal().unwrap();
+            assert_eq!(streamed.serialized_tokens, tokens);
+
+            let discovery_config = DiscoveryConfig::from_compression_config(&config);
+            assert!(discover_candidates(tokens, &discovery_config).is_empty());
+        }
+
+        let empty = StreamingCompressor {
+            chunks: Vec::new(),
+            config,
+            next_meta_token: DEFAULT_NEXT_META_TOKEN,
+            flushed: false,
+        };
+        assert!(empty.finish_internal().unwrap().serialized_tokens.is_empty());
+    }
+
+    #[test]
+    fn test_for_each_pattern_reports_every_candidate() {
+        let tokens: Vec<Token> = [1, 2, 3, 4].iter().cycle().take(40).copied().collect();
+        let config = DiscoveryConfig {
+            min_length: 2,
+            max_length: 6,
+            ..Default::default()
+        };
+        let expected = discover_candidates(&tokens, &config).len();
+        assert!(expected > 0);
+
+        let mut calls = 0;
+        let reported = for_each_pattern::<CompressionError>(&tokens, 2, 6, |_| {
+            calls += 1;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(calls, expected);
+        assert_eq!(reported, expected);
+    }
+
+    #[test]
+    fn test_for_each_pattern_stops_on_callback_error() {
+        let tokens: Vec<Token> = [1, 2, 3, 4].iter().cycle().take(40).copied().collect();
+
+        let mut calls = 0;
+        let result = for_each_pattern(&tokens, 2, 6, |_| {
+            calls += 1;
+            Err(CompressionError::VerificationFailed)
+        });
+
+        assert_eq!(result, Err(CompressionError::VerificationFailed));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_max_dict_fraction_trims_dictionary() {
+        // Many distinct patterns, each used only three times
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut separator = 10_000;
+        for p in 0..30 {
+            for _ in 0..3 {
+                tokens.extend([p * 10 + 1, p * 10 + 2, p * 10 + 3, p * 10 + 4]);
+                tokens.push(separator);
+                separator += 1;
+            }
+        }
+        let dict_fraction = |result: &CompressionResult| {
+            result.dictionary_tokens.len() as f64 / result.serialized_tokens.len() as f64
+        };
+
+        let uncapped_config = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+        let uncapped =
+            compress_internal(&tokens, &uncapped_config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(dict_fraction(&uncapped) > 0.3);
+
+        // Hierarchical passes would otherwise bring the trimmed patterns back
+        for hierarchical_enabled in [false, true] {
+            let config = CompressionConfig {
+                max_dict_fraction: 0.3,
+                hierarchical_enabled,
+                ..Default::default()
+            };
+            let capped = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+            assert!(!capped.dictionary_map.is_empty());
+            assert!(capped.dictionary_map.len() < uncapped.dictionary_map.len());
+            assert!(dict_fraction(&capped) <= 0.3);
+            assert_eq!(decode(&capped.serialized_tokens, &config), tokens);
+        }
+    }
+
+    #[test]
+    fn test_long_input_falls_back_to_segments() {
+        let config = CompressionConfig {
+            max_single_pass_length: 100,
+            ..Default::default()
+        };
+        // The last segment has nothing to compress and stays as is
+        let mut tokens: Vec<Token> = [1, 2, 3, 4, 5].iter().cycle().take(300).copied().collect();
+        tokens.extend(1000..1050);
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let frames = result
+            .serialized_tokens
+            .iter()
+            .filter(|&&t| t == config.dict_start_token)
+            .count();
+        assert_eq!(frames, 4);
+        assert!(result.compressed_length < tokens.len());
+        let sections = result.dictionary_tokens.len() + result.body_tokens.len();
+        assert_eq!(sections, result.compressed_length);
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+
+        let single_pass = CompressionConfig::default();
+        let whole = compress_internal(&tokens, &single_pass, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(decode(&whole.serialized_tokens, &single_pass), tokens);
+        assert_ne!(whole.serialized_tokens, result.serialized_tokens);
+    }
+
+    #[test]
+    fn test_compress_with_suffix_array_matches_compress() {
+        let tokens: Vec<Token> = [1, 2, 3, 4, 9, 1, 2, 3, 7, 8].repeat(8);
+        let sa = SuffixArray::build(&tokens);
+        let configs = [
+            CompressionConfig::default(),
+            CompressionConfig {
+                body_runs: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                detect_mirrored: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                max_gaps: 1,
+                ..Default::default()
+            },
+            CompressionConfig {
+                hierarchical_enabled: false,
+                dict_encoding: "nested".to_string(),
+                ..Default::default()
+            },
+            CompressionConfig {
+                auto_delimiters: true,
+                ..Default::default()
+            },
+        ];
+        for config in &configs {
+            let expected = compress_internal(&tokens, config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            let result = compress_with_suffix_array(&tokens, &sa, config).unwrap();
+            assert_eq!(result.serialized_tokens, expected.serialized_tokens);
+            assert!(result.compressed_length < tokens.len());
+        }
+
+        // Too small inputs are left as is before the array is looked at
+        let config = CompressionConfig::default();
+        let small = &tokens[..3];
+        let expected = compress_internal(small, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let result = compress_with_suffix_array(small, &sa, &config).unwrap();
+        assert_eq!(result.serialized_tokens, expected.serialized_tokens);
+
+        // An array built for other tokens is rejected
+        let other = SuffixArray::build(&tokens[1..]);
+        assert!(matches!(
+            compress_with_suffix_array(&tokens, &other, &config),
+            Err(CompressionError::InvalidConfig(_))
+        ));
+
+        // So are settings that discover over a transformed input
+        let unsupported = [
+            circular_config(),
+            CompressionConfig {
+                channels: 2,
+                ..Default::default()
+            },
+            CompressionConfig {
+                strict_python_parity: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                max_gaps: 1,
+                equivalence_classes: vec![vec![7, 9]],
+                ..Default::default()
+            },
+            CompressionConfig {
+                max_single_pass_length: 40,
+                ..Default::default()
+            },
+        ];
+        for config in &unsupported {
+            assert!(matches!(
+                compress_with_suffix_array(&tokens, &sa, config),
+                Err(CompressionError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decompress_into_reuses_buffer() {
+        let config = CompressionConfig::default();
+        let streams: Vec<Vec<Token>> = (0..4)
+            .map(|i| [1, 2, 3, 4 + i].repeat(12 - 2 * i as usize))
+            .map(|tokens| compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap())
+            .map(|result| result.serialized_tokens)
+            .collect();
+
+        let mut out = Vec::new();
+        let mut capacity = 0;
+        for stream in &streams {
+            decompress_into(stream, &config, &mut out).unwrap();
+            let fresh = decompress_internal(stream, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert_eq!(out, fresh);
+
+            // Streams shrink, so the first call's buffer serves every later one
+            if capacity != 0 {
+                assert_eq!(out.capacity(), capacity);
+            }
+            capacity = out.capacity();
+        }
+
+        // A failed decode leaves the buffer empty but keeps its capacity
+        let dangling = [config.dict_start_token, config.dict_end_token, DEFAULT_NEXT_META_TOKEN];
+        assert!(decompress_into(&dangling, &config, &mut out).is_err());
+        assert!(out.is_empty());
+        assert_eq!(out.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_accept_tie_keeps_equal_length_output() {
+        // The pattern saves two tokens and two escaped literals cost them back
+        let mut tokens: Vec<Token> = [1, 2, 3, 4].repeat(4);
+        tokens.extend([DEFAULT_NEXT_META_TOKEN + 100, DEFAULT_NEXT_META_TOKEN + 101]);
+
+        let config = CompressionConfig::default();
+        let raw = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(raw.reason, Some(NoCompressionReason::NotBeneficial));
+        assert_eq!(raw.serialized_tokens, tokens);
+
+        let tie = CompressionConfig {
+            accept_tie: true,
+            ..Default::default()
+        };
+        let kept = compress_internal(&tokens, &tie, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(kept.reason, None);
+        assert_eq!(kept.compressed_length, tokens.len());
+        assert!(!kept.dictionary_tokens.is_empty());
+        assert_eq!(decode(&kept.serialized_tokens, &tie), tokens);
+
+        // Longer output is still discarded
+        tokens.push(DEFAULT_NEXT_META_TOKEN + 102);
+        let longer = compress_internal(&tokens, &tie, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(longer.reason, Some(NoCompressionReason::NotBeneficial));
+    }
+
+    #[test]
+    fn test_canonical_output_is_lexicographically_minimal() {
+        // Equal-length patterns: the more frequent one would be entered first
+        let mut tokens = Vec::new();
+        for i in 0..4 {
+            tokens.extend([5, 6, 7, 100 + i]);
+        }
+        for i in 0..3 {
+            tokens.extend([1, 2, 3, 200 + i]);
+        }
+        let plain = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+        let canonical = CompressionConfig {
+            canonical_output: true,
+            ..plain.clone()
+        };
+        let descending = CompressionConfig {
+            meta_token_direction: MetaTokenDirection::Descending,
+            ..canonical.clone()
+        };
+
+        let (start, end) = (plain.dict_start_token, plain.dict_end_token);
+        let base = DEFAULT_NEXT_META_TOKEN;
+        for (input, config, ids) in [
+            (tokens.clone(), &canonical, [base, base + 1]),
+            (tokens.iter().rev().copied().collect(), &canonical, [base, base + 1]),
+            (tokens.clone(), &descending, [base - 1, base]),
+        ] {
+            let result = compress_internal(&input, config, base).unwrap();
+            let default = CompressionConfig {
+                canonical_output: false,
+                ..config.clone()
+            };
+            let free = compress_internal(&input, &default, base).unwrap();
+            assert_eq!(result.compressed_length, free.compressed_length);
+            assert!(result.serialized_tokens <= free.serialized_tokens);
+
+            let expected = if input == tokens {
+                [start, ids[0], 3, 1, 2, 3, ids[1], 3, 5, 6, 7, end]
+            } else {
+                [start, ids[0], 3, 3, 2, 1, ids[1], 3, 7, 6, 5, end]
+            };
+            assert_eq!(result.dictionary_tokens, expected);
+            let again = compress_internal(&input, config, base).unwrap();
+            assert_eq!(again.serialized_tokens, result.serialized_tokens);
+            assert_eq!(decode(&result.serialized_tokens, config), input);
+        }
+    }
+
+    #[test]
+    fn test_token_types_keep_patterns_within_one_type() {
+        // Structural tokens 1 and 2 wrap repeated content
+        let mut tokens = Vec::new();
+        for i in 0..12 {
+            tokens.extend([1, 10, 11, 12, 2, 100 + i]);
+        }
+        let config = CompressionConfig {
+            verify: true,
+            ..Default::default()
+        };
+        let typed = CompressionConfig {
+            token_types: [(1, 1), (2, 1)].into_iter().collect(),
+            ..config.clone()
+        };
+        let single_type = |config: &CompressionConfig, pattern: &[Token]| {
+            pattern.iter().all(|&t| config.token_type(t) == config.token_type(pattern[0]))
+        };
+
+        let mixed = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(mixed.dictionary_map.values().any(|p| !single_type(&typed, p)));
+
+        let result = compress_internal(&tokens, &typed, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.reason, None);
+        let patterns: Vec<&Vec<Token>> = result.dictionary_map.values().collect();
+        assert_eq!(patterns, [&vec![10, 11, 12]]);
+        assert_eq!(decode(&result.serialized_tokens, &typed), tokens);
+    }
+
+    #[test]
+    fn test_original_hash_is_stable() {
+        // Fixed by FNV-1a over little-endian bytes, on every platform
+        assert_eq!(hash_tokens(&[1, 2, 3]), 18239313798490686357);
+        assert_ne!(hash_tokens(&[1, 2, 3]), hash_tokens(&[3, 2, 1]));
+
+        let config = CompressionConfig::default();
+        let tokens: Vec<Token> = [1, 2, 3, 4, 5].repeat(10);
+        let first = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let again = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(first.original_hash, hash_tokens(&tokens));
+        assert_eq!(first.original_hash, again.original_hash);
+        let decoded = decode(&first.serialized_tokens, &config);
+        assert_eq!(hash_tokens(&decoded), first.original_hash);
+
+        // Set whether or not the input compresses, and however it is framed
+        let framed = CompressionConfig {
+            always_framed: true,
+            ..Default::default()
+        };
+        for config in [CompressionConfig::default(), framed] {
+            let result = compress_internal(&[7, 8, 9], &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert_eq!(result.original_hash, hash_tokens(&[7, 8, 9]));
+        }
+    }
+
+    #[test]
+    fn test_circular_discovery_finds_wrapped_patterns() {
+        // [10..16] occurs once in the input and once wrapping from its end
+        // to its start
+        let mut tokens: Vec<Token> = vec![13, 14, 15];
+        tokens.extend([1, 2, 3, 4].repeat(5));
+        tokens.extend(10..16);
+        tokens.extend([1, 2, 3, 4].repeat(5));
+        tokens.extend([10, 11, 12]);
+        let n = tokens.len();
+
+        let linear = DiscoveryConfig::default();
+        let ring = DiscoveryConfig {
+            circular: true,
+            ..linear.clone()
+        };
+        let wrapped: Vec<Token> = (10..16).collect();
+        let find = |config: &DiscoveryConfig| {
+            discover_candidates(&tokens, config)
+                .into_iter()
+                .find(|c| c.subsequence[..] == wrapped[..])
+        };
+        assert!(find(&linear).is_none());
+        let candidate = find(&ring).unwrap();
+        assert_eq!(candidate.positions().collect::<Vec<_>>(), vec![23, n - 3]);
+    }
+
+    /// [1..=8] twice and once wrapping from the end, where [1..=5] is
+    /// left, to the start, between runs of unique tokens.
+    fn ring_input() -> Vec<Token> {
+        let pattern: Vec<Token> = (1..=8).collect();
+        let mut tokens: Vec<Token> = pattern[5..].to_vec();
+        for filler in [100, 200, 300] {
+            tokens.extend(filler..filler + 10);
+            if filler < 300 {
+                tokens.extend(&pattern);
+            }
+        }
+        tokens.extend(&pattern[..5]);
+        tokens
+    }
+
+    fn circular_config() -> CompressionConfig {
+        CompressionConfig {
+            circular: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_circular_compression_replaces_wrapped_occurrence() {
+        let pattern: Vec<Token> = (1..=8).collect();
+        let tokens = ring_input();
+        let config = circular_config();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        // The ring is opened after the 3 tokens that end the wrapped
+        // occurrence, leaving no token of the pattern as a literal
+        assert_eq!(&result.serialized_tokens[..2], &[ROTATION_HEADER_TOKEN, 3]);
+        assert!(result.body_tokens.iter().all(|t| !pattern.contains(t)));
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+
+        let linear = CompressionConfig::default();
+        let linear = compress_internal(&tokens, &linear, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(result.compressed_length < linear.compressed_length);
+    }
+
+    #[test]
+    fn test_rotated_ring_spans_follow_input_order() {
+        let tokens = ring_input();
+        let config = circular_config();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens[0], ROTATION_HEADER_TOKEN);
+
+        // Only the unique runs are left as literals
+        let spans = result.uncompressed_spans(&config).unwrap();
+        assert_eq!(spans, vec![(3, 13), (21, 31), (39, 49)]);
+
+        let input_spans: Vec<spans::Span> = (0..tokens.len() as u32).map(|i| (i, i + 1)).collect();
+        let remapped = spans::remap_spans(&result, &input_spans, &config).unwrap();
+        let wrapped = remapped.body.iter().flatten().filter(|span| span.1 - span.0 > 1);
+        // The occurrence wrapping the ring's end spans the whole input
+        assert!(wrapped.clone().any(|&span| span == (0, tokens.len() as u32)));
+        assert_eq!(wrapped.count(), 3);
+
+        let positions = dictionary::decompress_with_positions(&result.serialized_tokens, &config);
+        assert!(matches!(positions, Err(CompressionError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_edit_of_rotated_ring_decodes() {
+        let tokens = ring_input();
+        let config = circular_config();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens[0], ROTATION_HEADER_TOKEN);
+
+        let edits: [(usize, usize, Vec<Token>); 2] = [(5, 0, vec![]), (5, 2, vec![900])];
+        for (start, len, new_tokens) in edits {
+            let edited = edit::apply_edit(&result, start, len, &new_tokens, &config).unwrap();
+            let mut expected = tokens.clone();
+            expected.splice(start..start + len, new_tokens);
+            assert_eq!(decode(&edited.serialized_tokens, &config), expected);
+        }
+    }
+
+    #[test]
+    fn test_circular_raw_output_opening_with_rotation_header_is_framed() {
+        let config = CompressionConfig {
+            circular: true,
+            ..Default::default()
+        };
+        let tokens = vec![ROTATION_HEADER_TOKEN, 1, 2, 3];
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens[0], config.dict_start_token);
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_raw_output_holding_escape_literal_is_framed() {
+        let config = CompressionConfig::default();
+        let tokens = vec![9, config.escape_token, 8];
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens[0], config.dict_start_token);
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_escaped_dict_start_literal_does_not_open_a_frame() {
+        let config = CompressionConfig::default();
+        let mut tokens: Vec<Token> = [1, 2, 3, 4].repeat(10);
+        tokens.extend([config.dict_start_token, 5, 6, 7]);
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(!result.dictionary_map.is_empty());
+        assert_eq!(split_frames(&result.serialized_tokens, &config).len(), 1);
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_push_windowed_frames_decode_to_input() {
+        let config = CompressionConfig {
+            always_framed: true,
+            ..Default::default()
+        };
+        let mut compressor = StreamingCompressor {
+            chunks: Vec::new(),
+            config: config.clone(),
+            next_meta_token: DEFAULT_NEXT_META_TOKEN,
+            flushed: false,
+        };
+        let input: Vec<Token> =
+            (0..500).map(|i| [1, 2, 3, 4, 5][i % 5] + (i / 100) as Token).collect();
+
+        // Chunks of uneven sizes, as a stream reader might deliver them
+        let mut out = Vec::new();
+        let mut frames = 0;
+        for chunk in input.chunks(37) {
+            let before = out.len();
+            compressor.push_windowed(chunk, 64, &mut out).unwrap();
+            frames += (out.len() > before) as usize;
+            assert!(compressor.memory_usage() < 64 * 4);
+        }
+        out.extend(compressor.finish_internal().unwrap().serialized_tokens);
+
+        assert_eq!(frames, input.len() / 64);
+        assert!(out.len() < input.len());
+        assert_eq!(decode(&out, &config), input);
+    }
+
+    #[test]
+    fn test_flush_emits_independent_frames() {
+        let config = CompressionConfig::default();
+        let mut compressor = StreamingCompressor {
+            chunks: Vec::new(),
+            config: config.clone(),
+            next_meta_token: DEFAULT_NEXT_META_TOKEN,
+            flushed: false,
+        };
+        let first: Vec<Token> = [1, 2, 3, 4].iter().cycle().take(40).copied().collect();
+        let second: Vec<Token> = [7, 8, 9].iter().cycle().take(30).copied().collect();
+
+        compressor.add_chunk(&first[..20]);
+        compressor.add_chunk(&first[20..]);
+        let frame_one = compressor.flush_internal().unwrap();
+        assert_eq!(compressor.memory_usage(), 0);
+
+        compressor.add_chunk(&second);
+        let frame_two = compressor.flush_internal().unwrap();
+        // Too short to compress, and holding one of frame two's meta-tokens
+        let raw = [5, DEFAULT_NEXT_META_TOKEN];
+        compressor.add_chunk(&raw);
+        let tail = compressor.finish_internal().unwrap();
+
+        assert!(frame_one.compressed_length < first.len());
+        assert!(frame_two.compressed_length < second.len());
+        assert_eq!(decode(&frame_two.serialized_tokens, &config), second);
+        assert_eq!(tail.reason, Some(NoCompressionReason::TooSmall));
+        assert_eq!(tail.serialized_tokens[0], config.dict_start_token);
+
+        let mut stream = frame_one.serialized_tokens;
+        stream.extend(&frame_two.serialized_tokens);
+        stream.extend(&tail.serialized_tokens);
+        let mut expected = first;
+        expected.extend(&second);
+        expected.extend(raw);
+        assert_eq!(decode(&stream, &config), expected);
+    }
+
+    #[test]
+    fn test_escape_preserves_literal_meta_token() {
+        let config = CompressionConfig::default();
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 4, 100 + i]);
+        }
+        // Same ids the dictionary assigns, plus the escape token itself
+        tokens.extend([DEFAULT_NEXT_META_TOKEN, 5, config.escape_token, 6]);
+        tokens.extend([DEFAULT_NEXT_META_TOKEN + 1, 1, 2, 3, 4]);
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(result.dictionary_map.contains_key(&DEFAULT_NEXT_META_TOKEN));
+        assert!(result.body_tokens.contains(&config.escape_token));
+        assert!(result.compressed_length < tokens.len());
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+
+        let runs_config = CompressionConfig {
+            body_runs: true,
+            ..config.clone()
+        };
+        let runs = compress_internal(&tokens, &runs_config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let restored = decode(&runs.serialized_tokens, &runs_config);
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_body_runs_never_grow_output() {
+        // Every literal becomes a one-token run, costing two more tokens
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..50 {
+            tokens.extend([1, 2, 100 + i]);
+        }
+        let config = CompressionConfig {
+            body_runs: true,
+            verify_full: true,
+            ..Default::default()
+        };
+        let plain = CompressionConfig::default();
+        let plain = compress_internal(&tokens, &plain, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(plain.compressed_length < tokens.len());
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.reason, Some(NoCompressionReason::NotBeneficial));
+        assert_eq!(result.serialized_tokens, tokens);
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_lenient_decompress_reports_unknown_meta_tokens() {
+        let config = CompressionConfig::default();
+        let known = DEFAULT_NEXT_META_TOKEN;
+        let unknown = DEFAULT_NEXT_META_TOKEN + 7;
+        let stream = vec![
+            config.dict_start_token,
+            known,
+            2,
+            1,
+            2,
+            config.dict_end_token,
+            known,
+            unknown,
+            9,
+            unknown,
+        ];
+
+        let (output, unresolved) =
+            decompress_lenient_internal(&stream, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(output, vec![1, 2, unknown, 9, unknown]);
+        assert_eq!(unresolved, vec![unknown]);
+
+        assert_eq!(
+            decompress_internal(&stream, &config, DEFAULT_NEXT_META_TOKEN),
+            Err(CompressionError::UnresolvedMetaTokens(vec![unknown]))
+        );
+    }
+
+    #[test]
+    fn test_blacklisted_pattern_stays_literal() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            tokens.extend([1, 2, 3, 4, 100 + i, 7, 8, 9]);
+        }
+        let literal_count =
+            |body: &[Token]| body.windows(4).filter(|w| *w == [1, 2, 3, 4]).count();
+        let config = CompressionConfig::default();
+        let baseline = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(literal_count(&baseline.body_tokens) < 8);
+
+        let config = CompressionConfig {
+            blacklist_patterns: vec![vec![1, 2, 3, 4]],
+            verify: true,
+            ..config
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert_eq!(literal_count(&result.body_tokens), 8);
+        assert!(result.dictionary_map.values().any(|p| p.as_slice() == [7, 8, 9]));
+        assert!(result
+            .dictionary_map
+            .values()
+            .all(|p| !p.iter().any(|t| [1, 2, 3, 4].contains(t))));
+        assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+    }
+
+    #[test]
+    fn test_always_framed_wraps_incompressible_input() {
+        let mut tokens: Vec<Token> = (0..20).collect();
+        tokens.extend([DEFAULT_NEXT_META_TOKEN, dictionary::DEFAULT_ESCAPE_TOKEN]);
+
+        for body_runs in [false, true] {
+            let config = CompressionConfig {
+                always_framed: true,
+                body_runs,
+                ..Default::default()
+            };
+            let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+            assert!(result.dictionary_map.is_empty());
+            assert_eq!(result.dictionary_tokens, [config.dict_start_token, config.dict_end_token]);
+            assert_eq!(&result.serialized_tokens[..2], result.dictionary_tokens.as_slice());
+            assert_eq!(result.compressed_length, result.serialized_tokens.len());
+            assert!(result.compressed_length > tokens.len());
+            assert_eq!(decode(&result.serialized_tokens, &config), tokens);
+        }
+
+        let short = [1, 2];
+        let config = CompressionConfig {
+            always_framed: true,
+            ..Default::default()
+        };
+        let result = compress_internal(&short, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.serialized_tokens[0], config.dict_start_token);
+        assert_eq!(decode(&result.serialized_tokens, &config), short);
+    }
+
+    #[test]
+    fn test_descending_meta_tokens_round_trip() {
+        let base: Token = 0xFFFF_FFEF;
+        let config = CompressionConfig {
+            max_subsequence_length: 4,
+            hierarchical_max_depth: 3,
+            meta_token_direction: MetaTokenDirection::Descending,
+            verify: true,
+            verify_full: true,
+            ..Default::default()
+        };
+        let tokens: Vec<Token> = (1..=8).cycle().take(8 * 20).collect();
+
+        let result = compress_internal(&tokens, &config, base).unwrap();
+        assert!(result.dictionary_map.len() > 2);
+
+        // Entries are serialized in assignment order, each id below the last
+        let parsed = parse_dictionary(&result.serialized_tokens, &config).unwrap();
+        let mut ids = Vec::new();
+        let mut pos = 1;
+        while result.dictionary_tokens[pos] != config.dict_end_token {
+            ids.push(result.dictionary_tokens[pos]);
+            pos += 2 + result.dictionary_tokens[pos + 1] as usize;
+        }
+        assert_eq!(ids.len(), parsed.len());
+        assert_eq!(ids[0], base);
+        assert!(ids.windows(2).all(|pair| pair[1] == pair[0] - 1));
+        assert_eq!(decompress_internal(&result.serialized_tokens, &config, base).unwrap(), tokens);
+
+        // Literals in the unassigned part of the pool are escaped
+        let mut tokens = tokens;
+        tokens.extend([base - 400, base - 1]);
+        let result = compress_internal(&tokens, &config, base).unwrap();
+        assert!(!result.dictionary_map.is_empty());
+        assert_eq!(decompress_internal(&result.serialized_tokens, &config, base).unwrap(), tokens);
+
+        let too_low = compress_internal(&tokens, &config, 0);
+        assert!(matches!(too_low, Err(CompressionError::InvalidConfig(_))));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_segmented_parallel_matches_sequential() {
+        let config = CompressionConfig::default();
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            tokens.extend([10 + i, 11, 12, 13].repeat(6));
+            tokens.push(0);
+        }
+        tokens.extend([1, 2, 3]);
+
+        let sequential = compress_segments(&tokens, 0, &config).unwrap();
+        let parallel = compress_segmented_parallel(&tokens, 0, &config).unwrap();
+        assert_eq!(parallel.serialized_tokens, sequential.serialized_tokens);
+        assert!(parallel.compressed_length < tokens.len());
+        assert_eq!(decode(&parallel.serialized_tokens, &config), tokens);
+
+        // Each segment's header starts its own meta-token range
+        let headers: HashSet<Token> = parallel
+            .serialized_tokens
+            .windows(4)
+            .filter(|w| w[0] == DELIMITER_HEADER_TOKEN)
+            .map(|w| w[3])
+            .collect();
+        assert_eq!(headers.len(), 9);
+
+        let reserved = [1, DELIMITER_HEADER_TOKEN];
+        assert!(matches!(
+            compress_segmented_parallel(&reserved, 0, &config),
+            Err(CompressionError::InvalidConfig(_))
+        ));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_discovery_matches_sequential_compression() {
+        use suffix_array_parallel::ParallelSAConfig;
+
+        let mut state: u32 = 7;
+        let mut tokens: Vec<Token> = Vec::new();
+        while tokens.len() < 12_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            match (state >> 16) % 4 {
+                0 => tokens.extend([10, 11, 12, 13, 14]),
+                1 => tokens.extend([20, 21, 22]),
+                _ => tokens.push((state >> 8) % 500),
+            }
+        }
+        // Large enough for `build_suffix_array_auto` to take the parallel path
+        assert!(tokens.len() >= ParallelSAConfig::default().parallel_threshold);
+
+        let parallel = CompressionConfig::default();
+        let sequential = CompressionConfig {
+            enable_parallel: false,
+            ..Default::default()
+        };
+        let with = compress_internal(&tokens, &parallel, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let without = compress_internal(&tokens, &sequential, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(!with.dictionary_map.is_empty());
+        assert_eq!(with.serialized_tokens, without.serialized_tokens);
+    }
 }
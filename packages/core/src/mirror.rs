@@ -0,0 +1,385 @@
+//! Discovery and encoding of mirrored repeats.
+//!
+//! A mirrored repeat is a pattern that also occurs reversed elsewhere in
+//! the input. Both orientations share one dictionary entry; a reversed
+//! occurrence is written to the body as `[REVERSE_MARKER, meta_token]` and
+//! expands to the entry's definition read backwards.
+
+use crate::discovery::DiscoveryConfig;
+use crate::hash::{PatternMap, PatternSet};
+use crate::suffix_array::{non_overlapping_positions, SuffixArray};
+use crate::types::{compute_savings, Candidate, Occurrence, Pattern, Token};
+use std::collections::HashMap;
+
+/// Maximum nesting depth followed when expanding meta-tokens.
+const MAX_EXPANSION_DEPTH: usize = 100;
+
+/// A pattern found both forwards and reversed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirroredCandidate {
+    /// The pattern in its forward orientation
+    pub subsequence: Pattern,
+    /// Positions where the pattern occurs as-is
+    pub positions: Vec<usize>,
+    /// Positions where the reversed pattern occurs
+    pub reversed_positions: Vec<usize>,
+}
+
+impl MirroredCandidate {
+    /// A candidate covering both orientations, for selection.
+    pub fn to_candidate(&self) -> Candidate {
+        let mut positions = self.positions.clone();
+        positions.extend(&self.reversed_positions);
+        Candidate::new(self.subsequence.clone(), positions)
+    }
+}
+
+/// Find patterns that recur in reversed form.
+///
+/// Builds one suffix array over `tokens ++ sentinel ++ reverse(tokens)`, so
+/// each LCP interval pairs forward occurrences of a pattern with forward
+/// occurrences of its reverse. Palindromes are left to regular discovery.
+/// A pattern is kept when both orientations survive overlap removal and
+/// the shared entry still saves tokens after paying one marker per
+/// reversed occurrence.
+pub fn discover_mirrored(tokens: &[Token], config: &DiscoveryConfig) -> Vec<MirroredCandidate> {
+    let n = tokens.len();
+    if n < config.min_length * 2 {
+        return Vec::new();
+    }
+
+    let present: PatternSet<Token> = tokens.iter().copied().collect();
+    let sentinel = match (0..=Token::MAX).rev().find(|t| !present.contains(t)) {
+        Some(sentinel) => sentinel,
+        None => return Vec::new(),
+    };
+
+    let mut combined = Vec::with_capacity(2 * n + 1);
+    combined.extend_from_slice(tokens);
+    combined.push(sentinel);
+    combined.extend(tokens.iter().rev());
+
+    let sa = SuffixArray::build(&combined);
+    let mut found: PatternMap<Pattern, (Vec<usize>, Vec<usize>)> = PatternMap::default();
+
+    // An interval counts forward and reversed occurrences together, so
+    // only the trivial bound applies
+    for (start_idx, end_idx, lcp_len) in sa.lcp_intervals(config.min_length, 2) {
+        let suffixes = &sa.suffix_array[start_idx..=end_idx];
+        for length in config.min_length..=lcp_len.min(config.max_length) {
+            let pattern = Pattern::from_slice(&combined[suffixes[0]..suffixes[0] + length]);
+            let reversed: Pattern = pattern.iter().rev().copied().collect();
+            // Each pair is reached from both orientations; keep the smaller
+            if pattern >= reversed {
+                continue;
+            }
+
+            let (forward, mirrored) = found.entry(pattern).or_default();
+            for &pos in suffixes {
+                if pos < n {
+                    forward.push(pos);
+                } else {
+                    // Reversed copy index j maps back to tokens[n - j - length..n - j]
+                    let j = pos - (n + 1);
+                    mirrored.push(n - j - length);
+                }
+            }
+        }
+    }
+
+    let mut candidates: Vec<MirroredCandidate> = found
+        .into_iter()
+        .filter_map(|(pattern, (forward, mirrored))| {
+            mirrored_candidate(pattern, forward, mirrored, config)
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.subsequence.cmp(&b.subsequence));
+    candidates
+}
+
+/// Resolve overlaps between both orientations and check the pair pays off.
+fn mirrored_candidate(
+    pattern: Pattern,
+    mut forward: Vec<usize>,
+    mut mirrored: Vec<usize>,
+    config: &DiscoveryConfig,
+) -> Option<MirroredCandidate> {
+    let length = pattern.len();
+    forward.sort_unstable();
+    forward.dedup();
+    mirrored.sort_unstable();
+    mirrored.dedup();
+
+    let mut all: Vec<usize> = forward.iter().chain(&mirrored).copied().collect();
+    all.sort_unstable();
+    let kept = non_overlapping_positions(&all, length);
+
+    let (positions, reversed_positions): (Vec<usize>, Vec<usize>) = kept
+        .iter()
+        .partition(|pos| forward.binary_search(pos).is_ok());
+    if positions.is_empty() || reversed_positions.is_empty() {
+        return None;
+    }
+
+    let count = kept.len();
+    let savings = compute_savings(length, count, config.extra_cost);
+    let min_count = config.min_occurrences.min_count(length, config.extra_cost);
+    if count < min_count || savings <= reversed_positions.len() as i64 {
+        return None;
+    }
+
+    Some(MirroredCandidate {
+        subsequence: pattern,
+        positions,
+        reversed_positions,
+    })
+}
+
+/// Build a body in which reversed occurrences carry the reverse marker.
+///
+/// An occurrence whose tokens do not match its pattern was selected as a
+/// mirrored occurrence and is written as `[marker, meta_token]`. Literals
+/// at or above the first meta-token, the marker and the escape are
+/// escaped, as in `build_body`.
+pub fn build_body_mirrored(
+    tokens: &[Token],
+    selected: &[Occurrence],
+    pattern_to_meta: &HashMap<Vec<Token>, Token>,
+    marker: Token,
+    escape_token: Token,
+) -> Vec<Token> {
+    let first_meta = pattern_to_meta.values().copied().min().unwrap_or(Token::MAX);
+    let copy_literals = |body: &mut Vec<Token>, literals: &[Token]| {
+        for &literal in literals {
+            if literal == marker || literal == escape_token || literal >= first_meta {
+                body.push(escape_token);
+            }
+            body.push(literal);
+        }
+    };
+
+    let mut sorted_selected: Vec<&Occurrence> = selected.iter().collect();
+    sorted_selected.sort_by_key(|occ| occ.start);
+
+    let mut body = Vec::with_capacity(tokens.len());
+    let mut pos = 0;
+
+    for occ in sorted_selected {
+        if pos < occ.start {
+            copy_literals(&mut body, &tokens[pos..occ.start]);
+        }
+
+        let span = &tokens[occ.start..occ.end()];
+        match pattern_to_meta.get(occ.subsequence.as_slice()) {
+            Some(&meta_token) => {
+                if span != occ.subsequence.as_slice() {
+                    body.push(marker);
+                }
+                body.push(meta_token);
+            }
+            None => copy_literals(&mut body, span),
+        }
+
+        pos = occ.end();
+    }
+
+    if pos < tokens.len() {
+        copy_literals(&mut body, &tokens[pos..]);
+    }
+
+    body
+}
+
+/// Expand a body that may contain reverse markers and escaped literals.
+pub fn expand_with_reversal(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    marker: Token,
+    escape_token: Token,
+) -> Vec<Token> {
+    let mut output = Vec::with_capacity(body.len() * 2);
+    expand_with_reversal_into(body, dictionary, marker, escape_token, &mut output);
+    output
+}
+
+/// `expand_with_reversal`, appending to `output`.
+pub fn expand_with_reversal_into(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    marker: Token,
+    escape_token: Token,
+    output: &mut Vec<Token>,
+) {
+    let markers = (marker, escape_token);
+    expand_into(body, dictionary, markers, 0, output);
+}
+
+fn expand_into(
+    tokens: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    markers: (Token, Token),
+    depth: usize,
+    output: &mut Vec<Token>,
+) {
+    let (marker, escape_token) = markers;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        if token == escape_token && i + 1 < tokens.len() {
+            output.push(tokens[i + 1]);
+            i += 2;
+            continue;
+        }
+        if token == marker && i + 1 < tokens.len() {
+            let start = output.len();
+            expand_into(&tokens[i + 1..i + 2], dictionary, markers, depth, output);
+            output[start..].reverse();
+            i += 2;
+            continue;
+        }
+
+        match dictionary.get(&token) {
+            Some(definition) if depth < MAX_EXPANSION_DEPTH => {
+                expand_into(definition, dictionary, markers, depth + 1, output);
+            }
+            _ => output.push(token),
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::{decompress, decompress_with_positions, parse_dictionary};
+    use crate::types::CompressionConfig;
+    use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+
+    fn mirrored_config() -> CompressionConfig {
+        CompressionConfig {
+            min_subsequence_length: 3,
+            max_subsequence_length: 3,
+            detect_mirrored: true,
+            ..Default::default()
+        }
+    }
+
+    fn mirrored_input() -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for i in 0..4 {
+            tokens.extend([1, 2, 3]);
+            tokens.push(100 + i);
+            tokens.extend([3, 2, 1]);
+            tokens.push(200 + i);
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_discover_mirrored_pairs_orientations() {
+        let tokens = mirrored_input();
+        let config = DiscoveryConfig {
+            min_length: 3,
+            max_length: 3,
+            ..Default::default()
+        };
+
+        let candidates = discover_mirrored(&tokens, &config);
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].subsequence[..], [1, 2, 3]);
+        assert_eq!(candidates[0].positions, vec![0, 8, 16, 24]);
+        assert_eq!(candidates[0].reversed_positions, vec![4, 12, 20, 28]);
+    }
+
+    #[test]
+    fn test_mirrored_repeats_share_one_entry() {
+        let tokens = mirrored_input();
+        let config = CompressionConfig {
+            min_subsequence_length: 3,
+            max_subsequence_length: 3,
+            detect_mirrored: true,
+            verify: true,
+            ..Default::default()
+        };
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert_eq!(result.dictionary_map.len(), 1);
+        assert_eq!(
+            result.dictionary_map.values().next().unwrap(),
+            &vec![1, 2, 3]
+        );
+        let markers = result
+            .body_tokens
+            .iter()
+            .filter(|&&t| t == config.reverse_marker_token)
+            .count();
+        assert_eq!(markers, 4);
+        assert!(result.compressed_length < tokens.len());
+
+        let dictionary = parse_dictionary(&result.serialized_tokens, &config).unwrap();
+        assert_eq!(
+            decompress(&result.serialized_tokens, &dictionary, &config),
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_reversed_occurrences_survive_body_runs() {
+        let tokens = vec![
+            1, 2, 3, 9, 3, 2, 1, 8, 1, 2, 3, 7, 3, 2, 1, 6, 1, 2, 3, 5, 3, 2, 1,
+        ];
+        let config = CompressionConfig {
+            body_runs: true,
+            ..mirrored_config()
+        };
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(result.body_tokens.contains(&config.reverse_marker_token));
+        let serialized = &result.serialized_tokens;
+        let restored = decompress_internal(serialized, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_raw_reverse_marker_literal_is_framed() {
+        let config = mirrored_config();
+        let tokens = vec![config.reverse_marker_token, 5];
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert_eq!(result.serialized_tokens[0], config.dict_start_token);
+        let serialized = &result.serialized_tokens;
+        let restored = decompress_internal(serialized, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(restored, tokens);
+    }
+
+    #[test]
+    fn test_positions_map_reversed_occurrences() {
+        let tokens = mirrored_input();
+        let config = mirrored_config();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        let (decoded, positions) =
+            decompress_with_positions(&result.serialized_tokens, &config).unwrap();
+
+        assert_eq!(decoded, tokens);
+        // Each reversed occurrence maps its meta-token, not the marker
+        let reversed: Vec<_> = positions
+            .iter()
+            .filter(|(_, range)| decoded[range.clone()] == [3, 2, 1])
+            .collect();
+        assert_eq!(reversed.len(), 4);
+        for (body_index, _) in reversed {
+            assert_eq!(result.body_tokens[body_index - 1], config.reverse_marker_token);
+        }
+        let mut next = 0;
+        for (_, range) in &positions {
+            assert_eq!(range.start, next);
+            next = range.end;
+        }
+        assert_eq!(next, tokens.len());
+    }
+}
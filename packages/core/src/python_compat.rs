@@ -0,0 +1,320 @@
+//! Decoding of streams produced by the Python `delta` implementation.
+//!
+//! The Python compressor works on string tokens and emits its special
+//! markers as dedicated vocabulary entries rather than raw integers. Once
+//! a tokenizer maps those strings to ids, a Python stream looks like:
+//!
+//! ```text
+//! <Dict> MT_a <Len:n> def_a... MT_b <Len:m> def_b... </Dict> body...
+//! ```
+//!
+//! It differs from this crate's native layout in three ways:
+//!
+//! * Length tokens are `<Len:n>` vocabulary entries, mapped here to
+//!   `length_token_base + n`, instead of the raw integer `n`.
+//! * With `dict_length_enabled = False` each entry runs until the next
+//!   meta-token header, so meta-tokens must be recognizable by id
+//!   (`meta_token_base..meta_token_base + meta_token_pool_size`).
+//! * Entries are ordered by dependency (a definition may reference earlier
+//!   meta-tokens), and body meta-tokens may be followed by a patch section
+//!   `<Patch> <Idx:i> token ... </Patch>` that overwrites positions of the
+//!   expansion. Index tokens map to `patch_index_base + i`.
+//!
+//! Port of the decode path in `delta/compressor.py`.
+
+use crate::dictionary::{DEFAULT_DICT_END, DEFAULT_DICT_START};
+use crate::types::Token;
+use std::collections::{HashMap, HashSet};
+
+/// Token ids assigned to the Python format's special markers.
+#[derive(Debug, Clone)]
+pub struct PythonFormatConfig {
+    /// Id of the `<Dict>` delimiter
+    pub dict_start_token: Token,
+    /// Id of the `</Dict>` delimiter
+    pub dict_end_token: Token,
+    /// Id of `<MT_0>`; `<MT_i>` maps to `meta_token_base + i`
+    pub meta_token_base: Token,
+    /// Number of meta-token ids reserved (Python `meta_token_pool_size`)
+    pub meta_token_pool_size: usize,
+    /// Whether entries carry `<Len:n>` tokens
+    pub dict_length_enabled: bool,
+    /// Id of `<Len:0>`; `<Len:n>` maps to `length_token_base + n`
+    pub length_token_base: Token,
+    /// Id of the `<Patch>` delimiter
+    pub patch_start_token: Token,
+    /// Id of the `</Patch>` delimiter
+    pub patch_end_token: Token,
+    /// Id of `<Idx:0>`; `<Idx:i>` maps to `patch_index_base + i`
+    pub patch_index_base: Token,
+}
+
+impl Default for PythonFormatConfig {
+    fn default() -> Self {
+        Self {
+            dict_start_token: DEFAULT_DICT_START,
+            dict_end_token: DEFAULT_DICT_END,
+            meta_token_base: 0xFFFF0000,
+            meta_token_pool_size: 500,
+            dict_length_enabled: true,
+            length_token_base: 0xFFFE0000,
+            patch_start_token: 0xFFFFFFF2,
+            patch_end_token: 0xFFFFFFF3,
+            patch_index_base: 0xFFFD0000,
+        }
+    }
+}
+
+impl PythonFormatConfig {
+    fn is_meta_token(&self, token: Token) -> bool {
+        token
+            .checked_sub(self.meta_token_base)
+            .is_some_and(|offset| (offset as usize) < self.meta_token_pool_size)
+    }
+}
+
+/// Decode a stream produced by the Python implementation.
+///
+/// Like the Python decoder, a stream that does not start with the
+/// dictionary delimiter (or has no closing delimiter) is returned as-is.
+/// Malformed entries stop dictionary parsing, and malformed patch sections
+/// are copied through literally.
+pub fn decode_delta_python(tokens: &[Token], config: &PythonFormatConfig) -> Vec<Token> {
+    if tokens.first() != Some(&config.dict_start_token) {
+        return tokens.to_vec();
+    }
+
+    let end_pos = match tokens[1..].iter().position(|&t| t == config.dict_end_token) {
+        Some(pos) => pos + 1,
+        None => return tokens.to_vec(),
+    };
+
+    let dictionary = parse_python_dictionary(&tokens[1..end_pos], config);
+    let body = &tokens[end_pos + 1..];
+
+    let mut memo: HashMap<Token, Vec<Token>> = HashMap::new();
+    let mut result = Vec::with_capacity(body.len() * 2);
+    let mut pos = 0;
+
+    while pos < body.len() {
+        let token = body[pos];
+        pos += 1;
+
+        if !dictionary.contains_key(&token) {
+            result.push(token);
+            continue;
+        }
+
+        let mut expanded = expand_token(token, &dictionary, &mut memo, &mut HashSet::new());
+
+        if body.get(pos) == Some(&config.patch_start_token) {
+            if let Some((patches, next)) = parse_patch_section(body, pos + 1, config) {
+                for (index, value) in patches {
+                    if let Some(slot) = expanded.get_mut(index) {
+                        *slot = value;
+                    }
+                }
+                pos = next;
+            }
+        }
+
+        result.extend(expanded);
+    }
+
+    result
+}
+
+/// Parse the tokens between `<Dict>` and `</Dict>`.
+fn parse_python_dictionary(
+    dict_tokens: &[Token],
+    config: &PythonFormatConfig,
+) -> HashMap<Token, Vec<Token>> {
+    let mut dictionary: HashMap<Token, Vec<Token>> = HashMap::new();
+
+    if config.dict_length_enabled {
+        let mut pos = 0;
+        while pos + 1 < dict_tokens.len() {
+            let meta_token = dict_tokens[pos];
+            if !config.is_meta_token(meta_token) || dictionary.contains_key(&meta_token) {
+                break;
+            }
+
+            let length = match dict_tokens[pos + 1].checked_sub(config.length_token_base) {
+                Some(len) => len as usize,
+                None => break,
+            };
+
+            let start = pos + 2;
+            if length > dict_tokens.len() - start {
+                break;
+            }
+
+            dictionary.insert(meta_token, dict_tokens[start..start + length].to_vec());
+            pos = start + length;
+        }
+    } else {
+        let mut current: Option<Token> = None;
+        for &token in dict_tokens {
+            // Every meta-token starts a new entry, so definitions cannot be
+            // nested in this layout.
+            if config.is_meta_token(token) {
+                if dictionary.contains_key(&token) {
+                    break;
+                }
+                dictionary.insert(token, Vec::new());
+                current = Some(token);
+                continue;
+            }
+            match current {
+                Some(meta_token) => dictionary.entry(meta_token).or_default().push(token),
+                None => break,
+            }
+        }
+    }
+
+    dictionary
+}
+
+/// Parse `<Idx:i> token ... </Patch>` starting after `<Patch>`.
+///
+/// Returns the patches and the position following `</Patch>`.
+fn parse_patch_section(
+    body: &[Token],
+    mut pos: usize,
+    config: &PythonFormatConfig,
+) -> Option<(Vec<(usize, Token)>, usize)> {
+    let mut patches = Vec::new();
+
+    while pos < body.len() && body[pos] != config.patch_end_token {
+        let index = body[pos].checked_sub(config.patch_index_base)? as usize;
+        let value = *body.get(pos + 1)?;
+        patches.push((index, value));
+        pos += 2;
+    }
+
+    if pos >= body.len() {
+        return None;
+    }
+
+    Some((patches, pos + 1))
+}
+
+/// Fully expand a meta-token, following nested references.
+fn expand_token(
+    token: Token,
+    dictionary: &HashMap<Token, Vec<Token>>,
+    memo: &mut HashMap<Token, Vec<Token>>,
+    in_progress: &mut HashSet<Token>,
+) -> Vec<Token> {
+    if let Some(expanded) = memo.get(&token) {
+        return expanded.clone();
+    }
+
+    let definition = match dictionary.get(&token) {
+        // A cyclic reference cannot be expanded; keep the token as-is.
+        Some(definition) if in_progress.insert(token) => definition,
+        _ => return vec![token],
+    };
+
+    let mut expanded = Vec::with_capacity(definition.len());
+    for &item in definition {
+        expanded.extend(expand_token(item, dictionary, memo, in_progress));
+    }
+
+    in_progress.remove(&token);
+    memo.insert(token, expanded.clone());
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mt(config: &PythonFormatConfig, index: u32) -> Token {
+        config.meta_token_base + index
+    }
+
+    fn len_token(config: &PythonFormatConfig, length: u32) -> Token {
+        config.length_token_base + length
+    }
+
+    #[test]
+    fn test_decode_with_length_tokens() {
+        let config = PythonFormatConfig::default();
+        // <Dict> <MT_3> <Len:2> 1 2 <MT_7> <Len:2> <MT_3> 3 </Dict> body
+        let tokens = vec![
+            config.dict_start_token,
+            mt(&config, 3),
+            len_token(&config, 2),
+            1,
+            2,
+            mt(&config, 7),
+            len_token(&config, 2),
+            mt(&config, 3),
+            3,
+            config.dict_end_token,
+            mt(&config, 7),
+            9,
+            mt(&config, 3),
+            mt(&config, 7),
+        ];
+
+        let decoded = decode_delta_python(&tokens, &config);
+        assert_eq!(decoded, vec![1, 2, 3, 9, 1, 2, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_decode_without_length_tokens() {
+        let config = PythonFormatConfig {
+            dict_length_enabled: false,
+            ..Default::default()
+        };
+        let tokens = vec![
+            config.dict_start_token,
+            mt(&config, 0),
+            1,
+            2,
+            3,
+            mt(&config, 1),
+            4,
+            5,
+            config.dict_end_token,
+            mt(&config, 1),
+            mt(&config, 0),
+            6,
+        ];
+
+        let decoded = decode_delta_python(&tokens, &config);
+        assert_eq!(decoded, vec![4, 5, 1, 2, 3, 6]);
+    }
+
+    #[test]
+    fn test_decode_with_patches() {
+        let config = PythonFormatConfig::default();
+        let tokens = vec![
+            config.dict_start_token,
+            mt(&config, 0),
+            len_token(&config, 3),
+            1,
+            2,
+            3,
+            config.dict_end_token,
+            mt(&config, 0),
+            config.patch_start_token,
+            config.patch_index_base + 1,
+            8,
+            config.patch_end_token,
+            mt(&config, 0),
+        ];
+
+        let decoded = decode_delta_python(&tokens, &config);
+        assert_eq!(decoded, vec![1, 8, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_decode_without_dictionary() {
+        let config = PythonFormatConfig::default();
+        let tokens = vec![1, 2, 3];
+        assert_eq!(decode_delta_python(&tokens, &config), tokens);
+    }
+}
@@ -0,0 +1,623 @@
+//! Compression that reproduces the Python `delta` output exactly.
+//!
+//! The native pipeline departs from Python in several places: discovery
+//! ranks tokens numerically where Python sorts their `repr`, selection
+//! tie-breaks differently, subsumed patterns are not pruned, and meta-tokens
+//! are numbered sequentially instead of popped from a shuffled pool. This
+//! module follows the Python code step by step so both implementations can
+//! be swapped and cross-checked.
+//!
+//! Python's string markers map onto integer ids: `<MT_i>` is
+//! `next_meta_token + i`, `<Len:n>` is `n`, and the dictionary delimiters
+//! are the configured delimiter tokens. Python's meta-token pool is
+//! shuffled with `random.Random(rng_seed)`, reproduced here with the same
+//! Mersenne Twister, so outputs only match for the same seed.
+//!
+//! Port of `compress` in `delta/compressor.py` for the default engine
+//! (suffix-array discovery, subsumption pruning, greedy selection).
+
+use crate::dictionary::{decompress, serialize_result, Dictionary};
+use crate::error::CompressionError;
+use crate::suffix_array::{non_overlapping_positions, SuffixArray};
+use crate::types::{
+    is_compressible, min_count_for_compressibility, CompressionConfig, CompressionResult,
+    NoCompressionReason, Pattern, Token,
+};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Python's `CompressionEngine.min_improvement_ratio`.
+const MIN_IMPROVEMENT_RATIO: f64 = 0.02;
+/// Python's `CompressionEngine.min_efficiency_ratio`.
+const MIN_EFFICIENCY_RATIO: f64 = 1.5;
+/// Python's `subsumption_min_independent` default.
+const MIN_INDEPENDENT_OCCURRENCES: usize = 2;
+/// Iteration cap of Python's greedy selection.
+const MAX_SELECTION_ITERATIONS: usize = 10;
+
+/// A discovered pattern with its non-overlapping positions.
+struct ParityCandidate {
+    subsequence: Pattern,
+    positions: Vec<usize>,
+}
+
+/// Compress exactly as the Python implementation would.
+///
+/// Only greedy selection is supported. Like Python, a result as long as
+/// the input is kept; only a longer one falls back to the input.
+pub fn compress_python_parity(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    next_meta_token: Token,
+) -> Result<CompressionResult, CompressionError> {
+    if config.selection_mode != "greedy" {
+        return Err(CompressionError::InvalidConfig(format!(
+            "strict_python_parity supports only greedy selection, got {:?}",
+            config.selection_mode
+        )));
+    }
+
+    let metas = MetaTokens {
+        base: next_meta_token,
+        pool_size: config.meta_token_pool_size,
+    };
+    let extra_cost = config.extra_cost();
+    let depth_limit = if config.hierarchical_enabled {
+        config.hierarchical_max_depth
+    } else {
+        1
+    };
+
+    let mut working = tokens.to_vec();
+    let mut dictionary: Vec<(Token, Pattern)> = Vec::new();
+    let mut prev_length = working.len();
+
+    for depth in 0..depth_limit {
+        let candidates = discover(&working, config, extra_cost, &metas);
+        if candidates.is_empty() {
+            break;
+        }
+        let candidates = prune_subsumed(candidates, extra_cost);
+        if candidates.is_empty() {
+            break;
+        }
+
+        let selected = select_greedy(&candidates, working.len(), extra_cost);
+        let (replacements, entries) =
+            assign_meta_tokens(&working, &selected, &candidates, config, &metas);
+        if entries.is_empty() {
+            break;
+        }
+
+        let new_dict_size: usize = entries.iter().map(|(_, p)| 1 + p.len() + extra_cost).sum();
+        for (meta, pattern) in entries {
+            // Python's dict.update: an existing key keeps its position
+            match dictionary.iter_mut().find(|(m, _)| *m == meta) {
+                Some(entry) => entry.1 = pattern,
+                None => dictionary.push((meta, pattern)),
+            }
+        }
+        working = replace(&working, &replacements);
+
+        let new_length = working.len();
+        if prev_length > 0 && depth > 0 {
+            let body_savings = prev_length as f64 - new_length as f64;
+            let improvement = body_savings / prev_length as f64;
+            let efficiency = body_savings / new_dict_size as f64;
+            if improvement < MIN_IMPROVEMENT_RATIO {
+                break;
+            }
+            if efficiency < MIN_EFFICIENCY_RATIO && new_dict_size > 0 {
+                break;
+            }
+        }
+        prev_length = new_length;
+    }
+
+    if dictionary.is_empty() {
+        let reason = NoCompressionReason::NoPatterns;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
+
+    let dict = serialize_dictionary(&dictionary, config, &metas)?;
+    if dict.tokens.len() + working.len() > tokens.len() {
+        let reason = NoCompressionReason::NotBeneficial;
+        return Ok(CompressionResult::no_compression(tokens.to_vec(), reason));
+    }
+    let result = serialize_result(&dict, &working, tokens, config);
+
+    // Replacements are not built by `build_body`, so either flag runs the
+    // full round trip
+    if config.verify || config.verify_full {
+        let restored = decompress(&result.serialized_tokens, &dict.entries, config);
+        if restored != tokens {
+            return Err(CompressionError::VerificationFailed);
+        }
+    }
+
+    Ok(result)
+}
+
+/// The id range Python would spell `<MT_0>` .. `<MT_{pool_size - 1}>`.
+struct MetaTokens {
+    base: Token,
+    pool_size: usize,
+}
+
+impl MetaTokens {
+    fn index(&self, token: Token) -> Option<usize> {
+        token
+            .checked_sub(self.base)
+            .map(|offset| offset as usize)
+            .filter(|&offset| offset < self.pool_size)
+    }
+
+    /// Python's `generate_meta_token_pool`: unused ids, shuffled.
+    fn pool(&self, existing: &[Token], seed: u64) -> Vec<Token> {
+        let used: HashSet<Token> = existing.iter().copied().collect();
+        let mut pool: Vec<Token> = (0..self.pool_size)
+            .map(|i| self.base.wrapping_add(i as Token))
+            .filter(|t| !used.contains(t))
+            .collect();
+        PythonRandom::new(seed).shuffle(&mut pool);
+        pool
+    }
+}
+
+/// Python's suffix-array ranking key: `(type name, repr)`.
+///
+/// Integers sort by their decimal text, and meta-tokens (strings in
+/// Python) sort after every integer.
+fn python_sort_key(token: Token, metas: &MetaTokens) -> (bool, String) {
+    match metas.index(token) {
+        Some(i) => (true, format!("<MT_{}>", i)),
+        None => (false, token.to_string()),
+    }
+}
+
+/// Port of `discover_candidates_sa`.
+fn discover(
+    tokens: &[Token],
+    config: &CompressionConfig,
+    extra_cost: usize,
+    metas: &MetaTokens,
+) -> Vec<ParityCandidate> {
+    let min_len = config.min_subsequence_length;
+    let max_len = config.max_subsequence_length;
+    if max_len < min_len {
+        return Vec::new();
+    }
+    let n = tokens.len();
+
+    let mut vocabulary: Vec<Token> = tokens.to_vec();
+    vocabulary.sort_unstable();
+    vocabulary.dedup();
+    vocabulary.sort_by_cached_key(|&t| python_sort_key(t, metas));
+    let ranks: HashMap<Token, Token> = vocabulary
+        .iter()
+        .enumerate()
+        .map(|(rank, &t)| (t, rank as Token + 1))
+        .collect();
+    let ranked: Vec<Token> = tokens.iter().map(|t| ranks[t]).collect();
+
+    let sa = SuffixArray::build(&ranked);
+    let intervals = sa.lcp_intervals(min_len, 2);
+    let interval_positions = |start: usize, end: usize| {
+        let mut positions = sa.suffix_array[start..=end].to_vec();
+        positions.sort_unstable();
+        positions
+    };
+
+    let mut seen: HashSet<Pattern> = HashSet::new();
+    let mut candidates = Vec::new();
+
+    // Longest to shortest within each interval
+    for &(start, end, lcp_len) in &intervals {
+        let positions = interval_positions(start, end);
+        for length in (min_len..=lcp_len.min(max_len)).rev() {
+            let non_overlapping = non_overlapping_positions(&positions, length);
+            if !is_compressible(length, non_overlapping.len(), extra_cost)
+                || positions[0] + length > n
+            {
+                continue;
+            }
+            let subsequence = Pattern::from_slice(&tokens[positions[0]..positions[0] + length]);
+            if seen.insert(subsequence.clone()) {
+                candidates.push(ParityCandidate {
+                    subsequence,
+                    positions: non_overlapping,
+                });
+            }
+        }
+    }
+
+    // Shorter patterns pooled across every interval they appear in
+    let mut order: Vec<Pattern> = Vec::new();
+    let mut pooled: HashMap<Pattern, HashSet<usize>> = HashMap::new();
+    for &(start, end, lcp_len) in &intervals {
+        let positions = interval_positions(start, end);
+        for length in min_len..=lcp_len.min(max_len) {
+            if positions[0] + length > n {
+                continue;
+            }
+            let subsequence = Pattern::from_slice(&tokens[positions[0]..positions[0] + length]);
+            let entry = pooled.entry(subsequence.clone()).or_insert_with(|| {
+                order.push(subsequence);
+                HashSet::new()
+            });
+            entry.extend(&positions);
+        }
+    }
+    for subsequence in order {
+        if seen.contains(&subsequence) {
+            continue;
+        }
+        let mut positions: Vec<usize> = pooled[&subsequence].iter().copied().collect();
+        positions.sort_unstable();
+        let non_overlapping = non_overlapping_positions(&positions, subsequence.len());
+        if is_compressible(subsequence.len(), non_overlapping.len(), extra_cost) {
+            seen.insert(subsequence.clone());
+            candidates.push(ParityCandidate {
+                subsequence,
+                positions: non_overlapping,
+            });
+        }
+    }
+
+    // Stable, like Python's sort
+    candidates.sort_by_key(|c| Reverse(c.subsequence.len()));
+    candidates
+}
+
+/// Port of `prune_subsumed_candidates`.
+fn prune_subsumed(candidates: Vec<ParityCandidate>, extra_cost: usize) -> Vec<ParityCandidate> {
+    let contains = |longer: &[Token], shorter: &[Token]| {
+        shorter.len() < longer.len() && longer.windows(shorter.len()).any(|w| w == shorter)
+    };
+
+    let mut keep = Vec::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        let pattern = candidate.subsequence.as_slice();
+        let mut covered: HashSet<usize> = HashSet::new();
+        let mut subsumed = false;
+        for (j, longer) in candidates.iter().enumerate() {
+            if i == j || !contains(&longer.subsequence, pattern) {
+                continue;
+            }
+            subsumed = true;
+            for (offset, window) in longer.subsequence.windows(pattern.len()).enumerate() {
+                if window == pattern {
+                    covered.extend(longer.positions.iter().map(|pos| pos + offset));
+                }
+            }
+        }
+
+        if !subsumed {
+            keep.push(ParityCandidate {
+                subsequence: candidate.subsequence.clone(),
+                positions: candidate.positions.clone(),
+            });
+            continue;
+        }
+
+        let independent: Vec<usize> = candidate
+            .positions
+            .iter()
+            .copied()
+            .filter(|pos| !covered.contains(pos))
+            .collect();
+        if independent.len() >= MIN_INDEPENDENT_OCCURRENCES
+            && is_compressible(pattern.len(), independent.len(), extra_cost)
+        {
+            keep.push(ParityCandidate {
+                subsequence: candidate.subsequence.clone(),
+                positions: independent,
+            });
+        }
+    }
+    keep
+}
+
+/// A selected occurrence: start position and index of its candidate.
+type Selected = (usize, usize);
+
+/// Start position -> (pattern length, meta-token).
+type Replacements = HashMap<usize, (usize, Token)>;
+
+/// Port of `_non_overlapping_with_compressibility`, Python's greedy mode.
+fn select_greedy(candidates: &[ParityCandidate], n: usize, extra_cost: usize) -> Vec<Selected> {
+    let length = |c: usize| candidates[c].subsequence.len();
+
+    let mut occurrences: Vec<Selected> = candidates
+        .iter()
+        .enumerate()
+        .flat_map(|(c, cand)| cand.positions.iter().map(move |&pos| (pos, c)))
+        .collect();
+    occurrences.sort_by_key(|&(start, c)| (start + length(c), start));
+
+    let mut viable: Vec<bool> = candidates
+        .iter()
+        .map(|cand| {
+            let min_count = min_count_for_compressibility(cand.subsequence.len(), extra_cost);
+            cand.positions.len() >= min_count
+        })
+        .collect();
+    let mut viable_occs: Vec<Selected> = occurrences
+        .into_iter()
+        .filter(|&(_, c)| viable[c])
+        .collect();
+    if viable_occs.is_empty() {
+        return Vec::new();
+    }
+
+    let density = |c: usize| (length(c) as f64 - 1.0) / length(c) as f64;
+    let mut selected: Vec<Selected> = Vec::new();
+    let mut counts: Vec<usize> = vec![0; candidates.len()];
+
+    for _ in 0..MAX_SELECTION_ITERATIONS {
+        let mut sorted = viable_occs.clone();
+        sorted.sort_by(|&(sa, ca), &(sb, cb)| {
+            density(cb)
+                .partial_cmp(&density(ca))
+                .unwrap_or(Ordering::Equal)
+                .then(sa.cmp(&sb))
+                .then(length(ca).cmp(&length(cb)))
+        });
+
+        selected.clear();
+        counts.iter_mut().for_each(|count| *count = 0);
+        let mut occupied = vec![false; n];
+        for (start, c) in sorted {
+            let span = start..start + length(c);
+            if occupied[span.clone()].iter().any(|&o| o) {
+                continue;
+            }
+            occupied[span].iter_mut().for_each(|o| *o = true);
+            selected.push((start, c));
+            counts[c] += 1;
+        }
+
+        let mut all_compressible = true;
+        for (c, &count) in counts.iter().enumerate() {
+            if count > 0 && !is_compressible(length(c), count, extra_cost) {
+                viable[c] = false;
+                all_compressible = false;
+            }
+        }
+        if all_compressible {
+            break;
+        }
+
+        viable_occs.retain(|&(_, c)| viable[c]);
+        if viable_occs.is_empty() {
+            selected.clear();
+            break;
+        }
+    }
+
+    selected.retain(|&(_, c)| is_compressible(length(c), counts[c], extra_cost));
+    selected.sort_by_key(|&(start, _)| start);
+    selected
+}
+
+/// Port of `perform_swaps`: one meta-token per selected pattern, in order
+/// of first occurrence, popped from the shuffled pool.
+fn assign_meta_tokens(
+    tokens: &[Token],
+    selected: &[Selected],
+    candidates: &[ParityCandidate],
+    config: &CompressionConfig,
+    metas: &MetaTokens,
+) -> (Replacements, Vec<(Token, Pattern)>) {
+    let mut pool = metas.pool(tokens, config.python_rng_seed);
+    let mut meta_of: HashMap<usize, Token> = HashMap::new();
+    let mut entries = Vec::new();
+
+    for &(_, c) in selected {
+        if meta_of.contains_key(&c) {
+            continue;
+        }
+        let Some(meta) = pool.pop() else { break };
+        meta_of.insert(c, meta);
+        entries.push((meta, candidates[c].subsequence.clone()));
+    }
+
+    let replacements = selected
+        .iter()
+        .filter_map(|&(start, c)| {
+            let length = candidates[c].subsequence.len();
+            meta_of.get(&c).map(|&meta| (start, (length, meta)))
+        })
+        .collect();
+    (replacements, entries)
+}
+
+/// Port of `build_body_tokens` without patches.
+fn replace(tokens: &[Token], replacements: &Replacements) -> Vec<Token> {
+    let mut body = Vec::with_capacity(tokens.len());
+    let mut idx = 0;
+    while idx < tokens.len() {
+        match replacements.get(&idx) {
+            Some(&(length, meta)) => {
+                body.push(meta);
+                idx += length;
+            }
+            None => {
+                body.push(tokens[idx]);
+                idx += 1;
+            }
+        }
+    }
+    body
+}
+
+/// Port of `build_dictionary_tokens`: entries in insertion order, except
+/// that an entry always follows the entries its definition references.
+fn serialize_dictionary(
+    entries: &[(Token, Pattern)],
+    config: &CompressionConfig,
+    metas: &MetaTokens,
+) -> Result<Dictionary, CompressionError> {
+    let mut deps: Vec<HashSet<Token>> = entries
+        .iter()
+        .map(|(_, pattern)| {
+            pattern
+                .iter()
+                .copied()
+                .filter(|&t| metas.index(t).is_some() && entries.iter().any(|(m, _)| *m == t))
+                .collect()
+        })
+        .collect();
+
+    let mut ordered: Vec<usize> = Vec::with_capacity(entries.len());
+    let mut ready: VecDeque<usize> = (0..entries.len()).filter(|&i| deps[i].is_empty()).collect();
+    while let Some(i) = ready.pop_front() {
+        ordered.push(i);
+        let meta = entries[i].0;
+        for (other, other_deps) in deps.iter_mut().enumerate() {
+            if other_deps.remove(&meta)
+                && other_deps.is_empty()
+                && !ordered.contains(&other)
+                && !ready.contains(&other)
+            {
+                ready.push_back(other);
+            }
+        }
+    }
+    if ordered.len() != entries.len() {
+        return Err(CompressionError::MalformedDictionary(
+            "cyclic meta-token dependencies".to_string(),
+        ));
+    }
+
+    let mut tokens = vec![config.dict_start_token];
+    for &i in &ordered {
+        let (meta, pattern) = &entries[i];
+        tokens.push(*meta);
+        if config.dict_length_enabled {
+            tokens.push(pattern.len() as Token);
+        }
+        tokens.extend(pattern.iter());
+    }
+    tokens.push(config.dict_end_token);
+
+    Ok(Dictionary {
+        entries: entries.iter().map(|(m, p)| (*m, p.to_vec())).collect(),
+        tokens,
+        pattern_to_meta: entries.iter().map(|(m, p)| (p.to_vec(), *m)).collect(),
+    })
+}
+
+/// CPython's Mersenne Twister, as far as `random.Random(seed).shuffle` uses it.
+struct PythonRandom {
+    state: [u32; 624],
+    index: usize,
+}
+
+impl PythonRandom {
+    /// Seed like `random.Random(seed)` with a non-negative integer.
+    fn new(seed: u64) -> Self {
+        let key: Vec<u32> = if seed >> 32 == 0 {
+            vec![seed as u32]
+        } else {
+            vec![seed as u32, (seed >> 32) as u32]
+        };
+
+        let mut state = [0u32; 624];
+        state[0] = 19650218;
+        for i in 1..624 {
+            let prev = state[i - 1];
+            state[i] = 1812433253u32
+                .wrapping_mul(prev ^ (prev >> 30))
+                .wrapping_add(i as u32);
+        }
+
+        let (mut i, mut j) = (1, 0);
+        for _ in 0..624.max(key.len()) {
+            let prev = state[i - 1];
+            state[i] = (state[i] ^ (prev ^ (prev >> 30)).wrapping_mul(1664525))
+                .wrapping_add(key[j])
+                .wrapping_add(j as u32);
+            i += 1;
+            j += 1;
+            if i >= 624 {
+                state[0] = state[623];
+                i = 1;
+            }
+            if j >= key.len() {
+                j = 0;
+            }
+        }
+        for _ in 0..623 {
+            let prev = state[i - 1];
+            state[i] =
+                (state[i] ^ (prev ^ (prev >> 30)).wrapping_mul(1566083941)).wrapping_sub(i as u32);
+            i += 1;
+            if i >= 624 {
+                state[0] = state[623];
+                i = 1;
+            }
+        }
+        state[0] = 0x80000000;
+
+        Self { state, index: 624 }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        if self.index >= 624 {
+            for i in 0..624 {
+                let y = (self.state[i] & 0x80000000) | (self.state[(i + 1) % 624] & 0x7fffffff);
+                let mag = if y & 1 == 0 { 0 } else { 0x9908b0df };
+                self.state[i] = self.state[(i + 397) % 624] ^ (y >> 1) ^ mag;
+            }
+            self.index = 0;
+        }
+
+        let mut y = self.state[self.index];
+        self.index += 1;
+        y ^= y >> 11;
+        y ^= (y << 7) & 0x9d2c5680;
+        y ^= (y << 15) & 0xefc60000;
+        y ^ (y >> 18)
+    }
+
+    /// `_randbelow`: rejection sampling on `n.bit_length()` random bits.
+    fn below(&mut self, n: usize) -> usize {
+        let bits = usize::BITS - n.leading_zeros();
+        loop {
+            let r = (self.next_u32() >> (32 - bits)) as usize;
+            if r < n {
+                return r;
+            }
+        }
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python_random_matches_cpython() {
+        // random.Random(0).shuffle(list(range(10)))
+        let mut items: Vec<u32> = (0..10).collect();
+        PythonRandom::new(0).shuffle(&mut items);
+        assert_eq!(items, [7, 8, 1, 5, 3, 4, 2, 0, 9, 6]);
+
+        // [random.Random(42).getrandbits(32) for _ in range(3)]
+        let mut rng = PythonRandom::new(42);
+        let draws: Vec<u32> = (0..3).map(|_| rng.next_u32()).collect();
+        assert_eq!(draws, [2746317213, 478163327, 107420369]);
+
+        // Seeds wider than 32 bits use two key words
+        let mut rng = PythonRandom::new((1 << 40) + 5);
+        assert_eq!([rng.next_u32(), rng.next_u32()], [2166296868, 2220160828]);
+    }
+}
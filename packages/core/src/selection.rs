@@ -5,35 +5,168 @@
 //!
 //! Port of `delta/selection.py`.
 
-use crate::types::{is_compressible, min_count_for_compressibility, Candidate, Occurrence, Token};
-use std::collections::{HashMap, HashSet};
+use crate::hash::{PatternMap, PatternSet};
+use crate::types::{
+    compute_savings, is_compressible, min_count_for_compressibility, Candidate, Occurrence,
+    Pattern, Token,
+};
+use serde::{Deserialize, Serialize};
+
+/// Refinement rounds run before selection stops waiting for convergence.
+pub const DEFAULT_MAX_ITERATIONS: usize = 10;
 
 /// Result of pattern selection.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SelectionResult {
     /// Selected occurrences, sorted by start position
     pub selected: Vec<Occurrence>,
 }
 
+/// Why refinement removed a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    /// Too few occurrences selected to be compressible, even counting those
+    /// other failing patterns blocked
+    Uncompressible,
+    /// Every failing pattern could have recovered, so all were removed
+    /// to make progress
+    Stalled,
+}
+
+/// One decision made during selection.
+///
+/// Refinement rounds (`iteration`) count from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SelectionEvent {
+    /// A pattern with too few occurrences to ever be compressible was
+    /// never considered
+    Unviable {
+        pattern: Vec<Token>,
+        occurrences: usize,
+    },
+    /// An occurrence was selected in a round
+    Accepted {
+        iteration: usize,
+        pattern: Vec<Token>,
+        start: usize,
+    },
+    /// An occurrence was passed over for overlapping the selected
+    /// occurrence starting at `blocked_by`
+    Overlapped {
+        iteration: usize,
+        pattern: Vec<Token>,
+        start: usize,
+        blocked_by: usize,
+    },
+    /// A pattern was removed after a round, with its selected count
+    Pruned {
+        iteration: usize,
+        pattern: Vec<Token>,
+        selected: usize,
+        reason: PruneReason,
+    },
+}
+
+/// Replay log of the decisions behind a selection, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectionTrace {
+    pub events: Vec<SelectionEvent>,
+}
+
+impl SelectionTrace {
+    /// `Pruned` events, as `(iteration, pattern, reason)`.
+    pub fn pruned(&self) -> impl Iterator<Item = (usize, &[Token], PruneReason)> {
+        self.events.iter().filter_map(|event| match event {
+            SelectionEvent::Pruned {
+                iteration,
+                pattern,
+                reason,
+                ..
+            } => Some((*iteration, pattern.as_slice(), *reason)),
+            _ => None,
+        })
+    }
+
+    fn push(trace: &mut Option<&mut SelectionTrace>, event: impl FnOnce() -> SelectionEvent) {
+        if let Some(trace) = trace {
+            trace.events.push(event());
+        }
+    }
+}
+
+/// Scoring formulas used by the selection algorithms.
+///
+/// Implement this to experiment with alternative scoring; `DefaultScorer`
+/// holds the built-in formulas.
+pub trait SelectionScorer {
+    /// Rank used by greedy selection; higher scores are taken first.
+    fn occurrence_score(&self, occ: &Occurrence) -> f64;
+
+    /// Weight of one occurrence in optimal selection, given the number of
+    /// non-overlapping occurrences its pattern is expected to keep.
+    fn pattern_weight(&self, occ: &Occurrence, expected_count: usize, extra_cost: usize) -> f64;
+}
+
+/// Built-in scoring: savings density for greedy selection and amortized
+/// net savings for optimal selection, both boosted by priority.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultScorer;
+
+impl SelectionScorer for DefaultScorer {
+    fn occurrence_score(&self, occ: &Occurrence) -> f64 {
+        savings_density(occ)
+    }
+
+    fn pattern_weight(&self, occ: &Occurrence, expected_count: usize, extra_cost: usize) -> f64 {
+        let dict_cost = (1 + occ.length + extra_cost) as f64 / expected_count as f64;
+        let savings = occ.length as f64 - 1.0 - occ.patches.len() as f64 - dict_cost;
+        savings.max(0.0) + occ.priority as f64 * 0.5
+    }
+}
+
 /// Compute savings-density score for an occurrence.
 ///
 /// Higher values indicate better compression value per position consumed.
+/// Gap fillers written after the meta-token count against the savings.
 #[inline]
 fn savings_density(occ: &Occurrence) -> f64 {
     if occ.length <= 1 {
         return 0.0;
     }
-    let pattern_savings = occ.length as f64 - 1.0;
+    let pattern_savings = (occ.length - 1).saturating_sub(occ.patches.len()) as f64;
     let density = pattern_savings / occ.length as f64;
     density + occ.priority as f64 * 0.1
 }
 
+/// `is_compressible` for a pattern whose occurrences are each followed by
+/// `fillers` gap tokens in the body.
+///
+/// Each occurrence saves as much as one of a pattern `fillers` tokens
+/// shorter, while the definition still holds every token.
+#[inline]
+fn compressible_with_fillers(
+    length: usize,
+    fillers: usize,
+    count: usize,
+    extra_cost: usize,
+) -> bool {
+    is_compressible(length.saturating_sub(fillers), count, extra_cost + fillers)
+}
+
+/// `min_count_for_compressibility` counterpart of `compressible_with_fillers`.
+#[inline]
+fn min_count_with_fillers(length: usize, fillers: usize, extra_cost: usize) -> usize {
+    min_count_for_compressibility(length.saturating_sub(fillers), extra_cost + fillers)
+}
+
 /// Build occurrence list from candidates.
 fn build_occurrences(candidates: &[Candidate]) -> Vec<Occurrence> {
     let mut occurrences = Vec::new();
 
     for cand in candidates {
-        for &pos in &cand.positions {
+        for pos in cand.positions() {
             let patches = cand.patches.get(&pos).cloned().unwrap_or_default();
             occurrences.push(Occurrence {
                 start: pos,
@@ -51,8 +184,8 @@ fn build_occurrences(candidates: &[Candidate]) -> Vec<Occurrence> {
 
 /// Group occurrences by their subsequence.
 #[allow(dead_code)]
-fn group_by_subsequence(occurrences: &[Occurrence]) -> HashMap<Vec<Token>, Vec<&Occurrence>> {
-    let mut grouped: HashMap<Vec<Token>, Vec<&Occurrence>> = HashMap::new();
+fn group_by_subsequence(occurrences: &[Occurrence]) -> PatternMap<Pattern, Vec<&Occurrence>> {
+    let mut grouped: PatternMap<Pattern, Vec<&Occurrence>> = PatternMap::default();
     for occ in occurrences {
         grouped
             .entry(occ.subsequence.clone())
@@ -62,27 +195,25 @@ fn group_by_subsequence(occurrences: &[Occurrence]) -> HashMap<Vec<Token>, Vec<&
     grouped
 }
 
-/// Estimate non-overlapping count for a group of occurrences.
-fn estimate_non_overlapping_count(occs: &[&Occurrence]) -> usize {
-    if occs.is_empty() {
-        return 0;
-    }
-
-    let mut sorted: Vec<_> = occs.iter().map(|o| o.start).collect();
-    sorted.sort_unstable();
-
-    let length = occs[0].length;
-    let mut count = 0;
-    let mut next_free = 0;
-
-    for &pos in &sorted {
-        if pos >= next_free {
-            count += 1;
-            next_free = pos + length;
+/// Estimate the non-overlapping count of every pattern among `indices`.
+///
+/// `indices` must be sorted by end position. Occurrences of one pattern
+/// share a length, so they arrive in start order and a single greedy pass
+/// per pattern counts them without grouping or re-sorting.
+fn expected_counts(occurrences: &[Occurrence], indices: &[usize]) -> PatternMap<Pattern, usize> {
+    let mut scan: PatternMap<&Pattern, (usize, usize)> = PatternMap::default();
+    for &i in indices {
+        let occ = &occurrences[i];
+        let (count, next_free) = scan.entry(&occ.subsequence).or_default();
+        if occ.start >= *next_free {
+            *count += 1;
+            *next_free = occ.end();
         }
     }
 
-    count
+    scan.into_iter()
+        .map(|(subsequence, (count, _))| (subsequence.clone(), count))
+        .collect()
 }
 
 /// Greedy selection with iterative refinement.
@@ -92,7 +223,66 @@ fn estimate_non_overlapping_count(occs: &[&Occurrence]) -> usize {
 /// 2. Greedily select non-overlapping occurrences
 /// 3. Release positions from patterns that didn't achieve compressibility
 /// 4. Repeat until stable (all selected patterns are compressible)
+///
+/// Patterns still not compressible after `DEFAULT_MAX_ITERATIONS` rounds
+/// are dropped entirely.
 pub fn select_greedy(candidates: &[Candidate], extra_cost: usize) -> SelectionResult {
+    select_greedy_with(candidates, extra_cost, DEFAULT_MAX_ITERATIONS, &DefaultScorer)
+}
+
+/// Greedy selection ranking occurrences with a custom scorer, running at
+/// most `max_iterations` refinement rounds.
+pub fn select_greedy_with<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+) -> SelectionResult {
+    select_greedy_traced(candidates, extra_cost, max_iterations, scorer, None)
+}
+
+/// `select_greedy_with`, re-ranking the surviving occurrences before every
+/// refinement round rather than once.
+///
+/// Rounds used to be ranked this way. Pruning keeps the ranked order, so
+/// the results are identical; this is the baseline the single ranking is
+/// tested and benchmarked against.
+pub fn select_greedy_reranking<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+) -> SelectionResult {
+    let ranking = Ranking::EveryRound;
+    select_greedy_ranked(candidates, extra_cost, max_iterations, scorer, ranking, None)
+}
+
+/// When greedy selection ranks the occurrences it offers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Ranking {
+    Once,
+    EveryRound,
+}
+
+fn select_greedy_traced<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+    trace: Option<&mut SelectionTrace>,
+) -> SelectionResult {
+    let ranking = Ranking::Once;
+    select_greedy_ranked(candidates, extra_cost, max_iterations, scorer, ranking, trace)
+}
+
+fn select_greedy_ranked<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+    ranking: Ranking,
+    mut trace: Option<&mut SelectionTrace>,
+) -> SelectionResult {
     if candidates.is_empty() {
         return SelectionResult {
             selected: Vec::new(),
@@ -106,35 +296,37 @@ pub fn select_greedy(candidates: &[Candidate], extra_cost: usize) -> SelectionRe
         };
     }
 
-    // Pre-compute minimum counts for each pattern length
-    let mut min_counts_cache: HashMap<usize, usize> = HashMap::new();
-    let get_min_count = |length: usize, cache: &mut HashMap<usize, usize>| -> usize {
-        *cache
-            .entry(length)
-            .or_insert_with(|| min_count_for_compressibility(length, extra_cost))
-    };
-
-    // Group occurrences by subsequence
-    let mut subseq_to_occs: HashMap<Vec<Token>, Vec<usize>> = HashMap::new();
-    for (i, occ) in occurrences.iter().enumerate() {
-        subseq_to_occs
-            .entry(occ.subsequence.clone())
-            .or_default()
-            .push(i);
+    // Bookkeeping is per pattern id rather than per cloned pattern
+    let (pattern_of, patterns) = pattern_ids(&occurrences);
+    let mut occurrence_counts = vec![0usize; patterns.len()];
+    let mut fillers = vec![0usize; patterns.len()];
+    for (occ, &id) in occurrences.iter().zip(&pattern_of) {
+        occurrence_counts[id] += 1;
+        fillers[id] = occ.patches.len();
     }
+    let compressible = |id: usize, count| {
+        compressible_with_fillers(patterns[id].len(), fillers[id], count, extra_cost)
+    };
 
     // Filter out patterns that can never be compressible
-    let mut viable_subseqs: HashSet<Vec<Token>> = HashSet::new();
-    for (subseq, indices) in &subseq_to_occs {
-        let min_count = get_min_count(subseq.len(), &mut min_counts_cache);
-        if indices.len() >= min_count {
-            viable_subseqs.insert(subseq.clone());
+    let mut viable: Vec<bool> = (0..patterns.len())
+        .map(|id| {
+            let min_count = min_count_with_fillers(patterns[id].len(), fillers[id], extra_cost);
+            occurrence_counts[id] >= min_count
+        })
+        .collect();
+    for (id, pattern) in patterns.iter().enumerate() {
+        if !viable[id] {
+            SelectionTrace::push(&mut trace, || SelectionEvent::Unviable {
+                pattern: pattern.to_vec(),
+                occurrences: occurrence_counts[id],
+            });
         }
     }
 
     // Filter occurrences to only viable patterns
     let mut viable_indices: Vec<usize> = (0..occurrences.len())
-        .filter(|&i| viable_subseqs.contains(&occurrences[i].subsequence))
+        .filter(|&i| viable[pattern_of[i]])
         .collect();
 
     if viable_indices.is_empty() {
@@ -144,76 +336,107 @@ pub fn select_greedy(candidates: &[Candidate], extra_cost: usize) -> SelectionRe
     }
 
     // Iterative refinement loop
-    let max_iterations = 10;
     let mut selected_indices: Vec<usize> = Vec::new();
-    let mut subseq_counts: HashMap<Vec<Token>, usize> = HashMap::new();
-
-    for _iteration in 0..max_iterations {
-        // Sort by savings-density (highest first)
-        viable_indices.sort_by(|&a, &b| {
-            let da = savings_density(&occurrences[a]);
-            let db = savings_density(&occurrences[b]);
+    let mut counts = vec![0usize; patterns.len()];
+    let mut converged = false;
+
+    // Sort by savings-density (highest first). This is the only sort:
+    // pruning removes occurrences with `retain`, which keeps the order, so
+    // each later round is a single linear `greedy_fill` pass.
+    let rank = |indices: &mut Vec<usize>| {
+        indices.sort_by(|&a, &b| {
+            let da = scorer.occurrence_score(&occurrences[a]);
+            let db = scorer.occurrence_score(&occurrences[b]);
             db.partial_cmp(&da)
                 .unwrap_or(std::cmp::Ordering::Equal)
                 .then_with(|| occurrences[a].start.cmp(&occurrences[b].start))
-        });
-
-        selected_indices.clear();
-        subseq_counts.clear();
-        let mut occupied: HashSet<usize> = HashSet::new();
-
-        for &idx in &viable_indices {
-            let occ = &occurrences[idx];
-            let positions: HashSet<usize> = (occ.start..occ.start + occ.length).collect();
-
-            if !positions.is_disjoint(&occupied) {
-                continue;
-            }
+        })
+    };
+    rank(&mut viable_indices);
 
-            selected_indices.push(idx);
-            occupied.extend(positions);
-            *subseq_counts.entry(occ.subsequence.clone()).or_default() += 1;
+    for iteration in 1..=max_iterations {
+        if ranking == Ranking::EveryRound && iteration > 1 {
+            rank(&mut viable_indices);
         }
-
-        // Find patterns that achieved compressibility
-        let mut compressible_subseqs: HashSet<Vec<Token>> = HashSet::new();
-        let mut non_compressible_subseqs: HashSet<Vec<Token>> = HashSet::new();
-
-        for (subseq, &count) in &subseq_counts {
-            if is_compressible(subseq.len(), count, extra_cost) {
-                compressible_subseqs.insert(subseq.clone());
-            } else {
-                non_compressible_subseqs.insert(subseq.clone());
-            }
+        let occupant = greedy_fill(
+            &occurrences,
+            &pattern_of,
+            &viable_indices,
+            &mut selected_indices,
+            &mut counts,
+            (iteration, trace.as_deref_mut()),
+        );
+
+        // Find selected patterns that missed compressibility
+        let mut failing = vec![false; patterns.len()];
+        for (id, &count) in counts.iter().enumerate() {
+            failing[id] = count > 0 && !compressible(id, count);
         }
+        let non_compressible: Vec<usize> = (0..patterns.len()).filter(|&id| failing[id]).collect();
 
         // If all selected patterns are compressible, we're done
-        if non_compressible_subseqs.is_empty() {
+        if non_compressible.is_empty() {
+            converged = true;
             break;
         }
 
+        // Patterns that only fell short because other failing patterns took
+        // their positions get another chance once those competitors are gone.
+        let recoverable = recoverable_counts(
+            &pattern_of,
+            &occurrences,
+            &viable_indices,
+            &occupant,
+            &failing,
+        );
+        let mut pruned: Vec<usize> = non_compressible
+            .iter()
+            .copied()
+            .filter(|&id| {
+                !compressible(id, counts[id] + recoverable[id])
+            })
+            .collect();
+        let mut reason = PruneReason::Uncompressible;
+        if pruned.is_empty() {
+            pruned = non_compressible;
+            reason = PruneReason::Stalled;
+        }
+
         // Remove non-compressible patterns from viable set and retry
-        for subseq in &non_compressible_subseqs {
-            viable_subseqs.remove(subseq);
+        for id in pruned {
+            viable[id] = false;
+            SelectionTrace::push(&mut trace, || SelectionEvent::Pruned {
+                iteration,
+                pattern: patterns[id].to_vec(),
+                selected: counts[id],
+                reason,
+            });
         }
-        viable_indices.retain(|&i| viable_subseqs.contains(&occurrences[i].subsequence));
+        viable_indices.retain(|&i| viable[pattern_of[i]]);
 
         if viable_indices.is_empty() {
             selected_indices.clear();
+            converged = true;
             break;
         }
     }
 
-    // Final filter: only keep compressible patterns
-    let mut final_selected: Vec<Occurrence> = Vec::new();
-    for &idx in &selected_indices {
-        let occ = &occurrences[idx];
-        let count = subseq_counts.get(&occ.subsequence).copied().unwrap_or(0);
-        if is_compressible(occ.length, count, extra_cost) {
-            final_selected.push(occ.clone());
-        }
+    // Re-offer positions freed by the last pruning round so the final
+    // counts reflect what the surviving patterns can actually claim.
+    if !converged {
+        greedy_fill(
+            &occurrences,
+            &pattern_of,
+            &viable_indices,
+            &mut selected_indices,
+            &mut counts,
+            (max_iterations + 1, trace),
+        );
     }
 
+    let mut final_selected: Vec<Occurrence> =
+        selected_indices.iter().map(|&idx| occurrences[idx].clone()).collect();
+    retain_compressible(&mut final_selected, extra_cost);
     final_selected.sort_by_key(|occ| occ.start);
 
     SelectionResult {
@@ -221,11 +444,146 @@ pub fn select_greedy(candidates: &[Candidate], extra_cost: usize) -> SelectionRe
     }
 }
 
+/// Number each distinct pattern among `occurrences`.
+///
+/// Returns each occurrence's pattern id and the pattern for each id. The
+/// patterns are slices of the occurrences' own storage, so none is copied.
+fn pattern_ids(occurrences: &[Occurrence]) -> (Vec<usize>, Vec<&[Token]>) {
+    let mut ids: PatternMap<&[Token], usize> = PatternMap::default();
+    let mut patterns: Vec<&[Token]> = Vec::new();
+    let pattern_of = occurrences
+        .iter()
+        .map(|occ| {
+            let pattern = occ.subsequence.as_slice();
+            *ids.entry(pattern).or_insert_with(|| {
+                patterns.push(pattern);
+                patterns.len() - 1
+            })
+        })
+        .collect();
+    (pattern_of, patterns)
+}
+
+/// Drop every occurrence of patterns not compressible at their selected count.
+///
+/// Counts are taken from `selected` itself, so the guarantee holds whether
+/// or not refinement converged.
+fn retain_compressible(selected: &mut Vec<Occurrence>, extra_cost: usize) {
+    let mut counts: PatternMap<Pattern, usize> = PatternMap::default();
+    for occ in selected.iter() {
+        *counts.entry(occ.subsequence.clone()).or_default() += 1;
+    }
+    selected.retain(|occ| {
+        let count = counts[&occ.subsequence];
+        compressible_with_fillers(occ.length, occ.patches.len(), count, extra_cost)
+    });
+}
+
+/// Greedily take non-overlapping occurrences in the order given.
+///
+/// Fills `selected` and `counts` (selected occurrences per pattern id) and
+/// returns a map from each covered position to the occurrence covering it.
+/// Decisions are traced as made in the given round.
+fn greedy_fill(
+    occurrences: &[Occurrence],
+    pattern_of: &[usize],
+    order: &[usize],
+    selected: &mut Vec<usize>,
+    counts: &mut [usize],
+    (iteration, mut trace): (usize, Option<&mut SelectionTrace>),
+) -> PatternMap<usize, usize> {
+    selected.clear();
+    counts.fill(0);
+    let mut occupant: PatternMap<usize, usize> = PatternMap::default();
+
+    for &idx in order {
+        let occ = &occurrences[idx];
+        if let Some(&blocker) = (occ.start..occ.end()).find_map(|pos| occupant.get(&pos)) {
+            SelectionTrace::push(&mut trace, || SelectionEvent::Overlapped {
+                iteration,
+                pattern: occ.subsequence.to_vec(),
+                start: occ.start,
+                blocked_by: occurrences[blocker].start,
+            });
+            continue;
+        }
+
+        SelectionTrace::push(&mut trace, || SelectionEvent::Accepted {
+            iteration,
+            pattern: occ.subsequence.to_vec(),
+            start: occ.start,
+        });
+        selected.push(idx);
+        occupant.extend((occ.start..occ.end()).map(|pos| (pos, idx)));
+        counts[pattern_of[idx]] += 1;
+    }
+
+    occupant
+}
+
+/// Count, per failing pattern id, the unselected occurrences blocked only
+/// by other failing patterns.
+///
+/// These occurrences become available once the blocking patterns are
+/// pruned, so the count is an estimate of what the pattern could gain.
+fn recoverable_counts(
+    pattern_of: &[usize],
+    occurrences: &[Occurrence],
+    viable_indices: &[usize],
+    occupant: &PatternMap<usize, usize>,
+    failing: &[bool],
+) -> Vec<usize> {
+    let mut recoverable = vec![0; failing.len()];
+
+    for &idx in viable_indices {
+        let occ = &occurrences[idx];
+        let id = pattern_of[idx];
+        if !failing[id] || occupant.get(&occ.start) == Some(&idx) {
+            continue;
+        }
+
+        let freed_by_pruning = (occ.start..occ.end()).all(|pos| match occupant.get(&pos) {
+            Some(&blocker) => {
+                let blocker_id = pattern_of[blocker];
+                blocker_id != id && failing[blocker_id]
+            }
+            None => true,
+        });
+
+        if freed_by_pruning {
+            recoverable[id] += 1;
+        }
+    }
+
+    recoverable
+}
+
 /// Weighted interval scheduling with proper savings calculation.
 ///
 /// Uses dynamic programming to find optimal non-overlapping selection,
 /// with iterative refinement for compressibility constraints.
 pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionResult {
+    select_optimal_with(candidates, extra_cost, DEFAULT_MAX_ITERATIONS, &DefaultScorer)
+}
+
+/// Optimal selection weighting occurrences with a custom scorer, running
+/// at most `max_iterations` refinement rounds.
+pub fn select_optimal_with<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+) -> SelectionResult {
+    select_optimal_traced(candidates, extra_cost, max_iterations, scorer, None)
+}
+
+fn select_optimal_traced<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+    mut trace: Option<&mut SelectionTrace>,
+) -> SelectionResult {
     if candidates.is_empty() {
         return SelectionResult {
             selected: Vec::new(),
@@ -240,7 +598,7 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
     }
 
     // Pre-filter patterns that can never be compressible
-    let mut subseq_to_occs: HashMap<Vec<Token>, Vec<usize>> = HashMap::new();
+    let mut subseq_to_occs: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
     for (i, occ) in occurrences.iter().enumerate() {
         subseq_to_occs
             .entry(occ.subsequence.clone())
@@ -248,13 +606,25 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
             .push(i);
     }
 
-    let mut viable_subseqs: HashSet<Vec<Token>> = HashSet::new();
+    let mut viable_subseqs: PatternSet<Pattern> = PatternSet::default();
+    let mut unviable: Vec<(&Pattern, usize)> = Vec::new();
     for (subseq, indices) in &subseq_to_occs {
-        let min_count = min_count_for_compressibility(subseq.len(), extra_cost);
+        let fillers = occurrences[indices[0]].patches.len();
+        let min_count = min_count_with_fillers(subseq.len(), fillers, extra_cost);
         if indices.len() >= min_count {
             viable_subseqs.insert(subseq.clone());
+        } else {
+            unviable.push((subseq, indices.len()));
         }
     }
+    // Map order depends on hashing
+    unviable.sort_unstable();
+    for (pattern, occurrences) in unviable {
+        SelectionTrace::push(&mut trace, || SelectionEvent::Unviable {
+            pattern: pattern.to_vec(),
+            occurrences,
+        });
+    }
 
     let mut viable_indices: Vec<usize> = (0..occurrences.len())
         .filter(|&i| viable_subseqs.contains(&occurrences[i].subsequence))
@@ -267,11 +637,10 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
     }
 
     // Iterative refinement loop
-    let max_iterations = 10;
     let mut selected_indices: Vec<usize>;
-    let mut grouped: HashMap<Vec<Token>, Vec<usize>> = HashMap::new();
+    let mut grouped: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
 
-    for _iteration in 0..max_iterations {
+    for iteration in 1..=max_iterations {
         // Sort by end position
         viable_indices.sort_by_key(|&i| (occurrences[i].start + occurrences[i].length, occurrences[i].start));
 
@@ -308,16 +677,7 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
         }
 
         // Estimate non-overlapping counts for weight calculation
-        let mut subseq_expected: HashMap<Vec<Token>, usize> = HashMap::new();
-        for &i in &viable_indices {
-            let subseq = &occurrences[i].subsequence;
-            let indices_ref: Vec<&Occurrence> = viable_indices
-                .iter()
-                .filter(|&&j| occurrences[j].subsequence == *subseq)
-                .map(|&j| &occurrences[j])
-                .collect();
-            subseq_expected.insert(subseq.clone(), estimate_non_overlapping_count(&indices_ref));
-        }
+        let subseq_expected = expected_counts(&occurrences, &viable_indices);
 
         // Compute weights
         let weights: Vec<f64> = viable_indices
@@ -325,9 +685,7 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
             .map(|&i| {
                 let occ = &occurrences[i];
                 let expected = *subseq_expected.get(&occ.subsequence).unwrap_or(&1);
-                let dict_cost = (1 + occ.length + extra_cost) as f64 / expected as f64;
-                let savings = occ.length as f64 - 1.0 - dict_cost;
-                savings.max(0.0) + occ.priority as f64 * 0.5
+                scorer.pattern_weight(occ, expected, extra_cost)
             })
             .collect();
 
@@ -359,6 +717,13 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
             }
         }
         selected_indices.reverse();
+        for &idx in &selected_indices {
+            SelectionTrace::push(&mut trace, || SelectionEvent::Accepted {
+                iteration,
+                pattern: occurrences[idx].subsequence.to_vec(),
+                start: occurrences[idx].start,
+            });
+        }
 
         // Check compressibility
         grouped.clear();
@@ -369,9 +734,10 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
                 .push(idx);
         }
 
-        let mut non_compressible: HashSet<Vec<Token>> = HashSet::new();
+        let mut non_compressible: PatternSet<Pattern> = PatternSet::default();
         for (subseq, indices) in &grouped {
-            if !is_compressible(subseq.len(), indices.len(), extra_cost) {
+            let fillers = occurrences[indices[0]].patches.len();
+            if !compressible_with_fillers(subseq.len(), fillers, indices.len(), extra_cost) {
                 non_compressible.insert(subseq.clone());
             }
         }
@@ -381,22 +747,27 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
         }
 
         // Remove non-compressible and retry
-        for subseq in &non_compressible {
+        let mut pruned: Vec<&Pattern> = non_compressible.iter().collect();
+        pruned.sort_unstable();
+        for subseq in pruned {
             viable_subseqs.remove(subseq);
+            SelectionTrace::push(&mut trace, || SelectionEvent::Pruned {
+                iteration,
+                pattern: subseq.to_vec(),
+                selected: grouped[subseq].len(),
+                reason: PruneReason::Uncompressible,
+            });
         }
         viable_indices.retain(|&i| viable_subseqs.contains(&occurrences[i].subsequence));
     }
 
     // Final selection
-    let mut final_selected: Vec<Occurrence> = Vec::new();
-    for (subseq, indices) in &grouped {
-        if is_compressible(subseq.len(), indices.len(), extra_cost) {
-            for &idx in indices {
-                final_selected.push(occurrences[idx].clone());
-            }
-        }
-    }
-
+    let mut final_selected: Vec<Occurrence> = grouped
+        .values()
+        .flatten()
+        .map(|&idx| occurrences[idx].clone())
+        .collect();
+    retain_compressible(&mut final_selected, extra_cost);
     final_selected.sort_by_key(|occ| occ.start);
 
     SelectionResult {
@@ -404,22 +775,301 @@ pub fn select_optimal(candidates: &[Candidate], extra_cost: usize) -> SelectionR
     }
 }
 
+/// Most patterns `select_exact` searches over before giving up.
+pub const EXACT_MAX_PATTERNS: usize = 20;
+
+/// Exact maximum-savings selection, or `None` when more than
+/// `EXACT_MAX_PATTERNS` patterns could save tokens.
+///
+/// For a fixed set of patterns the best selection is a maximum-weight
+/// independent set of their occurrence intervals, each occurrence saving
+/// `length - 1` tokens less its gap fillers, found by the interval
+/// scheduling DP. A branch-and-bound search over which patterns to include
+/// then charges each included pattern its dictionary entry,
+/// `length + extra_cost + 1` tokens, bounding every branch by the DP over
+/// all patterns still allowed. Priorities are ignored.
+pub fn select_exact(candidates: &[Candidate], extra_cost: usize) -> Option<SelectionResult> {
+    let occurrences = build_occurrences(candidates);
+
+    // Patterns saving tokens with every occurrence kept, best first
+    let mut counts: PatternMap<&Pattern, (usize, usize)> = PatternMap::default();
+    for occ in &occurrences {
+        let entry = counts.entry(&occ.subsequence).or_default();
+        *entry = (entry.0 + 1, occ.patches.len());
+    }
+    let mut patterns: Vec<(&Pattern, i64, usize)> = counts
+        .into_iter()
+        .map(|(pattern, (count, fillers))| {
+            let length = pattern.len().saturating_sub(fillers);
+            (pattern, compute_savings(length, count, extra_cost + fillers), fillers)
+        })
+        .filter(|&(_, savings, _)| savings > 0)
+        .collect();
+    if patterns.len() > EXACT_MAX_PATTERNS {
+        return None;
+    }
+    patterns.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    let ids: PatternMap<&Pattern, usize> =
+        patterns.iter().enumerate().map(|(id, &(pattern, _, _))| (pattern, id)).collect();
+    let intervals: Vec<(usize, usize, usize)> = occurrences
+        .iter()
+        .filter_map(|occ| Some((occ.start, occ.end(), *ids.get(&occ.subsequence)?)))
+        .collect();
+    let costs: Vec<i64> = patterns
+        .iter()
+        .map(|(pattern, _, _)| (pattern.len() + extra_cost + 1) as i64)
+        .collect();
+    let savings: Vec<i64> = patterns
+        .iter()
+        .map(|(pattern, _, fillers)| pattern.len() as i64 - 1 - *fillers as i64)
+        .collect();
+
+    let mut search = ExactSearch {
+        intervals: &intervals,
+        savings: &savings,
+        costs: &costs,
+        best: 0,
+        best_mask: 0,
+    };
+    search.branch(0, 0, 0);
+
+    let chosen = interval_schedule(&intervals, &savings, search.best_mask).1;
+    let mut selected: Vec<Occurrence> = occurrences
+        .iter()
+        .filter(|occ| ids.contains_key(&occ.subsequence))
+        .enumerate()
+        .filter(|(i, _)| chosen.binary_search(i).is_ok())
+        .map(|(_, occ)| occ.clone())
+        .collect();
+    retain_compressible(&mut selected, extra_cost);
+    selected.sort_by_key(|occ| occ.start);
+
+    Some(SelectionResult { selected })
+}
+
+/// Branch-and-bound state for `select_exact`.
+struct ExactSearch<'a> {
+    /// Occurrence `(start, end, pattern id)`, sorted by end
+    intervals: &'a [(usize, usize, usize)],
+    /// Tokens each occurrence of a pattern saves in the body
+    savings: &'a [i64],
+    /// Dictionary cost of each pattern
+    costs: &'a [i64],
+    best: i64,
+    best_mask: u32,
+}
+
+impl ExactSearch<'_> {
+    /// Decide pattern `next` onward, with `included` chosen so far at
+    /// dictionary cost `cost`.
+    fn branch(&mut self, next: usize, included: u32, cost: i64) {
+        let undecided = (!0u32 << next) & ((1u32 << self.costs.len()) - 1);
+        let mask = included | undecided;
+        let bound = interval_schedule(self.intervals, self.savings, mask).0 - cost;
+        if bound <= self.best {
+            return;
+        }
+        if next == self.costs.len() {
+            self.best = bound;
+            self.best_mask = included;
+            return;
+        }
+        self.branch(next + 1, included | 1 << next, cost + self.costs[next]);
+        self.branch(next + 1, included, cost);
+    }
+}
+
+/// Maximum-weight non-overlapping intervals among those whose pattern is in
+/// `mask`, each weighing its pattern's `savings`: the total weight and the
+/// chosen indices in ascending order.
+fn interval_schedule(
+    intervals: &[(usize, usize, usize)],
+    savings: &[i64],
+    mask: u32,
+) -> (i64, Vec<usize>) {
+    // best[i]: heaviest choice among the first i intervals
+    let mut best = vec![0i64; intervals.len() + 1];
+    let mut take = vec![None; intervals.len()];
+    for (i, &(start, _, id)) in intervals.iter().enumerate() {
+        best[i + 1] = best[i];
+        if mask & 1 << id == 0 {
+            continue;
+        }
+        let before = intervals[..i].partition_point(|&(_, e, _)| e <= start);
+        let weight = best[before] + savings[id];
+        if weight > best[i + 1] {
+            best[i + 1] = weight;
+            take[i] = Some(before);
+        }
+    }
+
+    let mut chosen = Vec::new();
+    let mut i = intervals.len();
+    while i > 0 {
+        match take[i - 1] {
+            Some(before) => {
+                chosen.push(i - 1);
+                i = before;
+            }
+            None => i -= 1,
+        }
+    }
+    chosen.reverse();
+    (best[intervals.len()], chosen)
+}
+
 /// Select occurrences using the specified mode.
 pub fn select_occurrences(
     candidates: &[Candidate],
     mode: &str,
     extra_cost: usize,
+) -> SelectionResult {
+    select_occurrences_with(candidates, mode, extra_cost, DEFAULT_MAX_ITERATIONS, &DefaultScorer)
+}
+
+/// Back-to-back occurrences of one pattern needed before its run is
+/// considered for a super-pattern.
+pub const MIN_RUN_LENGTH: usize = 4;
+
+/// Super-patterns covering runs of back-to-back selected occurrences.
+///
+/// For every pattern selected at least `MIN_RUN_LENGTH` times in a row,
+/// the super-pattern repeats it `multiplier` times and occurs at every
+/// `multiplier`-th occurrence of each such run. Occurrences with patches
+/// and super-patterns longer than `max_length` are skipped.
+pub fn run_super_candidates(
+    selected: &[Occurrence],
+    multiplier: usize,
+    max_length: usize,
+) -> Vec<Candidate> {
+    let mut positions: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
+    let mut run_start = 0;
+
+    for i in 1..=selected.len() {
+        let continues = i < selected.len() && {
+            let (prev, occ) = (&selected[i - 1], &selected[i]);
+            occ.start == prev.end() && occ.subsequence == prev.subsequence
+        };
+        if continues {
+            continue;
+        }
+
+        let run = &selected[run_start..i];
+        run_start = i;
+        let first = &run[0];
+        let length = first.length * multiplier;
+        if run.len() < MIN_RUN_LENGTH || length > max_length || !first.patches.is_empty() {
+            continue;
+        }
+
+        let starts = positions.entry(first.subsequence.repeat(multiplier).into()).or_default();
+        starts.extend(run.chunks_exact(multiplier).map(|chunk| chunk[0].start));
+    }
+
+    let mut candidates: Vec<Candidate> = positions
+        .into_iter()
+        .map(|(pattern, starts)| Candidate::new(pattern, starts))
+        .collect();
+    candidates.sort_by(|a, b| a.subsequence.cmp(&b.subsequence));
+    candidates
+}
+
+/// `select_occurrences_with`, recording each decision in `trace`.
+pub fn select_occurrences_traced<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    mode: &str,
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
+    trace: &mut SelectionTrace,
+) -> SelectionResult {
+    let trace = Some(trace);
+    let exact = (mode == "exact").then(|| select_exact(candidates, extra_cost)).flatten();
+    if let Some(result) = exact {
+        return result;
+    }
+    match mode {
+        "optimal" | "exact" => {
+            select_optimal_traced(candidates, extra_cost, max_iterations, scorer, trace)
+        }
+        _ => select_greedy_traced(candidates, extra_cost, max_iterations, scorer, trace),
+    }
+}
+
+/// Keep only the earliest `max` positions of each candidate (0 = all).
+///
+/// Applied before selection, so positions a very frequent pattern gives
+/// up can go to other patterns.
+pub fn cap_occurrences(candidates: &mut [Candidate], max: usize) {
+    if max == 0 {
+        return;
+    }
+    for candidate in candidates.iter_mut().filter(|c| c.count() > max) {
+        let mut positions: Vec<usize> = candidate.positions().collect();
+        positions.truncate(max);
+        candidate.patches.retain(|pos, _| positions.binary_search(pos).is_ok());
+        candidate.set_positions(&positions);
+    }
+}
+
+/// Raise the priority of candidates whose occurrences line up with strong
+/// token boundaries, such as a subword tokenizer's merge boundaries.
+///
+/// `boundaries` holds positions where a token boundary is strong, in any
+/// order. A candidate gains one priority point when at least half of its
+/// occurrences start on a strong boundary, and one more when at least half
+/// end on one. Unlike segment tokens, unaligned patterns stay eligible.
+pub fn prefer_aligned(candidates: &mut [Candidate], boundaries: &[usize]) {
+    if boundaries.is_empty() {
+        return;
+    }
+    let mut strong = boundaries.to_vec();
+    strong.sort_unstable();
+    let is_strong = |pos: usize| strong.binary_search(&pos).is_ok();
+
+    for candidate in candidates.iter_mut() {
+        let starts = candidate.positions().filter(|&pos| is_strong(pos)).count();
+        let ends = candidate
+            .positions()
+            .filter(|&pos| is_strong(pos + candidate.length))
+            .count();
+        let half = candidate.count().div_ceil(2);
+        candidate.priority += (starts >= half) as i32 + (ends >= half) as i32;
+    }
+}
+
+/// Selection modes `CompressionConfig::validate` accepts.
+///
+/// `select_occurrences` runs greedy selection for any other mode, and for
+/// `beam`, which has no dedicated implementation yet. `exact` falls back to
+/// `optimal` when `select_exact` declines.
+pub const SELECTION_MODES: [&str; 4] = ["greedy", "optimal", "beam", "exact"];
+
+/// Select occurrences using the specified mode, refinement cap and scorer.
+pub fn select_occurrences_with<S: SelectionScorer + ?Sized>(
+    candidates: &[Candidate],
+    mode: &str,
+    extra_cost: usize,
+    max_iterations: usize,
+    scorer: &S,
 ) -> SelectionResult {
     match mode {
-        "greedy" => select_greedy(candidates, extra_cost),
-        "optimal" => select_optimal(candidates, extra_cost),
-        _ => select_greedy(candidates, extra_cost), // Default to greedy
+        "greedy" => select_greedy_with(candidates, extra_cost, max_iterations, scorer),
+        "optimal" => select_optimal_with(candidates, extra_cost, max_iterations, scorer),
+        "exact" => select_exact(candidates, extra_cost).unwrap_or_else(|| {
+            select_optimal_with(candidates, extra_cost, max_iterations, scorer)
+        }),
+        // Default to greedy; `CompressionConfig::validate` rejects unknown modes
+        _ => select_greedy_with(candidates, extra_cost, max_iterations, scorer),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::Token;
+    use std::collections::HashSet;
 
     fn make_candidate(subseq: Vec<Token>, positions: Vec<usize>) -> Candidate {
         Candidate::new(subseq, positions)
@@ -481,12 +1131,239 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_select_greedy_reoffers_pruned_competitor_positions() {
+        // D (length 5) is compressible and blocks C at 11 and 21, leaving C
+        // with a single occurrence. C in turn blocks P at 0, so P only gets
+        // four occurrences on the first fill. Once C is pruned, P reclaims
+        // position 0 and reaches the five occurrences it needs.
+        let d = make_candidate(vec![7, 1, 2, 3, 8], vec![10, 20]);
+        let c = make_candidate(vec![1, 2, 3], vec![0, 11, 21]);
+        let p = make_candidate(vec![1, 2], vec![0, 30, 33, 36, 39]);
+
+        let result = select_greedy(&[d, c, p], 1);
+
+        let p_count = result
+            .selected
+            .iter()
+            .filter(|occ| occ.subsequence[..] == [1, 2])
+            .count();
+        assert_eq!(p_count, 5);
+        assert!(result.selected.iter().all(|occ| occ.subsequence[..] != [1, 2, 3]));
+        assert!(result
+            .selected
+            .iter()
+            .any(|occ| occ.subsequence[..] == [1, 2] && occ.start == 0));
+    }
+
+    #[test]
+    fn test_selection_trace_records_pruning() {
+        // Same refinement as above: C is pruned after the first round and
+        // P takes position 0 in the second
+        let d = make_candidate(vec![7, 1, 2, 3, 8], vec![10, 20]);
+        let c = make_candidate(vec![1, 2, 3], vec![0, 11, 21]);
+        let p = make_candidate(vec![1, 2], vec![0, 30, 33, 36, 39]);
+        let candidates = [d, c, p];
+
+        let mut trace = SelectionTrace::default();
+        let traced =
+            select_occurrences_traced(&candidates, "greedy", 1, 10, &DefaultScorer, &mut trace);
+        assert_eq!(traced.selected, select_greedy(&candidates, 1).selected);
+
+        let pruned: Vec<_> = trace.pruned().collect();
+        assert_eq!(pruned, vec![(1, &[1, 2, 3][..], PruneReason::Uncompressible)]);
+        assert!(trace.events.contains(&SelectionEvent::Pruned {
+            iteration: 1,
+            pattern: vec![1, 2, 3],
+            selected: 1,
+            reason: PruneReason::Uncompressible,
+        }));
+        assert!(trace.events.contains(&SelectionEvent::Overlapped {
+            iteration: 1,
+            pattern: vec![1, 2],
+            start: 0,
+            blocked_by: 0,
+        }));
+        assert!(trace.events.contains(&SelectionEvent::Accepted {
+            iteration: 2,
+            pattern: vec![1, 2],
+            start: 0,
+        }));
+    }
+
+    #[test]
+    fn test_cap_occurrences_frees_positions() {
+        // A appears 100 times; B only where A's later occurrences sit
+        let a = make_candidate(vec![1, 2, 3], (0..100).map(|i| i * 5).collect());
+        let b = make_candidate(vec![2, 3], (50..58).map(|i| i * 5 + 1).collect());
+        let count = |result: &SelectionResult, pattern: &[Token]| {
+            result
+                .selected
+                .iter()
+                .filter(|occ| occ.subsequence[..] == *pattern)
+                .count()
+        };
+
+        let uncapped = select_greedy(&[a.clone(), b.clone()], 1);
+        assert_eq!(count(&uncapped, &[1, 2, 3]), 100);
+        assert_eq!(count(&uncapped, &[2, 3]), 0);
+
+        let mut candidates = vec![a, b];
+        cap_occurrences(&mut candidates, 10);
+        assert_eq!(candidates[0].positions().max(), Some(45));
+        let capped = select_greedy(&candidates, 1);
+        assert_eq!(count(&capped, &[1, 2, 3]), 10);
+        assert_eq!(count(&capped, &[2, 3]), 8);
+    }
+
+    #[test]
+    fn test_prefer_aligned_picks_pattern_on_strong_boundaries() {
+        // [1, 2, 3] and [2, 3, 4] save the same and overlap in
+        // "1 2 3 4 1 2 3 4 ..."; the earlier-ending one wins by default
+        let candidates = vec![
+            make_candidate(vec![1, 2, 3], vec![0, 4, 8, 12]),
+            make_candidate(vec![2, 3, 4], vec![1, 5, 9, 13]),
+        ];
+        let picked = |candidates: &[Candidate], mode: &str| {
+            let result = select_occurrences(candidates, mode, 1);
+            assert_eq!(result.selected.len(), 4);
+            let patterns: HashSet<&[Token]> =
+                result.selected.iter().map(|occ| &occ.subsequence[..]).collect();
+            assert_eq!(patterns.len(), 1);
+            patterns.into_iter().next().unwrap().to_vec()
+        };
+        assert_eq!(picked(&candidates, "greedy"), vec![1, 2, 3]);
+
+        // Strong boundaries before each 2 and after each 4
+        let boundaries: Vec<usize> = (0..4).flat_map(|i| [4 * i + 1, 4 * i + 4]).collect();
+        let mut aligned = candidates.clone();
+        prefer_aligned(&mut aligned, &boundaries);
+        assert_eq!((aligned[0].priority, aligned[1].priority), (1, 2));
+        assert_eq!(picked(&aligned, "greedy"), vec![2, 3, 4]);
+        assert_eq!(picked(&aligned, "optimal"), vec![2, 3, 4]);
+
+        let mut unchanged = candidates.clone();
+        prefer_aligned(&mut unchanged, &[]);
+        assert_eq!(unchanged, candidates);
+    }
+
+    struct IgnorePriority;
+
+    impl SelectionScorer for IgnorePriority {
+        fn occurrence_score(&self, occ: &Occurrence) -> f64 {
+            (occ.length as f64 - 1.0) / occ.length as f64
+        }
+
+        fn pattern_weight(&self, occ: &Occurrence, expected: usize, extra_cost: usize) -> f64 {
+            let unprioritized = Occurrence {
+                priority: 0,
+                ..occ.clone()
+            };
+            DefaultScorer.pattern_weight(&unprioritized, expected, extra_cost)
+        }
+    }
+
+    #[test]
+    fn test_custom_scorer_changes_selection() {
+        // [1, 2, 3] and [2, 3, 4] overlap in "1 2 3 4 1 2 3 4 ..."; only the
+        // priority boost makes the later-starting pattern win.
+        let a = make_candidate(vec![1, 2, 3], vec![0, 4, 8, 12]);
+        let b = make_candidate(vec![2, 3, 4], vec![1, 5, 9, 13]).with_priority(1);
+        let candidates = [a, b];
+
+        let default = select_greedy(&candidates, 1);
+        assert_eq!(
+            default.selected,
+            select_greedy_with(&candidates, 1, DEFAULT_MAX_ITERATIONS, &DefaultScorer).selected
+        );
+        assert!(default.selected.iter().all(|occ| occ.subsequence[..] == [2, 3, 4]));
+
+        let custom = select_greedy_with(&candidates, 1, DEFAULT_MAX_ITERATIONS, &IgnorePriority);
+        assert_eq!(custom.selected.len(), 4);
+        assert!(custom.selected.iter().all(|occ| occ.subsequence[..] == [1, 2, 3]));
+
+        let optimal = select_optimal_with(&candidates, 1, DEFAULT_MAX_ITERATIONS, &IgnorePriority);
+        assert!(optimal.selected.iter().all(|occ| occ.subsequence[..] == [1, 2, 3]));
+        let optimal_default = select_optimal(&candidates, 1);
+        assert!(optimal_default.selected.iter().all(|occ| occ.subsequence[..] == [2, 3, 4]));
+    }
+
+    /// Assert no overlap and that every selected pattern is compressible.
+    fn assert_valid_selection(selected: &[Occurrence], extra_cost: usize) {
+        let mut counts: PatternMap<Pattern, usize> = PatternMap::default();
+        let mut next_free = 0;
+        for occ in selected {
+            assert!(occ.start >= next_free, "overlap at {}", occ.start);
+            next_free = occ.end();
+            *counts.entry(occ.subsequence.clone()).or_default() += 1;
+        }
+        for (subseq, count) in counts {
+            assert!(
+                is_compressible(subseq.len(), count, extra_cost),
+                "{:?} kept with {} occurrences",
+                subseq,
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn test_capped_refinement_keeps_only_compressible_patterns() {
+        use crate::discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
+
+        // Needs a second round: P only reaches five occurrences once C is
+        // pruned (see test_select_greedy_reoffers_pruned_competitor_positions)
+        let d = make_candidate(vec![7, 1, 2, 3, 8], vec![10, 20]);
+        let c = make_candidate(vec![1, 2, 3], vec![0, 11, 21]);
+        let p = make_candidate(vec![1, 2], vec![0, 30, 33, 36, 39]);
+        let chained = vec![d, c, p];
+        for mode in ["greedy", "optimal"] {
+            for max_iterations in 0..3 {
+                let result =
+                    select_occurrences_with(&chained, mode, 1, max_iterations, &DefaultScorer);
+                assert_valid_selection(&result.selected, 1);
+            }
+        }
+
+        // Small-alphabet streams where overlapping patterns compete
+        let mut capped_differs = false;
+        for seed in 1..=20u32 {
+            let mut state = seed;
+            let tokens: Vec<Token> = (0..200)
+                .map(|_| {
+                    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                    (state >> 16) % 5
+                })
+                .collect();
+            let candidates =
+                deduplicate_candidates(discover_candidates(&tokens, &DiscoveryConfig::default()));
+
+            for mode in ["greedy", "optimal"] {
+                let converged =
+                    select_occurrences_with(&candidates, mode, 1, 100, &DefaultScorer);
+                assert_valid_selection(&converged.selected, 1);
+                for max_iterations in 0..3 {
+                    let capped = select_occurrences_with(
+                        &candidates,
+                        mode,
+                        1,
+                        max_iterations,
+                        &DefaultScorer,
+                    );
+                    assert_valid_selection(&capped.selected, 1);
+                    capped_differs |= capped.selected != converged.selected;
+                }
+            }
+        }
+        assert!(capped_differs, "no input stopped short of convergence");
+    }
+
     #[test]
     fn test_savings_density() {
         let occ = Occurrence {
             start: 0,
             length: 4,
-            subsequence: vec![1, 2, 3, 4],
+            subsequence: vec![1, 2, 3, 4].into(),
             priority: 0,
             patches: vec![],
         };
@@ -495,4 +1372,150 @@ mod tests {
         // (4-1)/4 = 0.75
         assert!((density - 0.75).abs() < 0.001);
     }
+
+    #[test]
+    fn test_select_exact_beats_optimal() {
+        use crate::discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
+        use crate::types::{compute_savings, CompressionConfig};
+
+        let savings = |result: &SelectionResult| {
+            let mut counts: PatternMap<Pattern, usize> = PatternMap::default();
+            for occ in &result.selected {
+                *counts.entry(occ.subsequence.clone()).or_default() += 1;
+            }
+            let savings = counts.iter().map(|(p, &count)| compute_savings(p.len(), count, 1));
+            savings.sum::<i64>()
+        };
+
+        let tokens: Vec<Token> = vec![
+            0, 0, 1, 0, 1, 0, 1, 0, 0, 0, 1, 1, 1, 1, 0, 0, 1, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 1,
+        ];
+        let candidates =
+            deduplicate_candidates(discover_candidates(&tokens, &DiscoveryConfig::default()));
+        let exact = select_exact(&candidates, 1).unwrap();
+        assert_eq!(savings(&exact), 5);
+        assert_eq!(savings(&select_optimal(&candidates, 1)), 0);
+        assert!(exact.selected.windows(2).all(|w| w[0].end() <= w[1].start));
+
+        // Too many patterns to search
+        let many: Vec<Candidate> = (0..EXACT_MAX_PATTERNS as Token + 1)
+            .map(|t| make_candidate(vec![t, t], (0..5).map(|i| i * 100 + t as usize * 2).collect()))
+            .collect();
+        assert!(select_exact(&many, 1).is_none());
+
+        // Inputs past the size cap get optimal selection
+        let long: Vec<Token> = tokens.repeat(10);
+        let compress = |mode: &str| {
+            let config = CompressionConfig {
+                selection_mode: mode.to_string(),
+                ..Default::default()
+            };
+            assert!(long.len() > config.exact_max_input_length);
+            crate::compress_internal(&long, &config, crate::DEFAULT_NEXT_META_TOKEN).unwrap()
+        };
+        assert_eq!(compress("exact").serialized_tokens, compress("optimal").serialized_tokens);
+    }
+
+    #[test]
+    fn test_expected_counts_match_per_pattern_scan() {
+        use crate::discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
+
+        let tokens: Vec<Token> = (0..600).map(|i| [1, 1, 1, 2, 1, 1, 3][i % 7]).collect();
+        let mut candidates =
+            deduplicate_candidates(discover_candidates(&tokens, &DiscoveryConfig::default()));
+        // Overlapping positions, as a raw candidate list may carry
+        candidates.push(make_candidate(vec![7, 7], vec![0, 1, 2, 3, 5, 6, 9]));
+        candidates.push(make_candidate(vec![8, 8, 8], vec![4, 5, 7, 8, 12]));
+        let occurrences = build_occurrences(&candidates);
+        let mut indices: Vec<usize> = (0..occurrences.len()).collect();
+        indices.sort_by_key(|&i| (occurrences[i].end(), occurrences[i].start));
+
+        let counts = expected_counts(&occurrences, &indices);
+        let grouped = group_by_subsequence(&occurrences);
+        assert_eq!(counts.len(), grouped.len());
+        for (pattern, occs) in grouped {
+            let mut starts: Vec<usize> = occs.iter().map(|o| o.start).collect();
+            starts.sort_unstable();
+            let mut expected = 0;
+            let mut next_free = 0;
+            for start in starts {
+                if start >= next_free {
+                    expected += 1;
+                    next_free = start + pattern.len();
+                }
+            }
+            assert_eq!(counts[&pattern], expected);
+        }
+    }
+
+    #[test]
+    fn test_single_ranking_matches_reranking_every_round() {
+        use crate::discovery::{deduplicate_candidates, discover_candidates, DiscoveryConfig};
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(897);
+        let config = DiscoveryConfig::default();
+        for _ in 0..60 {
+            // Small alphabets and planted motifs give competing patterns
+            let alphabet = rng.gen_range(2..12);
+            let len = rng.gen_range(20..600);
+            let motifs: Vec<Vec<Token>> = (0..4)
+                .map(|_| (0..rng.gen_range(2..9)).map(|_| rng.gen_range(0..alphabet)).collect())
+                .collect();
+            let mut tokens: Vec<Token> = Vec::with_capacity(len);
+            while tokens.len() < len {
+                if rng.gen_bool(0.5) {
+                    tokens.extend(&motifs[rng.gen_range(0..motifs.len())]);
+                } else {
+                    tokens.push(rng.gen_range(0..alphabet));
+                }
+            }
+            let candidates = deduplicate_candidates(discover_candidates(&tokens, &config));
+
+            for max_iterations in [1, 2, DEFAULT_MAX_ITERATIONS] {
+                for extra_cost in [0, 1] {
+                    let once =
+                        select_greedy_with(&candidates, extra_cost, max_iterations, &DefaultScorer);
+                    let every_round = select_greedy_reranking(
+                        &candidates,
+                        extra_cost,
+                        max_iterations,
+                        &DefaultScorer,
+                    );
+                    assert_eq!(once, every_round);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_gap_fillers_count_against_savings() {
+        use crate::types::Patch;
+
+        // [1, ?, 3] with a different filler at each occurrence
+        let skip_gram = |count: usize| {
+            let positions: Vec<usize> = (0..count).map(|i| i * 4).collect();
+            let mut candidate = make_candidate(vec![1, 0xFFFFFFF5, 3], positions.clone());
+            for (i, &pos) in positions.iter().enumerate() {
+                let patch = Patch {
+                    index: 1,
+                    token: 100 + i as Token,
+                };
+                candidate.patches.insert(pos, vec![patch]);
+            }
+            candidate
+        };
+
+        for mode in ["greedy", "optimal", "exact"] {
+            // Three occurrences would pay for a plain pattern of length 3,
+            // but not once each carries a filler in the body
+            assert!(is_compressible(3, 3, 1));
+            let result = select_occurrences(&[skip_gram(3)], mode, 1);
+            assert!(result.selected.is_empty(), "{}", mode);
+
+            let result = select_occurrences(&[skip_gram(6)], mode, 1);
+            assert_eq!(result.selected.len(), 6, "{}", mode);
+        }
+    }
 }
@@ -0,0 +1,325 @@
+//! Discovery and decoding of skip-gram patterns.
+//!
+//! A skip-gram is a pattern with wildcard gaps, such as `[A, ?, B]`, whose
+//! occurrences agree on every other token. Its dictionary definition holds
+//! `gap_token` at each wildcard position. Each occurrence records the
+//! tokens filling its gaps as patches and is written to the body as the
+//! meta-token followed by those tokens, in order.
+
+use crate::dictionary::expand_into;
+use crate::discovery::DiscoveryConfig;
+use crate::hash::PatternMap;
+use crate::suffix_array::non_overlapping_positions;
+use crate::types::{Candidate, Patch, Pattern, Token};
+use std::collections::HashMap;
+
+/// Where skip-grams may have gaps and how they are marked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkipGramConfig {
+    /// Most gaps in one pattern
+    pub max_gaps: usize,
+    /// Tokens per gap
+    pub gap_len: usize,
+    /// Wildcard token written into definitions
+    pub gap_token: Token,
+}
+
+/// Find skip-grams whose gap fillers vary between occurrences.
+///
+/// Gaps are interior and separated by at least one fixed token. Patterns
+/// whose fillers are identical everywhere are left to exact discovery. A
+/// pattern is kept when it still saves tokens after every occurrence pays
+/// for its fillers in the body.
+pub fn discover_skip_grams(
+    tokens: &[Token],
+    config: &DiscoveryConfig,
+    skip_grams: &SkipGramConfig,
+) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    if skip_grams.max_gaps == 0 || skip_grams.gap_len == 0 {
+        return candidates;
+    }
+
+    for length in config.min_length..=config.max_length.min(tokens.len()) {
+        for gaps in gap_layouts(length, skip_grams.max_gaps, skip_grams.gap_len) {
+            let gap_indices: Vec<usize> = gaps
+                .iter()
+                .flat_map(|&start| start..start + skip_grams.gap_len)
+                .collect();
+            candidates.extend(layout_candidates(
+                tokens,
+                length,
+                &gap_indices,
+                config,
+                skip_grams.gap_token,
+            ));
+        }
+    }
+
+    candidates
+}
+
+/// Every placement of 1 to `max_gaps` interior gaps in a pattern of `length`.
+fn gap_layouts(length: usize, max_gaps: usize, gap_len: usize) -> Vec<Vec<usize>> {
+    fn extend(
+        layout: &mut Vec<usize>,
+        from: usize,
+        length: usize,
+        max_gaps: usize,
+        gap_len: usize,
+        out: &mut Vec<Vec<usize>>,
+    ) {
+        for start in from..length {
+            if start + gap_len >= length {
+                break;
+            }
+            layout.push(start);
+            out.push(layout.clone());
+            if layout.len() < max_gaps {
+                extend(layout, start + gap_len + 1, length, max_gaps, gap_len, out);
+            }
+            layout.pop();
+        }
+    }
+
+    let mut layouts = Vec::new();
+    extend(&mut Vec::new(), 1, length, max_gaps, gap_len, &mut layouts);
+    layouts
+}
+
+/// Candidates for one pattern length and gap layout.
+fn layout_candidates(
+    tokens: &[Token],
+    length: usize,
+    gap_indices: &[usize],
+    config: &DiscoveryConfig,
+    gap_token: Token,
+) -> Vec<Candidate> {
+    let fixed_length = length - gap_indices.len();
+    if fixed_length < 2 {
+        return Vec::new();
+    }
+
+    let mut groups: PatternMap<Pattern, Vec<usize>> = PatternMap::default();
+    let mut masked: Vec<Token> = Vec::with_capacity(length);
+    for (start, window) in tokens.windows(length).enumerate() {
+        if window.contains(&gap_token) {
+            continue;
+        }
+        masked.clear();
+        masked.extend_from_slice(window);
+        for &index in gap_indices {
+            masked[index] = gap_token;
+        }
+        // Only a new group copies its key
+        match groups.get_mut(masked.as_slice()) {
+            Some(positions) => positions.push(start),
+            None => {
+                groups.insert(Pattern::from_slice(&masked), vec![start]);
+            }
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for (masked, positions) in groups {
+        let positions = non_overlapping_positions(&positions, length);
+        let count = positions.len();
+        if count
+            < config
+                .min_occurrences
+                .min_count(fixed_length, config.extra_cost)
+        {
+            continue;
+        }
+
+        // Each occurrence costs its meta-token plus one token per gap
+        let original = length * count;
+        let compressed = count * (1 + gap_indices.len()) + 1 + config.extra_cost + length;
+        if original <= compressed {
+            continue;
+        }
+
+        let fillers = |pos: usize| gap_indices.iter().map(move |&i| tokens[pos + i]);
+        if positions
+            .iter()
+            .all(|&pos| fillers(pos).eq(fillers(positions[0])))
+        {
+            continue;
+        }
+
+        let patches: HashMap<usize, Vec<Patch>> = positions
+            .iter()
+            .map(|&pos| {
+                let patches = gap_indices
+                    .iter()
+                    .map(|&index| Patch {
+                        index,
+                        token: tokens[pos + index],
+                    })
+                    .collect();
+                (pos, patches)
+            })
+            .collect();
+        let mut candidate = Candidate::new(masked, positions);
+        candidate.patches = patches;
+        candidates.push(candidate);
+    }
+
+    // Group iteration order depends on hashing
+    candidates.sort_by(|a, b| a.subsequence.cmp(&b.subsequence));
+    candidates
+}
+
+/// Drop candidates that hold `gap_token` as a literal.
+///
+/// The decoder reads every `gap_token` in a definition as a gap, so only
+/// skip-grams, whose occurrences carry fillers, may contain it.
+pub fn retain_gap_free(candidates: &mut Vec<Candidate>, gap_token: Token) {
+    candidates.retain(|c| !c.patches.is_empty() || !c.subsequence.contains(&gap_token));
+}
+
+/// Expand a body in which skip-gram meta-tokens are followed by their
+/// gap fillers.
+///
+/// Meta-tokens without gaps expand as in `expand_body`.
+pub fn expand_with_gaps(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    gap_token: Token,
+    escape_token: Token,
+) -> Vec<Token> {
+    let mut output = Vec::with_capacity(body.len() * 2);
+    expand_with_gaps_into(body, dictionary, gap_token, escape_token, &mut output);
+    output
+}
+
+/// `expand_with_gaps`, appending to `output`.
+pub fn expand_with_gaps_into(
+    body: &[Token],
+    dictionary: &HashMap<Token, Vec<Token>>,
+    gap_token: Token,
+    escape_token: Token,
+    output: &mut Vec<Token>,
+) {
+    let mut pos = 0;
+
+    while pos < body.len() {
+        let token = body[pos];
+        pos += 1;
+
+        if token == escape_token && pos < body.len() {
+            output.push(body[pos]);
+            pos += 1;
+            continue;
+        }
+
+        match dictionary.get(&token) {
+            Some(definition) if definition.contains(&gap_token) => {
+                for &t in definition {
+                    if t == gap_token {
+                        output.extend(body.get(pos));
+                        pos += 1;
+                    } else {
+                        output.push(t);
+                    }
+                }
+            }
+            _ => expand_into(&body[pos - 1..pos], dictionary, escape_token, output),
+        }
+    }
+}
+
+/// Number of gap fillers following `token` in the body.
+pub fn gap_count(token: Token, dictionary: &HashMap<Token, Vec<Token>>, gap_token: Token) -> usize {
+    dictionary.get(&token).map_or(0, |definition| {
+        definition.iter().filter(|&&t| t == gap_token).count()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CompressionConfig;
+    use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+
+    #[test]
+    fn test_gap_layouts() {
+        assert_eq!(gap_layouts(3, 1, 1), vec![vec![1]]);
+        assert_eq!(gap_layouts(4, 2, 1), vec![vec![1], vec![2]]);
+        assert_eq!(
+            gap_layouts(5, 2, 1),
+            vec![vec![1], vec![1, 3], vec![2], vec![3]]
+        );
+        assert!(gap_layouts(4, 1, 3).is_empty());
+    }
+
+    #[test]
+    fn test_skip_gram_unifies_varying_middle_token() {
+        let (a, b) = (10, 20);
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            tokens.extend([a, 100 + i, b, 500 + i]);
+        }
+
+        let config = CompressionConfig {
+            min_subsequence_length: 3,
+            max_subsequence_length: 3,
+            max_gaps: 1,
+            verify: true,
+            verify_full: true,
+            ..Default::default()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert_eq!(result.dictionary_map.len(), 1);
+        let (&meta, definition) = result.dictionary_map.iter().next().unwrap();
+        assert_eq!(definition, &vec![a, config.gap_token, b]);
+        for i in 0..8 {
+            let at = result
+                .body_tokens
+                .iter()
+                .position(|&t| t == 500 + i)
+                .unwrap();
+            assert_eq!(&result.body_tokens[at - 2..at], &[meta, 100 + i]);
+        }
+        assert!(result.compressed_length < tokens.len());
+
+        let restored =
+            decompress_internal(&result.serialized_tokens, &config, DEFAULT_NEXT_META_TOKEN)
+                .unwrap();
+        assert_eq!(restored, tokens);
+
+        let exact = CompressionConfig {
+            max_gaps: 0,
+            ..config
+        };
+        let exact = compress_internal(&tokens, &exact, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(exact.dictionary_map.is_empty());
+    }
+
+    #[test]
+    fn test_literal_gap_token_stays_out_of_plain_definitions() {
+        let config = CompressionConfig {
+            max_gaps: 1,
+            verify: true,
+            verify_full: true,
+            ..Default::default()
+        };
+        let gap = config.gap_token;
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            tokens.extend([1, 2, gap, 3, 4, 5, 900 + i]);
+        }
+
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+
+        assert!(result.compressed_length < tokens.len());
+        for definition in result.dictionary_map.values() {
+            assert!(!definition.contains(&gap));
+        }
+        let restored =
+            decompress_internal(&result.serialized_tokens, &config, DEFAULT_NEXT_META_TOKEN)
+                .unwrap();
+        assert_eq!(restored, tokens);
+    }
+}
@@ -0,0 +1,395 @@
+//! Adaptive dictionary for long-lived streams whose patterns drift.
+//!
+//! Unlike a static dictionary, the sliding dictionary changes from frame
+//! to frame. Before compressing a frame, entries unused within the last
+//! `window` tokens are evicted; newly frequent patterns in the frame are
+//! admitted, evicting the least recently used entries when the dictionary
+//! is full. Each frame carries its mutations as explicit instructions:
+//!
+//! `[DICT_START, (ADD, meta, len, def... | EVICT, meta)*, DICT_END, body...]`
+//!
+//! The body references the dictionary as it stands after the frame's
+//! instructions, and a decoder replays the instructions frame by frame to
+//! track that state.
+
+use crate::dictionary::{build_body_with, expand_body, is_reserved_token};
+use crate::discovery::{discover_candidates, DiscoveryConfig};
+use crate::error::CompressionError;
+use crate::selection::{select_occurrences_with, DefaultScorer};
+use crate::types::{is_compressible, CompressionConfig, Occurrence, Token};
+use crate::DEFAULT_NEXT_META_TOKEN;
+use std::collections::HashMap;
+
+/// Default instruction admitting a dictionary entry.
+pub const DEFAULT_ADD_TOKEN: Token = 0xFFFFFFF6;
+/// Default instruction evicting a dictionary entry.
+pub const DEFAULT_EVICT_TOKEN: Token = 0xFFFFFFF7;
+
+/// Compressor keeping a bounded dictionary that follows the stream.
+///
+/// Meta-tokens are numbered from `DEFAULT_NEXT_META_TOKEN` in the
+/// config's direction and reused after eviction.
+#[derive(Debug, Clone)]
+pub struct SlidingDictionaryCompressor {
+    config: CompressionConfig,
+    window: usize,
+    max_entries: usize,
+    add_token: Token,
+    evict_token: Token,
+    entries: HashMap<Token, Vec<Token>>,
+    patterns: HashMap<Vec<Token>, Token>,
+    /// Stream offset just past each entry's latest use
+    last_used: HashMap<Token, usize>,
+    /// Tokens compressed so far
+    position: usize,
+}
+
+impl SlidingDictionaryCompressor {
+    /// Create a compressor holding at most `max_entries` entries, each
+    /// evicted once `window` tokens pass without it being used.
+    ///
+    /// `max_entries` is capped by `meta_token_pool_size`.
+    pub fn new(config: CompressionConfig, window: usize, max_entries: usize) -> Self {
+        let max_entries = max_entries.min(config.meta_token_pool_size);
+        Self {
+            config,
+            window,
+            max_entries,
+            add_token: DEFAULT_ADD_TOKEN,
+            evict_token: DEFAULT_EVICT_TOKEN,
+            entries: HashMap::new(),
+            patterns: HashMap::new(),
+            last_used: HashMap::new(),
+            position: 0,
+        }
+    }
+
+    /// Use different instruction tokens.
+    pub fn with_instruction_tokens(mut self, add_token: Token, evict_token: Token) -> Self {
+        self.add_token = add_token;
+        self.evict_token = evict_token;
+        self
+    }
+
+    /// The current dictionary, from meta-token to definition.
+    pub fn entries(&self) -> &HashMap<Token, Vec<Token>> {
+        &self.entries
+    }
+
+    /// Compress the next frame of the stream.
+    pub fn compress_frame(&mut self, tokens: &[Token]) -> Result<Vec<Token>, CompressionError> {
+        let mut instructions = Vec::new();
+
+        // Age out entries unused within the window
+        let mut stale: Vec<Token> = self
+            .last_used
+            .iter()
+            .filter(|&(_, &used)| used + self.window <= self.position)
+            .map(|(&meta, _)| meta)
+            .collect();
+        stale.sort_unstable();
+        for meta in stale {
+            self.evict(meta, &mut instructions);
+        }
+
+        // Existing entries are free to use, so they cover what they can first
+        let mut covered = vec![false; tokens.len()];
+        let mut selected = self.cover_with_entries(tokens, &mut covered);
+        self.mark_used(&selected);
+
+        let mut admitted = Vec::new();
+        for occ in self.select_new_patterns(tokens, &covered) {
+            let pattern = occ.subsequence.to_vec();
+            if !self.patterns.contains_key(&pattern) && !self.admit(pattern, &mut instructions) {
+                continue;
+            }
+            admitted.push(occ);
+        }
+        self.mark_used(&admitted);
+        selected.extend(admitted);
+        self.position += tokens.len();
+
+        let config = &self.config;
+        let mut frame = Vec::with_capacity(instructions.len() + tokens.len() + 2);
+        frame.push(config.dict_start_token);
+        frame.extend(instructions);
+        frame.push(config.dict_end_token);
+        frame.extend(build_body_with(
+            tokens,
+            &selected,
+            &self.patterns,
+            config.escape_token,
+            |t| is_reserved_token(t, config, DEFAULT_NEXT_META_TOKEN),
+        ));
+        Ok(frame)
+    }
+
+    /// Record uses of entries by occurrences in the current frame.
+    fn mark_used(&mut self, occurrences: &[Occurrence]) {
+        for occ in occurrences {
+            let meta = self.patterns[occ.subsequence.as_slice()];
+            let used = self.position + occ.end();
+            let last = self.last_used.entry(meta).or_insert(used);
+            *last = (*last).max(used);
+        }
+    }
+
+    /// Occurrences of existing entries, longest entries first.
+    fn cover_with_entries(&self, tokens: &[Token], covered: &mut [bool]) -> Vec<Occurrence> {
+        let mut patterns: Vec<&Vec<Token>> = self.patterns.keys().collect();
+        patterns.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        let mut occurrences = Vec::new();
+        for pattern in patterns {
+            let length = pattern.len();
+            let mut start = 0;
+            while start + length <= tokens.len() {
+                let end = start + length;
+                if tokens[start..end] == pattern[..] && !covered[start..end].contains(&true) {
+                    covered[start..end].fill(true);
+                    occurrences.push(Occurrence {
+                        start,
+                        length,
+                        subsequence: pattern.as_slice().into(),
+                        priority: 0,
+                        patches: Vec::new(),
+                    });
+                    start = end;
+                } else {
+                    start += 1;
+                }
+            }
+        }
+        occurrences
+    }
+
+    /// Selected occurrences of patterns discovered in the uncovered tokens.
+    fn select_new_patterns(&self, tokens: &[Token], covered: &[bool]) -> Vec<Occurrence> {
+        let config = &self.config;
+        let discovery_config = DiscoveryConfig::from_compression_config(config);
+        let reserved = |t: &Token| is_reserved_token(*t, config, DEFAULT_NEXT_META_TOKEN);
+
+        let candidates: Vec<_> = discover_candidates(tokens, &discovery_config)
+            .into_iter()
+            .filter(|c| !c.subsequence.iter().any(reserved))
+            .filter(|c| !self.patterns.contains_key(c.subsequence.as_slice()))
+            .filter_map(|mut candidate| {
+                let length = candidate.length;
+                let positions: Vec<usize> = candidate
+                    .positions()
+                    .filter(|&pos| !covered[pos..pos + length].contains(&true))
+                    .collect();
+                let extra_cost = discovery_config.extra_cost;
+                let min_count = discovery_config
+                    .min_occurrences
+                    .min_count(length, extra_cost);
+                if positions.len() < min_count
+                    || !is_compressible(length, positions.len(), extra_cost)
+                {
+                    return None;
+                }
+                candidate.set_positions(&positions);
+                Some(candidate)
+            })
+            .collect();
+
+        select_occurrences_with(
+            &candidates,
+            &config.selection_mode,
+            discovery_config.extra_cost,
+            config.selection_max_iterations,
+            &DefaultScorer,
+        )
+        .selected
+    }
+
+    /// Add `pattern`, evicting the least recently used entry not used in
+    /// this frame when full. Returns whether the pattern was admitted.
+    fn admit(&mut self, pattern: Vec<Token>, instructions: &mut Vec<Token>) -> bool {
+        if self.entries.len() >= self.max_entries {
+            let victim = self
+                .last_used
+                .iter()
+                .filter(|&(_, &used)| used <= self.position)
+                .min_by_key(|&(&meta, &used)| (used, meta))
+                .map(|(&meta, _)| meta);
+            match victim {
+                Some(meta) => self.evict(meta, instructions),
+                None => return false,
+            }
+        }
+
+        // Lowest free id in numbering order
+        let direction = self.config.meta_token_direction;
+        let meta = (0..self.max_entries)
+            .map_while(|i| direction.step(DEFAULT_NEXT_META_TOKEN, i))
+            .find(|meta| !self.entries.contains_key(meta));
+        let meta = match meta {
+            Some(meta) => meta,
+            None => return false,
+        };
+
+        instructions.extend([self.add_token, meta, pattern.len() as Token]);
+        instructions.extend_from_slice(&pattern);
+        self.entries.insert(meta, pattern.clone());
+        self.patterns.insert(pattern, meta);
+        true
+    }
+
+    fn evict(&mut self, meta: Token, instructions: &mut Vec<Token>) {
+        if let Some(pattern) = self.entries.remove(&meta) {
+            self.patterns.remove(&pattern);
+            self.last_used.remove(&meta);
+            instructions.extend([self.evict_token, meta]);
+        }
+    }
+}
+
+/// Decoder replaying a `SlidingDictionaryCompressor`'s frames.
+#[derive(Debug, Clone)]
+pub struct SlidingDictionaryDecoder {
+    config: CompressionConfig,
+    add_token: Token,
+    evict_token: Token,
+    entries: HashMap<Token, Vec<Token>>,
+}
+
+impl SlidingDictionaryDecoder {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            add_token: DEFAULT_ADD_TOKEN,
+            evict_token: DEFAULT_EVICT_TOKEN,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Use different instruction tokens.
+    pub fn with_instruction_tokens(mut self, add_token: Token, evict_token: Token) -> Self {
+        self.add_token = add_token;
+        self.evict_token = evict_token;
+        self
+    }
+
+    /// The dictionary as of the last decoded frame.
+    pub fn entries(&self) -> &HashMap<Token, Vec<Token>> {
+        &self.entries
+    }
+
+    /// Apply a frame's instructions and decode its body.
+    pub fn decompress_frame(&mut self, frame: &[Token]) -> Result<Vec<Token>, CompressionError> {
+        let malformed = |reason: &str| CompressionError::MalformedDictionary(reason.to_string());
+        if frame.first() != Some(&self.config.dict_start_token) {
+            return Err(malformed("frame does not start with a dictionary section"));
+        }
+
+        let mut pos = 1;
+        loop {
+            match frame.get(pos) {
+                Some(&t) if t == self.config.dict_end_token => break,
+                Some(&t) if t == self.add_token => {
+                    let (meta, len) = match (frame.get(pos + 1), frame.get(pos + 2)) {
+                        (Some(&meta), Some(&len)) => (meta, len as usize),
+                        _ => return Err(malformed("truncated add instruction")),
+                    };
+                    let definition = frame
+                        .get(pos + 3..pos + 3 + len)
+                        .ok_or_else(|| malformed("definition runs past the frame"))?;
+                    self.entries.insert(meta, definition.to_vec());
+                    pos += 3 + len;
+                }
+                Some(&t) if t == self.evict_token => {
+                    let meta = frame
+                        .get(pos + 1)
+                        .ok_or_else(|| malformed("truncated evict instruction"))?;
+                    self.entries.remove(meta);
+                    pos += 2;
+                }
+                Some(_) => return Err(malformed("unknown dictionary instruction")),
+                None => return Err(malformed("missing dictionary end delimiter")),
+            }
+        }
+
+        let body = frame[pos + 1..].to_vec();
+        Ok(expand_body(body, &self.entries, self.config.escape_token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The stream and the offset where its phrases change.
+    fn stream() -> (Vec<Token>, usize) {
+        // Phrases from one set for the first half, another for the second
+        let early: [&[Token]; 2] = [&[1, 2, 3, 4, 5], &[6, 7, 8, 9]];
+        let late: [&[Token]; 2] = [&[50, 51, 52, 53], &[60, 61, 62, 63, 64, 65]];
+        let mut tokens = Vec::new();
+        let mut shift = 0;
+        for i in 0..80u32 {
+            if i == 40 {
+                shift = tokens.len();
+            }
+            let phrases = if i < 40 { early } else { late };
+            tokens.extend_from_slice(phrases[i as usize % 2]);
+            tokens.push(1000 + i);
+        }
+        (tokens, shift)
+    }
+
+    #[test]
+    fn test_sliding_dictionary_follows_drift() {
+        let config = CompressionConfig::default();
+        let mut compressor = SlidingDictionaryCompressor::new(config.clone(), 100, 8);
+        let mut decoder = SlidingDictionaryDecoder::new(config);
+
+        let (tokens, shift) = stream();
+        let mut restored = Vec::new();
+        let mut compressed = 0;
+        for (i, chunk) in tokens.chunks(60).enumerate() {
+            let frame = compressor.compress_frame(chunk).unwrap();
+            compressed += frame.len();
+            restored.extend(decoder.decompress_frame(&frame).unwrap());
+            assert_eq!(decoder.entries(), compressor.entries());
+
+            let end = (i + 1) * 60;
+            let defines = |pattern: &[Token]| {
+                compressor
+                    .entries()
+                    .values()
+                    .any(|definition| definition == pattern)
+            };
+            if end <= shift {
+                assert!(defines(&[1, 2, 3, 4, 5]));
+            } else if end >= shift + 160 {
+                // The early phrases have aged out; the late ones replaced them
+                assert!(!defines(&[1, 2, 3, 4, 5]));
+                assert!(!defines(&[6, 7, 8, 9]));
+                assert!(defines(&[50, 51, 52, 53]));
+                assert!(defines(&[60, 61, 62, 63, 64, 65]));
+            }
+        }
+
+        assert_eq!(restored, tokens);
+        assert!(compressed < tokens.len());
+    }
+
+    #[test]
+    fn test_sliding_dictionary_escapes_reserved_literals() {
+        let config = CompressionConfig::default();
+        let mut compressor = SlidingDictionaryCompressor::new(config.clone(), 100, 8);
+        let mut decoder = SlidingDictionaryDecoder::new(config.clone());
+
+        let mut tokens = [1, 2, 3, 4].repeat(6);
+        tokens.extend([
+            DEFAULT_NEXT_META_TOKEN,
+            config.escape_token,
+            DEFAULT_ADD_TOKEN,
+        ]);
+        let frame = compressor.compress_frame(&tokens).unwrap();
+        assert_eq!(decoder.decompress_frame(&frame).unwrap(), tokens);
+
+        let truncated = &frame[..3];
+        assert!(decoder.decompress_frame(truncated).is_err());
+    }
+}
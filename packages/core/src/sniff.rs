@@ -0,0 +1,312 @@
+//! Heuristic detection of the serialized format.
+//!
+//! Recovery tool for streams whose compression config was lost: guesses
+//! the dictionary delimiters and whether entries carry length tokens by
+//! looking for a well-formed dictionary section at the start of the stream.
+
+use crate::types::Token;
+use std::collections::HashMap;
+
+/// Smallest token accepted as a dictionary delimiter.
+///
+/// Delimiters are reserved ids far above any tokenizer vocabulary.
+pub const MIN_DELIMITER_TOKEN: Token = 0x8000_0000;
+
+/// Format parameters recovered from a serialized stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedFormat {
+    /// Token opening the dictionary section
+    pub dict_start_token: Token,
+    /// Token closing the dictionary section
+    pub dict_end_token: Token,
+    /// Whether entries appear to carry length tokens
+    pub dict_length_enabled: bool,
+    /// Number of dictionary entries found
+    pub entry_count: usize,
+}
+
+/// Guess the format of a serialized stream.
+///
+/// A stream is plausible when it opens with a high-valued delimiter that is
+/// later followed by a different high-valued delimiter absent from the rest
+/// of the stream, the tokens between them parse as dictionary entries, and
+/// every entry's meta-token occurs again elsewhere in the stream.
+/// Layouts with length tokens are tried before layouts without them, which
+/// are assumed to use consecutive meta-token ids as this crate assigns them.
+///
+/// Runs in `O(n log n)`: entry boundaries depend only on the tokens before
+/// them, so each layout is walked once and every candidate end is checked
+/// against that walk.
+///
+/// Returns `None` when no plausible dictionary is found, including for
+/// streams that were stored uncompressed.
+pub fn sniff_format(tokens: &[Token]) -> Option<DetectedFormat> {
+    let dict_start = *tokens.first()?;
+    if dict_start < MIN_DELIMITER_TOKEN {
+        return None;
+    }
+
+    let mut counts: HashMap<Token, usize> = HashMap::new();
+    let mut last_index: HashMap<Token, usize> = HashMap::new();
+    for (i, &token) in tokens.iter().enumerate() {
+        *counts.entry(token).or_default() += 1;
+        last_index.insert(token, i);
+    }
+
+    // The closing delimiter is never expected inside the body, which rules
+    // out meta-tokens (also high-valued) as false end markers.
+    let end_candidates: Vec<usize> = (2..tokens.len())
+        .filter(|&j| tokens[j] >= MIN_DELIMITER_TOKEN && tokens[j] != dict_start)
+        .filter(|&j| last_index[&tokens[j]] == j)
+        .collect();
+
+    for dict_length_enabled in [true, false] {
+        let walk = if dict_length_enabled {
+            EntryWalk::with_lengths(tokens)
+        } else {
+            EntryWalk::consecutive(tokens)
+        };
+        let referenced = walk.referenced_prefix(tokens, dict_start, &counts);
+
+        for &end in &end_candidates {
+            let Some(entry_count) = walk.entries_ending_at(end) else {
+                continue;
+            };
+            let dict_end = tokens[end];
+            let clashes = walk.key_index(dict_end).is_some_and(|i| i < entry_count);
+            if entry_count <= referenced && !clashes {
+                return Some(DetectedFormat {
+                    dict_start_token: dict_start,
+                    dict_end_token: dict_end,
+                    dict_length_enabled,
+                    entry_count,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Entry boundaries of one dictionary layout, walked from the start of a
+/// stream as if the dictionary ran to its end.
+struct EntryWalk {
+    /// Position of each entry's meta-token
+    keys: Vec<usize>,
+    /// Position just past each entry, for layouts with length tokens
+    ends: Option<Vec<usize>>,
+    /// Last position a dictionary may end at, for layouts without them
+    limit: usize,
+    /// First entry index of each meta-token
+    first_key: HashMap<Token, usize>,
+}
+
+impl EntryWalk {
+    /// Walk `meta, len, definition...` entries.
+    fn with_lengths(tokens: &[Token]) -> Self {
+        let mut keys = Vec::new();
+        let mut ends = Vec::new();
+        let mut pos = 1;
+
+        while let Some(&length) = tokens.get(pos + 1) {
+            let start = pos + 2;
+            let length = length as usize;
+            if length == 0 || length > tokens.len() - start {
+                break;
+            }
+            keys.push(pos);
+            pos = start + length;
+            ends.push(pos);
+        }
+
+        Self::new(tokens, keys, Some(ends), tokens.len())
+    }
+
+    /// Walk entries delimited by consecutive meta-token ids. Every entry
+    /// but the last ends where the next id first appears, so only the last
+    /// one depends on where the dictionary ends.
+    fn consecutive(tokens: &[Token]) -> Self {
+        let mut keys = Vec::new();
+        let mut limit = tokens.len();
+        let mut pos = 1;
+
+        while pos < tokens.len() {
+            keys.push(pos);
+            let Some(next) = tokens[pos].checked_add(1) else {
+                break;
+            };
+            let Some(offset) = tokens[pos + 1..].iter().position(|&t| t == next) else {
+                break;
+            };
+            if offset == 0 {
+                // An empty entry: no dictionary may extend past it
+                limit = pos + 1;
+                break;
+            }
+            pos += 1 + offset;
+        }
+
+        Self::new(tokens, keys, None, limit)
+    }
+
+    fn new(tokens: &[Token], keys: Vec<usize>, ends: Option<Vec<usize>>, limit: usize) -> Self {
+        let mut first_key = HashMap::new();
+        for (i, &key) in keys.iter().enumerate() {
+            first_key.entry(tokens[key]).or_insert(i);
+        }
+        Self {
+            keys,
+            ends,
+            limit,
+            first_key,
+        }
+    }
+
+    /// Number of entries that exactly fill `tokens[1..end]`, if they do.
+    fn entries_ending_at(&self, end: usize) -> Option<usize> {
+        match &self.ends {
+            Some(ends) => ends.binary_search(&end).ok().map(|i| i + 1),
+            None => {
+                if end > self.limit {
+                    return None;
+                }
+                let count = self.keys.partition_point(|&key| key < end);
+                // The last entry needs at least one definition token
+                (count > 0 && self.keys[count - 1] + 1 < end).then_some(count)
+            }
+        }
+    }
+
+    /// Number of leading entries whose meta-tokens are distinct, differ
+    /// from `dict_start` and occur again elsewhere in the stream.
+    fn referenced_prefix(
+        &self,
+        tokens: &[Token],
+        dict_start: Token,
+        counts: &HashMap<Token, usize>,
+    ) -> usize {
+        self.keys
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &key)| {
+                let meta = tokens[key];
+                meta != dict_start && self.first_key[&meta] == i && counts[&meta] > 1
+            })
+            .count()
+    }
+
+    /// Entry index of the first meta-token equal to `token`.
+    fn key_index(&self, token: Token) -> Option<usize> {
+        self.first_key.get(&token).copied()
+    }
+}
+
+/// Parse `meta, len, definition...` entries that exactly fill `region`.
+pub(crate) fn parse_with_lengths(region: &[Token]) -> Option<Vec<(Token, &[Token])>> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+
+    while pos < region.len() {
+        let meta = region[pos];
+        let length = *region.get(pos + 1)? as usize;
+        let start = pos + 2;
+        if length == 0 || length > region.len() - start {
+            return None;
+        }
+        entries.push((meta, &region[start..start + length]));
+        pos = start + length;
+    }
+
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::{build_body, build_dictionary, serialize_result, DEFAULT_ESCAPE_TOKEN};
+    use crate::types::{CompressionConfig, Occurrence};
+
+    fn compressed_stream(config: &CompressionConfig) -> Vec<Token> {
+        let original: Vec<Token> = vec![1, 2, 3, 9, 1, 2, 3, 8, 1, 2, 3, 4, 5, 4, 5, 4, 5];
+        let occurrence = |subsequence: Vec<Token>, start| Occurrence {
+            start,
+            length: subsequence.len(),
+            subsequence: subsequence.into(),
+            priority: 0,
+            patches: vec![],
+        };
+        let selected = vec![
+            occurrence(vec![1, 2, 3], 0),
+            occurrence(vec![1, 2, 3], 4),
+            occurrence(vec![1, 2, 3], 8),
+            occurrence(vec![4, 5], 11),
+            occurrence(vec![4, 5], 13),
+            occurrence(vec![4, 5], 15),
+        ];
+
+        let dict = build_dictionary(&selected, config, 0xFFFF0000);
+        let body = build_body(&original, &selected, &dict.pattern_to_meta, DEFAULT_ESCAPE_TOKEN);
+        serialize_result(&dict, &body, &original, config).serialized_tokens
+    }
+
+    #[test]
+    fn test_sniff_default_stream() {
+        let config = CompressionConfig::default();
+        let stream = compressed_stream(&config);
+
+        let detected = sniff_format(&stream).unwrap();
+
+        assert_eq!(detected.dict_start_token, config.dict_start_token);
+        assert_eq!(detected.dict_end_token, config.dict_end_token);
+        assert!(detected.dict_length_enabled);
+        assert_eq!(detected.entry_count, 2);
+    }
+
+    #[test]
+    fn test_sniff_stream_without_lengths() {
+        let config = CompressionConfig {
+            dict_length_enabled: false,
+            ..Default::default()
+        };
+        let stream = compressed_stream(&config);
+
+        let detected = sniff_format(&stream).unwrap();
+
+        assert_eq!(detected.dict_end_token, config.dict_end_token);
+        assert!(!detected.dict_length_enabled);
+        assert_eq!(detected.entry_count, 2);
+    }
+
+    #[test]
+    fn test_sniff_random_tokens() {
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 32) as Token
+        };
+
+        for _ in 0..20 {
+            let tokens: Vec<Token> = (0..200).map(|_| next()).collect();
+            assert_eq!(sniff_format(&tokens), None);
+        }
+
+        let small: Vec<Token> = (0..200).map(|_| next() % 50_000).collect();
+        assert_eq!(sniff_format(&small), None);
+    }
+
+    #[test]
+    fn test_sniff_long_stream_of_unique_delimiters() {
+        // Every token is a candidate end; each must be ruled out cheaply
+        let tokens: Vec<Token> = (0..200_000).map(|i| MIN_DELIMITER_TOKEN + i).collect();
+        assert_eq!(sniff_format(&tokens), None);
+
+        let config = CompressionConfig::default();
+        let mut stream = compressed_stream(&config);
+        stream.extend(&tokens[1..]);
+        let detected = sniff_format(&stream).unwrap();
+        assert_eq!(detected.dict_end_token, config.dict_end_token);
+        assert_eq!(detected.entry_count, 2);
+    }
+}
@@ -0,0 +1,123 @@
+//! Source spans for compressed output.
+//!
+//! Callers whose tokens carry annotations, such as the source byte range
+//! each token came from, keep them in a parallel array. Decoding is
+//! lossless, so the decoded tokens keep their input spans; each body token
+//! covers the spans of the tokens it expands to.
+
+use crate::dictionary::decompress_ring_positions;
+use crate::error::CompressionError;
+use crate::types::{CompressionConfig, CompressionResult};
+
+/// A `(start, end)` source range attached to a token.
+pub type Span = (u32, u32);
+
+/// Spans of a compression result's decoded output and body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanMap {
+    /// Span of each decoded token
+    pub decoded: Vec<Span>,
+    /// Span covered by each body token: from the smallest start to the
+    /// largest end of its expansion. `None` for run markers, run lengths
+    /// and escape tokens, which decode to nothing.
+    pub body: Vec<Option<Span>>,
+}
+
+/// Map `spans`, one per input token, onto a result's decoded output and body.
+///
+/// Fails with `InvalidConfig` on channel results, as
+/// `decompress_with_positions` does. Rotated rings (see `circular`) are
+/// mapped back to input positions.
+pub fn remap_spans(
+    result: &CompressionResult,
+    spans: &[Span],
+    config: &CompressionConfig,
+) -> Result<SpanMap, CompressionError> {
+    if spans.len() != result.original_length {
+        return Err(CompressionError::InvalidConfig(format!(
+            "expected {} spans, got {}",
+            result.original_length,
+            spans.len()
+        )));
+    }
+
+    let (decoded, positions, cut) = decompress_ring_positions(&result.serialized_tokens, config)?;
+    if decoded.len() != spans.len() {
+        return Err(CompressionError::VerificationFailed);
+    }
+
+    // Ranges of a rotated ring index the rotated input
+    let mut body = vec![None; result.body_tokens.len()];
+    for (index, range) in positions {
+        let covered = || range.clone().map(|i| spans[(i + cut) % spans.len()]);
+        let start = covered().map(|span| span.0).min();
+        let end = covered().map(|span| span.1).max();
+        body[index] = start.zip(end);
+    }
+
+    Ok(SpanMap {
+        decoded: spans.to_vec(),
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::expand_body;
+    use crate::types::Token;
+    use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+    fn spans_for(tokens: &[Token]) -> Vec<Span> {
+        (0..tokens.len() as u32)
+            .map(|i| (i * 4, i * 4 + 3))
+            .collect()
+    }
+
+    #[test]
+    fn test_spans_survive_round_trip() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 4, 5, 6, 7, 8, 50 + i]);
+        }
+        let spans = spans_for(&tokens);
+
+        for body_runs in [false, true] {
+            let config = CompressionConfig {
+                body_runs,
+                ..Default::default()
+            };
+            let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert!(!result.dictionary_map.is_empty());
+
+            let map = remap_spans(&result, &spans, &config).unwrap();
+            assert_eq!(map.decoded, spans);
+            assert_eq!(map.body.len(), result.body_tokens.len());
+        }
+    }
+
+    #[test]
+    fn test_body_spans_cover_expansions() {
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..8 {
+            tokens.extend([7, 8, 9, 100 + i, 7, 8]);
+        }
+        let spans = spans_for(&tokens);
+        let config = CompressionConfig::default();
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(!result.dictionary_map.is_empty());
+
+        let map = remap_spans(&result, &spans, &config).unwrap();
+
+        let mut offset = 0;
+        for (&token, &span) in result.body_tokens.iter().zip(&map.body) {
+            let expanded = expand_body(vec![token], &result.dictionary_map, config.escape_token);
+            let end = offset + expanded.len();
+            assert_eq!(span, Some((spans[offset].0, spans[end - 1].1)));
+            offset = end;
+        }
+        assert_eq!(offset, tokens.len());
+
+        assert!(remap_spans(&result, &spans[1..], &config).is_err());
+    }
+}
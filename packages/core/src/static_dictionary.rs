@@ -0,0 +1,304 @@
+//! Prebuilt dictionaries shared across inputs.
+//!
+//! Mirrors the SDK's `StaticDictionary`: a named set of patterns, each
+//! assigned a fixed meta-token id, built once per domain and reused.
+
+use crate::error::CompressionError;
+use crate::hash::hash_tokens;
+use crate::types::{CompressionConfig, CompressionResult, NoCompressionReason, Token};
+use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+use std::collections::HashMap;
+
+/// Default first meta-token id of a static dictionary, matching the SDK.
+pub const DEFAULT_STATIC_META_TOKEN: Token = 0xFFFF8000;
+
+/// A named dictionary with fixed meta-token ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticDictionary {
+    /// Unique identifier for the dictionary
+    pub id: String,
+    /// Mapping from meta-token to its definition
+    pub entries: HashMap<Token, Vec<Token>>,
+    /// Meta-token assigned to each pattern
+    pub patterns: HashMap<Vec<Token>, Token>,
+}
+
+/// How [`StaticDictionary::merge`] resolves conflicts with `other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Shift every id of `other` past the largest id of `self`, keeping
+    /// their relative order. Patterns already in `self` are dropped.
+    Renumber,
+    /// Keep `other`'s ids, dropping entries whose id or pattern is already
+    /// used by `self`.
+    PreferSelf,
+}
+
+impl StaticDictionary {
+    /// Build a dictionary assigning consecutive ids from `start_meta_token`.
+    ///
+    /// Repeated patterns keep their first id.
+    pub fn from_patterns(
+        id: impl Into<String>,
+        patterns: Vec<Vec<Token>>,
+        start_meta_token: Token,
+    ) -> Result<Self, CompressionError> {
+        let mut dictionary = Self {
+            id: id.into(),
+            entries: HashMap::new(),
+            patterns: HashMap::new(),
+        };
+
+        let mut next = start_meta_token;
+        for pattern in patterns {
+            if dictionary.patterns.contains_key(&pattern) {
+                continue;
+            }
+            dictionary.insert(next, pattern);
+            next = next.checked_add(1).ok_or_else(|| {
+                CompressionError::MalformedDictionary("meta-token ids overflow".to_string())
+            })?;
+        }
+        Ok(dictionary)
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Combine two dictionaries into one with unique ids and patterns.
+    ///
+    /// Entries of `self` are always kept unchanged.
+    pub fn merge(
+        mut self,
+        other: StaticDictionary,
+        strategy: MergeStrategy,
+    ) -> Result<Self, CompressionError> {
+        let mut incoming: Vec<(Token, Vec<Token>)> = other.entries.into_iter().collect();
+        incoming.sort_unstable_by_key(|(id, _)| *id);
+
+        let offset = match (strategy, self.entries.keys().max(), incoming.first()) {
+            (MergeStrategy::Renumber, Some(&self_max), Some(&(other_min, _))) => {
+                Some((self_max as u64 + 1).saturating_sub(other_min as u64))
+            }
+            _ => None,
+        };
+
+        for (id, pattern) in incoming {
+            if self.patterns.contains_key(&pattern) {
+                continue;
+            }
+            let id = match offset {
+                Some(offset) => Token::try_from(id as u64 + offset).map_err(|_| {
+                    CompressionError::MalformedDictionary(
+                        "renumbered meta-token ids overflow".to_string(),
+                    )
+                })?,
+                None => id,
+            };
+            if self.entries.contains_key(&id) {
+                continue;
+            }
+            self.insert(id, pattern);
+        }
+
+        self.id = format!("{}+{}", self.id, other.id);
+        Ok(self)
+    }
+
+    fn insert(&mut self, id: Token, pattern: Vec<Token>) {
+        self.patterns.insert(pattern.clone(), id);
+        self.entries.insert(id, pattern);
+    }
+
+    /// Replace occurrences of patterns two or more tokens long with their
+    /// ids, preferring the longest match at each position.
+    fn substitute(&self, tokens: &[Token]) -> Vec<Token> {
+        let longest = self.patterns.keys().map(Vec::len).max().unwrap_or(0);
+        let mut output = Vec::with_capacity(tokens.len());
+        let mut pos = 0;
+        while pos < tokens.len() {
+            let max = longest.min(tokens.len() - pos);
+            let matched = (2..=max).rev().find_map(|length| {
+                let id = self.patterns.get(&tokens[pos..pos + length])?;
+                Some((*id, length))
+            });
+            match matched {
+                Some((id, length)) => {
+                    output.push(id);
+                    pos += length;
+                }
+                None => {
+                    output.push(tokens[pos]);
+                    pos += 1;
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Compress against a shared base dictionary plus an inline secondary one.
+///
+/// Occurrences of `base` patterns are replaced by their ids first, then
+/// discovery runs on what remains, so the output is the usual
+/// `[dictionary][body]` stream whose dictionary holds only the
+/// message-specific patterns. Its body, and the secondary definitions, can
+/// reference both dictionaries; decode with `decompress_layered` and the
+/// same `base`. Fails if the input holds a token that is a `base` id.
+pub fn compress_layered(
+    tokens: &[Token],
+    base: &StaticDictionary,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    if let Some(&token) = tokens.iter().find(|t| base.entries.contains_key(t)) {
+        return Err(CompressionError::InvalidConfig(format!(
+            "input token {} is a meta-token of base dictionary {}",
+            token, base.id
+        )));
+    }
+
+    let residual = base.substitute(tokens);
+    let mut result = compress_internal(&residual, config, DEFAULT_NEXT_META_TOKEN)?;
+    result.original_tokens = tokens.to_vec();
+    result.original_length = tokens.len();
+    result.original_hash = hash_tokens(tokens);
+    result.static_dictionary_id = Some(base.id.clone());
+    // Base substitutions alone can make the output smaller
+    result.reason = if result.compressed_length < tokens.len() {
+        None
+    } else {
+        result.reason.or(Some(NoCompressionReason::NotBeneficial))
+    };
+    Ok(result)
+}
+
+/// Decompress the output of `compress_layered` made with `base`.
+pub fn decompress_layered(
+    tokens: &[Token],
+    base: &StaticDictionary,
+    config: &CompressionConfig,
+) -> Result<Vec<Token>, CompressionError> {
+    let residual = decompress_internal(tokens, config, DEFAULT_NEXT_META_TOKEN)?;
+    let mut output = Vec::with_capacity(residual.len());
+    for token in residual {
+        match base.entries.get(&token) {
+            Some(pattern) => output.extend(pattern),
+            None => output.push(token),
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_consistent(dictionary: &StaticDictionary) {
+        assert_eq!(dictionary.entries.len(), dictionary.patterns.len());
+        for (id, pattern) in &dictionary.entries {
+            assert_eq!(dictionary.patterns.get(pattern), Some(id));
+        }
+    }
+
+    fn sample() -> (StaticDictionary, StaticDictionary) {
+        let base = StaticDictionary::from_patterns(
+            "base",
+            vec![vec![1, 2], vec![3, 4, 5], vec![6, 7]],
+            DEFAULT_STATIC_META_TOKEN,
+        )
+        .unwrap();
+        // [3, 4, 5] conflicts by pattern, [8, 9] by id
+        let domain = StaticDictionary::from_patterns(
+            "domain",
+            vec![vec![8, 9], vec![3, 4, 5], vec![10, 11, 12], vec![13, 14]],
+            DEFAULT_STATIC_META_TOKEN,
+        )
+        .unwrap();
+        (base, domain)
+    }
+
+    #[test]
+    fn test_merge_renumber_shifts_other_past_self() {
+        let (base, domain) = sample();
+        let self_max = *base.entries.keys().max().unwrap();
+
+        let merged = base.clone().merge(domain, MergeStrategy::Renumber).unwrap();
+
+        assert_consistent(&merged);
+        assert_eq!(merged.id, "base+domain");
+        assert_eq!(merged.len(), 6);
+        for (id, pattern) in &base.entries {
+            assert_eq!(merged.entries.get(id), Some(pattern));
+        }
+        assert_eq!(merged.patterns[&vec![8, 9]], self_max + 1);
+        assert_eq!(merged.patterns[&vec![10, 11, 12]], self_max + 3);
+        assert_eq!(merged.patterns[&vec![13, 14]], self_max + 4);
+    }
+
+    #[test]
+    fn test_layered_compression_uses_both_dictionaries() {
+        let base = StaticDictionary::from_patterns(
+            "base",
+            vec![vec![1, 2, 3, 4], vec![5, 6, 7]],
+            DEFAULT_STATIC_META_TOKEN,
+        )
+        .unwrap();
+        // Base patterns and a message-specific [20, 21, 22, 23] around
+        // unique fillers
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 4, 100 + i, 20, 21, 22, 23, 200 + i, 5, 6, 7]);
+        }
+        let config = CompressionConfig::default();
+
+        let result = compress_layered(&tokens, &base, &config).unwrap();
+        assert_eq!(result.static_dictionary_id.as_deref(), Some("base"));
+        assert_eq!(result.reason, None);
+        let definitions: Vec<&Vec<Token>> = result.dictionary_map.values().collect();
+        assert!(definitions.iter().any(|d| d[..] == [20, 21, 22, 23]));
+        assert!(!definitions.iter().flat_map(|d| d.iter()).any(|t| (1..8).contains(t)));
+        assert_eq!(decompress_layered(&result.serialized_tokens, &base, &config).unwrap(), tokens);
+
+        // Body tokens reference the base ids, so the stream alone does
+        // not decode to the input
+        let serialized = &result.serialized_tokens;
+        let residual = decompress_internal(serialized, &config, DEFAULT_NEXT_META_TOKEN);
+        assert_ne!(residual.unwrap(), tokens);
+        let plain = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(result.compressed_length < plain.compressed_length);
+
+        let colliding = [DEFAULT_STATIC_META_TOKEN, 9];
+        assert!(matches!(
+            compress_layered(&colliding, &base, &config),
+            Err(CompressionError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_merge_prefer_self_drops_conflicts() {
+        let (base, domain) = sample();
+
+        let merged = base
+            .clone()
+            .merge(domain, MergeStrategy::PreferSelf)
+            .unwrap();
+
+        assert_consistent(&merged);
+        assert_eq!(merged.len(), 4);
+        for (id, pattern) in &base.entries {
+            assert_eq!(merged.entries.get(id), Some(pattern));
+        }
+        assert!(!merged.patterns.contains_key(&vec![8, 9]));
+        assert!(!merged.patterns.contains_key(&vec![10, 11, 12]));
+        assert_eq!(
+            merged.patterns[&vec![13, 14]],
+            DEFAULT_STATIC_META_TOKEN + 3
+        );
+    }
+}
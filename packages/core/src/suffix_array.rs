@@ -5,8 +5,10 @@
 //!
 //! Port of `delta/suffix_array.py` and `delta/suffix_array_fast.py`.
 
+use crate::error::CompressionError;
+use crate::hash::PatternMap;
 use crate::types::Token;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 
 /// Suffix array with LCP (Longest Common Prefix) array.
 #[derive(Debug, Clone)]
@@ -25,23 +27,67 @@ impl SuffixArray {
     /// Time complexity: O(n log n)
     /// Space complexity: O(n)
     pub fn build(tokens: &[Token]) -> Self {
+        // Initial ranking based on token values
+        Self::from_ranks(tokens, rank_tokens(tokens))
+    }
+
+    /// `build`, returning `OutOfMemory` instead of aborting when the
+    /// O(n) working arrays cannot be allocated.
+    pub fn try_build(tokens: &[Token]) -> Result<Self, CompressionError> {
+        Self::try_from_ranks(tokens, try_rank_tokens(tokens)?)
+    }
+
+    /// Build a suffix array using a prebuilt vocabulary remapping.
+    ///
+    /// Skips re-ranking the input when every token is in the remapper's
+    /// vocabulary; otherwise falls back to ranking the input as `build` does.
+    pub fn build_with_remapper(tokens: &[Token], remapper: &TokenRemapper) -> Self {
+        match remapper.remap(tokens) {
+            Some(rank) => Self::from_ranks(tokens, rank),
+            None => Self::build(tokens),
+        }
+    }
+
+    /// Build a suffix array for tokens known to lie in `0..alphabet_size`.
+    ///
+    /// Ranks tokens with a counting pass over the alphabet instead of
+    /// sorting and deduplicating the input, then proceeds as `build` and
+    /// yields the identical suffix array. Falls back to `build` when the
+    /// alphabet is larger than the input or a token lies outside it.
+    pub fn build_with_alphabet(tokens: &[Token], alphabet_size: usize) -> Self {
+        if alphabet_size > tokens.len() {
+            return Self::build(tokens);
+        }
+        match rank_by_counting(tokens, alphabet_size) {
+            Some(rank) => Self::from_ranks(tokens, rank),
+            None => Self::build(tokens),
+        }
+    }
+
+    /// Run the doubling algorithm from initial order-preserving token ranks.
+    fn from_ranks(tokens: &[Token], rank: Vec<usize>) -> Self {
+        Self::try_from_ranks(tokens, rank).expect("suffix array allocation")
+    }
+
+    fn try_from_ranks(tokens: &[Token], mut rank: Vec<usize>) -> Result<Self, CompressionError> {
         let n = tokens.len();
         if n == 0 {
-            return Self {
+            return Ok(Self {
                 suffix_array: Vec::new(),
                 lcp: Vec::new(),
-            };
+            });
         }
 
-        // Initial ranking based on token values
-        let mut rank = rank_tokens(tokens);
-        let mut sa: Vec<usize> = (0..n).collect();
-        let mut tmp = vec![0usize; n];
+        let mut sa: Vec<usize> = try_with_capacity(n)?;
+        sa.extend(0..n);
+        let mut tmp: Vec<usize> = try_with_capacity(n)?;
+        tmp.resize(n, 0);
         let mut k = 1usize;
 
         loop {
-            // Sort by (rank[i], rank[i+k])
-            sa.sort_by(|&a, &b| {
+            // Sort by (rank[i], rank[i+k]); in place, as ties are ranked
+            // equal whatever their order
+            sa.sort_unstable_by(|&a, &b| {
                 let ra = rank[a];
                 let rb = rank[b];
                 if ra != rb {
@@ -81,12 +127,12 @@ impl SuffixArray {
         }
 
         // Build LCP array using Kasai's algorithm
-        let lcp = build_lcp(tokens, &sa);
+        let lcp = build_lcp(tokens, &sa)?;
 
-        Self {
+        Ok(Self {
             suffix_array: sa,
             lcp,
-        }
+        })
     }
 
     /// Extract LCP intervals representing repeated substrings.
@@ -95,10 +141,40 @@ impl SuffixArray {
     /// - start_idx, end_idx are indices into the suffix array
     /// - lcp_value is the minimum LCP in that interval
     ///
-    /// Only returns intervals with lcp_value >= min_len.
-    pub fn lcp_intervals(&self, min_len: usize) -> Vec<(usize, usize, usize)> {
+    /// Only returns intervals with lcp_value >= min_len that span at least
+    /// `min_count` suffixes (`end_idx - start_idx + 1`), so callers needing
+    /// several occurrences are not handed intervals they would discard.
+    pub fn lcp_intervals(&self, min_len: usize, min_count: usize) -> Vec<(usize, usize, usize)> {
+        self.lcp_intervals_with_parent(min_len, min_count)
+            .into_iter()
+            .map(|(start, end, lcp_value, _)| (start, end, lcp_value))
+            .collect()
+    }
+
+    /// `lcp_intervals`, with the LCP value of each interval's parent.
+    ///
+    /// Returns `(start_idx, end_idx, lcp_value, parent_lcp)`. The prefixes
+    /// of length `parent_lcp + 1..=lcp_value` occur exactly at the
+    /// interval's suffixes; shorter prefixes occur at the parent's too.
+    /// Intervals are listed children before parents.
+    pub fn lcp_intervals_with_parent(
+        &self,
+        min_len: usize,
+        min_count: usize,
+    ) -> Vec<(usize, usize, usize, usize)> {
+        self.try_lcp_intervals_with_parent(min_len, min_count)
+            .expect("LCP interval allocation")
+    }
+
+    /// `lcp_intervals_with_parent`, returning `OutOfMemory` instead of
+    /// aborting when the interval list cannot grow.
+    pub fn try_lcp_intervals_with_parent(
+        &self,
+        min_len: usize,
+        min_count: usize,
+    ) -> Result<Vec<(usize, usize, usize, usize)>, CompressionError> {
         if self.lcp.is_empty() {
-            return Vec::new();
+            return Ok(Vec::new());
         }
 
         let mut intervals = Vec::new();
@@ -109,13 +185,18 @@ impl SuffixArray {
 
             while !stack.is_empty() && stack.last().unwrap().1 > lcp_value {
                 let (prev_start, prev_lcp) = stack.pop().unwrap();
-                if prev_lcp >= min_len {
-                    intervals.push((prev_start, i, prev_lcp));
+                // The parent is the next interval down the stack, or one
+                // opened at `lcp_value` that extends this one
+                let parent_lcp = stack.last().map_or(0, |&(_, lcp)| lcp).max(lcp_value);
+                if prev_lcp >= min_len && i - prev_start + 1 >= min_count {
+                    intervals.try_reserve(1).map_err(|_| CompressionError::OutOfMemory)?;
+                    intervals.push((prev_start, i, prev_lcp, parent_lcp));
                 }
                 start = prev_start;
             }
 
             if stack.is_empty() || stack.last().unwrap().1 < lcp_value {
+                stack.try_reserve(1).map_err(|_| CompressionError::OutOfMemory)?;
                 stack.push((start, lcp_value));
             }
         }
@@ -123,45 +204,182 @@ impl SuffixArray {
         // Process remaining stack
         let n = self.lcp.len();
         while let Some((start, lcp_value)) = stack.pop() {
-            if lcp_value >= min_len {
-                intervals.push((start, n, lcp_value));
+            let parent_lcp = stack.last().map_or(0, |&(_, lcp)| lcp);
+            if lcp_value >= min_len && n - start + 1 >= min_count {
+                intervals.try_reserve(1).map_err(|_| CompressionError::OutOfMemory)?;
+                intervals.push((start, n, lcp_value, parent_lcp));
             }
         }
 
-        intervals
+        Ok(intervals)
+    }
+
+    /// Burrows-Wheeler transform of the `tokens` this array was built from.
+    ///
+    /// `bwt[i] = tokens[(sa[i] + n - 1) % n]`. Suffixes, not rotations, are
+    /// sorted, so this equals the rotation-sorted transform when the input
+    /// ends with a unique smallest token (a sentinel). Either way
+    /// `inverse_bwt` restores the input given `bwt_primary_index`.
+    pub fn bwt(&self, tokens: &[Token]) -> Vec<Token> {
+        let n = tokens.len();
+        self.suffix_array
+            .iter()
+            .map(|&start| tokens[(start + n - 1) % n])
+            .collect()
+    }
+
+    /// Row of the transform holding the whole input's suffix.
+    pub fn bwt_primary_index(&self) -> usize {
+        self.suffix_array
+            .iter()
+            .position(|&start| start == 0)
+            .unwrap_or(0)
+    }
+}
+
+/// Reverse `SuffixArray::bwt`, given its `bwt_primary_index`.
+///
+/// The transform is that of the input with an implicit sentinel appended,
+/// minus the sentinel's row: row `primary_index` holds the input's last
+/// token where the sentinel would be. Decoding walks the LF mapping of the
+/// sentinel-extended transform from the sentinel's row.
+pub fn inverse_bwt(bwt: &[Token], primary_index: usize) -> Vec<Token> {
+    let n = bwt.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Last column of the sentinel-extended transform; `None` is the sentinel
+    let mut last: Vec<Option<Token>> = Vec::with_capacity(n + 1);
+    last.push(Some(bwt[primary_index]));
+    last.extend(bwt.iter().map(|&t| Some(t)));
+    last[primary_index + 1] = None;
+
+    // A stable sort of the last column gives the first column; each row's
+    // position in it is that row's LF mapping
+    let mut order: Vec<usize> = (0..=n).collect();
+    order.sort_by_key(|&row| last[row]);
+    let mut lf = vec![0usize; n + 1];
+    for (first_row, &row) in order.iter().enumerate() {
+        lf[row] = first_row;
+    }
+
+    let mut tokens = Vec::with_capacity(n);
+    let mut row = 0;
+    for _ in 0..n {
+        tokens.extend(last[row]);
+        row = lf[row];
+    }
+    tokens.reverse();
+    tokens
+}
+
+/// Dense, order-preserving remapping of a token vocabulary.
+///
+/// Maps each distinct token to its rank `1..=k` among the vocabulary, which
+/// is the initial ranking the suffix array sorts by. Building it once for a
+/// vocabulary avoids re-sorting the distinct tokens of every sequence.
+#[derive(Debug, Clone, Default)]
+pub struct TokenRemapper {
+    ranks: PatternMap<Token, usize>,
+}
+
+impl TokenRemapper {
+    /// Build a remapper over the distinct tokens in `vocabulary`.
+    pub fn new(vocabulary: &[Token]) -> Self {
+        Self::from_vec(vocabulary.to_vec())
+    }
+
+    /// Build a remapper from an owned copy of the vocabulary.
+    fn from_vec(mut unique: Vec<Token>) -> Self {
+        unique.sort_unstable();
+        unique.dedup();
+
+        let ranks = unique
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| (t, i + 1))
+            .collect();
+
+        Self { ranks }
+    }
+
+    /// Number of distinct tokens in the vocabulary.
+    pub fn alphabet_size(&self) -> usize {
+        self.ranks.len()
+    }
+
+    /// Dense rank of a token, if it is in the vocabulary.
+    pub fn rank(&self, token: Token) -> Option<usize> {
+        self.ranks.get(&token).copied()
+    }
+
+    /// Remap a sequence to dense ranks.
+    ///
+    /// Returns `None` if any token is outside the vocabulary.
+    pub fn remap(&self, tokens: &[Token]) -> Option<Vec<usize>> {
+        tokens.iter().map(|&t| self.rank(t)).collect()
     }
 }
 
 /// Rank tokens to integers for suffix array construction.
 fn rank_tokens(tokens: &[Token]) -> Vec<usize> {
-    // Create a sorted list of unique tokens
-    let mut unique: Vec<Token> = tokens.iter().copied().collect();
-    unique.sort_unstable();
-    unique.dedup();
-
-    // Create mapping from token to rank
-    let mapping: HashMap<Token, usize> = unique
-        .into_iter()
-        .enumerate()
-        .map(|(i, t)| (t, i + 1))
-        .collect();
-
-    tokens.iter().map(|t| mapping[t]).collect()
+    let remapper = TokenRemapper::new(tokens);
+    tokens.iter().map(|t| remapper.ranks[t]).collect()
+}
+
+/// `rank_tokens`, with the per-token buffers allocated fallibly.
+fn try_rank_tokens(tokens: &[Token]) -> Result<Vec<usize>, CompressionError> {
+    let mut vocabulary = try_with_capacity(tokens.len())?;
+    vocabulary.extend_from_slice(tokens);
+    let remapper = TokenRemapper::from_vec(vocabulary);
+
+    let mut rank = try_with_capacity(tokens.len())?;
+    rank.extend(tokens.iter().map(|t| remapper.ranks[t]));
+    Ok(rank)
+}
+
+/// An empty vector with room for `capacity` elements, or `OutOfMemory`.
+pub(crate) fn try_with_capacity<T>(capacity: usize) -> Result<Vec<T>, CompressionError> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(capacity).map_err(|_| CompressionError::OutOfMemory)?;
+    Ok(vec)
+}
+
+/// Rank tokens from `0..alphabet_size` by marking which values occur.
+///
+/// Gives the same dense ranks as `rank_tokens`, or `None` if a token is
+/// outside the alphabet.
+fn rank_by_counting(tokens: &[Token], alphabet_size: usize) -> Option<Vec<usize>> {
+    let mut ranks = vec![0usize; alphabet_size];
+    for &t in tokens {
+        *ranks.get_mut(t as usize)? = 1;
+    }
+
+    let mut next = 0;
+    for rank in ranks.iter_mut().filter(|rank| **rank != 0) {
+        next += 1;
+        *rank = next;
+    }
+
+    Some(tokens.iter().map(|&t| ranks[t as usize]).collect())
 }
 
 /// Build LCP array using Kasai's algorithm.
 ///
 /// Time complexity: O(n)
-fn build_lcp(tokens: &[Token], sa: &[usize]) -> Vec<usize> {
+fn build_lcp(tokens: &[Token], sa: &[usize]) -> Result<Vec<usize>, CompressionError> {
     let n = tokens.len();
     if n == 0 {
-        return Vec::new();
+        return Ok(Vec::new());
     }
 
-    let mut lcp = vec![0usize; n - 1];
+    let mut lcp = try_with_capacity(n - 1)?;
+    lcp.resize(n - 1, 0usize);
 
     // Build inverse suffix array
-    let mut inv = vec![0usize; n];
+    let mut inv = try_with_capacity(n)?;
+    inv.resize(n, 0usize);
     for (i, &idx) in sa.iter().enumerate() {
         inv[idx] = i;
     }
@@ -189,7 +407,7 @@ fn build_lcp(tokens: &[Token], sa: &[usize]) -> Vec<usize> {
         }
     }
 
-    lcp
+    Ok(lcp)
 }
 
 /// Count non-overlapping occurrences of a pattern of given length.
@@ -208,6 +426,21 @@ pub fn count_non_overlapping(positions: &[usize], length: usize) -> usize {
     count
 }
 
+/// Which occurrences to keep when occurrences of a pattern overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonOverlapStrategy {
+    /// Scan left to right, keeping the earliest position of each overlap.
+    #[default]
+    EarliestFirst,
+    /// Scan right to left, keeping the latest position of each overlap.
+    LatestFirst,
+    /// Keep as many positions as possible, accepting unsorted input with
+    /// duplicates. Occurrences of one pattern all share a length, so the
+    /// earliest-first scan is already maximal; ties resolve the same way.
+    MaxCount,
+}
+
 /// Extract non-overlapping positions from a sorted position list.
 pub fn non_overlapping_positions(positions: &[usize], length: usize) -> Vec<usize> {
     let mut result = Vec::with_capacity(positions.len());
@@ -223,6 +456,40 @@ pub fn non_overlapping_positions(positions: &[usize], length: usize) -> Vec<usiz
     result
 }
 
+/// Extract non-overlapping positions using the given strategy.
+///
+/// `positions` must be sorted for `EarliestFirst` and `LatestFirst`. The
+/// result is always sorted in ascending order.
+pub fn non_overlapping_positions_with(
+    positions: &[usize],
+    length: usize,
+    strategy: NonOverlapStrategy,
+) -> Vec<usize> {
+    match strategy {
+        NonOverlapStrategy::EarliestFirst => non_overlapping_positions(positions, length),
+        NonOverlapStrategy::LatestFirst => {
+            let mut result = Vec::with_capacity(positions.len());
+            let mut next_taken = usize::MAX;
+
+            for &pos in positions.iter().rev() {
+                if pos.saturating_add(length) <= next_taken {
+                    result.push(pos);
+                    next_taken = pos;
+                }
+            }
+
+            result.reverse();
+            result
+        }
+        NonOverlapStrategy::MaxCount => {
+            let mut sorted = positions.to_vec();
+            sorted.sort_unstable();
+            sorted.dedup();
+            non_overlapping_positions(&sorted, length)
+        }
+    }
+}
+
 /// Build suffix array with automatic parallel/sequential selection.
 ///
 /// When compiled with the `parallel` feature and input size exceeds threshold,
@@ -238,6 +505,24 @@ pub fn build_suffix_array_auto(tokens: &[Token], _enable_parallel: bool) -> Suff
     SuffixArray::build(tokens)
 }
 
+/// `build_suffix_array_auto`, returning `OutOfMemory` instead of aborting.
+#[cfg(feature = "parallel")]
+pub fn try_build_suffix_array_auto(
+    tokens: &[Token],
+    enable_parallel: bool,
+) -> Result<SuffixArray, CompressionError> {
+    crate::suffix_array_parallel::try_build_suffix_array_auto(tokens, enable_parallel)
+}
+
+/// `build_suffix_array_auto`, returning `OutOfMemory` instead of aborting.
+#[cfg(not(feature = "parallel"))]
+pub fn try_build_suffix_array_auto(
+    tokens: &[Token],
+    _enable_parallel: bool,
+) -> Result<SuffixArray, CompressionError> {
+    SuffixArray::try_build(tokens)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -285,11 +570,32 @@ mod tests {
         let tokens = vec![1, 2, 3, 1, 2, 3, 1, 2, 3];
         let sa = SuffixArray::build(&tokens);
 
-        let intervals = sa.lcp_intervals(2);
+        let intervals = sa.lcp_intervals(2, 2);
         // Should find intervals for repeated patterns
         assert!(!intervals.is_empty());
     }
 
+    #[test]
+    fn test_lcp_intervals_prunes_narrow_intervals() {
+        let mut tokens = Vec::new();
+        for i in 0..40 {
+            tokens.extend([1, 2, 3, 100 + i % 7, 4, 5, 200 + i, 300 + i / 2, 900]);
+        }
+        let sa = SuffixArray::build(&tokens);
+        let all = sa.lcp_intervals(2, 2);
+
+        for min_count in [3, 6, 10] {
+            let expected: Vec<_> = all
+                .iter()
+                .copied()
+                .filter(|&(start, end, _)| end - start + 1 >= min_count)
+                .collect();
+            let pruned = sa.lcp_intervals(2, min_count);
+            assert_eq!(pruned, expected);
+            assert!(pruned.len() < all.len());
+        }
+    }
+
     #[test]
     fn test_count_non_overlapping() {
         let positions = vec![0, 2, 4, 6, 8];
@@ -306,4 +612,110 @@ mod tests {
         // 0 (takes 0-2), skip 1 and 2, 5 (takes 5-7), skip 6, 10 (takes 10-12)
         assert_eq!(result, vec![0, 5, 10]);
     }
+
+    #[test]
+    fn test_non_overlapping_strategies() {
+        let clustered = vec![0, 1, 2, 3, 7, 8];
+
+        let earliest =
+            non_overlapping_positions_with(&clustered, 2, NonOverlapStrategy::EarliestFirst);
+        assert_eq!(earliest, vec![0, 2, 7]);
+        assert_eq!(earliest, non_overlapping_positions(&clustered, 2));
+
+        let latest = non_overlapping_positions_with(&clustered, 2, NonOverlapStrategy::LatestFirst);
+        assert_eq!(latest, vec![1, 3, 8]);
+
+        let unsorted = vec![3, 8, 0, 2, 7, 1, 2];
+        let max_count = non_overlapping_positions_with(&unsorted, 2, NonOverlapStrategy::MaxCount);
+        assert_eq!(max_count, vec![0, 2, 7]);
+
+        assert_eq!(NonOverlapStrategy::default(), NonOverlapStrategy::EarliestFirst);
+    }
+
+    #[test]
+    fn test_remapper_matches_inline_ranking() {
+        let vocabulary: Vec<Token> = vec![7, 1_000_000, 42, 0xFFFF_0000, 3, 99_999];
+        let remapper = TokenRemapper::new(&vocabulary);
+        assert_eq!(remapper.alphabet_size(), 6);
+
+        let sequences: [Vec<Token>; 3] = [
+            vec![42, 7, 42, 7, 1_000_000, 42, 7],
+            vec![0xFFFF_0000, 3, 3, 99_999, 3, 3, 0xFFFF_0000],
+            vec![7],
+        ];
+        for tokens in &sequences {
+            let inline = SuffixArray::build(tokens);
+            let prebuilt = SuffixArray::build_with_remapper(tokens, &remapper);
+            assert_eq!(prebuilt.suffix_array, inline.suffix_array);
+            assert_eq!(prebuilt.lcp, inline.lcp);
+        }
+
+        // Tokens outside the vocabulary fall back to inline ranking
+        let unknown: Vec<Token> = vec![5, 6, 5, 6];
+        assert_eq!(remapper.remap(&unknown), None);
+        assert_eq!(
+            SuffixArray::build_with_remapper(&unknown, &remapper).suffix_array,
+            SuffixArray::build(&unknown).suffix_array
+        );
+    }
+
+    #[test]
+    fn test_build_with_alphabet_matches_build() {
+        let mut state: u32 = 12345;
+        let mut bytes = Vec::new();
+        for _ in 0..2_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            bytes.push((state >> 16) % 256);
+        }
+
+        let inputs: [Vec<Token>; 5] = [
+            bytes,
+            b"abracadabra banana bandana".iter().map(|&b| b as Token).collect(),
+            vec![3, 3, 3, 3, 3, 3],
+            vec![0, 255, 0, 255, 1],
+            vec![],
+        ];
+        for tokens in &inputs {
+            let expected = SuffixArray::build(tokens);
+            let counted = SuffixArray::build_with_alphabet(tokens, 256);
+            assert_eq!(counted.suffix_array, expected.suffix_array);
+            assert_eq!(counted.lcp, expected.lcp);
+        }
+
+        // Tokens outside the alphabet fall back to sorting
+        let outside: Vec<Token> = vec![300; 400];
+        assert_eq!(rank_by_counting(&outside, 256), None);
+        assert_eq!(
+            SuffixArray::build_with_alphabet(&outside, 256).suffix_array,
+            SuffixArray::build(&outside).suffix_array
+        );
+    }
+
+    #[test]
+    fn test_bwt_round_trip_and_rotation_reference() {
+        // Trailing 0 is a sentinel, so suffix order equals rotation order
+        let tokens: Vec<Token> = vec![2, 1, 3, 1, 3, 1, 0];
+        let sa = SuffixArray::build(&tokens);
+        let n = tokens.len();
+        let mut rotations: Vec<Vec<Token>> = (0..n)
+            .map(|i| tokens[i..].iter().chain(&tokens[..i]).copied().collect())
+            .collect();
+        rotations.sort();
+        let reference: Vec<Token> = rotations.iter().map(|r| r[n - 1]).collect();
+        assert_eq!(sa.bwt(&tokens), reference);
+        assert_eq!(inverse_bwt(&reference, sa.bwt_primary_index()), tokens);
+
+        let inputs: [Vec<Token>; 4] = [
+            (0..200).map(|i| [5, 9, 9, 2][i % 4] + (i / 50) as Token).collect(),
+            vec![7; 10],
+            vec![4],
+            vec![],
+        ];
+        for tokens in &inputs {
+            let sa = SuffixArray::build(tokens);
+            let transformed = sa.bwt(tokens);
+            assert_eq!(transformed.len(), tokens.len());
+            assert_eq!(&inverse_bwt(&transformed, sa.bwt_primary_index()), tokens);
+        }
+    }
 }
@@ -3,10 +3,13 @@
 //! Provides parallel implementations of suffix array construction
 //! for improved performance on multi-core systems.
 
-use crate::suffix_array::SuffixArray;
+use crate::error::CompressionError;
+use crate::suffix_array::{try_with_capacity, SuffixArray};
 use crate::types::Token;
 use rayon::prelude::*;
 use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 /// Configuration for parallel suffix array construction.
 #[derive(Debug, Clone)]
@@ -27,6 +30,82 @@ impl Default for ParallelSAConfig {
     }
 }
 
+/// Input sizes `ParallelSAConfig::auto_tune` times both constructions at.
+const TUNING_SIZES: [usize; 5] = [1_000, 4_000, 16_000, 64_000, 128_000];
+
+/// Threshold `auto_tune` settles on when parallel construction never wins,
+/// such as on a single core.
+const UNTUNED_THRESHOLD: usize = 4 * TUNING_SIZES[TUNING_SIZES.len() - 1];
+
+static TUNED: OnceLock<ParallelSAConfig> = OnceLock::new();
+
+impl ParallelSAConfig {
+    /// Measure where parallel construction starts beating sequential on
+    /// this machine and set `parallel_threshold` there.
+    ///
+    /// Times both constructions on synthetic inputs of a few sizes and
+    /// picks the smallest size from which parallel wins at every larger
+    /// size tried, or a threshold above all of them when it never does.
+    /// The benchmark runs once per process; later calls return the cached
+    /// result, which `build_suffix_array_auto` then uses.
+    pub fn auto_tune() -> ParallelSAConfig {
+        TUNED.get_or_init(tune).clone()
+    }
+
+    /// The tuned settings once `auto_tune` has run, the defaults before.
+    pub fn current() -> ParallelSAConfig {
+        TUNED.get().cloned().unwrap_or_default()
+    }
+}
+
+fn tune() -> ParallelSAConfig {
+    let forced = ParallelSAConfig {
+        parallel_threshold: 0,
+        ..Default::default()
+    };
+    if rayon::current_num_threads() < 2 {
+        return ParallelSAConfig {
+            parallel_threshold: UNTUNED_THRESHOLD,
+            ..Default::default()
+        };
+    }
+
+    // Best of three runs, on a mix of repeats and a few hundred symbols
+    let time = |build: &dyn Fn() -> SuffixArray| {
+        (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                std::hint::black_box(build());
+                start.elapsed()
+            })
+            .min()
+            .unwrap_or(Duration::ZERO)
+    };
+    let mut state: u32 = 1;
+    let input: Vec<Token> = (0..TUNING_SIZES[TUNING_SIZES.len() - 1])
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) % 300
+        })
+        .collect();
+
+    let mut threshold = UNTUNED_THRESHOLD;
+    for &size in TUNING_SIZES.iter().rev() {
+        let tokens = &input[..size];
+        let sequential = time(&|| SuffixArray::build(tokens));
+        let parallel = time(&|| build_suffix_array_parallel(tokens, &forced));
+        if parallel >= sequential {
+            break;
+        }
+        threshold = size;
+    }
+
+    ParallelSAConfig {
+        parallel_threshold: threshold,
+        ..Default::default()
+    }
+}
+
 /// Build suffix array using parallel doubling algorithm.
 ///
 /// Uses Rayon for parallel sorting and rank updates.
@@ -36,31 +115,42 @@ impl Default for ParallelSAConfig {
 /// Time complexity: O(n log n / p) where p is number of processors
 /// Space complexity: O(n)
 pub fn build_suffix_array_parallel(tokens: &[Token], config: &ParallelSAConfig) -> SuffixArray {
+    try_build_suffix_array_parallel(tokens, config).expect("suffix array allocation")
+}
+
+/// `build_suffix_array_parallel`, returning `OutOfMemory` instead of
+/// aborting when its buffers cannot be allocated.
+pub fn try_build_suffix_array_parallel(
+    tokens: &[Token],
+    config: &ParallelSAConfig,
+) -> Result<SuffixArray, CompressionError> {
     let n = tokens.len();
 
     // Fall back to sequential for small inputs
     if n < config.parallel_threshold {
-        return SuffixArray::build(tokens);
+        return SuffixArray::try_build(tokens);
     }
 
     if n == 0 {
-        return SuffixArray {
+        return Ok(SuffixArray {
             suffix_array: Vec::new(),
             lcp: Vec::new(),
-        };
+        });
     }
 
     // Initial ranking based on token values
-    let mut rank = rank_tokens_parallel(tokens);
-    let mut sa: Vec<usize> = (0..n).collect();
-    let mut tmp = vec![0usize; n];
+    let mut rank = rank_tokens_parallel(tokens)?;
+    let mut sa: Vec<usize> = try_with_capacity(n)?;
+    sa.par_extend(0..n);
+    let mut tmp: Vec<usize> = try_with_capacity(n)?;
+    tmp.resize(n, 0);
     let mut k = 1usize;
 
     loop {
-        // Parallel sort by (rank[i], rank[i+k])
-        // We need to capture rank as a reference for the closure
+        // Parallel sort by (rank[i], rank[i+k]); in place, as ties are
+        // ranked equal whatever their order
         let rank_ref = &rank;
-        sa.par_sort_by(|&a, &b| {
+        sa.par_sort_unstable_by(|&a, &b| {
             let ra = rank_ref[a];
             let rb = rank_ref[b];
             if ra != rb {
@@ -95,47 +185,52 @@ pub fn build_suffix_array_parallel(tokens: &[Token], config: &ParallelSAConfig)
     }
 
     // Build LCP array using parallel Kasai's algorithm
-    let lcp = build_lcp_parallel(tokens, &sa);
+    let lcp = build_lcp_parallel(tokens, &sa)?;
 
-    SuffixArray {
+    Ok(SuffixArray {
         suffix_array: sa,
         lcp,
-    }
+    })
 }
 
 /// Parallel token ranking using HashMap with parallel collection.
-fn rank_tokens_parallel(tokens: &[Token]) -> Vec<usize> {
+fn rank_tokens_parallel(tokens: &[Token]) -> Result<Vec<usize>, CompressionError> {
     // Create a sorted list of unique tokens
-    let mut unique: Vec<Token> = tokens.to_vec();
+    let mut unique: Vec<Token> = try_with_capacity(tokens.len())?;
+    unique.extend_from_slice(tokens);
     unique.par_sort_unstable();
     unique.dedup();
 
     // Create mapping from token to rank
-    let mapping: HashMap<Token, usize> = unique
-        .into_iter()
-        .enumerate()
-        .map(|(i, t)| (t, i + 1))
-        .collect();
+    let mut mapping: HashMap<Token, usize> = HashMap::new();
+    mapping
+        .try_reserve(unique.len())
+        .map_err(|_| CompressionError::OutOfMemory)?;
+    mapping.extend(unique.into_iter().enumerate().map(|(i, t)| (t, i + 1)));
 
     // Parallel rank assignment
-    tokens.par_iter().map(|t| mapping[t]).collect()
+    let mut rank = try_with_capacity(tokens.len())?;
+    rank.par_extend(tokens.par_iter().map(|t| mapping[t]));
+    Ok(rank)
 }
 
 /// Build LCP array using Kasai's algorithm with parallel inverse construction.
 ///
 /// The main LCP computation has data dependencies that prevent full parallelization,
 /// but the inverse suffix array construction can be done in parallel.
-fn build_lcp_parallel(tokens: &[Token], sa: &[usize]) -> Vec<usize> {
+fn build_lcp_parallel(tokens: &[Token], sa: &[usize]) -> Result<Vec<usize>, CompressionError> {
     let n = tokens.len();
     if n == 0 {
-        return Vec::new();
+        return Ok(Vec::new());
     }
 
-    let mut lcp = vec![0usize; n - 1];
+    let mut lcp = try_with_capacity(n - 1)?;
+    lcp.resize(n - 1, 0);
 
     // Build inverse suffix array
     // inv[sa[i]] = i, meaning inv[pos] = rank of suffix starting at pos
-    let mut inv = vec![0usize; n];
+    let mut inv = try_with_capacity(n)?;
+    inv.resize(n, 0);
     for (i, &idx) in sa.iter().enumerate() {
         inv[idx] = i;
     }
@@ -164,22 +259,42 @@ fn build_lcp_parallel(tokens: &[Token], sa: &[usize]) -> Vec<usize> {
         }
     }
 
-    lcp
+    Ok(lcp)
 }
 
 /// Build suffix array with automatic parallel/sequential selection.
 ///
-/// Chooses the best implementation based on input size and configuration.
+/// Chooses the best implementation based on input size and configuration,
+/// with the threshold from `ParallelSAConfig::current`.
 pub fn build_suffix_array_auto(tokens: &[Token], enable_parallel: bool) -> SuffixArray {
-    let config = ParallelSAConfig::default();
+    let config = ParallelSAConfig::current();
     
-    if enable_parallel && tokens.len() >= config.parallel_threshold {
+    if uses_parallel(tokens.len(), enable_parallel, &config) {
         build_suffix_array_parallel(tokens, &config)
     } else {
         SuffixArray::build(tokens)
     }
 }
 
+/// `build_suffix_array_auto`, returning `OutOfMemory` instead of aborting
+/// whichever construction is chosen.
+pub fn try_build_suffix_array_auto(
+    tokens: &[Token],
+    enable_parallel: bool,
+) -> Result<SuffixArray, CompressionError> {
+    let config = ParallelSAConfig::current();
+
+    if uses_parallel(tokens.len(), enable_parallel, &config) {
+        try_build_suffix_array_parallel(tokens, &config)
+    } else {
+        SuffixArray::try_build(tokens)
+    }
+}
+
+fn uses_parallel(len: usize, enable_parallel: bool, config: &ParallelSAConfig) -> bool {
+    enable_parallel && len >= config.parallel_threshold
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,6 +375,29 @@ mod tests {
         assert_eq!(sa2.suffix_array, sa3.suffix_array);
     }
 
+    #[test]
+    fn test_auto_tune_sets_a_sane_threshold() {
+        let tuned = ParallelSAConfig::auto_tune();
+        assert!(tuned.parallel_threshold >= TUNING_SIZES[0]);
+        assert!(tuned.parallel_threshold <= UNTUNED_THRESHOLD);
+        assert_eq!(
+            ParallelSAConfig::auto_tune().parallel_threshold,
+            tuned.parallel_threshold
+        );
+
+        // The auto builder switches at the tuned threshold
+        let config = ParallelSAConfig::current();
+        assert_eq!(config.parallel_threshold, tuned.parallel_threshold);
+        let threshold = tuned.parallel_threshold;
+        assert!(!uses_parallel(threshold - 1, true, &config));
+        assert!(uses_parallel(threshold, true, &config));
+        assert!(!uses_parallel(threshold, false, &config));
+
+        let tokens: Vec<Token> = (0..2000).map(|i| (i * 7 % 97) as Token).collect();
+        let auto = build_suffix_array_auto(&tokens, true);
+        assert_eq!(auto.suffix_array, SuffixArray::build(&tokens).suffix_array);
+    }
+
     #[test]
     fn test_correctness_with_all_same() {
         let tokens: Vec<Token> = vec![42; 1000];
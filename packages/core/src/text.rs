@@ -0,0 +1,83 @@
+//! Text compression with one token per Unicode scalar value.
+//!
+//! Lets callers compress a string without a tokenizer. Scalar values are
+//! at most 0x10FFFF, well below the default delimiters and meta-token
+//! ids; with a config whose meta-token pool overlaps them, colliding
+//! characters are escaped in the body like any other literal.
+
+use crate::error::CompressionError;
+use crate::types::{CompressionConfig, CompressionResult, Token};
+use crate::{compress_internal, decompress_internal, DEFAULT_NEXT_META_TOKEN};
+
+/// Compress `s` as the sequence of its characters' scalar values.
+pub fn compress_str(
+    s: &str,
+    config: &CompressionConfig,
+) -> Result<CompressionResult, CompressionError> {
+    let tokens: Vec<Token> = s.chars().map(Token::from).collect();
+    compress_internal(&tokens, config, DEFAULT_NEXT_META_TOKEN)
+}
+
+/// Decompress `tokens` from `compress_str` back into the string.
+///
+/// Fails if the stream does not decode, or decodes to a token that is not
+/// a Unicode scalar value.
+pub fn decompress_to_str(
+    tokens: &[Token],
+    config: &CompressionConfig,
+) -> Result<String, CompressionError> {
+    decompress_internal(tokens, config, DEFAULT_NEXT_META_TOKEN)?
+        .into_iter()
+        .map(|token| char::from_u32(token).ok_or(CompressionError::InvalidCharacter(token)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(s: &str, config: &CompressionConfig) -> CompressionResult {
+        let result = compress_str(s, config).unwrap();
+        assert_eq!(
+            decompress_to_str(&result.serialized_tokens, config).unwrap(),
+            s
+        );
+        result
+    }
+
+    #[test]
+    fn test_str_round_trip() {
+        let config = CompressionConfig::default();
+        let repetitive = "the cat sat on the mat; the cat sat on the hat; ".repeat(6);
+        let result = round_trip(&repetitive, &config);
+        assert!(result.compressed_length < repetitive.chars().count());
+
+        let emoji = "🦀 rust 🦀 crab 🦀 rust 🦀 crab 👩‍💻 déjà vu 🦀 rust 🦀 crab ".repeat(4);
+        let result = round_trip(&emoji, &config);
+        assert!(result
+            .dictionary_map
+            .values()
+            .flatten()
+            .any(|&t| t == '🦀' as Token));
+    }
+
+    #[test]
+    fn test_str_escapes_characters_in_meta_token_pool() {
+        // Meta-tokens assigned from 'a' collide with the text itself
+        let config = CompressionConfig {
+            meta_token_pool_size: 50,
+            ..Default::default()
+        };
+        let s: String = "qrstuvwx".chars().map(|c| format!("HELLO WORLD {} ", c)).collect();
+        let tokens: Vec<Token> = s.chars().map(Token::from).collect();
+        let result = compress_internal(&tokens, &config, 'a' as Token).unwrap();
+        assert!(result.body_tokens.contains(&config.escape_token));
+        let decoded = decompress_internal(&result.serialized_tokens, &config, 'a' as Token);
+        assert_eq!(decoded.unwrap(), tokens);
+
+        assert_eq!(
+            decompress_to_str(&[0xD800], &config),
+            Err(CompressionError::InvalidCharacter(0xD800))
+        );
+    }
+}
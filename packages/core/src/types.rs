@@ -3,8 +3,18 @@
 //! These types mirror the Python implementation in `delta/types.py`
 //! but are optimized for WASM performance.
 
+use crate::discovery::MinOccurrences;
+use crate::hash::hash_tokens;
+use crate::selection::SelectionTrace;
+use crate::suffix_array::NonOverlapStrategy;
 use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
 /// A token is represented as a u32 for WASM efficiency.
@@ -14,6 +24,114 @@ pub type Token = u32;
 /// A sequence of tokens.
 pub type TokenSeq = Vec<Token>;
 
+/// A pattern subsequence: a `(start, len)` slice of a shared token buffer.
+///
+/// Discovery copies its input into one buffer and slices every candidate's
+/// pattern out of it, so candidates and the occurrences built from them
+/// share tokens instead of each owning a copy; cloning a pattern never
+/// allocates. Patterns built from a slice, `Vec` or iterator own a buffer
+/// of their own. Compares, hashes and orders as its tokens.
+#[derive(Clone)]
+pub struct Pattern {
+    buffer: Arc<[Token]>,
+    start: usize,
+    len: usize,
+}
+
+impl Pattern {
+    /// A pattern owning a copy of `tokens`.
+    pub fn from_slice(tokens: &[Token]) -> Self {
+        Self::from(Arc::<[Token]>::from(tokens))
+    }
+
+    /// The tokens `range` of this pattern, sharing its buffer.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.len);
+        Self {
+            buffer: Arc::clone(&self.buffer),
+            start: self.start + range.start,
+            len: range.end - range.start,
+        }
+    }
+
+    /// The pattern's tokens.
+    pub fn as_slice(&self) -> &[Token] {
+        &self.buffer[self.start..self.start + self.len]
+    }
+}
+
+impl From<Arc<[Token]>> for Pattern {
+    fn from(buffer: Arc<[Token]>) -> Self {
+        let len = buffer.len();
+        Self { buffer, start: 0, len }
+    }
+}
+
+impl From<Vec<Token>> for Pattern {
+    fn from(tokens: Vec<Token>) -> Self {
+        Self::from(Arc::<[Token]>::from(tokens))
+    }
+}
+
+impl From<&[Token]> for Pattern {
+    fn from(tokens: &[Token]) -> Self {
+        Self::from_slice(tokens)
+    }
+}
+
+impl FromIterator<Token> for Pattern {
+    fn from_iter<I: IntoIterator<Item = Token>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Arc<[Token]>>())
+    }
+}
+
+impl Deref for Pattern {
+    type Target = [Token];
+
+    fn deref(&self) -> &[Token] {
+        self.as_slice()
+    }
+}
+
+impl Borrow<[Token]> for Pattern {
+    fn borrow(&self) -> &[Token] {
+        self.as_slice()
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl PartialOrd for Pattern {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pattern {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl Hash for Pattern {
+    // Must match `[Token]`'s hash for lookups through `Borrow`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
 /// A patch represents a position and replacement token for fuzzy matching.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Patch {
@@ -21,15 +139,123 @@ pub struct Patch {
     pub token: Token,
 }
 
+/// Occurrence positions stored compactly.
+///
+/// Each position is kept as the zigzag-encoded difference from the previous
+/// one, written as a LEB128 varint. Sorted positions of a frequent pattern
+/// take one or two bytes each instead of a full `usize`; unsorted input is
+/// still stored exactly, just less compactly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackedPositions {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl PackedPositions {
+    /// Encode positions, preserving their order.
+    pub fn from_slice(positions: &[usize]) -> Self {
+        positions.iter().copied().collect()
+    }
+
+    /// Number of stored positions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decode positions on demand.
+    pub fn iter(&self) -> PositionIter<'_> {
+        PositionIter {
+            bytes: &self.bytes,
+            remaining: self.len,
+            prev: 0,
+        }
+    }
+
+    /// Heap bytes held by the encoding.
+    pub fn heap_size(&self) -> usize {
+        self.bytes.capacity()
+    }
+}
+
+impl FromIterator<usize> for PackedPositions {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut bytes = Vec::new();
+        let mut len = 0;
+        let mut prev = 0i64;
+        for pos in iter {
+            let delta = pos as i64 - prev;
+            let mut zigzag = ((delta << 1) ^ (delta >> 63)) as u64;
+            while zigzag >= 0x80 {
+                bytes.push(zigzag as u8 | 0x80);
+                zigzag >>= 7;
+            }
+            bytes.push(zigzag as u8);
+            prev = pos as i64;
+            len += 1;
+        }
+        bytes.shrink_to_fit();
+        Self { bytes, len }
+    }
+}
+
+/// Iterator decoding a [`PackedPositions`].
+#[derive(Debug, Clone)]
+pub struct PositionIter<'a> {
+    bytes: &'a [u8],
+    remaining: usize,
+    prev: i64,
+}
+
+impl Iterator for PositionIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let mut zigzag = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.bytes[0];
+            self.bytes = &self.bytes[1..];
+            zigzag |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+
+        let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        self.prev += delta;
+        self.remaining -= 1;
+        Some(self.prev as usize)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for PositionIter<'_> {}
+
 /// A candidate pattern discovered during compression.
+///
+/// The pattern tokens are read through [`Candidate::subsequence`]; the
+/// field's storage type is an implementation detail.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Candidate {
     /// The token subsequence that forms this pattern
-    pub subsequence: Vec<Token>,
+    pub(crate) subsequence: Pattern,
     /// Length of the subsequence
     pub length: usize,
-    /// Positions where this pattern occurs (non-overlapping)
-    pub positions: Vec<usize>,
+    /// Positions where this pattern occurs (non-overlapping), sorted and
+    /// unique
+    positions: PackedPositions,
     /// Priority for selection (higher = prefer compression)
     pub priority: i32,
     /// Patches for fuzzy matching (position -> patches)
@@ -37,12 +263,14 @@ pub struct Candidate {
 }
 
 impl Candidate {
-    pub fn new(subsequence: Vec<Token>, positions: Vec<usize>) -> Self {
+    /// Create a candidate, sorting `positions` and dropping duplicates.
+    pub fn new(subsequence: impl Into<Pattern>, positions: Vec<usize>) -> Self {
+        let subsequence = subsequence.into();
         let length = subsequence.len();
         Self {
             subsequence,
             length,
-            positions,
+            positions: pack_sorted(positions),
             priority: 0,
             patches: HashMap::new(),
         }
@@ -52,9 +280,43 @@ impl Candidate {
         self.priority = priority;
         self
     }
+
+    /// The token subsequence that forms this pattern.
+    pub fn subsequence(&self) -> &[Token] {
+        &self.subsequence
+    }
+
+    /// Positions where this pattern occurs, decoded lazily.
+    pub fn positions(&self) -> PositionIter<'_> {
+        self.positions.iter()
+    }
+
+    /// Number of occurrences.
+    pub fn count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Replace the occurrence positions, sorting them and dropping
+    /// duplicates.
+    pub fn set_positions(&mut self, positions: &[usize]) {
+        self.positions = if positions.windows(2).all(|w| w[0] < w[1]) {
+            PackedPositions::from_slice(positions)
+        } else {
+            pack_sorted(positions.to_vec())
+        };
+    }
+}
+
+fn pack_sorted(mut positions: Vec<usize>) -> PackedPositions {
+    positions.sort_unstable();
+    positions.dedup();
+    PackedPositions::from_slice(&positions)
 }
 
 /// An occurrence of a pattern at a specific position.
+///
+/// The pattern tokens are read through [`Occurrence::subsequence`]; the
+/// field's storage type is an implementation detail.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Occurrence {
     /// Start position in the token sequence
@@ -62,7 +324,7 @@ pub struct Occurrence {
     /// Length of the pattern
     pub length: usize,
     /// The pattern subsequence
-    pub subsequence: Vec<Token>,
+    pub(crate) subsequence: Pattern,
     /// Priority for selection
     pub priority: i32,
     /// Patches for this specific occurrence
@@ -70,11 +332,61 @@ pub struct Occurrence {
 }
 
 impl Occurrence {
+    /// Create an occurrence of `subsequence` at `start` with no patches.
+    pub fn new(start: usize, subsequence: impl Into<Pattern>, priority: i32) -> Self {
+        let subsequence = subsequence.into();
+        Self {
+            start,
+            length: subsequence.len(),
+            subsequence,
+            priority,
+            patches: Vec::new(),
+        }
+    }
+
+    /// The pattern subsequence.
+    pub fn subsequence(&self) -> &[Token] {
+        &self.subsequence
+    }
+
     pub fn end(&self) -> usize {
         self.start + self.length
     }
 }
 
+/// Order in which meta-token ids are assigned from `next_meta_token`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetaTokenDirection {
+    /// `next_meta_token`, `next_meta_token + 1`, ...
+    #[default]
+    Ascending,
+    /// `next_meta_token`, `next_meta_token - 1`, ... for vocabularies that
+    /// reserve the top of the token space
+    Descending,
+}
+
+impl MetaTokenDirection {
+    /// The id assigned `steps` ids after `base`, or `None` if it would
+    /// fall outside the token range.
+    pub fn step(self, base: Token, steps: usize) -> Option<Token> {
+        let steps = Token::try_from(steps).ok()?;
+        match self {
+            MetaTokenDirection::Ascending => base.checked_add(steps),
+            MetaTokenDirection::Descending => base.checked_sub(steps),
+        }
+    }
+
+    /// Ids assignable from `base` before leaving the token range.
+    pub fn capacity(self, base: Token) -> usize {
+        let ids = match self {
+            MetaTokenDirection::Ascending => u64::from(Token::MAX - base) + 1,
+            MetaTokenDirection::Descending => u64::from(base) + 1,
+        };
+        usize::try_from(ids).unwrap_or(usize::MAX)
+    }
+}
+
 /// Configuration for compression.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[wasm_bindgen]
@@ -91,6 +403,9 @@ pub struct CompressionConfig {
     pub meta_token_suffix: String,
     /// Size of meta-token pool
     pub meta_token_pool_size: usize,
+    /// Whether meta-token ids count up or down from `next_meta_token`
+    #[wasm_bindgen(skip)]
+    pub meta_token_direction: MetaTokenDirection,
     /// Dictionary start delimiter token ID
     pub dict_start_token: Token,
     /// Dictionary end delimiter token ID
@@ -101,13 +416,122 @@ pub struct CompressionConfig {
     pub hierarchical_enabled: bool,
     /// Maximum hierarchical compression depth
     pub hierarchical_max_depth: usize,
-    /// Selection mode: "greedy", "optimal", "beam"
+    /// Selection mode: "greedy", "optimal", "beam", "exact"
     #[wasm_bindgen(skip)]
     pub selection_mode: String,
     /// Beam width for beam search
     pub beam_width: usize,
-    /// Enable round-trip verification
+    /// Refinement rounds selection runs before giving up on convergence
+    pub selection_max_iterations: usize,
+    /// Check each replacement against the input and dictionary while building
     pub verify: bool,
+    /// Decompress the result and compare it to the input
+    pub verify_full: bool,
+    /// Keep only the top-N discovered candidates by savings (0 = unbounded)
+    pub max_candidates: usize,
+    /// Prefix each literal run in the body with a marker and its length
+    pub body_runs: bool,
+    /// Marker token introducing a literal run when `body_runs` is enabled
+    pub literal_marker_token: Token,
+    /// Largest share of the serialized output the dictionary may take (1.0 = no cap)
+    pub max_dict_fraction: f64,
+    /// Longest dictionary entry accepted when decoding (0 = `max_subsequence_length`)
+    pub max_dict_entry_length: usize,
+    /// Let a pattern and its reverse share one dictionary entry
+    pub detect_mirrored: bool,
+    /// Marker token preceding a reversed meta-token when `detect_mirrored` is enabled
+    pub reverse_marker_token: Token,
+    /// Escape token; `[escape, literal]` in the body emits `literal` unexpanded
+    pub escape_token: Token,
+    /// Reproduce the Python implementation's output exactly (see `python_parity`)
+    pub strict_python_parity: bool,
+    /// Python `rng_seed` for the meta-token pool shuffle in parity mode
+    pub python_rng_seed: u64,
+    /// Sequences that must appear verbatim in the body, never replaced
+    /// or split by a meta-token
+    #[wasm_bindgen(skip)]
+    pub blacklist_patterns: Vec<Vec<Token>>,
+    /// Most wildcard gaps in a skip-gram pattern (0 = no skip-grams). Not
+    /// combined with `body_runs` or `detect_mirrored`
+    pub max_gaps: usize,
+    /// Tokens per skip-gram gap
+    pub gap_len: usize,
+    /// Wildcard token marking gaps in skip-gram definitions
+    pub gap_token: Token,
+    /// Frame incompressible input with an empty dictionary section instead
+    /// of returning it raw
+    pub always_framed: bool,
+    /// Build suffix arrays in parallel for large inputs; has no effect
+    /// without the `parallel` feature
+    pub enable_parallel: bool,
+    /// Shortest input worth compressing (0 = `2 * min_subsequence_length`)
+    pub min_input_length: usize,
+    /// Interleaved channels to compress separately (1 = a single stream).
+    /// Only `compress_internal` splits channels, without hierarchical passes
+    pub channels: usize,
+    /// Check pipeline invariants as compression runs, panicking on the
+    /// first violation (see `invariants`)
+    pub debug_checks: bool,
+    /// Dictionary encoding: "flat", "trie" or "nested" (see `DICT_ENCODINGS`)
+    #[wasm_bindgen(skip)]
+    pub dict_encoding: String,
+    /// Order of dictionary entries with equal length and count:
+    /// "lexicographic" or "first_occurrence" (see `DICT_TIE_BREAKS`)
+    #[wasm_bindgen(skip)]
+    pub dict_tie_break: String,
+    /// Pick delimiters and meta-tokens above the largest input token and
+    /// record them in a stream header (see `DelimiterHeader`)
+    pub auto_delimiters: bool,
+    /// Try doubled and quadrupled super-patterns for runs of back-to-back
+    /// occurrences, keeping them when they shrink the output
+    pub coalesce_runs: bool,
+    /// Tokens discovery treats as interchangeable; the first of each class
+    /// is its representative (see `equivalence`). Not combined with
+    /// `body_runs` or `detect_mirrored`
+    #[wasm_bindgen(skip)]
+    pub equivalence_classes: Vec<Vec<Token>>,
+    /// Record selection decisions in `CompressionResult::selection_trace`
+    pub selection_trace: bool,
+    /// Longest input compressed in one pass; longer inputs are split into
+    /// independently framed segments to bound discovery memory
+    pub max_single_pass_length: usize,
+    /// Only discover patterns whose length is a multiple of this, such as
+    /// whole fixed-size records (1 = any length)
+    pub length_multiple_of: usize,
+    /// Occurrences a pattern needs before discovery reports it
+    #[wasm_bindgen(skip)]
+    pub min_occurrences: MinOccurrences,
+    /// Which occurrences discovery keeps when a pattern overlaps itself
+    #[wasm_bindgen(skip)]
+    pub non_overlap: NonOverlapStrategy,
+    /// Offer selection at most this many occurrences of each pattern, the
+    /// earliest ones, leaving the rest to other patterns (0 = unbounded)
+    pub max_occurrences_per_pattern: usize,
+    /// Input positions where a token boundary is strong, such as subword
+    /// merge boundaries; patterns aligned to them get a priority boost in
+    /// the first pass (see `selection::prefer_aligned`)
+    #[wasm_bindgen(skip)]
+    pub merge_boundaries: Vec<usize>,
+    /// Treat the input as a ring, so repeats wrapping from its end to its
+    /// start can be replaced. The ring is opened where its most valuable
+    /// wrapped occurrence ends, recorded in a rotation header when that
+    /// beats compressing the input as is (see `ROTATION_HEADER_TOKEN`)
+    pub circular: bool,
+    /// Longest input `"exact"` selection is run on; longer inputs use
+    /// `"optimal"` (see `selection::select_exact`)
+    pub exact_max_input_length: usize,
+    /// Keep the compressed form when it is exactly as long as the input,
+    /// for the dictionary it carries, instead of returning the input
+    pub accept_tie: bool,
+    /// Order the dictionary and assign its meta-tokens so the output is
+    /// the lexicographically smallest of the equally long encodings of
+    /// the selected occurrences (see `dictionary::canonical_order`)
+    pub canonical_output: bool,
+    /// Type of each token, such as structural or content; patterns never
+    /// mix types (see `discovery::retain_single_type`). Unlisted tokens
+    /// are type 0, so an empty map puts no constraint on patterns
+    #[wasm_bindgen(skip)]
+    pub token_types: HashMap<Token, u8>,
 }
 
 #[wasm_bindgen]
@@ -146,6 +570,208 @@ impl CompressionConfig {
     pub fn set_selection_mode(&mut self, mode: String) {
         self.selection_mode = mode;
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn dict_encoding(&self) -> String {
+        self.dict_encoding.clone()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_dict_encoding(&mut self, encoding: String) {
+        self.dict_encoding = encoding;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn dict_tie_break(&self) -> String {
+        self.dict_tie_break.clone()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_dict_tie_break(&mut self, tie_break: String) {
+        self.dict_tie_break = tie_break;
+    }
+}
+
+impl CompressionConfig {
+    /// Rough upper estimate of peak heap use, in bytes, for compressing
+    /// `tokens_len` tokens.
+    ///
+    /// Covers the larger of the suffix-array phase (ranks, suffix array,
+    /// LCP and its inverse, LCP intervals) and the selection phase
+    /// (candidate positions, one occurrence record per position with its
+    /// pattern id and place in the selection order, and a copy of those
+    /// selected), plus the token buffers: the result's and the one
+    /// patterns are sliced from. Pure arithmetic; nothing is allocated.
+    pub fn estimate_memory(&self, tokens_len: usize) -> usize {
+        let word = std::mem::size_of::<usize>();
+        let token = std::mem::size_of::<Token>();
+        let lengths = self
+            .max_subsequence_length
+            .saturating_sub(self.min_subsequence_length)
+            .saturating_add(1);
+
+        // Input, original copy, body, serialized output and pattern buffer
+        let buffers = tokens_len.saturating_mul(5 * token);
+
+        // rank, tmp, suffix array, LCP and inverse arrays, the token ranking
+        // map (worst case all tokens distinct) and up to n LCP intervals
+        // with their parent's LCP
+        let suffix_words = 5 + 3 + 4;
+        let suffix_phase = tokens_len.saturating_mul(suffix_words * word + token);
+
+        // Repetitive input can place a position at every token for every
+        // pattern length. Occurrences slice their pattern, so their size
+        // does not depend on its length.
+        let positions = tokens_len.saturating_mul(lengths);
+        let selection_phase =
+            positions.saturating_mul(3 * word + 2 * std::mem::size_of::<Occurrence>());
+
+        buffers.saturating_add(suffix_phase.max(selection_phase))
+    }
+
+    /// Tokens a dictionary entry costs beyond its meta-token and definition.
+    pub fn extra_cost(&self) -> usize {
+        usize::from(self.dict_length_enabled)
+    }
+
+    /// Occurrences a pattern of `length` tokens needs to be worth compressing.
+    ///
+    /// `usize::MAX` for lengths below 2, which never compress.
+    pub fn min_occurrences_for(&self, length: usize) -> usize {
+        min_count_for_compressibility(length, self.extra_cost())
+    }
+
+    /// Net tokens saved by replacing `count` occurrences of a pattern of
+    /// `length` tokens, including its dictionary entry.
+    ///
+    /// Unlike `compute_savings` this is negative when compressing the
+    /// pattern would grow the output.
+    pub fn savings_for(&self, length: usize, count: usize) -> i64 {
+        if count == 0 {
+            return 0;
+        }
+        let (original, compressed) = pattern_costs(length, count, self.extra_cost());
+        if original >= compressed {
+            i64::try_from(original - compressed).unwrap_or(i64::MAX)
+        } else {
+            i64::try_from(compressed - original).map_or(i64::MIN, |cost| -cost)
+        }
+    }
+
+    /// Reject settings the pipeline would otherwise silently work around.
+    ///
+    /// Checks the discovery length bounds, that `selection_mode` is one
+    /// of `SELECTION_MODES`, `dict_encoding` one of `DICT_ENCODINGS` and
+    /// `dict_tie_break` one of `DICT_TIE_BREAKS`. Compression itself does
+    /// not call this, so an unrecognized mode still falls back to greedy
+    /// there; callers who want a typo to fail validate up front.
+    pub fn validate(&self) -> Result<(), crate::error::CompressionError> {
+        use crate::dictionary::{DICT_ENCODINGS, DICT_TIE_BREAKS};
+        use crate::selection::SELECTION_MODES;
+
+        crate::discovery::DiscoveryConfig::from_compression_config(self).validate()?;
+        if !SELECTION_MODES.contains(&self.selection_mode.as_str()) {
+            return Err(crate::error::CompressionError::InvalidConfig(format!(
+                "selection_mode {:?} is not one of: {}",
+                self.selection_mode,
+                SELECTION_MODES.join(", ")
+            )));
+        }
+        if self.max_single_pass_length == 0 {
+            return Err(crate::error::CompressionError::InvalidConfig(
+                "max_single_pass_length must be at least 1".to_string(),
+            ));
+        }
+        if !DICT_ENCODINGS.contains(&self.dict_encoding.as_str()) {
+            return Err(crate::error::CompressionError::InvalidConfig(format!(
+                "dict_encoding {:?} is not one of: {}",
+                self.dict_encoding,
+                DICT_ENCODINGS.join(", ")
+            )));
+        }
+        if !DICT_TIE_BREAKS.contains(&self.dict_tie_break.as_str()) {
+            return Err(crate::error::CompressionError::InvalidConfig(format!(
+                "dict_tie_break {:?} is not one of: {}",
+                self.dict_tie_break,
+                DICT_TIE_BREAKS.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether a stream encoded with `self` decodes correctly with `other`.
+    ///
+    /// Compares only the settings the decoder reads: delimiters, length
+    /// tokens, the dictionary encoding, channels, escaping and the body
+    /// features with their marker tokens. Encode-only settings such as
+    /// `selection_mode` or `beam_width` are ignored, as are markers of
+    /// features neither config enables. Streams with a delimiter header
+    /// (see `auto_delimiters`) decode regardless of the delimiters.
+    pub fn decode_compatible_with(&self, other: &CompressionConfig) -> bool {
+        let trie = |config: &CompressionConfig| config.dict_encoding == "trie";
+        let same_marker = |enabled: bool, a: Token, b: Token| !enabled || a == b;
+
+        self.dict_start_token == other.dict_start_token
+            && self.dict_end_token == other.dict_end_token
+            && self.dict_length_enabled == other.dict_length_enabled
+            && trie(self) == trie(other)
+            && self.channels.max(1) == other.channels.max(1)
+            && self.escape_token == other.escape_token
+            && self.body_runs == other.body_runs
+            && same_marker(self.body_runs, self.literal_marker_token, other.literal_marker_token)
+            && self.detect_mirrored == other.detect_mirrored
+            && same_marker(
+                self.detect_mirrored,
+                self.reverse_marker_token,
+                other.reverse_marker_token,
+            )
+            && self.gap_fillers_enabled() == other.gap_fillers_enabled()
+            && same_marker(self.gap_fillers_enabled(), self.gap_token, other.gap_token)
+    }
+
+    /// Whether skip-grams are discovered.
+    pub fn skip_grams_enabled(&self) -> bool {
+        self.max_gaps > 0 && !self.body_runs && !self.detect_mirrored
+    }
+
+    /// Whether bodies carry gap fillers after skip-gram and equivalence
+    /// class meta-tokens.
+    pub fn gap_fillers_enabled(&self) -> bool {
+        (self.max_gaps > 0 || !self.equivalence_classes.is_empty())
+            && !self.body_runs
+            && !self.detect_mirrored
+    }
+
+    /// Inputs shorter than this are returned uncompressed.
+    pub fn input_length_threshold(&self) -> usize {
+        if self.min_input_length == 0 {
+            self.min_subsequence_length * 2
+        } else {
+            self.min_input_length
+        }
+    }
+
+    /// Whether a compressed form of `compressed_length` tokens is kept over
+    /// the `original_length` input: when shorter, or as long with
+    /// `accept_tie` set.
+    pub fn keeps_compressed(&self, compressed_length: usize, original_length: usize) -> bool {
+        compressed_length < original_length
+            || (self.accept_tie && compressed_length == original_length)
+    }
+
+    /// Type of `token` under `token_types`.
+    pub fn token_type(&self, token: Token) -> u8 {
+        self.token_types.get(&token).copied().unwrap_or(0)
+    }
+
+    /// Longest dictionary entry a decoder should accept.
+    pub fn dict_entry_length_limit(&self) -> usize {
+        if self.max_dict_entry_length == 0 {
+            self.max_subsequence_length
+        } else {
+            self.max_dict_entry_length
+        }
+    }
 }
 
 impl Default for CompressionConfig {
@@ -156,6 +782,7 @@ impl Default for CompressionConfig {
             meta_token_prefix: "<MT_".to_string(),
             meta_token_suffix: ">".to_string(),
             meta_token_pool_size: 500,
+            meta_token_direction: MetaTokenDirection::Ascending,
             dict_start_token: 0xFFFFFFF0, // Reserved token IDs
             dict_end_token: 0xFFFFFFF1,
             dict_length_enabled: true,
@@ -163,7 +790,71 @@ impl Default for CompressionConfig {
             hierarchical_max_depth: 3,
             selection_mode: "greedy".to_string(),
             beam_width: 8,
+            selection_max_iterations: crate::selection::DEFAULT_MAX_ITERATIONS,
             verify: false,
+            verify_full: false,
+            max_candidates: 0,
+            body_runs: false,
+            literal_marker_token: 0xFFFFFFF2,
+            max_dict_fraction: 1.0,
+            max_dict_entry_length: 0,
+            detect_mirrored: false,
+            reverse_marker_token: 0xFFFFFFF3,
+            escape_token: 0xFFFFFFF4,
+            strict_python_parity: false,
+            python_rng_seed: 0,
+            blacklist_patterns: Vec::new(),
+            max_gaps: 0,
+            gap_len: 1,
+            gap_token: 0xFFFFFFF5,
+            always_framed: false,
+            enable_parallel: true,
+            min_input_length: 0,
+            channels: 1,
+            debug_checks: false,
+            dict_encoding: "flat".to_string(),
+            dict_tie_break: "lexicographic".to_string(),
+            auto_delimiters: false,
+            coalesce_runs: false,
+            equivalence_classes: Vec::new(),
+            selection_trace: false,
+            max_single_pass_length: crate::DEFAULT_MAX_SINGLE_PASS_LENGTH,
+            length_multiple_of: 1,
+            min_occurrences: MinOccurrences::default(),
+            non_overlap: NonOverlapStrategy::default(),
+            max_occurrences_per_pattern: 0,
+            merge_boundaries: Vec::new(),
+            circular: false,
+            exact_max_input_length: 256,
+            accept_tie: false,
+            canonical_output: false,
+            token_types: HashMap::new(),
+        }
+    }
+}
+
+/// Why a result holds its input unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoCompressionReason {
+    /// Input shorter than `min_input_length`
+    TooSmall,
+    /// Discovery found no repeated patterns
+    NoPatterns,
+    /// Selection kept no occurrences
+    NoViableSelection,
+    /// The compressed output would not be shorter than the input
+    NotBeneficial,
+}
+
+impl NoCompressionReason {
+    /// Name as serialized, such as `"too_small"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NoCompressionReason::TooSmall => "too_small",
+            NoCompressionReason::NoPatterns => "no_patterns",
+            NoCompressionReason::NoViableSelection => "no_viable_selection",
+            NoCompressionReason::NotBeneficial => "not_beneficial",
         }
     }
 }
@@ -194,6 +885,18 @@ pub struct CompressionResult {
     /// Static dictionary ID if used
     #[wasm_bindgen(skip)]
     pub static_dictionary_id: Option<String>,
+    /// Why the input was left uncompressed, if it was
+    #[wasm_bindgen(skip)]
+    #[serde(default)]
+    pub reason: Option<NoCompressionReason>,
+    /// Selection decisions, when `selection_trace` is set
+    #[wasm_bindgen(skip)]
+    #[serde(default)]
+    pub selection_trace: Option<SelectionTrace>,
+    /// Stable hash of the original tokens, taken from the input as it was
+    /// compressed (see `hash::hash_tokens`), for checking decodes
+    #[serde(default)]
+    pub original_hash: u64,
 }
 
 #[wasm_bindgen]
@@ -213,6 +916,22 @@ impl CompressionResult {
         self.original_length as i64 - self.compressed_length as i64
     }
 
+    /// Get the body-only ratio (body/original), for a dictionary sent once
+    /// and reused across messages.
+    #[wasm_bindgen(getter)]
+    pub fn body_ratio(&self) -> f64 {
+        if self.original_length == 0 {
+            return 1.0;
+        }
+        self.body_tokens.len() as f64 / self.original_length as f64
+    }
+
+    /// Get tokens saved by the body alone, ignoring the dictionary.
+    #[wasm_bindgen(getter)]
+    pub fn body_tokens_saved(&self) -> i64 {
+        self.original_length as i64 - self.body_tokens.len() as i64
+    }
+
     /// Get the serialized tokens as a JS array.
     #[wasm_bindgen(js_name = getSerializedTokens)]
     pub fn get_serialized_tokens(&self) -> Vec<Token> {
@@ -242,13 +961,226 @@ impl CompressionResult {
     pub fn get_static_dictionary_id(&self) -> Option<String> {
         self.static_dictionary_id.clone()
     }
+
+    /// Get why the input was left uncompressed, such as `"too_small"`.
+    #[wasm_bindgen(js_name = getReason)]
+    pub fn get_reason(&self) -> Option<String> {
+        self.reason.map(|reason| reason.as_str().to_string())
+    }
+
+    /// Get the body symbol frequencies as an object from token id to count.
+    #[wasm_bindgen(js_name = getBodySymbolFrequencies)]
+    pub fn get_body_symbol_frequencies(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        for (token, count) in self.body_symbol_frequencies() {
+            js_sys::Reflect::set(&object, &JsValue::from(token), &JsValue::from(count as f64))?;
+        }
+        Ok(object.into())
+    }
+
+    /// Get the Huffman code lengths as an object from token id to length.
+    #[wasm_bindgen(js_name = getCodeLengths)]
+    pub fn get_code_lengths(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        for (token, length) in self.code_lengths() {
+            let length = JsValue::from(f64::from(length));
+            js_sys::Reflect::set(&object, &JsValue::from(token), &length)?;
+        }
+        Ok(object.into())
+    }
 }
 
 impl CompressionResult {
-    /// Create a new compression result indicating no compression was beneficial.
-    pub fn no_compression(tokens: Vec<Token>) -> Self {
+    /// Readable names for the result's meta-tokens, for debugging.
+    ///
+    /// Each id is named `{meta_token_prefix}{index}{meta_token_suffix}`,
+    /// such as `<MT_0>`, where `index` is its position in the config's
+    /// numbering order, as in the Python implementation.
+    pub fn meta_token_names(&self, config: &CompressionConfig) -> HashMap<Token, String> {
+        let mut ids: Vec<Token> = self.dictionary_map.keys().copied().collect();
+        ids.sort_unstable();
+        if config.meta_token_direction == MetaTokenDirection::Descending {
+            ids.reverse();
+        }
+
+        ids.into_iter()
+            .enumerate()
+            .map(|(index, id)| {
+                let name = format!(
+                    "{}{}{}",
+                    config.meta_token_prefix, index, config.meta_token_suffix
+                );
+                (id, name)
+            })
+            .collect()
+    }
+
+    /// Count each distinct body token, literal or meta-token.
+    ///
+    /// Escapes, literal run markers and run lengths are counted like any
+    /// other body token, so the counts sum to `body_tokens.len()`. Suited
+    /// to seeding the model of a downstream entropy coder.
+    pub fn body_symbol_frequencies(&self) -> HashMap<Token, usize> {
+        let mut frequencies = HashMap::new();
+        for &token in &self.body_tokens {
+            *frequencies.entry(token).or_insert(0) += 1;
+        }
+        frequencies
+    }
+
+    /// Huffman code lengths for the body symbols, from
+    /// `body_symbol_frequencies`.
+    ///
+    /// Assigning codes canonically, in order of (length, token), gives a
+    /// prefix code; the lengths satisfy the Kraft inequality with equality
+    /// when there are two or more symbols. A body with a single distinct
+    /// symbol gets a one-bit code, and an empty body no codes.
+    pub fn code_lengths(&self) -> HashMap<Token, u8> {
+        let mut symbols: Vec<(usize, Token)> = self
+            .body_symbol_frequencies()
+            .into_iter()
+            .map(|(token, count)| (count, token))
+            .collect();
+        symbols.sort_unstable();
+        let n = symbols.len();
+        if n <= 1 {
+            return symbols.into_iter().map(|(_, token)| (token, 1)).collect();
+        }
+
+        // Two-queue construction: merged nodes are created in ascending
+        // weight, so the lightest node is at the front of one of the queues
+        let mut weight: Vec<usize> = symbols.iter().map(|&(count, _)| count).collect();
+        let mut parent = vec![0; 2 * n - 1];
+        let (mut next_leaf, mut next_merged) = (0, n);
+        for node in n..2 * n - 1 {
+            let mut children = [0; 2];
+            for child in &mut children {
+                let leaf_first = next_leaf < n
+                    && (next_merged == node || weight[next_leaf] <= weight[next_merged]);
+                let queue = if leaf_first { &mut next_leaf } else { &mut next_merged };
+                *child = *queue;
+                *queue += 1;
+            }
+            weight.push(weight[children[0]] + weight[children[1]]);
+            parent[children[0]] = node;
+            parent[children[1]] = node;
+        }
+
+        // Parents come after their children, so depths fill in from the root
+        let mut depth = vec![0u8; 2 * n - 1];
+        for node in (0..2 * n - 2).rev() {
+            depth[node] = depth[parent[node]] + 1;
+        }
+        symbols
+            .iter()
+            .zip(&depth)
+            .map(|(&(_, token), &length)| (token, length))
+            .collect()
+    }
+
+    /// Compare two results by what they encode rather than how.
+    ///
+    /// Results are equal when their serialized streams decode under
+    /// `config` to the same tokens and their compressed lengths match,
+    /// regardless of meta-token numbering or dictionary entry order. The
+    /// streams are decoded as `decompress` would, headers included; one
+    /// that fails to decode equals nothing.
+    pub fn semantically_equals(
+        &self,
+        other: &CompressionResult,
+        config: &CompressionConfig,
+    ) -> bool {
+        if self.compressed_length != other.compressed_length {
+            return false;
+        }
+        let decode = |result: &CompressionResult| {
+            let mut output = Vec::new();
+            crate::decompress_into(&result.serialized_tokens, config, &mut output).map(|()| output)
+        };
+        match (decode(self), decode(other)) {
+            (Ok(decoded), Ok(other_decoded)) => decoded == other_decoded,
+            _ => false,
+        }
+    }
+
+    /// Split the body into about `n` segments that decode independently.
+    ///
+    /// Segments break only between body units (see `body_unit_len`), so
+    /// decoding each against the shared dictionary and concatenating the
+    /// outputs reproduces the full decode. Segments hold roughly equal
+    /// token counts; fewer than `n` come back when the body is short. With
+    /// `channels > 1` the outputs hold the channels back to back, as
+    /// `decompress_segments_parallel` expects.
+    pub fn split_body(&self, n: usize, config: &CompressionConfig) -> Vec<Vec<Token>> {
+        use crate::dictionary::body_unit_len;
+
+        let body = &self.body_tokens;
+        let target = body.len().div_ceil(n.max(1)).max(1);
+        let mut segments = Vec::with_capacity(n);
+        let mut start = 0;
+        let mut pos = 0;
+        while pos < body.len() {
+            pos += body_unit_len(body, pos, &self.dictionary_map, config);
+            if pos - start >= target || pos == body.len() {
+                segments.push(body[start..pos].to_vec());
+                start = pos;
+            }
+        }
+        segments
+    }
+
+    /// Half-open `(start, end)` ranges of the original input left as
+    /// literals, in order.
+    ///
+    /// Reconstructed from the body: every input position covered by a
+    /// meta-token's expansion is compressed, the rest are gaps between
+    /// selected occurrences. Takes the config the result was built with.
+    /// Fails like `decompress_with_positions` on channel results; rotated
+    /// rings (see `circular`) are mapped back to input positions.
+    pub fn uncompressed_spans(
+        &self,
+        config: &CompressionConfig,
+    ) -> Result<Vec<(usize, usize)>, crate::error::CompressionError> {
+        if self.dictionary_map.is_empty() {
+            let whole = (0, self.original_length);
+            return Ok(if self.original_length == 0 { Vec::new() } else { vec![whole] });
+        }
+
+        // Patterns span at least two tokens, literals exactly one
+        let (_, positions, cut) =
+            crate::dictionary::decompress_ring_positions(&self.serialized_tokens, config)?;
+        let mut covered = vec![false; self.original_length];
+        for (_, range) in positions.into_iter().filter(|(_, range)| range.len() > 1) {
+            for flag in covered.iter_mut().take(range.end).skip(range.start) {
+                *flag = true;
+            }
+        }
+        // Ranges of a rotated ring index the rotated input
+        covered.rotate_right(cut);
+
+        let mut spans = Vec::new();
+        let mut start = None;
+        for (pos, &is_covered) in covered.iter().enumerate() {
+            match (start, is_covered) {
+                (None, false) => start = Some(pos),
+                (Some(open), true) => {
+                    spans.push((open, pos));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(open) = start {
+            spans.push((open, covered.len()));
+        }
+        Ok(spans)
+    }
+
+    /// Create a result holding `tokens` unchanged, for `reason`.
+    pub fn no_compression(tokens: Vec<Token>, reason: NoCompressionReason) -> Self {
         let len = tokens.len();
         Self {
+            original_hash: hash_tokens(&tokens),
             original_tokens: tokens.clone(),
             serialized_tokens: tokens.clone(),
             dictionary_tokens: Vec::new(),
@@ -257,6 +1189,8 @@ impl CompressionResult {
             original_length: len,
             compressed_length: len,
             static_dictionary_id: None,
+            reason: Some(reason),
+            selection_trace: None,
         }
     }
 }
@@ -304,7 +1238,20 @@ pub fn is_compressible(length: usize, count: usize, extra_cost: usize) -> bool {
     if length <= 1 || count == 0 {
         return false;
     }
-    length * count > 1 + length + count + extra_cost
+    let (original, compressed) = pattern_costs(length, count, extra_cost);
+    original > compressed
+}
+
+/// Original and compressed token costs of a pattern.
+///
+/// Computed in `u64` so `length * count` cannot wrap where `usize` is
+/// 32 bits (wasm32).
+#[inline]
+fn pattern_costs(length: usize, count: usize, extra_cost: usize) -> (u64, u64) {
+    let (length, count, extra_cost) = (length as u64, count as u64, extra_cost as u64);
+    let original = length.saturating_mul(count);
+    let compressed = length.saturating_add(count).saturating_add(extra_cost).saturating_add(1);
+    (original, compressed)
 }
 
 /// Compute minimum occurrence count for a pattern to be compressible.
@@ -316,9 +1263,9 @@ pub fn min_count_for_compressibility(length: usize, extra_cost: usize) -> usize
     // Solving: length * count > 1 + length + count + extra_cost
     // count * (length - 1) > 1 + length + extra_cost
     // count > (2 + length + extra_cost) / (length - 1)
-    let numerator = 2 + length + extra_cost;
+    let numerator = length.saturating_add(extra_cost).saturating_add(2);
     let denominator = length - 1;
-    (numerator + denominator - 1) / denominator // Ceiling division
+    numerator.div_ceil(denominator)
 }
 
 /// Compute net token savings for a pattern.
@@ -327,14 +1274,57 @@ pub fn compute_savings(length: usize, count: usize, extra_cost: usize) -> i64 {
     if count == 0 {
         return 0;
     }
-    let original = (length * count) as i64;
-    let compressed = (1 + length + count + extra_cost) as i64;
-    (original - compressed).max(0)
+    let (original, compressed) = pattern_costs(length, count, extra_cost);
+    i64::try_from(original.saturating_sub(compressed)).unwrap_or(i64::MAX)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::PatternMap;
+
+    #[test]
+    fn test_pattern_slices_share_their_buffer() {
+        let arena = Pattern::from_slice(&[1, 2, 3, 1, 2, 3]);
+        let first = arena.slice(0..3);
+        let second = arena.slice(3..6);
+
+        assert!(Arc::ptr_eq(&first.buffer, &second.buffer));
+        assert_eq!(first, second);
+        assert_eq!(first, Pattern::from(vec![1, 2, 3]));
+        assert!(arena.slice(1..3) > first);
+        assert_eq!(format!("{:?}", first), "[1, 2, 3]");
+
+        // Patterns are found by their tokens
+        let mut counts: PatternMap<Pattern, usize> = PatternMap::default();
+        counts.insert(first, 1);
+        *counts.get_mut([1, 2, 3].as_slice()).unwrap() += 1;
+        assert_eq!(counts[&second], 2);
+    }
+
+    #[test]
+    fn test_planning_helpers_match_internal_math() {
+        for dict_length_enabled in [true, false] {
+            let config = CompressionConfig {
+                dict_length_enabled,
+                ..Default::default()
+            };
+            let extra_cost = usize::from(dict_length_enabled);
+            for length in 0..20 {
+                let threshold = config.min_occurrences_for(length);
+                assert_eq!(threshold, min_count_for_compressibility(length, extra_cost));
+                if length < 2 {
+                    continue;
+                }
+                assert!(config.savings_for(length, threshold) > 0);
+                assert!(config.savings_for(length, threshold - 1) <= 0);
+                for count in 1..10 {
+                    let savings = config.savings_for(length, count);
+                    assert_eq!(savings.max(0), compute_savings(length, count, extra_cost));
+                }
+            }
+        }
+    }
 
     #[test]
     fn test_is_compressible() {
@@ -350,6 +1340,20 @@ mod tests {
         assert!(!is_compressible(1, 100, 0));
     }
 
+    #[test]
+    fn test_compressibility_beyond_u32_range() {
+        // 70_000 * 70_000 exceeds u32::MAX; wrapping on a 32-bit usize
+        // would give 605_032_704 and a wrong comparison below.
+        let (length, count) = (70_000, 70_000);
+        assert!(is_compressible(length, count, 1));
+        assert_eq!(compute_savings(length, count, 1), 4_900_000_000 - 140_002);
+
+        // Wrapped, 65_537 * 65_537 would fall below 1 + 65_537 + 65_537.
+        assert!(is_compressible(65_537, 65_537, 0));
+        let huge = u32::MAX as usize;
+        assert_eq!(compute_savings(huge, huge, 0), i64::MAX);
+    }
+
     #[test]
     fn test_min_count_for_compressibility() {
         // Length 2: need count > (2+2+1)/(2-1) = 5, so min is 6
@@ -376,12 +1380,403 @@ mod tests {
         assert!(config.hierarchical_enabled);
     }
 
+    fn result_with_meta(meta: Token, body: Vec<Token>) -> CompressionResult {
+        let mut dictionary_map = HashMap::new();
+        dictionary_map.insert(meta, vec![1, 2, 3]);
+        let dictionary_tokens = vec![0xFFFFFFF0, meta, 3, 1, 2, 3, 0xFFFFFFF1];
+        let mut serialized_tokens = dictionary_tokens.clone();
+        serialized_tokens.extend(&body);
+        CompressionResult {
+            original_tokens: Vec::new(),
+            compressed_length: serialized_tokens.len(),
+            serialized_tokens,
+            dictionary_tokens,
+            body_tokens: body,
+            dictionary_map,
+            original_length: 12,
+            static_dictionary_id: None,
+            reason: None,
+            selection_trace: None,
+            original_hash: 0,
+        }
+    }
+
+    #[test]
+    fn test_semantically_equals() {
+        let config = CompressionConfig::default();
+        let a = result_with_meta(1000, vec![1000, 9, 1000, 1000]);
+        let b = result_with_meta(2000, vec![2000, 9, 2000, 2000]);
+        assert_ne!(a.serialized_tokens, b.serialized_tokens);
+        assert!(a.semantically_equals(&b, &config));
+
+        let c = result_with_meta(2000, vec![2000, 8, 2000, 2000]);
+        assert!(!a.semantically_equals(&c, &config));
+    }
+
+    #[test]
+    fn test_semantically_equals_decodes_with_config() {
+        use crate::{DEFAULT_NEXT_META_TOKEN, ROTATION_HEADER_TOKEN};
+
+        let tokens: Vec<Token> = [1, 2, 3, 4, 9, 7, 3, 2, 1, 6, 5].repeat(6);
+        let configs = [
+            CompressionConfig {
+                body_runs: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                detect_mirrored: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                escape_token: 0xFFFFFFE0,
+                ..Default::default()
+            },
+        ];
+        for config in &configs {
+            let a = crate::compress_internal(&tokens, config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            let b = crate::compress_internal(&tokens, config, DEFAULT_NEXT_META_TOKEN + 50)
+                .unwrap();
+            assert_ne!(a.serialized_tokens, b.serialized_tokens);
+            assert!(a.semantically_equals(&b, config));
+
+            // [DICT_START, meta, length, definition...]: alter a definition
+            let mut c = a.clone();
+            c.serialized_tokens[3] = 8;
+            assert!(!a.semantically_equals(&c, config));
+        }
+
+        // Rotation headers are read, so the same body opened elsewhere differs
+        let circular = CompressionConfig {
+            circular: true,
+            ..Default::default()
+        };
+        let rotated = |cut: Token| {
+            let mut result = result_with_meta(1000, vec![1000, 9, 1000, 1000]);
+            result.serialized_tokens.splice(0..0, [ROTATION_HEADER_TOKEN, cut]);
+            result.compressed_length += 2;
+            result
+        };
+        assert!(rotated(1).semantically_equals(&rotated(1), &circular));
+        assert!(!rotated(1).semantically_equals(&rotated(2), &circular));
+    }
+
     #[test]
     fn test_compression_result_no_compression() {
         let tokens = vec![1, 2, 3, 4, 5];
-        let result = CompressionResult::no_compression(tokens.clone());
+        let reason = NoCompressionReason::NotBeneficial;
+        let result = CompressionResult::no_compression(tokens.clone(), reason);
+        assert_eq!(result.reason, Some(reason));
         assert_eq!(result.compression_ratio(), 1.0);
         assert_eq!(result.tokens_saved(), 0);
         assert_eq!(result.serialized_tokens, tokens);
+        assert_eq!(result.body_ratio(), 1.0);
+        assert_eq!(result.body_tokens_saved(), 0);
+    }
+
+    #[test]
+    fn test_meta_token_names() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 4, 100 + i, 5, 6, 7, 200 + i]);
+        }
+        let config = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert_eq!(result.dictionary_map.len(), 2);
+
+        let names = result.meta_token_names(&config);
+        assert_eq!(names[&DEFAULT_NEXT_META_TOKEN], "<MT_0>");
+        assert_eq!(names[&(DEFAULT_NEXT_META_TOKEN + 1)], "<MT_1>");
+        assert_eq!(names, result.meta_token_names(&config));
+
+        let custom = CompressionConfig {
+            meta_token_prefix: "[".to_string(),
+            meta_token_suffix: "]".to_string(),
+            ..config
+        };
+        assert_eq!(result.meta_token_names(&custom)[&DEFAULT_NEXT_META_TOKEN], "[0]");
+
+        let descending = CompressionConfig {
+            meta_token_direction: MetaTokenDirection::Descending,
+            ..custom
+        };
+        let result = compress_internal(&tokens, &descending, Token::MAX - 16).unwrap();
+        let names = result.meta_token_names(&descending);
+        assert_eq!(names[&(Token::MAX - 16)], "[0]");
+        assert_eq!(names[&(Token::MAX - 17)], "[1]");
+    }
+
+    #[test]
+    fn test_body_symbol_frequencies() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..6 {
+            tokens.extend([1, 2, 3, 4, 100 + i, 100 + i]);
+        }
+        let config = CompressionConfig {
+            hierarchical_enabled: false,
+            ..Default::default()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        let frequencies = result.body_symbol_frequencies();
+        assert_eq!(frequencies.values().sum::<usize>(), result.body_tokens.len());
+
+        let (&meta, _) = result
+            .dictionary_map
+            .iter()
+            .find(|(_, definition)| definition[..] == [1, 2, 3, 4])
+            .unwrap();
+        assert_eq!(frequencies[&meta], 6);
+        assert_eq!(frequencies[&103], 2);
+    }
+
+    #[test]
+    fn test_code_lengths_form_a_huffman_code() {
+        let result = CompressionResult {
+            body_tokens: vec![5, 5, 5, 5, 5, 5, 5, 5, 7, 7, 7, 7, 9, 9, 11, 13],
+            ..CompressionResult::no_compression(Vec::new(), NoCompressionReason::TooSmall)
+        };
+        let lengths = result.code_lengths();
+        let frequencies = result.body_symbol_frequencies();
+        assert_eq!(lengths.len(), frequencies.len());
+
+        // Weights 8, 4, 2, 1, 1: each merge pairs the two lightest nodes
+        let expected: HashMap<Token, u8> = [(5, 1), (7, 2), (9, 3), (11, 4), (13, 4)].into();
+        assert_eq!(lengths, expected);
+
+        let kraft: f64 = lengths.values().map(|&len| 0.5f64.powi(len as i32)).sum();
+        assert!(kraft <= 1.0);
+        for (a, count_a) in &frequencies {
+            for (b, count_b) in &frequencies {
+                if count_a > count_b {
+                    assert!(lengths[a] <= lengths[b]);
+                }
+            }
+        }
+
+        let single = CompressionResult {
+            body_tokens: vec![4, 4, 4],
+            ..result.clone()
+        };
+        assert_eq!(single.code_lengths(), [(4, 1)].into());
+    }
+
+    #[test]
+    fn test_body_ratio_excludes_dictionary() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let config = CompressionConfig::default();
+        let inputs: [Vec<Token>; 3] = [
+            [1, 2, 3, 4, 5].repeat(10),
+            [1, 2, 3, 9, 1, 2, 3, 8, 1, 2, 3].to_vec(),
+            (0..40).map(|i| [7, 8, 9][i % 3] + (i % 7) as Token).collect(),
+        ];
+        for tokens in &inputs {
+            let result = compress_internal(tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            if result.dictionary_tokens.is_empty() {
+                assert_eq!(result.body_ratio(), 1.0);
+                assert_eq!(result.compression_ratio(), 1.0);
+                continue;
+            }
+            assert!(result.body_ratio() <= result.compression_ratio());
+            let dictionary = result.dictionary_tokens.len() as i64;
+            assert_eq!(result.body_tokens_saved(), result.tokens_saved() + dictionary);
+        }
+    }
+
+    #[test]
+    fn test_packed_positions_roundtrip() {
+        let sorted = vec![0, 1, 3, 200, 70_000, usize::MAX / 2];
+        let packed = PackedPositions::from_slice(&sorted);
+        assert_eq!(packed.len(), sorted.len());
+        assert_eq!(packed.iter().collect::<Vec<_>>(), sorted);
+
+        let unsorted = vec![500, 2, 9_000_000, 9_000_000, 0];
+        let packed = PackedPositions::from_slice(&unsorted);
+        assert_eq!(packed.iter().collect::<Vec<_>>(), unsorted);
+        assert!(PackedPositions::from_slice(&[]).is_empty());
+
+        let mut candidate = Candidate::new(vec![1, 2], sorted.clone());
+        assert_eq!(candidate.count(), sorted.len());
+        assert_eq!(candidate.positions().len(), sorted.len());
+        candidate.set_positions(&[4, 8]);
+        assert!(candidate.positions().eq([4, 8]));
+    }
+
+    #[test]
+    fn test_candidate_positions_are_sorted_and_unique() {
+        let mut candidate = Candidate::new(vec![1, 2], vec![12, 4, 8, 4, 0, 12]);
+        assert!(candidate.positions().eq([0, 4, 8, 12]));
+        assert_eq!(candidate.count(), 4);
+
+        candidate.set_positions(&[30, 10, 20, 10]);
+        assert!(candidate.positions().eq([10, 20, 30]));
+        assert_eq!(candidate, Candidate::new(vec![1, 2], vec![10, 20, 30]));
+    }
+
+    #[test]
+    fn test_packed_positions_memory() {
+        // A pattern occurring a million times, every three tokens
+        let positions: Vec<usize> = (0..1_000_000).map(|i| i * 3).collect();
+        let unpacked = positions.len() * std::mem::size_of::<usize>();
+
+        let packed = PackedPositions::from_slice(&positions);
+
+        assert_eq!(packed.heap_size(), positions.len());
+        assert!(packed.heap_size() * 4 <= unpacked);
+        assert!(packed.iter().eq(positions));
+    }
+
+    #[test]
+    fn test_split_body_segments_decode_like_whole_body() {
+        use crate::dictionary::{decode_body, decompress, DEFAULT_ESCAPE_TOKEN};
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let mut tokens: Vec<Token> = Vec::new();
+        for i in 0..12 {
+            tokens.extend([1, 2, 3, 100 + i, 4, 5, 6, 4, 5, 6, 7]);
+            tokens.extend([9, 200 + i, 8, DEFAULT_ESCAPE_TOKEN, 3, 2, 1]);
+        }
+
+        let configs = [
+            CompressionConfig::default(),
+            CompressionConfig {
+                body_runs: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                detect_mirrored: true,
+                ..Default::default()
+            },
+            CompressionConfig {
+                max_gaps: 1,
+                ..Default::default()
+            },
+        ];
+        for config in configs {
+            let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+            assert!(!result.dictionary_map.is_empty());
+            let sequential = decompress(&result.serialized_tokens, &result.dictionary_map, &config);
+            assert_eq!(sequential, tokens);
+
+            for n in 1..=6 {
+                let segments = result.split_body(n, &config);
+                assert!(!segments.is_empty() && segments.len() <= n);
+                assert_eq!(segments.concat(), result.body_tokens);
+
+                let decoded: Vec<Token> = segments
+                    .iter()
+                    .flat_map(|segment| decode_body(segment, &result.dictionary_map, &config))
+                    .collect();
+                assert_eq!(decoded, sequential);
+
+                #[cfg(feature = "parallel")]
+                {
+                    let map = &result.dictionary_map;
+                    let parallel =
+                        crate::dictionary::decompress_segments_parallel(&segments, map, &config);
+                    assert_eq!(parallel, sequential);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_uncompressed_spans_bracket_compressible_region() {
+        use crate::{compress_internal, DEFAULT_NEXT_META_TOKEN};
+
+        let mut tokens: Vec<Token> = (100..110).collect();
+        for _ in 0..6 {
+            tokens.extend([1, 2, 3, 4]);
+        }
+        tokens.extend(200..210);
+
+        let config = CompressionConfig {
+            max_subsequence_length: 4,
+            ..Default::default()
+        };
+        let result = compress_internal(&tokens, &config, DEFAULT_NEXT_META_TOKEN).unwrap();
+        assert!(!result.dictionary_map.is_empty());
+        assert_eq!(result.uncompressed_spans(&config).unwrap(), [(0, 10), (34, 44)]);
+
+        let unique: Vec<Token> = (0..12).collect();
+        let result = CompressionResult::no_compression(unique, NoCompressionReason::NoPatterns);
+        assert_eq!(result.uncompressed_spans(&config).unwrap(), [(0, 12)]);
+        let empty = CompressionResult::no_compression(Vec::new(), NoCompressionReason::TooSmall);
+        assert!(empty.uncompressed_spans(&config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_compatible_with() {
+        let config = CompressionConfig::default();
+        let optimal = CompressionConfig {
+            selection_mode: "optimal".to_string(),
+            beam_width: 16,
+            ..Default::default()
+        };
+        assert!(config.decode_compatible_with(&optimal));
+        assert!(optimal.decode_compatible_with(&config));
+
+        let moved_end = CompressionConfig {
+            dict_end_token: 0xFFFFFFE1,
+            ..Default::default()
+        };
+        assert!(!config.decode_compatible_with(&moved_end));
+
+        // A marker only matters once its feature is enabled
+        let marker = CompressionConfig {
+            literal_marker_token: 0xFFFFFFE2,
+            ..Default::default()
+        };
+        assert!(config.decode_compatible_with(&marker));
+        let runs = CompressionConfig {
+            body_runs: true,
+            ..Default::default()
+        };
+        let runs_marker = CompressionConfig {
+            body_runs: true,
+            ..marker
+        };
+        assert!(!config.decode_compatible_with(&runs));
+        assert!(!runs.decode_compatible_with(&runs_marker));
+    }
+
+    #[test]
+    fn test_validate_rejects_unknown_selection_mode() {
+        for mode in ["greedy", "optimal", "beam", "exact"] {
+            let config = CompressionConfig {
+                selection_mode: mode.to_string(),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok());
+        }
+
+        let config = CompressionConfig {
+            selection_mode: "optiml".to_string(),
+            ..Default::default()
+        };
+        match config.validate() {
+            Err(crate::error::CompressionError::InvalidConfig(message)) => {
+                assert!(message.contains("optiml"));
+                assert!(message.contains("greedy, optimal, beam"));
+            }
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+
+        // Compression itself keeps falling back to greedy
+        let tokens: Vec<Token> = [1, 2, 3, 4].repeat(6);
+        let result = crate::compress_internal(&tokens, &config, crate::DEFAULT_NEXT_META_TOKEN);
+        assert!(result.is_ok());
+
+        let config = CompressionConfig {
+            dict_encoding: "tree".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
     }
 }
@@ -0,0 +1,98 @@
+//! Checks that `decompress_into` decodes without allocating for its output.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use delta_ltsc_core::types::CompressionConfig;
+use delta_ltsc_core::{compress_tokens, decompress_into};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// System allocator wrapper that counts allocated bytes.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Nested repeats of a few motifs between unique tokens.
+fn repetitive(size: usize) -> Vec<u32> {
+    let motifs: [&[u32]; 3] = [&[1, 2, 3, 4, 5, 6], &[7, 8, 9, 10], &[11, 12, 13, 14, 15]];
+    let mut tokens = Vec::with_capacity(size);
+    let mut i = 0;
+    while tokens.len() < size {
+        for _ in 0..8 {
+            tokens.extend_from_slice(motifs[i % 3]);
+            tokens.extend_from_slice(motifs[(i + 1) % 3]);
+        }
+        tokens.push(1000 + i as u32);
+        i += 1;
+    }
+    tokens.truncate(size);
+    tokens
+}
+
+#[test]
+fn test_decompress_into_allocates_independently_of_output() {
+    let tokens = repetitive(50_000);
+    let configs = [
+        CompressionConfig::default(),
+        CompressionConfig {
+            body_runs: true,
+            ..Default::default()
+        },
+        CompressionConfig {
+            detect_mirrored: true,
+            ..Default::default()
+        },
+        CompressionConfig {
+            max_gaps: 1,
+            ..Default::default()
+        },
+        CompressionConfig {
+            channels: 2,
+            ..Default::default()
+        },
+    ];
+
+    for config in configs {
+        let result = compress_tokens(&tokens, &config).unwrap();
+        assert!(result.compressed_length * 4 < tokens.len());
+
+        // The first call sizes the buffer; later calls reuse it
+        let mut out = Vec::new();
+        decompress_into(&result.serialized_tokens, &config, &mut out).unwrap();
+        assert_eq!(out, tokens);
+
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        decompress_into(&result.serialized_tokens, &config, &mut out).unwrap();
+        let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+        assert_eq!(out, tokens);
+
+        // Parsing the dictionary allocates in proportion to the stream;
+        // nothing should in proportion to the decoded output
+        let stream_bytes = result.serialized_tokens.len() * std::mem::size_of::<u32>();
+        assert!(
+            allocated <= stream_bytes * 4,
+            "allocated {} bytes decoding a {} byte stream",
+            allocated,
+            stream_bytes
+        );
+    }
+}
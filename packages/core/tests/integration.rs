@@ -24,7 +24,7 @@ fn test_roundtrip_repeated_pattern() {
     );
 
     // Verify round-trip
-    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config);
+    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config).unwrap();
     let restored = dictionary::decompress(&result.serialized_tokens, &dict, &config);
     assert_eq!(restored, tokens, "Round-trip should preserve tokens");
 }
@@ -43,7 +43,7 @@ fn test_roundtrip_complex_patterns() {
     let config = types::CompressionConfig::default();
     let result = compress_internal(&tokens, &config, 0xFFFF0000).unwrap();
 
-    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config);
+    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config).unwrap();
     let restored = dictionary::decompress(&result.serialized_tokens, &dict, &config);
     assert_eq!(restored, tokens);
 }
@@ -82,7 +82,7 @@ fn test_suffix_array_correctness() {
     assert_eq!(positions, vec![0, 1, 2, 3, 4, 5]);
 
     // Verify LCP finds repeated pattern
-    let intervals = sa.lcp_intervals(2);
+    let intervals = sa.lcp_intervals(2, 2);
     assert!(!intervals.is_empty(), "Should find LCP intervals");
 }
 
@@ -115,8 +115,9 @@ fn test_discovery_finds_patterns() {
     let config = discovery::DiscoveryConfig {
         min_length: 2,
         max_length: 5,
-        min_occurrences: 2,
+        min_occurrences: discovery::MinOccurrences::Uniform(2),
         extra_cost: 1,
+        ..Default::default()
     };
 
     let candidates = discovery::discover_candidates(&tokens, &config);
@@ -124,7 +125,7 @@ fn test_discovery_finds_patterns() {
     // Should find the [1, 2, 3] pattern
     assert!(!candidates.is_empty(), "Should discover patterns");
 
-    let found_123 = candidates.iter().any(|c| c.subsequence == vec![1, 2, 3]);
+    let found_123 = candidates.iter().any(|c| c.subsequence() == [1, 2, 3]);
     assert!(found_123, "Should find [1, 2, 3] pattern");
 }
 
@@ -132,20 +133,8 @@ fn test_discovery_finds_patterns() {
 fn test_dictionary_serialization() {
     let config = types::CompressionConfig::default();
     let occurrences = vec![
-        types::Occurrence {
-            start: 0,
-            length: 3,
-            subsequence: vec![1, 2, 3],
-            priority: 0,
-            patches: vec![],
-        },
-        types::Occurrence {
-            start: 3,
-            length: 3,
-            subsequence: vec![1, 2, 3],
-            priority: 0,
-            patches: vec![],
-        },
+        types::Occurrence::new(0, vec![1, 2, 3], 0),
+        types::Occurrence::new(3, vec![1, 2, 3], 0),
     ];
 
     let dict = dictionary::build_dictionary(&occurrences, &config, 0xFFFF0000);
@@ -157,7 +146,7 @@ fn test_dictionary_serialization() {
     assert_eq!(*dict.tokens.last().unwrap(), config.dict_end_token);
 
     // Verify parsing works
-    let parsed = dictionary::parse_dictionary(&dict.tokens, &config);
+    let parsed = dictionary::parse_dictionary(&dict.tokens, &config).unwrap();
     assert_eq!(parsed.len(), 1);
     assert_eq!(parsed.get(&0xFFFF0000), Some(&vec![1u32, 2u32, 3u32]));
 }
@@ -195,7 +184,7 @@ fn test_hierarchical_compression() {
     assert!(result.compressed_length < result.original_length);
 
     // Verify round-trip
-    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config);
+    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config).unwrap();
     let restored = dictionary::decompress(&result.serialized_tokens, &dict, &config);
     assert_eq!(restored, tokens);
 }
@@ -218,7 +207,7 @@ fn test_large_input() {
     );
 
     // Verify round-trip
-    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config);
+    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config).unwrap();
     let restored = dictionary::decompress(&result.serialized_tokens, &dict, &config);
     assert_eq!(restored, tokens);
 }
@@ -238,11 +227,41 @@ fn test_streaming_compressor() {
     let result = compress_internal(&expected, &config, 0xFFFF0000).unwrap();
 
     // Verify it works
-    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config);
+    let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config).unwrap();
     let restored = dictionary::decompress(&result.serialized_tokens, &dict, &config);
     assert_eq!(restored, expected);
 }
 
+#[test]
+fn test_python_parity_golden_outputs() {
+    // Captured by scripts/generate_parity_goldens.py
+    let golden: serde_json::Value =
+        serde_json::from_str(include_str!("fixtures/python_parity.json")).unwrap();
+    let config = types::CompressionConfig {
+        strict_python_parity: true,
+        python_rng_seed: golden["rng_seed"].as_u64().unwrap(),
+        verify: true,
+        ..Default::default()
+    };
+    let token_list = |value: &serde_json::Value| -> Vec<u32> {
+        let list = value.as_array().unwrap();
+        list.iter().map(|t| t.as_u64().unwrap() as u32).collect()
+    };
+
+    for case in golden["cases"].as_array().unwrap() {
+        let tokens = token_list(&case["tokens"]);
+        let expected = token_list(&case["expected"]);
+
+        let result =
+            python_parity::compress_python_parity(&tokens, &config, 0xFFFF0000).unwrap();
+
+        assert_eq!(result.serialized_tokens, expected, "case {}", case["name"]);
+        let dict = dictionary::parse_dictionary(&result.serialized_tokens, &config).unwrap();
+        let restored = dictionary::decompress(&result.serialized_tokens, &dict, &config);
+        assert_eq!(restored, tokens, "case {}", case["name"]);
+    }
+}
+
 // Helper function for internal tests
 fn compress_internal(
     tokens: &[u32],
@@ -250,20 +269,24 @@ fn compress_internal(
     next_meta_token: u32,
 ) -> Result<types::CompressionResult, String> {
     // Handle empty or small inputs
-    if tokens.len() < config.min_subsequence_length * 2 {
-        return Ok(types::CompressionResult::no_compression(tokens.to_vec()));
+    if tokens.len() < config.input_length_threshold() {
+        let reason = types::NoCompressionReason::TooSmall;
+        return Ok(types::CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     let discovery_config = discovery::DiscoveryConfig {
         min_length: config.min_subsequence_length,
         max_length: config.max_subsequence_length,
-        min_occurrences: 2,
+        min_occurrences: discovery::MinOccurrences::Uniform(2),
         extra_cost: if config.dict_length_enabled { 1 } else { 0 },
+        max_candidates: config.max_candidates,
+        ..Default::default()
     };
 
     let candidates = discovery::discover_candidates(tokens, &discovery_config);
     if candidates.is_empty() {
-        return Ok(types::CompressionResult::no_compression(tokens.to_vec()));
+        let reason = types::NoCompressionReason::NoPatterns;
+        return Ok(types::CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     let candidates = discovery::deduplicate_candidates(candidates);
@@ -275,19 +298,27 @@ fn compress_internal(
     );
 
     if selection_result.selected.is_empty() {
-        return Ok(types::CompressionResult::no_compression(tokens.to_vec()));
+        let reason = types::NoCompressionReason::NoViableSelection;
+        return Ok(types::CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     let dict = dictionary::build_dictionary(&selection_result.selected, config, next_meta_token);
     if dict.entries.is_empty() {
-        return Ok(types::CompressionResult::no_compression(tokens.to_vec()));
+        let reason = types::NoCompressionReason::NoViableSelection;
+        return Ok(types::CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
-    let body = dictionary::build_body(tokens, &selection_result.selected, &dict.pattern_to_meta);
+    let body = dictionary::build_body(
+        tokens,
+        &selection_result.selected,
+        &dict.pattern_to_meta,
+        config.escape_token,
+    );
 
     let compressed_len = dict.tokens.len() + body.len();
     if compressed_len >= tokens.len() {
-        return Ok(types::CompressionResult::no_compression(tokens.to_vec()));
+        let reason = types::NoCompressionReason::NotBeneficial;
+        return Ok(types::CompressionResult::no_compression(tokens.to_vec(), reason));
     }
 
     Ok(dictionary::serialize_result(&dict, &body, tokens, config))
@@ -0,0 +1,107 @@
+//! Checks `CompressionConfig::estimate_memory` against measured peak heap use.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use delta_ltsc_core::compress_with_cache;
+use delta_ltsc_core::discovery::DiscoveryConfig;
+use delta_ltsc_core::discovery_cache::DiscoveryCache;
+use delta_ltsc_core::types::CompressionConfig;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// System allocator wrapper that tracks live and peak bytes.
+struct PeakAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn record_alloc(size: usize) {
+    let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_alloc(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        record_alloc(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAllocator = PeakAllocator;
+
+/// Measure peak heap growth while compressing `tokens`.
+fn measured_peak(tokens: &[u32], config: &CompressionConfig) -> usize {
+    let mut cache = DiscoveryCache::new(DiscoveryConfig::from_compression_config(config));
+    let baseline = LIVE.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+
+    let result = compress_with_cache(tokens, &mut cache, config).unwrap();
+    assert!(result.compressed_length <= tokens.len());
+
+    PEAK.load(Ordering::Relaxed) - baseline
+}
+
+fn pattern_rich(size: usize) -> Vec<u32> {
+    let mut tokens = Vec::with_capacity(size);
+    let mut filler = 100_000u32;
+    let mut i = 0u32;
+    while tokens.len() < size {
+        let p = i % 32;
+        tokens.extend((0..2 + p % 7).map(|t| p * 16 + t));
+        tokens.push(filler);
+        filler += 1;
+        i = i.wrapping_mul(31).wrapping_add(7) % 1009;
+    }
+    tokens.truncate(size);
+    tokens
+}
+
+#[test]
+fn test_estimate_memory_tracks_measured_peak() {
+    let config = CompressionConfig::default();
+
+    // Many short overlapping patterns: the most selection-heavy input
+    let tokens = pattern_rich(20_000);
+    let peak = measured_peak(&tokens, &config);
+    let estimate = config.estimate_memory(tokens.len());
+    assert!(
+        estimate >= peak,
+        "estimate {} below peak {}",
+        estimate,
+        peak
+    );
+    assert!(
+        estimate <= peak * 4,
+        "estimate {} far above peak {}",
+        estimate,
+        peak
+    );
+
+    // Other shapes stay under the estimate
+    let mut state = 0x9E37_79B9u32;
+    let random: Vec<u32> = (0..20_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % 50_000
+        })
+        .collect();
+    let cyclic: Vec<u32> = (0..20_000).map(|i| i % 5).collect();
+    for tokens in [random, cyclic] {
+        let peak = measured_peak(&tokens, &config);
+        assert!(config.estimate_memory(tokens.len()) >= peak);
+    }
+}
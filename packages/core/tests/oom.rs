@@ -0,0 +1,88 @@
+//! Checks that compression fails with `OutOfMemory` instead of aborting
+//! when the allocator refuses the suffix array.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use delta_ltsc_core::batch::compress_batch;
+use delta_ltsc_core::compress_tokens;
+use delta_ltsc_core::error::CompressionError;
+use delta_ltsc_core::types::CompressionConfig;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// System allocator wrapper that fails once live bytes would pass `LIMIT`.
+struct LimitAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn admit(size: usize) -> bool {
+    let live = LIVE.load(Ordering::Relaxed);
+    if live.saturating_add(size) > LIMIT.load(Ordering::Relaxed) {
+        return false;
+    }
+    LIVE.fetch_add(size, Ordering::Relaxed);
+    true
+}
+
+unsafe impl GlobalAlloc for LimitAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !admit(layout.size()) {
+            return ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        if !admit(new_size) {
+            LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+            return ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: LimitAllocator = LimitAllocator;
+
+#[test]
+fn test_compress_reports_out_of_memory() {
+    let config = CompressionConfig {
+        enable_parallel: false,
+        ..CompressionConfig::default()
+    };
+    let tokens: Vec<u32> = (0..1u32 << 20).map(|i| i % 97).collect();
+
+    // A megabyte of headroom, far short of the suffix array's working set
+    LIMIT.store(LIVE.load(Ordering::Relaxed) + (1 << 20), Ordering::Relaxed);
+    let result = compress_tokens(&tokens, &config);
+    LIMIT.store(usize::MAX, Ordering::Relaxed);
+    assert_eq!(result.unwrap_err(), CompressionError::OutOfMemory);
+
+    // Compression works again once memory is available
+    let result = compress_tokens(&tokens[..4096], &config).unwrap();
+    assert!(result.compressed_length < 4096);
+
+    // A batch reports it from discovery over its concatenation, which fits
+    let sequences: Vec<Vec<u32>> = tokens.chunks(1 << 16).map(<[u32]>::to_vec).collect();
+    LIMIT.store(LIVE.load(Ordering::Relaxed) + (5 << 20), Ordering::Relaxed);
+    let result = compress_batch(sequences, &config);
+    LIMIT.store(usize::MAX, Ordering::Relaxed);
+    assert_eq!(result.unwrap_err(), CompressionError::OutOfMemory);
+
+    // The parallel construction reports it too
+    if cfg!(feature = "parallel") {
+        let parallel = CompressionConfig::default();
+        LIMIT.store(LIVE.load(Ordering::Relaxed) + (1 << 20), Ordering::Relaxed);
+        let result = compress_tokens(&tokens, &parallel);
+        LIMIT.store(usize::MAX, Ordering::Relaxed);
+        assert_eq!(result.unwrap_err(), CompressionError::OutOfMemory);
+    }
+}
@@ -0,0 +1,83 @@
+"""Capture Python compressor output for the Rust core's parity tests.
+
+Writes packages/core/tests/fixtures/python_parity.json. Python emits string
+markers; they are mapped to the Rust core's integer ids:
+
+- "<Dict>" / "</Dict>" -> 0xFFFFFFF0 / 0xFFFFFFF1
+- "<MT_i>" -> 0xFFFF0000 + i
+- "<Len:n>" -> n
+"""
+
+import json
+import random
+from pathlib import Path
+
+from delta import CompressionConfig, compress
+
+DICT_START = 0xFFFFFFF0
+DICT_END = 0xFFFFFFF1
+META_BASE = 0xFFFF0000
+RNG_SEED = 0
+
+
+def to_rust_token(token):
+    if isinstance(token, int):
+        return token
+    if token == "<Dict>":
+        return DICT_START
+    if token == "</Dict>":
+        return DICT_END
+    if token.startswith("<MT_"):
+        return META_BASE + int(token[4:-1])
+    if token.startswith("<Len:"):
+        return int(token[5:-1])
+    raise ValueError(f"Unexpected token {token!r}")
+
+
+def structured(seed, size):
+    rng = random.Random(seed)
+    phrases = [[rng.randrange(2000) for _ in range(rng.randint(2, 9))] for _ in range(12)]
+    tokens = []
+    while len(tokens) < size:
+        if rng.random() < 0.6:
+            tokens.extend(rng.choice(phrases))
+        else:
+            tokens.append(rng.randrange(2000))
+    return tokens[:size]
+
+
+def cases():
+    yield "repeated_triple", [1, 2, 3] * 5
+    yield "complex_patterns", (
+        [1, 2, 3, 4, 5] * 2 + [2, 3, 4] + [1, 2, 3, 4, 5] + [6, 7] * 4
+    )
+    # Multi-digit ids sort differently as strings than as integers
+    yield "string_order", [9, 10, 100, 11] * 4 + [10, 100, 9] * 3 + [2, 10, 100] * 3
+    yield "hierarchical", [1, 2, 3, 4] * 10
+    yield "nested_phrases", ([5, 6, 7, 8, 40] + [5, 6, 7, 8, 41] + [12, 13, 5, 6]) * 6
+    yield "structured_300", structured(7, 300)
+    yield "structured_900", structured(11, 900)
+    yield "unique", list(range(1, 60))
+
+
+def main() -> None:
+    config = CompressionConfig(rng_seed=RNG_SEED, metrics_enabled=False)
+    output = {
+        "rng_seed": RNG_SEED,
+        "cases": [
+            {
+                "name": name,
+                "tokens": tokens,
+                "expected": [to_rust_token(t) for t in compress(tokens, config).serialized_tokens],
+            }
+            for name, tokens in cases()
+        ],
+    }
+
+    path = Path("packages/core/tests/fixtures/python_parity.json")
+    path.parent.mkdir(parents=True, exist_ok=True)
+    path.write_text(json.dumps(output) + "\n")
+
+
+if __name__ == "__main__":
+    main()